@@ -104,6 +104,8 @@ builtin
     : 'println'
     | 'print'
     | 'panic'
+    | 'read_int'
+    | 'read_line'
     ;
 
 lit
@@ -0,0 +1,426 @@
+//! Static information about a source file, for editor support.
+//!
+//! The AST has no positions, but it keeps the identifiers in source order.
+//! So walking it in that order lines its names up with the identifiers the parser recorded,
+//! see `spans::ExprSpans`.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    ast::*,
+    error::Diagnostic,
+    namer::{Namer, NamerErrKind},
+    parser::parse_with_spans,
+    pass::ExprTransformer,
+    spans::ExprSpans,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Name {
+    /// A variable, by the unique name `Namer` gave it.
+    Var(String),
+    Ctor(String),
+    Type(String),
+}
+
+/// An identifier in the source.
+#[derive(Debug, Clone)]
+pub struct Occurrence {
+    /// Byte offset into the source.
+    pub offset: usize,
+    pub len: usize,
+    pub name: Name,
+    /// Whether this occurrence defines the name.
+    pub def: bool,
+    /// Shown on hover at a definition.
+    pub info: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    DataType,
+    Ctor,
+    Let,
+    LetRec,
+}
+
+/// A datatype or a top-level `let`, located at its name.
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub offset: usize,
+    pub len: usize,
+    pub children: Vec<Symbol>,
+}
+
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub diags: Vec<Diagnostic>,
+    /// Unused and shadowing variables, in source order. Empty unless `diags` is.
+    pub warnings: Vec<Diagnostic>,
+    /// In source order. Empty unless the source is free of errors.
+    pub occurrences: Vec<Occurrence>,
+    pub symbols: Vec<Symbol>,
+}
+
+/// A name of the AST, before it is lined up with its token.
+struct Slot {
+    name: Name,
+    def: bool,
+    ty: Option<Ty>,
+}
+
+/// The kind and slot of a symbol.
+type SymbolSlot = (SymbolKind, usize);
+
+/// Collects the names of a program in source order.
+#[derive(Default)]
+struct Walk {
+    slots: Vec<Slot>,
+    /// The kind and slot of each symbol, and those of its children.
+    symbols: Vec<(SymbolKind, usize, Vec<SymbolSlot>)>,
+    /// The slot of the second function of the first `let rec` that defines one twice.
+    dup_letrec: Option<usize>,
+    ctors: Vec<String>,
+}
+
+impl Walk {
+    fn slot(&mut self, name: Name, def: bool, ty: Option<Ty>) -> usize {
+        self.slots.push(Slot { name, def, ty });
+        self.slots.len() - 1
+    }
+
+    fn var_def(&mut self, name: &str, ty: &Ty) -> usize {
+        let ty = (*ty != Ty::UnkTy).then(|| ty.clone());
+        self.slot(Name::Var(name.to_string()), true, ty)
+    }
+
+    fn ty(&mut self, ty: &Ty) {
+        match ty {
+            Ty::DataTy(name) => {
+                self.slot(Name::Type(name.clone()), false, None);
+            }
+            Ty::AbsTy(lhs, rhs) => {
+                self.ty(lhs);
+                self.ty(rhs);
+            }
+            _ => (),
+        }
+    }
+
+    fn data_type(&mut self, dt: &DataType) {
+        let at = self.slot(Name::Type(dt.name.clone()), true, None);
+        let mut ctors = Vec::new();
+        for arm in dt.arms.iter() {
+            let ty = arm
+                .arg_tys
+                .iter()
+                .rev()
+                .fold(Ty::DataTy(dt.name.clone()), |acc, x| {
+                    Ty::AbsTy(Box::new(x.clone()), Box::new(acc))
+                });
+            ctors.push((
+                SymbolKind::Ctor,
+                self.slot(Name::Ctor(arm.ctor.clone()), true, Some(ty)),
+            ));
+            arm.arg_tys.iter().for_each(|x| self.ty(x));
+        }
+        self.symbols.push((SymbolKind::DataType, at, ctors));
+    }
+
+    fn ptn(&mut self, ptn: &MatchPattern) {
+        match ptn {
+            MatchPattern::Binder { name } => {
+                self.var_def(name, &Ty::UnkTy);
+            }
+            MatchPattern::Tuple { subs } => subs.iter().for_each(|x| self.ptn(x)),
+            MatchPattern::Lit { .. } => (),
+            MatchPattern::DataType { ctor, subs } => {
+                self.slot(Name::Ctor(ctor.clone()), false, None);
+                subs.iter().for_each(|x| self.ptn(x));
+            }
+        }
+    }
+
+    /// `top` is whether `e` is on the chain of bodies of top-level `let`s.
+    fn expr(&mut self, e: &Expr, top: bool) {
+        use Expr::*;
+        match e {
+            VarRef { id } if self.ctors.contains(id) => {
+                self.slot(Name::Ctor(id.clone()), false, None);
+            }
+            VarRef { id } => {
+                self.slot(Name::Var(id.clone()), false, None);
+            }
+            Let {
+                name,
+                ty,
+                val,
+                body,
+            } => {
+                let at = self.var_def(name, ty);
+                if top {
+                    self.symbols.push((SymbolKind::Let, at, Vec::new()));
+                }
+                self.ty(ty);
+                self.expr(val, false);
+                self.expr(body, top);
+            }
+            LetRec { arms, body } => {
+                let mut fns: Vec<&String> = Vec::new();
+                for arm in arms.iter() {
+                    let at = self.var_def(&arm.fn_name, &arm.fn_ty);
+                    if fns.contains(&&arm.fn_name) && self.dup_letrec.is_none() {
+                        self.dup_letrec = Some(at);
+                    }
+                    fns.push(&arm.fn_name);
+                    if top {
+                        self.symbols.push((SymbolKind::LetRec, at, Vec::new()));
+                    }
+                    self.ty(&arm.fn_ty);
+                    self.var_def(&arm.arg_name, &arm.arg_ty);
+                    self.ty(&arm.arg_ty);
+                    self.expr(&arm.body, false);
+                }
+                self.expr(body, top);
+            }
+            Abs {
+                arg_name,
+                arg_ty,
+                body,
+            } => {
+                self.var_def(arg_name, arg_ty);
+                self.ty(arg_ty);
+                self.expr(body, false);
+            }
+            Match { sub, arms } => {
+                self.expr(sub, false);
+                for arm in arms.iter() {
+                    self.ptn(&arm.ptn);
+                    self.expr(&arm.res, false);
+                }
+            }
+            Binary { lhs, rhs, .. } => {
+                self.expr(lhs, false);
+                self.expr(rhs, false);
+            }
+            App { fun, arg } => {
+                self.expr(fun, false);
+                self.expr(arg, false);
+            }
+            Ite { cond, tr, fl } => {
+                self.expr(cond, false);
+                self.expr(tr, false);
+                self.expr(fl, false);
+            }
+            Seq { subs } | Tuple { subs } => subs.iter().for_each(|x| self.expr(x, false)),
+            Unary { sub, .. } | Nth { sub, .. } => self.expr(sub, false),
+            IntLit { .. } | UnitLit {} | Builtin { .. } | Error {} => (),
+        }
+    }
+}
+
+fn diagnostic(src: &str, offset: usize, len: usize, msg: String) -> Diagnostic {
+    let before = &src[..offset];
+    let line_start = before.rfind('\n').map_or(0, |x| x + 1);
+    Diagnostic {
+        offset,
+        line: before.matches('\n').count() as u32 + 1,
+        col: before[line_start..].chars().count() + 1,
+        len,
+        msg,
+    }
+}
+
+pub fn analyze(src: &str) -> Analysis {
+    let (prog, diags, spans) = parse_with_spans(src);
+    if !diags.is_empty() {
+        return Analysis {
+            diags,
+            ..Default::default()
+        };
+    }
+    analyze_prog(src, &prog, &spans)
+}
+
+/// `analyze` of a program already parsed from `src` without errors, with these spans.
+pub fn analyze_prog(src: &str, prog: &Prog, spans: &ExprSpans) -> Analysis {
+    // constructors are not variables, but are referred to like them
+    let ctors = prog
+        .data_types
+        .iter()
+        .flat_map(|x| x.arms.iter().map(|x| x.ctor.clone()))
+        .collect::<Vec<_>>();
+    let mut namer = Namer::new();
+    for ctor in ctors.iter() {
+        namer.define_global(ctor, ctor);
+    }
+    // the namer renames in place, and the caller may name `prog` itself
+    let mut main_expr = prog.main_expr.clone();
+    let named = namer.visit(&mut main_expr);
+
+    let mut walk = Walk {
+        ctors,
+        ..Default::default()
+    };
+    prog.data_types.iter().for_each(|x| walk.data_type(x));
+    walk.expr(&main_expr, true);
+    let toks = &spans.names;
+    // a mismatch would be a bug, but is no reason to take the editor down
+    if toks.len() != walk.slots.len() {
+        let msg = format!(
+            "internal error: {} names in the program, but {} identifiers in the source",
+            walk.slots.len(),
+            toks.len()
+        );
+        return Analysis {
+            diags: vec![diagnostic(src, 0, 0, msg)],
+            ..Default::default()
+        };
+    }
+    let text = |at: usize| &src[toks[at].0..toks[at].0 + toks[at].1];
+
+    let symbol = |(kind, at): (SymbolKind, usize)| Symbol {
+        name: text(at).to_string(),
+        kind,
+        offset: toks[at].0,
+        len: toks[at].1,
+        children: Vec::new(),
+    };
+    let symbols = walk
+        .symbols
+        .iter()
+        .map(|(kind, at, children)| Symbol {
+            children: children.iter().map(|x| symbol(*x)).collect(),
+            ..symbol((*kind, *at))
+        })
+        .collect();
+
+    let at_error = |at: Option<usize>, msg: String| match at {
+        Some(at) => diagnostic(src, toks[at].0, toks[at].1, msg),
+        None => diagnostic(src, 0, 0, msg),
+    };
+    let diags = match named {
+        Ok(()) => Vec::new(),
+        // the namer stops at the first error, so that is the first variable it did not rename
+        Err(err) => {
+            let at = match &err {
+                NamerErrKind::UnknownVarRef { id } => walk
+                    .slots
+                    .iter()
+                    .position(|x| !x.def && x.name == Name::Var(id.clone())),
+                NamerErrKind::DuplicateLetRecFn {} => walk.dup_letrec,
+            };
+            vec![at_error(at, err.to_string())]
+        }
+    };
+    if !diags.is_empty() {
+        return Analysis {
+            diags,
+            symbols,
+            ..Default::default()
+        };
+    }
+
+    // variables have unique names by now, so each has one definition
+    let mut defs = HashMap::new();
+    let mut used = HashSet::new();
+    for (at, x) in walk.slots.iter().enumerate() {
+        if x.def {
+            defs.entry(&x.name).or_insert(at);
+        } else {
+            used.insert(&x.name);
+        }
+    }
+    let def_at = |name: &str| defs.get(&Name::Var(name.to_string())).copied();
+    let mut warnings = Vec::new();
+    for (at, x) in walk.slots.iter().enumerate() {
+        let var = matches!(x.name, Name::Var(_));
+        if x.def && var && !used.contains(&x.name) && !text(at).starts_with('_') {
+            let msg = format!("unused variable `{}`", text(at));
+            warnings.push(diagnostic(src, toks[at].0, toks[at].1, msg));
+        }
+    }
+    for (new, prev) in namer.shadowed() {
+        if let Some((at, prev_at)) = def_at(new).zip(def_at(prev)) {
+            let prev = diagnostic(src, toks[prev_at].0, 0, String::new());
+            let msg = format!(
+                "`{}` shadows the one at {}:{}",
+                text(at),
+                prev.line,
+                prev.col
+            );
+            warnings.push(diagnostic(src, toks[at].0, toks[at].1, msg));
+        }
+    }
+    warnings.sort_by_key(|x| x.offset);
+
+    let occurrences = walk
+        .slots
+        .into_iter()
+        .enumerate()
+        .map(|(at, x)| {
+            let info = match (&x.name, x.ty) {
+                (Name::Type(_), _) => format!("datatype {}", text(at)),
+                (_, Some(ty)) => format!("{} : {ty}", text(at)),
+                (_, None) => text(at).to_string(),
+            };
+            Occurrence {
+                offset: toks[at].0,
+                len: toks[at].1,
+                name: x.name,
+                def: x.def,
+                info,
+            }
+        })
+        .collect();
+    Analysis {
+        diags: Vec::new(),
+        warnings,
+        occurrences,
+        symbols,
+    }
+}
+
+impl Analysis {
+    /// The occurrence that contains `offset`, including its end so the cursor may be past it.
+    pub fn at(&self, offset: usize) -> Option<&Occurrence> {
+        self.occurrences
+            .iter()
+            .find(|x| x.offset <= offset && offset <= x.offset + x.len)
+    }
+
+    pub fn definition(&self, offset: usize) -> Option<&Occurrence> {
+        let name = &self.at(offset)?.name;
+        self.occurrences.iter().find(|x| x.def && x.name == *name)
+    }
+
+    /// All occurrences of the name at `offset`, in source order.
+    pub fn references(&self, offset: usize, include_def: bool) -> Vec<&Occurrence> {
+        let Some(at) = self.at(offset) else {
+            return Vec::new();
+        };
+        self.occurrences
+            .iter()
+            .filter(|x| x.name == at.name && (include_def || !x.def))
+            .collect()
+    }
+
+    pub fn hover(&self, offset: usize) -> Option<&str> {
+        self.definition(offset).map(|x| &x.info[..])
+    }
+
+    /// For each occurrence, the index of its definition.
+    /// Two sources with the same bindings agree on this.
+    pub fn bindings(&self) -> Vec<Option<usize>> {
+        self.occurrences
+            .iter()
+            .map(|x| {
+                self.occurrences
+                    .iter()
+                    .position(|y| y.def && y.name == x.name)
+            })
+            .collect()
+    }
+}
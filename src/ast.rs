@@ -34,12 +34,17 @@ pub enum UnaOp {
     Lnot,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum BuiltinOp {
     Println,
-    Nth,
+    Print,
+    Panic,
     True,
     False,
+    ReadInt,
+    ReadLine,
+    /// Registered by the embedding application, see `secd::host`.
+    Host(String),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -52,7 +57,22 @@ pub enum Ty {
     DataTy(String),
 }
 
-#[derive(Debug)]
+impl std::fmt::Display for Ty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ty::UnitTy => write!(f, "unit"),
+            Ty::IntTy => write!(f, "int"),
+            Ty::BoolTy => write!(f, "bool"),
+            Ty::UnkTy => write!(f, "?"),
+            // -> is r-assoc
+            Ty::AbsTy(box lhs @ Ty::AbsTy(..), rhs) => write!(f, "({lhs}) -> {rhs}"),
+            Ty::AbsTy(lhs, rhs) => write!(f, "{lhs} -> {rhs}"),
+            Ty::DataTy(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct LetRecArm {
     pub fn_name: String,
     pub fn_ty: Ty,
@@ -61,7 +81,7 @@ pub struct LetRecArm {
     pub body: Box<Expr>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum MatchPattern {
     Binder {
         name: String,
@@ -78,13 +98,24 @@ pub enum MatchPattern {
     },
 }
 
-#[derive(Debug)]
+impl MatchPattern {
+    /// Whether a constructor appears in the pattern.
+    pub fn has_ctor(&self) -> bool {
+        match self {
+            MatchPattern::DataType { .. } => true,
+            MatchPattern::Tuple { subs } => subs.iter().any(|x| x.has_ctor()),
+            MatchPattern::Binder { .. } | MatchPattern::Lit { .. } => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct MatchArm {
     pub ptn: MatchPattern,
     pub res: Expr,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     IntLit {
         val: i64,
@@ -143,6 +174,8 @@ pub enum Expr {
         sub: Box<Expr>,
         arms: Vec<MatchArm>,
     },
+    /// Placeholder for a malformed expression the parser recovered from.
+    Error {},
 }
 
 #[derive(Debug, PartialEq)]
@@ -157,24 +190,41 @@ pub struct DataType {
     pub arms: Vec<DataTypeArm>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Prog {
     pub data_types: Vec<DataType>,
     pub main_expr: Expr,
 }
 
+/// One line of REPL input.
+///
+/// A `Binding` is a `Let` or `LetRec` whose body refers back to the bound name,
+/// so evaluating it leaves the bound value on the env (and a copy on the stack).
+#[derive(Debug)]
+pub enum ReplInput {
+    Binding(Expr),
+    Expr(Expr),
+}
+
 pub static BUILTIN_PARSE: phf::Map<&'static str, BuiltinOp> = phf_map! {
     "println" => BuiltinOp::Println,
+    "print" => BuiltinOp::Print,
+    "panic" => BuiltinOp::Panic,
     "true" => BuiltinOp::True,
     "false" => BuiltinOp::False,
-    "nth" => BuiltinOp::Nth,
+    "read_int" => BuiltinOp::ReadInt,
+    "read_line" => BuiltinOp::ReadLine,
 };
 
-pub fn builtin_print(op: BuiltinOp) -> &'static str {
+pub fn builtin_print(op: &BuiltinOp) -> &str {
     match op {
         BuiltinOp::Println => "println",
-        BuiltinOp::Nth => "nth",
+        BuiltinOp::Print => "print",
+        BuiltinOp::Panic => "panic",
         BuiltinOp::True => "true",
         BuiltinOp::False => "false",
+        BuiltinOp::ReadInt => "read_int",
+        BuiltinOp::ReadLine => "read_line",
+        BuiltinOp::Host(name) => name,
     }
 }
@@ -0,0 +1,50 @@
+//! Language server for MiniML, speaking LSP over stdio.
+use std::{
+    io::{stdin, stdout, BufRead, Write},
+    process::exit,
+};
+
+extern crate tut;
+
+use tut::lsp::Server;
+
+/// The content of the next message, or `None` at the end of input.
+fn read_message(input: &mut impl BufRead) -> Option<Vec<u8>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).unwrap() == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(n) = line.strip_prefix("Content-Length:") {
+            len = n.trim().parse().ok();
+        }
+    }
+    let mut buf = vec![0; len.expect("message without Content-Length")];
+    input.read_exact(&mut buf).unwrap();
+    Some(buf)
+}
+
+fn main() {
+    let mut server = Server::new();
+    let mut input = stdin().lock();
+    let mut output = stdout().lock();
+    while let Some(buf) = read_message(&mut input) {
+        let Ok(msg) = serde_json::from_slice(&buf) else {
+            eprintln!("miniml-lsp: ignoring malformed message");
+            continue;
+        };
+        for res in server.handle(&msg) {
+            let res = res.to_string();
+            write!(output, "Content-Length: {}\r\n\r\n{res}", res.len()).unwrap();
+            output.flush().unwrap();
+        }
+        if let Some(code) = server.exit {
+            exit(code);
+        }
+    }
+}
@@ -1,61 +1,381 @@
 use clap::Parser;
 use std::{
     fs::{self, File},
-    io::{stdout, Write},
-    path::PathBuf,
+    io::{stdin, stdout, Write},
+    path::{Path, PathBuf},
+    process::exit,
 };
 
 extern crate tut;
 
 use tut::{
+    analysis::analyze_prog,
+    ast::Prog,
     debrujin::DeBrujin,
+    desugar::Desugar,
+    error::{Diagnostic, MiniMLErr},
+    inspector::Inspector,
+    ir::{anf, cps},
     namer::Namer,
-    parser::parse,
+    opt::optimize,
+    parser::parse_with_spans,
     pass::{ExprListener, ExprTransformer},
-    secd::secdgen::secdgen,
+    printer::format_source,
+    repl::Repl,
+    secd::{
+        cpsgen::CPSGen, effects::Stdout, machine::SECDMachine, peephole::peephole,
+        repr::secd_print, secdgen::SECDGen,
+    },
+    spans::{ExprSpans, SpanInfo},
+    typer::infer,
 };
 
-#[derive(Debug, Clone, clap::ValueEnum)]
+/// Stages of the pipeline, in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
 enum Stage {
     Parse,
+    Named,
+    /// The type of each expression. Only checked for this stage, as the later ones
+    /// do not need types and run programs that are not well typed.
+    Typed,
+    Desugared,
+    Optimized,
+    Debrujin,
+    Anf,
+    Cps,
     SECD,
 }
 
+/// What the closures made by the SECD code capture.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum Closures {
+    /// The whole env where they are made.
+    Shared,
+    /// Only their free variables.
+    Flat,
+    /// Like `flat`, with closed functions made once at the start of the program.
+    Lifted,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Interactive read-eval-print loop.
+    Repl,
+    /// Compile and execute a program.
+    Run {
+        infile: PathBuf,
+        /// Optimization level, see `-O` of the compiler.
+        #[arg(short = 'O', default_value_t = 0)]
+        opt_level: u8,
+        /// Compile through CPS, see `--cps` of the compiler.
+        #[arg(long)]
+        cps: bool,
+        /// See `--closures` of the compiler.
+        #[arg(long, value_enum, default_value = "shared")]
+        closures: Closures,
+    },
+    /// Reformat source files in place.
+    Fmt { files: Vec<PathBuf> },
+}
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
-    infile: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    infile: Option<PathBuf>,
 
     #[arg(short, long)]
     outfile: Option<PathBuf>,
 
-    #[arg(short, long, value_enum, default_value = "secd")]
-    stage: Stage,
+    /// Print the output of this stage and stop.
+    #[arg(short = 's', long, alias = "stage", value_enum, default_value = "secd")]
+    stop_after: Stage,
+
+    /// Print the output of this stage and keep going. Can be repeated.
+    #[arg(long, value_enum)]
+    dump_after: Vec<Stage>,
+
+    /// Keep a source map in the SECD code, for `secdi --debug`.
+    #[arg(short = 'g', long)]
+    debug_info: bool,
+
+    /// Optimization level: 0 for none, 1 to fold constants, remove dead code and
+    /// run the peephole optimizer on the SECD code, 2 to also inline functions.
+    /// `-g` caps it at 1 without the peephole optimizer, as inlined code and removed
+    /// instructions cannot be mapped back to the source.
+    #[arg(short = 'O', default_value_t = 0)]
+    opt_level: u8,
+
+    /// Compile through CPS, so that every call is a jump and the code runs without a dump.
+    /// Implied by `--stop-after cps`.
+    #[arg(long, conflicts_with = "debug_info")]
+    cps: bool,
+
+    /// What closures capture. Continuations in CPS code always capture the whole env.
+    #[arg(long, value_enum, default_value = "shared")]
+    closures: Closures,
+}
+
+/// Decides what is printed after each stage.
+struct Driver {
+    stop_after: Stage,
+    dump_after: Vec<Stage>,
+    os: Box<dyn Write>,
+}
+
+impl Driver {
+    /// Whether the output of `stage` is printed.
+    fn shows(&self, stage: Stage) -> bool {
+        stage == self.stop_after || self.dump_after.contains(&stage)
+    }
+
+    /// Print the output of `stage` if asked to. Returns whether to stop.
+    fn after(&mut self, stage: Stage, show: impl FnOnce() -> String) -> bool {
+        let stop = stage == self.stop_after;
+        if self.shows(stage) {
+            if !self.dump_after.is_empty() {
+                writeln!(self.os, "--- after {stage:?}").unwrap();
+            }
+            writeln!(self.os, "{}", show()).unwrap();
+        }
+        stop
+    }
+}
+
+fn repl() {
+    let mut repl = Repl::new();
+    loop {
+        print!("> ");
+        stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        match repl.handle(&line) {
+            None => break,
+            Some(Ok(out)) if out.is_empty() => (),
+            Some(Ok(out)) => println!("{out}"),
+            Some(Err(err)) => eprintln!("{err}"),
+        }
+    }
+}
+
+/// The program and the spans of its expressions.
+/// Exits with 1 after reporting all the syntax errors, if there are any.
+/// Otherwise reports the warnings.
+fn parse_file(infile: &Path) -> (Prog, ExprSpans) {
+    let buf = fs::read_to_string(infile).unwrap();
+    let (prog, diags, spans) = parse_with_spans(&buf);
+    if !diags.is_empty() {
+        for diag in diags.iter() {
+            eprintln!("{}:{diag}", infile.display());
+        }
+        exit(1);
+    }
+    for warning in analyze_prog(&buf, &prog, &spans).warnings {
+        let Diagnostic { line, col, msg, .. } = warning;
+        eprintln!("{}:{line}:{col}: warning: {msg}", infile.display());
+    }
+    (prog, spans)
+}
+
+/// Lowers `prog` to ANF, lifting closed functions if asked to.
+fn lower(prog: &Prog, spans: Option<&SpanInfo>, closures: Closures) -> anf::Term {
+    let mut main = anf::lower(&prog.main_expr, spans);
+    if closures == Closures::Lifted {
+        main = anf::lift(main);
+    }
+    if let Err(err) = anf::verify(&main, &[]) {
+        panic!("malformed ANF: {err}\n{main}");
+    }
+    main
+}
+
+/// Exits with 0 if the program halts and 1 on runtime errors.
+fn run(infile: PathBuf, opt_level: u8, cps: bool, closures: Closures) -> ! {
+    let (mut prog, _) = parse_file(&infile);
+
+    let mut namer = Namer::new();
+    namer.visit(&mut prog.main_expr).unwrap();
+    Desugar::new().visit(&mut prog.main_expr);
+    optimize(&mut prog.main_expr, opt_level);
+
+    let main = lower(&prog, None, closures);
+    let instrs = if cps {
+        let mut cpsgen = CPSGen::new();
+        cpsgen.gen_main(&cps::convert(&main));
+        cpsgen.program()
+    } else {
+        let mut secdgen = SECDGen::new();
+        if closures != Closures::Shared {
+            secdgen = secdgen.with_flat_closures();
+        }
+        secdgen.gen_main(&main);
+        secdgen.program()
+    };
+    let instrs = match opt_level {
+        0 => instrs,
+        _ => peephole(instrs, "main"),
+    };
+
+    let mut machine = SECDMachine::with_sink(Stdout);
+    let mut res = machine.load(instrs, "main");
+    while res.is_ok() && !machine.halted() {
+        res = machine.step();
+    }
+    if let Err(err) = res {
+        eprintln!("Execution terminated with error: {err}");
+        exit(1);
+    }
+    exit(0)
+}
+
+/// Exits with 1 if any file could not be formatted.
+fn fmt(files: Vec<PathBuf>) -> ! {
+    let mut ok = true;
+    for file in files {
+        let src = fs::read_to_string(&file).unwrap();
+        match format_source(&src) {
+            Ok(out) if out == src => (),
+            Ok(out) => fs::write(&file, out).unwrap(),
+            Err(MiniMLErr::ParseError(diags)) => {
+                for diag in diags.iter() {
+                    eprintln!("{}:{diag}", file.display());
+                }
+                ok = false;
+            }
+            Err(err) => {
+                eprintln!("{}: {err}", file.display());
+                ok = false;
+            }
+        }
+    }
+    exit(if ok { 0 } else { 1 })
 }
 
 fn main() {
     let cli = Cli::parse();
 
-    let buf = fs::read_to_string(cli.infile).unwrap();
-
-    let mut prog = parse(&buf).unwrap();
-    if let Stage::Parse = cli.stage {
-        println!("{:#?}", prog);
+    match cli.command {
+        Some(Command::Repl) => {
+            repl();
+            return;
+        }
+        Some(Command::Run {
+            infile,
+            opt_level,
+            cps,
+            closures,
+        }) => run(infile, opt_level, cps, closures),
+        Some(Command::Fmt { files }) => fmt(files),
+        None => (),
     }
 
-    let mut os: Box<dyn Write> = match cli.outfile {
+    let infile = cli.infile.unwrap_or_else(|| {
+        eprintln!("No input file. See --help.");
+        exit(1);
+    });
+    let (mut prog, spans) = parse_file(&infile);
+
+    let os: Box<dyn Write> = match cli.outfile {
         Some(outfile) => Box::new(File::create(outfile).unwrap()),
         None => Box::new(stdout()),
     };
+    let mut driver = Driver {
+        stop_after: cli.stop_after,
+        dump_after: cli.dump_after,
+        os,
+    };
+
+    if driver.after(Stage::Parse, || Inspector::plain().show_prog(&prog)) {
+        return;
+    }
 
     let mut namer = Namer::new();
     namer.visit(&mut prog.main_expr).unwrap();
+    if driver.after(Stage::Named, || Inspector::plain().show_prog(&prog)) {
+        return;
+    }
+
+    if driver.shows(Stage::Typed) {
+        let types = infer(&prog).unwrap_or_else(|err| {
+            let span = spans.info(&prog).and_then(|x| x.get(err.expr).copied());
+            let at = span.map_or(String::new(), |x| format!("{}:{}:", x.line, x.col));
+            eprintln!("{}:{at} type error: {}", infile.display(), err.kind);
+            exit(1);
+        });
+        if driver.after(Stage::Typed, || Inspector::new(&types).show_prog(&prog)) {
+            return;
+        }
+    }
+
+    // desugaring and the optimizer change the AST, so find the spans first
+    let spans = cli.debug_info.then(|| spans.info(&prog));
+    Desugar::new().visit(&mut prog.main_expr);
+    if driver.after(Stage::Desugared, || Inspector::plain().show_prog(&prog)) {
+        return;
+    }
+
+    let opt_level = if cli.debug_info {
+        cli.opt_level.min(1)
+    } else {
+        cli.opt_level
+    };
+    optimize(&mut prog.main_expr, opt_level);
+    if driver.after(Stage::Optimized, || Inspector::plain().show_prog(&prog)) {
+        return;
+    }
 
     let mut db = DeBrujin::new();
     db.walk(&mut prog.main_expr);
     let debrujin_info = db.get_info();
-    let secd_instrs = secdgen(debrujin_info, &prog.main_expr);
-    if let Stage::SECD = cli.stage {
-        writeln!(os, "{}", secd_instrs).unwrap();
+    if driver.after(Stage::Debrujin, || {
+        Inspector::new(&debrujin_info).show_prog(&prog)
+    }) {
+        return;
+    }
+
+    let mut header = String::new();
+    let spans = spans.and_then(|spans| {
+        if spans.is_none() {
+            eprintln!("Cannot map the code back to {}.", infile.display());
+        }
+        let path = infile.canonicalize().unwrap_or(infile);
+        header = format!("# source: {}\n", path.display());
+        spans
+    });
+    let main = lower(&prog, spans.as_ref(), cli.closures);
+    if driver.after(Stage::Anf, || main.to_string()) {
+        return;
+    }
+
+    if cli.cps || driver.stop_after == Stage::Cps {
+        let main = cps::convert(&main);
+        if driver.after(Stage::Cps, || main.to_string()) {
+            return;
+        }
+        let mut cpsgen = CPSGen::new();
+        cpsgen.gen_main(&main);
+        driver.after(Stage::SECD, || match opt_level {
+            0 => cpsgen.assemble(),
+            _ => secd_print(&peephole(cpsgen.program(), "main")),
+        });
+        return;
+    }
+
+    let mut secdgen = SECDGen::new();
+    if let Some(spans) = spans {
+        secdgen = secdgen.with_spans(spans);
+    }
+    if cli.closures != Closures::Shared {
+        secdgen = secdgen.with_flat_closures();
     }
+    secdgen.gen_main(&main);
+    driver.after(Stage::SECD, || match opt_level {
+        1.. if !cli.debug_info => secd_print(&peephole(secdgen.program(), "main")),
+        _ => header + &secdgen.assemble(),
+    });
 }
@@ -1,6 +1,8 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use serde_json::{json, Value};
 use tut::secd::langdef::SECDVal;
-use tut::secd::repr::secd_parse;
+use tut::secd::repr::{effect_to_json, secd_parse, EnvTable};
+use tut::secd::srcmap::{Loc, SourceMap};
 
 use std::cmp::{max, min};
 use std::fmt::Write;
@@ -11,7 +13,11 @@ use std::{
     io::{stdin, Read},
 };
 
-use tut::secd::machine::{SECDMachine, SECDState, SECDStepResult};
+use tut::parser::parse_with_spans;
+use tut::secd::coverage::SrcCoverage;
+use tut::secd::effects::Capture;
+use tut::secd::machine::{Limits, SECDEffect, SECDError, SECDMachine, SECDState, SECDStepResult};
+use tut::secd::profile::Profiler;
 
 extern crate tut;
 
@@ -23,22 +29,106 @@ struct Cli {
     #[arg(short, long)]
     interactive: bool,
 
+    /// Step through the MiniML source, for code compiled with `miniml -g`.
+    #[arg(short, long)]
+    debug: bool,
+
     #[arg(short, long)]
     brief: bool,
 
+    /// Continue from a state saved with `--save`, or `save` in interactive mode.
+    #[arg(long)]
+    resume: Option<PathBuf>,
+
+    /// Save the state where execution stopped, e.g. to resume after running out of steps.
+    #[arg(long)]
+    save: Option<PathBuf>,
+
+    /// Lines for `read_int` and `read_line`. Without it there is no input.
+    #[arg(long)]
+    input: Option<PathBuf>,
+
+    /// Stop after this many steps.
     #[arg(short, long)]
     maxstep: Option<usize>,
+
+    #[arg(long)]
+    max_stack: Option<usize>,
+
+    #[arg(long)]
+    max_env: Option<usize>,
+
+    /// Bytes of values on the stack and in the env.
+    #[arg(long)]
+    max_heap: Option<usize>,
+
+    /// Bytes printed.
+    #[arg(long)]
+    max_output: Option<usize>,
+
+    /// How to print the result of a batch run.
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// With `--format json`, also record the pc and instruction of every step.
+    #[arg(long)]
+    trace: bool,
+
+    /// Print instructions executed per function, and other counts, to stderr.
+    #[arg(long)]
+    profile: bool,
+
+    /// Write the profile as folded stacks, for flamegraph tools.
+    #[arg(long)]
+    folded: Option<PathBuf>,
+
+    /// Write line and branch coverage of the MiniML source as an lcov tracefile.
+    /// The code must have been compiled with `miniml -g`.
+    #[arg(long)]
+    lcov: Option<PathBuf>,
+
+    /// Write the MiniML source annotated with coverage.
+    #[arg(long)]
+    annotate: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Format {
+    Text,
+    Json,
 }
 
-pub fn clrscr() {
-    println!("\x1b[H\x1b[J")
+/// The machine after some step, enough to go back to it.
+struct Snapshot {
+    state: SECDState,
+    /// Effects are only ever appended, so the number of them so far.
+    neffects: usize,
+    /// Lines of input read so far.
+    nread: usize,
 }
 
+/// Snapshots every `every` steps, from which any earlier step can be replayed.
+/// When there are too many, every other one is dropped, so memory stays bounded.
+struct History {
+    every: usize,
+    /// Steps taken before the first checkpoint, when resumed from a saved state.
+    start: usize,
+    /// The `i`th is taken after `start + i * every` steps.
+    checkpoints: Vec<Snapshot>,
+}
+
+const CHECKPOINT_EVERY: usize = 64;
+
+const MAX_CHECKPOINTS: usize = 1024;
+
 struct SECDInterp<'s> {
     machine: SECDMachine,
-    nsteps: usize,
     lines: Vec<&'s str>,
-    dumps: Vec<String>,
+    /// Only kept if we may go back in time.
+    history: Option<History>,
+    srcmap: SourceMap,
+    /// Lines of the MiniML source, if it could be read.
+    source: Vec<String>,
 }
 
 /// When dumping, these limits the portion of code being printed.
@@ -48,7 +138,7 @@ const BEFORE_MAX: usize = 10;
 const AFTER_MAX: usize = 15;
 
 impl<'s> SECDInterp<'s> {
-    fn new(code: &'s str) -> Self {
+    fn new(code: &'s str, keep_history: bool) -> Result<Self, SECDError> {
         let lines = code
             .lines()
             .filter(|line| line.trim().len() != 0 && !line.trim().starts_with("#"))
@@ -58,19 +148,46 @@ impl<'s> SECDInterp<'s> {
             .map(|x| x.trim().to_string())
             .collect::<Vec<_>>();
         let machine = secd_parse(&lines_parse);
+        let srcmap = SourceMap::parse(code);
+        let source = match &srcmap.source {
+            Some(path) => fs::read_to_string(path)
+                .map(|x| x.lines().map(|x| x.to_string()).collect())
+                .unwrap_or_default(),
+            None => Vec::new(),
+        };
         let mut res = Self {
-            machine: SECDMachine::init(machine),
-            nsteps: 0,
+            machine: SECDMachine::init(machine)?,
             lines,
-            dumps: Vec::new(),
+            history: None,
+            srcmap,
+            source,
         };
-        res.dumps.push(res.dump());
-        res
+        if keep_history {
+            res.reset_history();
+        }
+        Ok(res)
+    }
+
+    /// Start history from the current state.
+    fn reset_history(&mut self) {
+        self.history = Some(History {
+            every: CHECKPOINT_EVERY,
+            start: self.machine.steps,
+            checkpoints: vec![self.snapshot()],
+        });
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            state: self.machine.state.clone(),
+            neffects: self.machine.sink.effects.len(),
+            nread: self.machine.sink.read,
+        }
     }
 
     pub fn dump(&self) -> String {
         let mut s = String::new();
-        writeln!(s, "--- Step: {}\n", self.nsteps).unwrap();
+        writeln!(s, "--- Step: {}\n", self.machine.steps).unwrap();
         let machine = &self.machine;
         let SECDState(pc, stk, env) = &machine.state;
         writeln!(s, "--- pc {}", pc).unwrap();
@@ -96,7 +213,7 @@ impl<'s> SECDInterp<'s> {
         }
         writeln!(s, "\n").unwrap();
         writeln!(s, "\n--- effect").unwrap();
-        for v in &machine.effects {
+        for v in &machine.sink.effects {
             writeln!(s, "{:?}", v).unwrap();
         }
         s
@@ -104,28 +221,475 @@ impl<'s> SECDInterp<'s> {
 
     fn dump_brief(&self) -> String {
         let mut res = String::new();
-        for v in &self.machine.effects {
-            match v {
-                tut::secd::machine::SECDEffect::Println(s) => writeln!(res, "{s}").unwrap(),
-            }
+        for v in &self.machine.sink.effects {
+            res.push_str(&v.text());
         }
         let SECDState(_pc, stk, _env) = &self.machine.state;
-        assert!(stk.len() == 1);
-        match stk.get(0).unwrap() {
-            SECDVal::UnitVal => writeln!(res, "()"),
-            SECDVal::IntVal(v) => writeln!(res, "{v}"),
-            _ => unreachable!(),
+        match stk.last() {
+            Some(v) => writeln!(res, "{}", show_val(v)).unwrap(),
+            None => writeln!(res, "<empty stack>").unwrap(),
+        }
+        res
+    }
+
+    /// The outcome of a batch run that stopped with `err`.
+    /// Closures in the value refer to their env by its index in `envs`.
+    fn dump_json(&self, err: &SECDError, trace: Option<Vec<Value>>) -> Value {
+        let SECDState(_pc, stk, _env) = &self.machine.state;
+        let mut envs = EnvTable::new();
+        let mut res = json!({
+            "result": match err {
+                SECDError::Halted => "halted",
+                SECDError::LimitExceeded(_) => "aborted",
+                SECDError::Fault(_) | SECDError::Panic(_) => "error",
+            },
+            "steps": self.machine.steps,
+            "effects": self.machine.sink.effects.iter().map(effect_to_json).collect::<Vec<_>>(),
+            "value": match stk.last() {
+                Some(v) if *err == SECDError::Halted => envs.val_to_json(v),
+                _ => Value::Null,
+            },
+        });
+        res["envs"] = envs.into_json();
+        if *err != SECDError::Halted {
+            res["error"] = json!(err.to_string());
+        }
+        if let Some(trace) = trace {
+            res["trace"] = json!(trace);
         }
-        .unwrap();
         res
     }
 
     fn step(&mut self) -> SECDStepResult {
-        self.nsteps += 1;
         let res = self.machine.step();
-        self.dumps.push(self.dump());
+        if let Some(h) = &mut self.history {
+            if self.machine.steps == h.start + h.checkpoints.len() * h.every {
+                h.checkpoints.push(Snapshot {
+                    state: self.machine.state.clone(),
+                    neffects: self.machine.sink.effects.len(),
+                    nread: self.machine.sink.read,
+                });
+            }
+            if h.checkpoints.len() > MAX_CHECKPOINTS {
+                let mut i = 0;
+                h.checkpoints.retain(|_| {
+                    i += 1;
+                    i % 2 == 1
+                });
+                h.every *= 2;
+            }
+        }
         res
     }
+
+    /// Go to after `step` steps, replaying from a checkpoint if that is in the past.
+    fn seek(&mut self, step: usize) -> SECDStepResult {
+        if step < self.machine.steps {
+            let h = self.history.as_ref().expect("no history kept");
+            let step = step.max(h.start);
+            let i = (step - h.start) / h.every;
+            let Snapshot {
+                state,
+                neffects,
+                nread,
+            } = &h.checkpoints[i];
+            self.machine.set_state(state.clone());
+            self.machine.sink.effects.truncate(*neffects);
+            self.machine.sink.read = *nread;
+            self.machine.output_bytes = self.machine.sink.effects.iter().map(effect_bytes).sum();
+            self.machine.steps = h.start + i * h.every;
+        }
+        while self.machine.steps < step {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    /// Go back to the last step before this one that is at one of `pcs`, or to the start.
+    fn seek_back(&mut self, pcs: &[usize]) {
+        let h = self.history.as_ref().expect("no history kept");
+        let (every, first) = (h.every, h.start);
+        let mut end = self.machine.steps;
+        while end > first {
+            // replay the steps since the last checkpoint before `end`
+            let start = first + (end - 1 - first) / every * every;
+            self.seek(start).unwrap();
+            let mut found = None;
+            while self.machine.steps < end {
+                if pcs.contains(&self.machine.state.0) {
+                    found = Some(self.machine.steps);
+                }
+                // the last step may be the one that ended execution
+                let _ = self.step();
+            }
+            if let Some(step) = found {
+                self.seek(step).unwrap();
+                return;
+            }
+            end = start;
+        }
+        self.seek(0).unwrap();
+    }
+
+    /// The pc of a label, or the pc itself.
+    fn resolve_pc(&self, s: &str) -> Option<usize> {
+        match s.parse() {
+            Ok(pc) if pc < self.lines.len() => Some(pc),
+            Ok(_) => None,
+            Err(_) => self.lines.iter().position(|x| x.trim() == format!("{s}:")),
+        }
+    }
+
+    /// The label of the function that `pc` is in.
+    fn function_of(&self, pc: usize) -> &str {
+        let mut fns = vec!["main"];
+        for line in self.lines.iter() {
+            let mut t = line.split_whitespace();
+            if let Some("closure" | "closures") = t.next() {
+                fns.extend(t);
+            }
+        }
+        self.lines[..=pc]
+            .iter()
+            .rev()
+            .filter_map(|x| x.trim().strip_suffix(':'))
+            .find(|x| fns.contains(x))
+            .unwrap_or("?")
+    }
+
+    /// Calls in progress, innermost first.
+    /// `Apply` leaves the caller's env and the return pc on the stack, so those are the frames.
+    fn backtrace(&self) -> String {
+        let SECDState(pc, stk, env) = &self.machine.state;
+        let mut s = String::new();
+        let fun = self.function_of(*pc);
+        writeln!(s, "#0  pc {pc:<4} in {fun}, env of {}", env.len()).unwrap();
+        let frames = stk.windows(2).rev().filter_map(|x| match x {
+            [SECDVal::EnvVal(env), SECDVal::PCVal(pc)] => Some((*pc, env.len())),
+            _ => None,
+        });
+        for (i, (pc, nenv)) in frames.enumerate() {
+            let fun = self.function_of(pc);
+            writeln!(s, "#{:<2} pc {pc:<4} in {fun}, env of {nenv}", i + 1).unwrap();
+        }
+        s
+    }
+
+    /// Env entries, each as `access n` would load it.
+    fn dump_watches(&self, watches: &[usize]) -> String {
+        let SECDState(_pc, _stk, env) = &self.machine.state;
+        let mut s = String::new();
+        for n in watches.iter() {
+            match env.len().checked_sub(*n).and_then(|x| env.get(x)) {
+                Some(v) => writeln!(s, "access {n} = {}", show_val(v)).unwrap(),
+                None => writeln!(s, "access {n} = <none>").unwrap(),
+            }
+        }
+        s
+    }
+
+    fn loc(&self) -> Option<&Loc> {
+        self.srcmap.loc(self.machine.state.0)
+    }
+
+    /// Step until the next instruction with a different location.
+    fn step_expr(&mut self) -> SECDStepResult {
+        let cur = self.loc().cloned();
+        loop {
+            self.step()?;
+            if self.loc().is_some() && self.loc() != cur.as_ref() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Step until entering one of the `breakpoints` lines.
+    fn cont(&mut self, breakpoints: &[u32]) -> SECDStepResult {
+        loop {
+            let line = self.loc().map(|x| x.span.line);
+            self.step()?;
+            match self.loc().map(|x| x.span.line) {
+                Some(l) if breakpoints.contains(&l) && line != Some(l) => return Ok(()),
+                _ => (),
+            }
+        }
+    }
+
+    fn src_coverage(&self) -> Result<SrcCoverage, String> {
+        let path = self
+            .srcmap
+            .source
+            .as_ref()
+            .ok_or("no source map, compile with -g")?;
+        if self.source.is_empty() {
+            return Err(format!("cannot read {path}"));
+        }
+        let src = self.source.join("\n");
+        let (prog, diags, spans) = parse_with_spans(&src);
+        if !diags.is_empty() {
+            return Err(format!("cannot parse {path}"));
+        }
+        let hits = self.machine.coverage.as_deref().unwrap_or_default();
+        SrcCoverage::new(&prog, &spans, &self.srcmap, hits)
+            .ok_or_else(|| format!("{path} does not match the code"))
+    }
+
+    /// The current source line with the current expression underlined, and the locals.
+    fn dump_source(&self) -> String {
+        let mut s = String::new();
+        let SECDState(pc, _stk, env) = &self.machine.state;
+        writeln!(
+            s,
+            "--- Step: {}, pc {pc}: {}",
+            self.machine.steps,
+            self.lines[*pc].trim()
+        )
+        .unwrap();
+        let Some(loc) = self.loc() else {
+            writeln!(s, "no source location").unwrap();
+            return s;
+        };
+        let span = loc.span;
+        match self.source.get(span.line as usize - 1) {
+            Some(line) => {
+                let end_col = if span.end_line == span.line {
+                    span.end_col
+                } else {
+                    line.chars().count() + 1
+                };
+                writeln!(s, "{:>4} | {line}", span.line).unwrap();
+                let pad = " ".repeat(span.col - 1);
+                let mark = "^".repeat(max(end_col - span.col, 1));
+                writeln!(s, "     | {pad}{mark}").unwrap();
+            }
+            None => writeln!(s, "at {span}").unwrap(),
+        }
+        writeln!(s, "--- locals (innermost first)").unwrap();
+        for (name, v) in loc.env.iter().zip(env.iter()).rev() {
+            writeln!(s, "{name} = {}", show_val(v)).unwrap();
+        }
+        s
+    }
+}
+
+/// As counted against `Limits::max_output`.
+fn effect_bytes(e: &SECDEffect) -> usize {
+    e.text().len()
+}
+
+/// Like `Display`, but closures are not expanded.
+fn show_val(v: &SECDVal) -> String {
+    match v {
+        SECDVal::ClosureVal { .. } => "<fun>".to_string(),
+        SECDVal::TupleVal(vs) => {
+            let vs = vs.iter().map(show_val).collect::<Vec<_>>();
+            format!("({})", vs.join(", "))
+        }
+        _ => format!("{v}"),
+    }
+}
+
+/// Commands:
+/// * `s`: step to the next expression. An empty line repeats the last command.
+/// * `c`: continue to a breakpoint.
+/// * `b <line>`: set or clear a breakpoint at a source line.
+/// * `q`: quit.
+fn debug(interp: &mut SECDInterp) {
+    if interp.srcmap.locs.is_empty() {
+        eprintln!("No source map. Compile with `miniml -g`.");
+        exit(1);
+    }
+    let mut breakpoints = Vec::<u32>::new();
+    let mut last_op = "s".to_string();
+    let mut neffects = 0;
+    if interp.loc().is_none() {
+        // skip to the first expression, past labels
+        if let Err(err) = interp.step_expr() {
+            for eff in interp.machine.sink.effects.iter() {
+                print!("{}", eff.text());
+            }
+            println!("Execution terminated with error: {err}");
+            return;
+        }
+    }
+    println!("{}", interp.dump_source());
+    loop {
+        let mut cmd = String::new();
+        if stdin().read_line(&mut cmd).unwrap() == 0 {
+            break;
+        }
+        let mut t = cmd.split_whitespace();
+        let default = last_op.clone();
+        let op = t.next().unwrap_or(default.as_str());
+        last_op = op.to_string();
+        let args: Vec<&str> = t.collect();
+        let res = match op {
+            "q" => break,
+            "s" => interp.step_expr(),
+            "c" => interp.cont(&breakpoints),
+            "b" => {
+                match args.first().and_then(|x| x.parse::<u32>().ok()) {
+                    Some(line) if breakpoints.contains(&line) => {
+                        breakpoints.retain(|x| *x != line);
+                        println!("Cleared breakpoint at line {line}");
+                    }
+                    Some(line) => {
+                        breakpoints.push(line);
+                        println!("Set breakpoint at line {line}");
+                    }
+                    None => eprintln!("usage: b <line>"),
+                }
+                continue;
+            }
+            _ => {
+                eprintln!("bad op!");
+                continue;
+            }
+        };
+        for eff in interp.machine.sink.effects[neffects..].iter() {
+            print!("{}", eff.text());
+        }
+        neffects = interp.machine.sink.effects.len();
+        if let Err(err) = res {
+            println!("Execution terminated with error: {err}");
+            break;
+        }
+        println!("{}", interp.dump_source());
+    }
+}
+
+/// Commands, where an empty line repeats the last one:
+/// * `n [k]` or `s [k]`: step `k` instructions, 1 by default. `p [k]` steps back.
+/// * `g <step>`: go to a step, forward or back.
+/// * `c`: continue to a breakpoint. `rc` continues back to one.
+/// * `b <label|pc>`: set or clear a breakpoint. Without an argument, list them.
+/// * `w <n>`: watch or unwatch the env entry that `access n` loads.
+/// * `bt`: the calls in progress.
+/// * `find <instr>`: the pcs of instructions containing `instr`.
+/// * `save <file>`: save the state, to be resumed with `--resume`.
+/// * `q`: quit.
+fn interactive(interp: &mut SECDInterp) {
+    let mut breakpoints = Vec::<usize>::new();
+    let mut watches = Vec::<usize>::new();
+    // the last step and the error that ended execution
+    let mut end: Option<(usize, SECDError)> = None;
+    let mut last_cmd = "n".to_string();
+    let mut show = true;
+    loop {
+        if show {
+            println!("{}", interp.dump());
+            print!("{}", interp.dump_watches(&watches));
+            if let Some((step, err)) = &end {
+                if *step == interp.machine.steps {
+                    println!("Execution terminated with error: {err}");
+                }
+            }
+        }
+        show = true;
+        let mut cmd = String::new();
+        if stdin().read_line(&mut cmd).unwrap() == 0 {
+            break;
+        }
+        if cmd.trim().is_empty() {
+            cmd = last_cmd.clone();
+        }
+        last_cmd = cmd.clone();
+        let mut t = cmd.split_whitespace();
+        let op = t.next().unwrap();
+        let args: Vec<&str> = t.collect();
+        let count = || args.first().map_or(Some(1), |x| x.parse::<usize>().ok());
+        // the step to go to, if any
+        let target = match op {
+            "q" => break,
+            "n" | "s" => count().map(|k| interp.machine.steps + k),
+            "p" => count().map(|k| interp.machine.steps.saturating_sub(k)),
+            "g" => args.first().and_then(|x| x.parse().ok()),
+            "c" => {
+                while end.as_ref().map_or(true, |x| x.0 > interp.machine.steps) {
+                    if let Err(err) = interp.step() {
+                        end = Some((interp.machine.steps, err));
+                    } else if breakpoints.contains(&interp.machine.state.0) {
+                        break;
+                    }
+                }
+                None
+            }
+            "rc" => {
+                interp.seek_back(&breakpoints);
+                None
+            }
+            "b" if args.is_empty() => {
+                for pc in breakpoints.iter() {
+                    println!("{pc:<4}: {}", interp.lines[*pc].trim());
+                }
+                show = false;
+                None
+            }
+            "b" => {
+                match interp.resolve_pc(args[0]) {
+                    Some(pc) if breakpoints.contains(&pc) => {
+                        breakpoints.retain(|x| *x != pc);
+                        println!("Cleared breakpoint at pc {pc}");
+                    }
+                    Some(pc) => {
+                        breakpoints.push(pc);
+                        println!("Set breakpoint at pc {pc}");
+                    }
+                    None => eprintln!("no such label or pc: {}", args[0]),
+                }
+                show = false;
+                None
+            }
+            "w" => {
+                match args.first().and_then(|x| x.parse::<usize>().ok()) {
+                    Some(n) if watches.contains(&n) => watches.retain(|x| *x != n),
+                    Some(n) if n > 0 => watches.push(n),
+                    _ => eprintln!("usage: w <n>, where n >= 1"),
+                }
+                None
+            }
+            "bt" => {
+                print!("{}", interp.backtrace());
+                show = false;
+                None
+            }
+            "find" => {
+                let instr = args.join(" ");
+                for (pc, line) in interp.lines.iter().enumerate() {
+                    if line.contains(&instr) {
+                        println!("{pc:<4}: {}", line.trim());
+                    }
+                }
+                show = false;
+                None
+            }
+            "save" => {
+                match args.first() {
+                    Some(path) => match fs::write(path, interp.machine.save().to_string()) {
+                        Ok(()) => println!("Saved to {path}"),
+                        Err(err) => eprintln!("cannot save to {path}: {err}"),
+                    },
+                    None => eprintln!("usage: save <file>"),
+                }
+                show = false;
+                None
+            }
+            _ => {
+                eprintln!("bad op!");
+                show = false;
+                None
+            }
+        };
+        if let Some(step) = target {
+            let step = match &end {
+                Some((last, _)) => min(step, *last),
+                None => step,
+            };
+            if let Err(err) = interp.seek(step) {
+                end = Some((interp.machine.steps, err));
+            }
+        }
+    }
 }
 
 fn main() {
@@ -134,8 +698,8 @@ fn main() {
     let buf = match cli.infile {
         Some(infile) => fs::read_to_string(infile).unwrap(),
         None => {
-            if cli.interactive {
-                eprintln!("When interactive is set, input must not be from stdin.");
+            if cli.interactive || cli.debug {
+                eprintln!("When interactive or debug is set, input must not be from stdin.");
                 exit(1);
             }
             let mut buf = String::new();
@@ -144,60 +708,98 @@ fn main() {
         }
     };
 
-    let mut interp = SECDInterp::new(buf.as_str());
+    let mut interp = match SECDInterp::new(buf.as_str(), cli.interactive) {
+        Ok(interp) => interp,
+        Err(err) => {
+            eprintln!("cannot load the code: {err}");
+            exit(1);
+        }
+    };
+    if let Some(input) = &cli.input {
+        interp.machine.sink = Capture::with_input(&fs::read_to_string(input).unwrap());
+    }
+    if let Some(path) = &cli.resume {
+        let snap = fs::read_to_string(path).unwrap();
+        let res = serde_json::from_str(&snap).map_err(|x| x.to_string());
+        if let Err(err) = res.and_then(|snap| interp.machine.restore(&snap)) {
+            eprintln!("cannot resume from {}: {err}", path.display());
+            exit(1);
+        }
+        if cli.interactive {
+            interp.reset_history();
+        }
+    }
 
-    if cli.interactive {
-        let mut display_step = 0;
-        let mut last_op = "n".to_string();
-        loop {
-            clrscr();
-            println!("{}", interp.dumps[display_step]);
-            let mut cmd = String::new();
-            stdin().read_line(&mut cmd).unwrap();
-            let mut t = cmd.trim().split_whitespace();
-            let default = last_op.clone();
-            let op = t.next().unwrap_or(default.as_str());
-            last_op = op.to_string();
-            let _args: Vec<&str> = t.collect();
-            match op {
-                "q" => break,
-                "p" => {
-                    if display_step > 0 {
-                        display_step -= 1;
-                    }
-                }
-                "n" => {
-                    display_step += 1;
-                    if display_step >= interp.nsteps {
-                        let stepres = interp.step();
-                        if let Err(err) = stepres {
-                            println!("Execution terminated with error: {err}");
-                            break;
-                        }
-                    }
+    interp.machine.limits = Limits {
+        fuel: cli.maxstep,
+        max_stack: cli.max_stack,
+        max_env: cli.max_env,
+        max_heap: cli.max_heap,
+        max_output: cli.max_output,
+    };
+    if cli.debug {
+        debug(&mut interp);
+    } else if cli.interactive {
+        interactive(&mut interp);
+    } else {
+        if cli.profile || cli.folded.is_some() {
+            interp.machine.profiler = Some(Profiler::new());
+        }
+        if cli.lcov.is_some() || cli.annotate.is_some() {
+            interp.machine.coverage = Some(Vec::new());
+        }
+        let mut trace = (cli.trace && cli.format == Format::Json).then(Vec::new);
+        let res = loop {
+            if let Some(trace) = &mut trace {
+                let pc = interp.machine.state.0;
+                trace.push(
+                    json!({"step": interp.machine.steps, "pc": pc, "instr": interp.lines[pc].trim()}),
+                );
+            }
+            if let Err(err) = interp.step() {
+                break err;
+            }
+        };
+        match cli.format {
+            Format::Json => println!("{}", interp.dump_json(&res, trace)),
+            Format::Text => {
+                if let SECDError::LimitExceeded(_) = res {
+                    println!("{res}. ABORTED.");
                 }
-                _ => {
-                    eprintln!("bad op!");
+                if cli.brief {
+                    println!("{}", interp.dump_brief());
+                } else {
+                    println!("Execution result: {}\n", res);
+                    println!("--- terminal state:\n{}", interp.dump());
                 }
             }
         }
-    } else {
-        let maxstep = cli.maxstep.unwrap_or(usize::MAX);
-        let res = loop {
-            let stepres = interp.step();
-            if interp.nsteps > maxstep {
-                println!("Reached maxstep {maxstep}. ABORTED.");
-                break "ABORTED".to_string();
+        if let Some(path) = &cli.save {
+            fs::write(path, interp.machine.save().to_string()).unwrap();
+        }
+        if let Some(profiler) = &interp.machine.profiler {
+            if cli.profile {
+                eprint!("{}", profiler.report());
             }
-            if let Err(res) = stepres {
-                break res;
+            if let Some(path) = &cli.folded {
+                fs::write(path, profiler.folded_stacks()).unwrap();
+            }
+        }
+        if cli.lcov.is_some() || cli.annotate.is_some() {
+            let cov = match interp.src_coverage() {
+                Ok(cov) => cov,
+                Err(err) => {
+                    eprintln!("cannot report coverage: {err}");
+                    exit(1);
+                }
+            };
+            if let Some(path) = &cli.lcov {
+                let src = interp.srcmap.source.as_ref().unwrap();
+                fs::write(path, cov.lcov(src)).unwrap();
+            }
+            if let Some(path) = &cli.annotate {
+                fs::write(path, cov.annotate(&interp.source.join("\n"))).unwrap();
             }
-        };
-        if cli.brief {
-            println!("{}", interp.dump_brief());
-        } else {
-            println!("Execution result: {}\n", res);
-            println!("--- terminal state:\n{}", interp.dumps[interp.nsteps - 1]);
         }
     }
 }
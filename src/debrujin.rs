@@ -3,7 +3,7 @@
 use std::collections::VecDeque;
 
 use crate::{
-    ast::{Expr, LetRecArm, Ty},
+    ast::{Expr, LetRecArm, MatchArm, MatchPattern, Ty},
     node_id::NodeInfo,
     pass::ExprListener,
 };
@@ -53,11 +53,11 @@ impl DeBrujin {
         None
     }
 
-    fn define_var(&mut self, id: &String) {
+    pub fn define_var(&mut self, id: &String) {
         self.vars.push_front(VarBundle::Var(id.clone()));
     }
 
-    fn define_rec(&mut self, rec: &Vec<String>) {
+    pub fn define_rec(&mut self, rec: &Vec<String>) {
         self.vars.push_front(VarBundle::Rec(rec.clone()));
     }
 
@@ -70,6 +70,27 @@ impl DeBrujin {
         unreachable!()
     }
 
+    /// Define the binders of `ptn` from left to right.
+    fn define_ptn(&mut self, ptn: &MatchPattern) {
+        match ptn {
+            MatchPattern::Binder { name } => self.define_var(name),
+            MatchPattern::Tuple { subs } | MatchPattern::DataType { subs, .. } => {
+                subs.iter().for_each(|x| self.define_ptn(x))
+            }
+            MatchPattern::Lit { .. } => (),
+        }
+    }
+
+    fn undefine_ptn(&mut self, ptn: &MatchPattern) {
+        match ptn {
+            MatchPattern::Binder { name } => self.undefine_var(name),
+            MatchPattern::Tuple { subs } | MatchPattern::DataType { subs, .. } => {
+                subs.iter().rev().for_each(|x| self.undefine_ptn(x))
+            }
+            MatchPattern::Lit { .. } => (),
+        }
+    }
+
     fn undefine_rec(&mut self, rec: &Vec<String>) {
         if let Some(VarBundle::Rec(vs)) = self.vars.pop_front() {
             if &vs == rec {
@@ -125,4 +146,12 @@ impl ExprListener for DeBrujin {
     fn exit_letrecarm(&mut self, arm: &LetRecArm) {
         self.undefine_var(&arm.arg_name);
     }
+
+    fn enter_matcharm(&mut self, arm: &MatchArm) {
+        self.define_ptn(&arm.ptn);
+    }
+
+    fn exit_matcharm(&mut self, arm: &MatchArm) {
+        self.undefine_ptn(&arm.ptn);
+    }
 }
@@ -0,0 +1,139 @@
+//! Desugaring of the named AST into fewer kinds of expressions.
+//!
+//! A `Seq` becomes `let _ = ...`s of the values it drops, `_` being the name that ANF gives
+//! to values only computed for their effects.
+//! A `match` binds the value it takes apart to `sub%N`, and becomes `if`s that test its parts
+//! against the literals of each arm in turn, then `let`s of the binders of the arm.
+//! As in the ANF lowering, arms with constructor patterns never match,
+//! and the program panics if no arm does.
+
+use crate::{
+    ast::{BinOp, BuiltinOp, Expr, MatchArm, MatchPattern, Ty},
+    pass::ExprTransformer,
+};
+
+pub struct Desugar {
+    nnames: usize,
+}
+
+impl Desugar {
+    pub fn new() -> Self {
+        Desugar { nnames: 0 }
+    }
+
+    fn new_name(&mut self, prefix: &str) -> String {
+        self.nnames += 1;
+        format!("{prefix}%{}", self.nnames - 1)
+    }
+}
+
+impl Default for Desugar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `arm` of a match on the variable `sub`, which goes on with `rest` if it does not match.
+fn arm(sub: &Expr, arm: MatchArm, rest: Expr) -> Expr {
+    let mut tests = Vec::new();
+    let mut binds = Vec::new();
+    match_ptn(&arm.ptn, sub.clone(), &mut tests, &mut binds);
+    let res = binds
+        .into_iter()
+        .rev()
+        .fold(arm.res, |body, (name, val)| Expr::Let {
+            name,
+            ty: Ty::UnkTy,
+            val: Box::new(val),
+            body: Box::new(body),
+        });
+    // `a && b` is `if a then b else 0`, as the machine has no booleans
+    let cond = tests.into_iter().reduce(|cond, test| Expr::Ite {
+        cond: Box::new(cond),
+        tr: Box::new(test),
+        fl: Box::new(Expr::IntLit { val: 0 }),
+    });
+    match cond {
+        Some(cond) => Expr::Ite {
+            cond: Box::new(cond),
+            tr: Box::new(res),
+            fl: Box::new(rest),
+        },
+        None => res,
+    }
+}
+
+/// The tests that `at` matches `ptn`, and the values of its binders.
+fn match_ptn(ptn: &MatchPattern, at: Expr, tests: &mut Vec<Expr>, binds: &mut Vec<(String, Expr)>) {
+    match ptn {
+        MatchPattern::Binder { name } => binds.push((name.clone(), at)),
+        // there is only one unit
+        MatchPattern::Lit {
+            val: Expr::UnitLit {},
+        } => (),
+        MatchPattern::Lit { val } => tests.push(Expr::Binary {
+            lhs: Box::new(at),
+            op: BinOp::Eq,
+            rhs: Box::new(val.clone()),
+        }),
+        MatchPattern::Tuple { subs } => {
+            for (i, sub) in subs.iter().enumerate() {
+                let part = Expr::Nth {
+                    idx: i as i64,
+                    sub: Box::new(at.clone()),
+                };
+                match_ptn(sub, part, tests, binds);
+            }
+        }
+        MatchPattern::DataType { .. } => unreachable!("constructor patterns are dropped"),
+    }
+}
+
+impl ExprTransformer<()> for Desugar {
+    fn default(&mut self) {}
+
+    fn visit_seq(&mut self, e: &mut Expr) {
+        self.visit_children(e);
+        let Expr::Seq { subs } = e else {
+            unreachable!()
+        };
+        let mut subs = std::mem::take(subs);
+        let last = subs.pop().unwrap();
+        let body = subs.into_iter().rev().fold(last, |body, val| {
+            Box::new(Expr::Let {
+                name: "_".to_string(),
+                ty: Ty::UnkTy,
+                val,
+                body,
+            })
+        });
+        *e = *body;
+    }
+
+    fn visit_match(&mut self, e: &mut Expr) {
+        self.visit_children(e);
+        let Expr::Match { sub, arms } = e else {
+            unreachable!()
+        };
+        let name = self.new_name("sub");
+        let var = Expr::VarRef { id: name.clone() };
+        let panic = Expr::App {
+            fun: Box::new(Expr::Builtin {
+                op: BuiltinOp::Panic,
+            }),
+            arg: Box::new(Expr::UnitLit {}),
+        };
+        let arms = std::mem::take(arms);
+        let body = arms
+            .into_iter()
+            .filter(|x| !x.ptn.has_ctor())
+            .rev()
+            .fold(panic, |rest, x| arm(&var, x, rest));
+        *e = Expr::Let {
+            name,
+            ty: Ty::UnkTy,
+            val: std::mem::replace(sub, Box::new(Expr::UnitLit {})),
+            body: Box::new(body),
+        };
+    }
+}
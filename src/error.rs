@@ -1,5 +1,41 @@
 /// Error Definitions
+use std::fmt;
+
 #[derive(Debug)]
 pub enum MiniMLErr {
-    ParseError(String),
+    /// All the syntax errors of the input, in source order.
+    ParseError(Vec<Diagnostic>),
+    FormatError(String),
+}
+
+impl fmt::Display for MiniMLErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MiniMLErr::ParseError(diags) => {
+                let lines = diags.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+                write!(f, "{}", lines.join("\n"))
+            }
+            MiniMLErr::FormatError(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+/// An error or warning at some position of the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// Byte offset into the source.
+    pub offset: usize,
+    /// 1-based.
+    pub line: u32,
+    /// 1-based, in chars.
+    pub col: usize,
+    /// Length in bytes of the offending token, 0 at the end of input.
+    pub len: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.col, self.msg)
+    }
 }
@@ -1,21 +1,217 @@
 //! Prints the AST, but also tag node with addition info.
 
-use std::fmt::Debug;
+use std::fmt::{Debug, Write};
 
-use crate::{node_id::NodeInfo, pass::ExprListener};
+use crate::{ast::*, node_id::NodeInfo, pass::ExprListener};
 
+/// Renders the AST as an indented tree, one node per line.
+/// Nodes with an entry in `info` are suffixed with it, e.g. `VarRef _x@0 [Var(1)]`.
 pub struct Inspector<'a, T: Debug> {
-    info: &'a NodeInfo<T>,
+    info: Option<&'a NodeInfo<T>>,
+    depth: usize,
+    out: String,
 }
 
 impl<'a, T: Debug> Inspector<'a, T> {
     pub fn new(info: &'a NodeInfo<T>) -> Self {
-        Inspector { info }
+        Inspector {
+            info: Some(info),
+            depth: 0,
+            out: String::new(),
+        }
+    }
+
+    pub fn show_prog(mut self, prog: &Prog) -> String {
+        for dt in prog.data_types.iter() {
+            writeln!(self.out, "DataType {}", dt.name).unwrap();
+            for arm in dt.arms.iter() {
+                let tys = arm.arg_tys.iter().map(|x| format!(" {x}"));
+                writeln!(self.out, "  | {}{}", arm.ctor, tys.collect::<String>()).unwrap();
+            }
+        }
+        self.walk(&prog.main_expr);
+        self.out
+    }
+
+    fn line(&mut self, s: String, eself: Option<&Expr>) {
+        let indent = "  ".repeat(self.depth);
+        write!(self.out, "{indent}{s}").unwrap();
+        if let (Some(info), Some(e)) = (self.info, eself) {
+            if let Some(x) = info.get(e) {
+                write!(self.out, " [{x:?}]").unwrap();
+            }
+        }
+        writeln!(self.out).unwrap();
+    }
+
+    fn enter(&mut self, s: String, eself: Option<&Expr>) {
+        self.line(s, eself);
+        self.depth += 1;
+    }
+
+    fn exit(&mut self) {
+        self.depth -= 1;
+    }
+}
+
+impl Inspector<'static, ()> {
+    /// An inspector without any info, just the tree.
+    pub fn plain() -> Self {
+        Inspector {
+            info: None,
+            depth: 0,
+            out: String::new(),
+        }
+    }
+}
+
+/// `: ty`, or nothing if the type is unknown.
+pub(crate) fn annot(ty: &Ty) -> String {
+    match ty {
+        Ty::UnkTy => String::new(),
+        _ => format!(": {ty}"),
+    }
+}
+
+fn show_ptn(ptn: &MatchPattern) -> String {
+    match ptn {
+        MatchPattern::Binder { name } => name.clone(),
+        MatchPattern::Tuple { subs } => {
+            let subs = subs.iter().map(show_ptn).collect::<Vec<_>>();
+            format!("({})", subs.join(", "))
+        }
+        MatchPattern::Lit { val } => match val {
+            Expr::IntLit { val } => format!("{val}"),
+            Expr::UnitLit {} => "()".to_string(),
+            Expr::Builtin { op } => builtin_print(op).to_string(),
+            _ => unreachable!(),
+        },
+        MatchPattern::DataType { ctor, subs } => {
+            let subs = subs.iter().map(|x| match x {
+                MatchPattern::DataType { .. } => format!(" ({})", show_ptn(x)),
+                _ => format!(" {}", show_ptn(x)),
+            });
+            format!("{ctor}{}", subs.collect::<String>())
+        }
     }
 }
 
 impl<'a, T: Debug> ExprListener for Inspector<'a, T> {
-    fn walk_varref(&mut self, id: &String, eself: &crate::ast::Expr) {
-        println!("inspector: varref got {id} -> {:?}", self.info.get(eself));
+    fn walk_intlit(&mut self, val: &i64, eself: &Expr) {
+        self.line(format!("IntLit {val}"), Some(eself));
+    }
+
+    fn walk_unitlit(&mut self, eself: &Expr) {
+        self.line("UnitLit".to_string(), Some(eself));
+    }
+
+    fn enter_binary(&mut self, _lhs: &Expr, op: &BinOp, _rhs: &Expr, eself: &Expr) {
+        self.enter(format!("Binary {op:?}"), Some(eself));
+    }
+    fn exit_binary(&mut self, _lhs: &Expr, _op: &BinOp, _rhs: &Expr, _eself: &Expr) {
+        self.exit();
+    }
+
+    fn enter_unary(&mut self, op: &UnaOp, _sub: &Expr, eself: &Expr) {
+        self.enter(format!("Unary {op:?}"), Some(eself));
+    }
+    fn exit_unary(&mut self, _op: &UnaOp, _sub: &Expr, _eself: &Expr) {
+        self.exit();
+    }
+
+    fn walk_error(&mut self, eself: &Expr) {
+        self.line("Error".to_string(), Some(eself));
+    }
+
+    fn walk_varref(&mut self, id: &String, eself: &Expr) {
+        self.line(format!("VarRef {id}"), Some(eself));
+    }
+
+    fn walk_builtin(&mut self, op: &BuiltinOp, eself: &Expr) {
+        self.line(format!("Builtin {}", builtin_print(op)), Some(eself));
+    }
+
+    fn enter_app(&mut self, _fun: &Expr, _arg: &Expr, eself: &Expr) {
+        self.enter("App".to_string(), Some(eself));
+    }
+    fn exit_app(&mut self, _fun: &Expr, _arg: &Expr, _eself: &Expr) {
+        self.exit();
+    }
+
+    fn enter_seq(&mut self, _subs: &Vec<Box<Expr>>, eself: &Expr) {
+        self.enter("Seq".to_string(), Some(eself));
+    }
+    fn exit_seq(&mut self, _subs: &Vec<Box<Expr>>, _eself: &Expr) {
+        self.exit();
+    }
+
+    fn enter_abs(&mut self, arg_name: &String, arg_ty: &Ty, _body: &Expr, eself: &Expr) {
+        self.enter(format!("Abs \\{arg_name}{}", annot(arg_ty)), Some(eself));
+    }
+    fn exit_abs(&mut self, _arg_name: &String, _arg_ty: &Ty, _body: &Expr, _eself: &Expr) {
+        self.exit();
+    }
+
+    fn enter_let(&mut self, name: &String, ty: &Ty, _val: &Expr, _body: &Expr, eself: &Expr) {
+        self.enter(format!("Let {name}{}", annot(ty)), Some(eself));
+    }
+    fn exit_let(&mut self, _name: &String, _ty: &Ty, _val: &Expr, _body: &Expr, _eself: &Expr) {
+        self.exit();
+    }
+
+    fn enter_tuple(&mut self, _subs: &Vec<Box<Expr>>, eself: &Expr) {
+        self.enter("Tuple".to_string(), Some(eself));
+    }
+    fn exit_tuple(&mut self, _subs: &Vec<Box<Expr>>, _eself: &Expr) {
+        self.exit();
+    }
+
+    fn enter_nth(&mut self, idx: &i64, _sub: &Expr, eself: &Expr) {
+        self.enter(format!("Nth {idx}"), Some(eself));
+    }
+    fn exit_nth(&mut self, _idx: &i64, _sub: &Expr, _eself: &Expr) {
+        self.exit();
+    }
+
+    fn enter_ite(&mut self, _cond: &Expr, _tr: &Expr, _fl: &Expr, eself: &Expr) {
+        self.enter("Ite".to_string(), Some(eself));
+    }
+    fn exit_ite(&mut self, _cond: &Expr, _tr: &Expr, _fl: &Expr, _eself: &Expr) {
+        self.exit();
+    }
+
+    fn enter_letrec(&mut self, _arms: &Vec<LetRecArm>, _body: &Expr, eself: &Expr) {
+        self.enter("LetRec".to_string(), Some(eself));
+    }
+    fn exit_letrec(&mut self, _arms: &Vec<LetRecArm>, _body: &Expr, _eself: &Expr) {
+        self.exit();
+    }
+
+    fn enter_match(&mut self, _sub: &Expr, _arms: &Vec<MatchArm>, eself: &Expr) {
+        self.enter("Match".to_string(), Some(eself));
+    }
+    fn exit_match(&mut self, _sub: &Expr, _arms: &Vec<MatchArm>, _eself: &Expr) {
+        self.exit();
+    }
+
+    fn enter_letrecarm(&mut self, arm: &LetRecArm) {
+        let s = format!(
+            "{}{} = \\{}{}",
+            arm.fn_name,
+            annot(&arm.fn_ty),
+            arm.arg_name,
+            annot(&arm.arg_ty)
+        );
+        self.enter(s, None);
+    }
+    fn exit_letrecarm(&mut self, _arm: &LetRecArm) {
+        self.exit();
+    }
+
+    fn enter_matcharm(&mut self, arm: &MatchArm) {
+        self.enter(format!("| {}", show_ptn(&arm.ptn)), None);
+    }
+    fn exit_matcharm(&mut self, _arm: &MatchArm) {
+        self.exit();
     }
 }
@@ -0,0 +1,94 @@
+//! Free variables, i.e. those a function has to capture.
+
+use super::{Atom, Comp, CompKind, RecFn, Term};
+
+/// The variables used by `t` but not bound in it, in the order they are first used.
+pub fn free_vars(t: &Term) -> Vec<String> {
+    let mut fv = FreeVars::default();
+    fv.term(t);
+    fv.free
+}
+
+/// Those of the lambda `\param -> body`.
+pub fn abs_free_vars(param: &str, body: &Term) -> Vec<String> {
+    let mut fv = FreeVars::default();
+    fv.scope.push(param.to_string());
+    fv.term(body);
+    fv.free
+}
+
+/// Those of the functions of a `let rec`, which do not include the functions themselves.
+pub fn rec_free_vars(fns: &[RecFn]) -> Vec<String> {
+    let mut fv = FreeVars::default();
+    fv.rec_fns(fns);
+    fv.free
+}
+
+#[derive(Default)]
+struct FreeVars {
+    scope: Vec<String>,
+    free: Vec<String>,
+}
+
+impl FreeVars {
+    fn atom(&mut self, a: &Atom) {
+        if let Some(x) = a.var() {
+            if !self.scope.iter().any(|y| y == x) && !self.free.iter().any(|y| y == x) {
+                self.free.push(x.to_string());
+            }
+        }
+    }
+
+    fn comp(&mut self, c: &Comp) {
+        for a in c.atoms() {
+            self.atom(a);
+        }
+        if let CompKind::Abs(param, body) = &c.kind {
+            self.scope.push(param.clone());
+            self.term(body);
+            self.scope.pop();
+        }
+    }
+
+    /// Leaves the names of `fns` in scope.
+    fn rec_fns(&mut self, fns: &[RecFn]) {
+        self.scope.extend(fns.iter().map(|x| x.name.clone()));
+        for fun in fns {
+            self.scope.push(fun.param.clone());
+            self.term(&fun.body);
+            self.scope.pop();
+        }
+    }
+
+    fn term(&mut self, t: &Term) {
+        let scope_len = self.scope.len();
+        match t {
+            Term::Let {
+                name, val, body, ..
+            } => {
+                self.comp(val);
+                self.scope.push(name.clone());
+                self.term(body);
+            }
+            Term::LetRec { fns, body, .. } => {
+                self.rec_fns(fns);
+                self.term(body);
+            }
+            Term::If { cond, tr, fl, .. } => {
+                self.atom(cond);
+                self.term(tr);
+                self.term(fl);
+            }
+            Term::Join {
+                param, rest, body, ..
+            } => {
+                self.term(body);
+                self.scope.push(param.clone());
+                self.term(rest);
+            }
+            Term::Jump { arg, .. } => self.atom(arg),
+            Term::Ret(a) => self.atom(a),
+        }
+        self.scope.truncate(scope_len);
+    }
+}
@@ -0,0 +1,193 @@
+//! Lambda lifting of closed functions: those that capture nothing, but the functions lifted
+//! before them, are bound at the start of the program rather than made every time the
+//! function around them runs.
+//!
+//! Lifted temporaries are renamed from `%N` to `lam%N`, as they are used in another function.
+
+use std::collections::HashSet;
+
+use super::{
+    free::{abs_free_vars, rec_free_vars},
+    is_temp, Atom, AtomKind, Comp, CompKind, RecFn, Span, Term,
+};
+
+/// Lift the closed functions out of the functions of `main`, the ANF of a program.
+pub fn lift(main: Term) -> Term {
+    let mut l = Lift {
+        lifted: Vec::new(),
+        names: HashSet::new(),
+    };
+    let mut res = l.term(main, false);
+    for bind in l.lifted.into_iter().rev() {
+        res = match bind {
+            Lifted::Let(name, val, span) => Term::Let {
+                name,
+                val,
+                body: Box::new(res),
+                span,
+            },
+            Lifted::LetRec(fns, span) => Term::LetRec {
+                fns,
+                body: Box::new(res),
+                span,
+            },
+        }
+    }
+    res
+}
+
+enum Lifted {
+    Let(String, Comp, Span),
+    LetRec(Vec<RecFn>, Span),
+}
+
+/// * `lifted`: bindings to put at the start of the program, in order.
+/// * `names`: the names they bind.
+struct Lift {
+    lifted: Vec<Lifted>,
+    names: HashSet<String>,
+}
+
+fn rename_atom(a: &mut Atom, from: &str, to: &str) {
+    if a.var() == Some(from) {
+        a.kind = AtomKind::Var(to.to_string());
+    }
+}
+
+/// Replace the uses of the variable `from` by `to`.
+fn rename(t: &mut Term, from: &str, to: &str) {
+    match t {
+        Term::Let { val, body, .. } => {
+            match &mut val.kind {
+                CompKind::Atom(a) | CompKind::Unary(_, a) | CompKind::Nth(_, a) => {
+                    rename_atom(a, from, to)
+                }
+                CompKind::Binary(_, l, r) | CompKind::App(l, r) => {
+                    rename_atom(l, from, to);
+                    rename_atom(r, from, to);
+                }
+                CompKind::Tuple(subs) => subs.iter_mut().for_each(|x| rename_atom(x, from, to)),
+                CompKind::Abs(_, fbody) => rename(fbody, from, to),
+            }
+            rename(body, from, to);
+        }
+        Term::LetRec { fns, body, .. } => {
+            for fun in fns.iter_mut() {
+                rename(&mut fun.body, from, to);
+            }
+            rename(body, from, to);
+        }
+        Term::If { cond, tr, fl, .. } => {
+            rename_atom(cond, from, to);
+            rename(tr, from, to);
+            rename(fl, from, to);
+        }
+        Term::Join { rest, body, .. } => {
+            rename(rest, from, to);
+            rename(body, from, to);
+        }
+        Term::Jump { arg, .. } => rename_atom(arg, from, to),
+        Term::Ret(a) => rename_atom(a, from, to),
+    }
+}
+
+impl Lift {
+    /// Whether a function with free variables `fvs` can be lifted.
+    fn closed(&self, fvs: &[String]) -> bool {
+        fvs.iter().all(|x| self.names.contains(x))
+    }
+
+    /// `t`, in a function if `in_fn`.
+    fn term(&mut self, t: Term, in_fn: bool) -> Term {
+        match t {
+            Term::Let {
+                name,
+                val:
+                    Comp {
+                        kind: CompKind::Abs(param, fbody),
+                        span: fspan,
+                    },
+                body,
+                span,
+            } => {
+                let fbody = self.term(*fbody, true);
+                let closed = in_fn && self.closed(&abs_free_vars(&param, &fbody));
+                let val = Comp {
+                    kind: CompKind::Abs(param, Box::new(fbody)),
+                    span: fspan,
+                };
+                if !closed {
+                    return Term::Let {
+                        name,
+                        val,
+                        body: Box::new(self.term(*body, in_fn)),
+                        span,
+                    };
+                }
+                let mut body = *body;
+                let name = match is_temp(&name) {
+                    true => {
+                        let lifted = format!("lam{name}");
+                        rename(&mut body, &name, &lifted);
+                        lifted
+                    }
+                    false => name,
+                };
+                self.names.insert(name.clone());
+                self.lifted.push(Lifted::Let(name, val, span));
+                self.term(body, in_fn)
+            }
+            Term::Let {
+                name,
+                val,
+                body,
+                span,
+            } => Term::Let {
+                name,
+                val,
+                body: Box::new(self.term(*body, in_fn)),
+                span,
+            },
+            Term::LetRec { fns, body, span } => {
+                let fns = fns
+                    .into_iter()
+                    .map(|x| RecFn {
+                        body: self.term(x.body, true),
+                        ..x
+                    })
+                    .collect::<Vec<_>>();
+                let closed = in_fn && self.closed(&rec_free_vars(&fns));
+                if !closed {
+                    return Term::LetRec {
+                        fns,
+                        body: Box::new(self.term(*body, in_fn)),
+                        span,
+                    };
+                }
+                self.names.extend(fns.iter().map(|x| x.name.clone()));
+                self.lifted.push(Lifted::LetRec(fns, span));
+                self.term(*body, in_fn)
+            }
+            Term::If { cond, tr, fl, span } => Term::If {
+                cond,
+                tr: Box::new(self.term(*tr, in_fn)),
+                fl: Box::new(self.term(*fl, in_fn)),
+                span,
+            },
+            Term::Join {
+                name,
+                param,
+                rest,
+                body,
+                span,
+            } => Term::Join {
+                name,
+                param,
+                rest: Box::new(self.term(*rest, in_fn)),
+                body: Box::new(self.term(*body, in_fn)),
+                span,
+            },
+            t @ (Term::Jump { .. } | Term::Ret(_)) => t,
+        }
+    }
+}
@@ -0,0 +1,398 @@
+//! Lowering the named AST into ANF.
+
+use crate::{
+    ast::{BinOp, BuiltinOp, Expr, LetRecArm, MatchArm, MatchPattern},
+    spans::SpanInfo,
+};
+
+use super::{is_temp, Atom, AtomKind, Comp, CompKind, RecFn, Span, Term};
+
+/// Lower `e`, which must have been through the namer.
+/// With `spans`, every node remembers the expression it came from.
+pub fn lower(e: &Expr, spans: Option<&SpanInfo>) -> Term {
+    Lower {
+        spans,
+        ntemps: 0,
+        njoins: 0,
+    }
+    .tail(e)
+}
+
+/// A binding made on the way to the value of an expression.
+enum Bind {
+    Let(String, Comp, Span),
+    LetRec(Vec<RecFn>, Span),
+    /// A non-tail `if` or `match`, whose value is `param` in whatever follows.
+    Join {
+        name: String,
+        param: String,
+        body: Box<Term>,
+        span: Span,
+    },
+}
+
+/// A step of matching a pattern.
+enum Step {
+    Let(String, Comp),
+    /// Go on if the atom is true, else try the next arm.
+    Test(Atom),
+}
+
+struct Lower<'a> {
+    spans: Option<&'a SpanInfo>,
+    ntemps: usize,
+    njoins: usize,
+}
+
+/// `t` preceded by `binds`.
+fn wrap(binds: Vec<Bind>, t: Term) -> Term {
+    binds.into_iter().rev().fold(t, |body, b| match b {
+        Bind::Let(name, val, span) => Term::Let {
+            name,
+            val,
+            body: Box::new(body),
+            span,
+        },
+        Bind::LetRec(fns, span) => Term::LetRec {
+            fns,
+            body: Box::new(body),
+            span,
+        },
+        Bind::Join {
+            name,
+            param,
+            body: join_body,
+            span,
+        } => Term::Join {
+            name,
+            param,
+            rest: Box::new(body),
+            body: join_body,
+            span,
+        },
+    })
+}
+
+/// Bind `name` to `val`. If `val` is the value of a join point that was just made,
+/// `name` is bound by the join point itself, unless the parameter was already given
+/// a variable of the program, which the rest may still refer to.
+fn bind_let(binds: &mut Vec<Bind>, name: &str, val: Comp, span: Span) {
+    if let (CompKind::Atom(a), Some(Bind::Join { param, .. })) = (&val.kind, binds.last_mut()) {
+        if is_temp(param) && a.var() == Some(param.as_str()) {
+            *param = name.to_string();
+            return;
+        }
+    }
+    binds.push(Bind::Let(name.to_string(), val, span));
+}
+
+impl Lower<'_> {
+    fn span(&self, e: &Expr) -> Span {
+        self.spans.and_then(|x| x.get(e).copied())
+    }
+
+    fn new_temp(&mut self) -> String {
+        self.ntemps += 1;
+        format!("%{}", self.ntemps - 1)
+    }
+
+    /// A variable for a value that a `match` takes apart, which may be used several times.
+    fn new_matched(&mut self) -> String {
+        format!("match{}", self.new_temp())
+    }
+
+    fn new_join(&mut self) -> String {
+        self.njoins += 1;
+        format!("j{}", self.njoins - 1)
+    }
+
+    /// `e` as the value of a function or of the program.
+    fn tail(&mut self, e: &Expr) -> Term {
+        let mut binds = Vec::new();
+        let t = self.tail_after(e, &mut binds);
+        wrap(binds, t)
+    }
+
+    fn tail_after(&mut self, e: &Expr, binds: &mut Vec<Bind>) -> Term {
+        match e {
+            Expr::Ite { cond, tr, fl } => Term::If {
+                cond: self.atom(cond, binds),
+                tr: Box::new(self.tail(tr)),
+                fl: Box::new(self.tail(fl)),
+                span: self.span(e),
+            },
+            Expr::Let {
+                name, val, body, ..
+            } => {
+                let val = self.comp(val, binds);
+                bind_let(binds, name, val, self.span(e));
+                self.tail_after(body, binds)
+            }
+            Expr::LetRec { arms, body } => {
+                let fns = self.rec_fns(arms);
+                binds.push(Bind::LetRec(fns, self.span(e)));
+                self.tail_after(body, binds)
+            }
+            Expr::Seq { subs } => {
+                let (last, init) = subs.split_last().unwrap();
+                for x in init {
+                    self.discard(x, e, binds);
+                }
+                self.tail_after(last, binds)
+            }
+            Expr::Match { sub, arms } => {
+                self.match_arms(e, sub, arms, binds, &mut |this, res| this.tail(res))
+            }
+            _ => Term::Ret(self.atom(e, binds)),
+        }
+    }
+
+    /// `e` in a branch that continues at the join point `join`.
+    fn jump_to(&mut self, join: &str, e: &Expr, span: Span) -> Box<Term> {
+        let mut binds = Vec::new();
+        let arg = self.atom(e, &mut binds);
+        let jump = Term::Jump {
+            target: join.to_string(),
+            arg,
+            span,
+        };
+        Box::new(wrap(binds, jump))
+    }
+
+    /// The `match` `e`, trying its arms in order. Each arm that matches ends with `leaf` of
+    /// its result, and the others continue at a join point with the next arm.
+    /// If none matches, the program panics.
+    fn match_arms(
+        &mut self,
+        e: &Expr,
+        sub: &Expr,
+        arms: &[MatchArm],
+        binds: &mut Vec<Bind>,
+        leaf: &mut dyn FnMut(&mut Self, &Expr) -> Term,
+    ) -> Term {
+        let span = self.span(e);
+        let val = self.comp(sub, binds);
+        let matched = self.new_matched();
+        bind_let(binds, &matched, val, span);
+        // each with the join point of the next arm, unless it always matches
+        let mut lowered = Vec::new();
+        for arm in arms.iter() {
+            // there are no values made by constructors, so their patterns never match
+            if arm.ptn.has_ctor() {
+                continue;
+            }
+            let mut steps = Vec::new();
+            self.ptn(&arm.ptn, &matched, span, &mut steps);
+            let always = !steps.iter().any(|x| matches!(x, Step::Test(_)));
+            let next = (!always).then(|| self.new_join());
+            let res = leaf(self, &arm.res);
+            let t = steps.into_iter().rev().fold(res, |t, step| match step {
+                Step::Let(name, val) => Term::Let {
+                    name,
+                    val,
+                    body: Box::new(t),
+                    span,
+                },
+                Step::Test(cond) => Term::If {
+                    cond,
+                    tr: Box::new(t),
+                    fl: Box::new(Term::Jump {
+                        target: next.clone().unwrap(),
+                        arg: Atom {
+                            kind: AtomKind::Unit,
+                            span,
+                        },
+                        span,
+                    }),
+                    span,
+                },
+            });
+            lowered.push((t, next));
+            if always {
+                break;
+            }
+        }
+        let no_match = match lowered.last() {
+            Some((_, None)) => None,
+            _ => {
+                let panic = Expr::App {
+                    fun: Box::new(Expr::Builtin {
+                        op: BuiltinOp::Panic,
+                    }),
+                    arg: Box::new(Expr::UnitLit {}),
+                };
+                Some(leaf(self, &panic))
+            }
+        };
+        let rest = lowered.into_iter().rev().fold(no_match, |rest, (t, next)| {
+            Some(match next {
+                Some(next) => Term::Join {
+                    name: next,
+                    param: "_".to_string(),
+                    rest: Box::new(rest.unwrap()),
+                    body: Box::new(t),
+                    span,
+                },
+                None => t,
+            })
+        });
+        rest.unwrap()
+    }
+
+    /// The steps that match `ptn` against the variable `val`, and bind its binders.
+    fn ptn(&mut self, ptn: &MatchPattern, val: &str, span: Span, steps: &mut Vec<Step>) {
+        let var = |x: &str| Atom {
+            kind: AtomKind::Var(x.to_string()),
+            span,
+        };
+        match ptn {
+            MatchPattern::Binder { name } => {
+                let kind = CompKind::Atom(var(val));
+                steps.push(Step::Let(name.clone(), Comp { kind, span }));
+            }
+            MatchPattern::Lit { val: lit } => {
+                let CompKind::Atom(lit) = self.comp(lit, &mut Vec::new()).kind else {
+                    unreachable!("literals are atoms")
+                };
+                // there is only one unit
+                if lit.kind == AtomKind::Unit {
+                    return;
+                }
+                let eq = self.new_temp();
+                let kind = CompKind::Binary(BinOp::Eq, var(val), lit);
+                steps.push(Step::Let(eq.clone(), Comp { kind, span }));
+                steps.push(Step::Test(var(&eq)));
+            }
+            MatchPattern::Tuple { subs } => {
+                for (i, sub) in subs.iter().enumerate() {
+                    let kind = CompKind::Nth(i as i64, var(val));
+                    let nth = Comp { kind, span };
+                    if let MatchPattern::Binder { name } = sub {
+                        steps.push(Step::Let(name.clone(), nth));
+                        continue;
+                    }
+                    let part = self.new_matched();
+                    steps.push(Step::Let(part.clone(), nth));
+                    self.ptn(sub, &part, span, steps);
+                }
+            }
+            MatchPattern::DataType { .. } => unreachable!("constructor patterns are dropped"),
+        }
+    }
+
+    fn rec_fns(&mut self, arms: &[LetRecArm]) -> Vec<RecFn> {
+        arms.iter()
+            .map(|arm| RecFn {
+                name: arm.fn_name.clone(),
+                param: arm.arg_name.clone(),
+                body: self.tail(&arm.body),
+                span: self.span(&arm.body),
+            })
+            .collect()
+    }
+
+    /// Evaluate `x`, a non-last element of the sequence `seq`, for its effects.
+    fn discard(&mut self, x: &Expr, seq: &Expr, binds: &mut Vec<Bind>) {
+        let val = self.comp(x, binds);
+        binds.push(Bind::Let("_".to_string(), val, self.span(seq)));
+    }
+
+    /// The value of `e`, bound to a temporary unless it is already an atom.
+    fn atom(&mut self, e: &Expr, binds: &mut Vec<Bind>) -> Atom {
+        let val = self.comp(e, binds);
+        match val.kind {
+            CompKind::Atom(a) => a,
+            _ => {
+                let span = val.span;
+                let name = self.new_temp();
+                binds.push(Bind::Let(name.clone(), val, span));
+                Atom {
+                    kind: AtomKind::Var(name),
+                    span,
+                }
+            }
+        }
+    }
+
+    /// The computation that gives the value of `e`, after `binds`.
+    fn comp(&mut self, e: &Expr, binds: &mut Vec<Bind>) -> Comp {
+        let span = self.span(e);
+        let atom = |kind| Comp {
+            kind: CompKind::Atom(Atom { kind, span }),
+            span,
+        };
+        let kind = match e {
+            Expr::IntLit { val } => return atom(AtomKind::Int(*val)),
+            Expr::UnitLit {} => return atom(AtomKind::Unit),
+            Expr::VarRef { id } => return atom(AtomKind::Var(id.clone())),
+            Expr::Builtin { op } => return atom(AtomKind::Builtin(op.clone())),
+            Expr::Binary { lhs, op, rhs } => {
+                let lhs = self.atom(lhs, binds);
+                let rhs = self.atom(rhs, binds);
+                CompKind::Binary(*op, lhs, rhs)
+            }
+            Expr::Unary { op, sub } => CompKind::Unary(*op, self.atom(sub, binds)),
+            Expr::App { fun, arg } => {
+                let fun = self.atom(fun, binds);
+                let arg = self.atom(arg, binds);
+                CompKind::App(fun, arg)
+            }
+            Expr::Tuple { subs } => {
+                CompKind::Tuple(subs.iter().map(|x| self.atom(x, binds)).collect())
+            }
+            Expr::Nth { idx, sub } => CompKind::Nth(*idx, self.atom(sub, binds)),
+            Expr::Abs { arg_name, body, .. } => {
+                CompKind::Abs(arg_name.clone(), Box::new(self.tail(body)))
+            }
+            Expr::Let {
+                name, val, body, ..
+            } => {
+                let val = self.comp(val, binds);
+                bind_let(binds, name, val, span);
+                return self.comp(body, binds);
+            }
+            Expr::LetRec { arms, body } => {
+                let fns = self.rec_fns(arms);
+                binds.push(Bind::LetRec(fns, span));
+                return self.comp(body, binds);
+            }
+            Expr::Seq { subs } => {
+                let (last, init) = subs.split_last().unwrap();
+                for x in init {
+                    self.discard(x, e, binds);
+                }
+                return self.comp(last, binds);
+            }
+            Expr::Ite { cond, tr, fl } => {
+                let cond = self.atom(cond, binds);
+                let name = self.new_join();
+                let param = self.new_temp();
+                let tr = self.jump_to(&name, tr, span);
+                let fl = self.jump_to(&name, fl, span);
+                binds.push(Bind::Join {
+                    name,
+                    param: param.clone(),
+                    body: Box::new(Term::If { cond, tr, fl, span }),
+                    span,
+                });
+                return atom(AtomKind::Var(param));
+            }
+            Expr::Match { sub, arms } => {
+                let name = self.new_join();
+                let param = self.new_temp();
+                let body = self.match_arms(e, sub, arms, binds, &mut |this, res| {
+                    *this.jump_to(&name, res, span)
+                });
+                binds.push(Bind::Join {
+                    name,
+                    param: param.clone(),
+                    body: Box::new(body),
+                    span,
+                });
+                return atom(AtomKind::Var(param));
+            }
+            Expr::Error {} => unreachable!("lowering a malformed expression"),
+        };
+        Comp { kind, span }
+    }
+}
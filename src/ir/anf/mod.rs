@@ -0,0 +1,229 @@
+//! A-normal form: every operand is an atom, and every intermediate value is named.
+//!
+//! `Term`s are sequences of bindings ending in a `Ret`, an `If` or a `Jump`.
+//! An `if` whose value is used by the rest of the computation becomes a join point:
+//! `join j x = rest in body`, where the branches in `body` end with `jump j <atom>`.
+//!
+//! Names are those given by the `Namer`. Temporaries made by the lowering are named `%N`.
+//! Those bound by a `Let` are used exactly once, by the computation right after them or
+//! by a later temporary, so that their code can be generated in place.
+//! A `match` binds the value it takes apart, and each part, to variables named `match%N`,
+//! and an arm that fails jumps to a join point that tries the next one.
+//! `_` names a value that is computed only for its effects.
+
+mod free;
+mod lift;
+mod lower;
+mod verify;
+
+pub use free::{abs_free_vars, free_vars, rec_free_vars};
+pub use lift::lift;
+pub use lower::lower;
+pub use verify::verify;
+
+use std::fmt::{self, Write};
+
+use crate::{
+    ast::{builtin_print, BinOp, BuiltinOp, UnaOp},
+    printer::{binop_print, unaop_print},
+    spans::SrcSpan,
+};
+
+use super::Indented;
+
+/// Of the expression a node came from. Only kept if lowered with spans.
+pub type Span = Option<SrcSpan>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AtomKind {
+    Int(i64),
+    Unit,
+    Var(String),
+    Builtin(BuiltinOp),
+}
+
+/// A value that takes no computation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Atom {
+    pub kind: AtomKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompKind {
+    Atom(Atom),
+    Binary(BinOp, Atom, Atom),
+    Unary(UnaOp, Atom),
+    App(Atom, Atom),
+    Tuple(Vec<Atom>),
+    Nth(i64, Atom),
+    /// A lambda, with its parameter.
+    Abs(String, Box<Term>),
+}
+
+/// A computation on atoms, whose value is bound by a `Let`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comp {
+    pub kind: CompKind,
+    pub span: Span,
+}
+
+/// A function of a `LetRec`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecFn {
+    pub name: String,
+    pub param: String,
+    pub body: Term,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Let {
+        name: String,
+        val: Comp,
+        body: Box<Term>,
+        span: Span,
+    },
+    LetRec {
+        fns: Vec<RecFn>,
+        body: Box<Term>,
+        span: Span,
+    },
+    If {
+        cond: Atom,
+        tr: Box<Term>,
+        fl: Box<Term>,
+        span: Span,
+    },
+    /// `body` runs first. Its `Jump`s to `name` continue with `rest`, with `param` bound.
+    Join {
+        name: String,
+        param: String,
+        rest: Box<Term>,
+        body: Box<Term>,
+        span: Span,
+    },
+    Jump {
+        target: String,
+        arg: Atom,
+        span: Span,
+    },
+    /// The value of the enclosing function, or of the program.
+    Ret(Atom),
+}
+
+/// Whether `name` is a temporary made by the lowering rather than a variable of the program.
+pub fn is_temp(name: &str) -> bool {
+    name.starts_with('%')
+}
+
+impl Atom {
+    /// Uses of variables, i.e. the name of a `Var`.
+    pub fn var(&self) -> Option<&str> {
+        match &self.kind {
+            AtomKind::Var(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+impl Comp {
+    /// The atoms it reads, in the order they are evaluated. Lambda bodies are not included.
+    pub fn atoms(&self) -> Vec<&Atom> {
+        match &self.kind {
+            CompKind::Atom(a) | CompKind::Unary(_, a) | CompKind::Nth(_, a) => vec![a],
+            CompKind::Binary(_, l, r) | CompKind::App(l, r) => vec![l, r],
+            CompKind::Tuple(subs) => subs.iter().collect(),
+            CompKind::Abs(..) => vec![],
+        }
+    }
+}
+
+impl fmt::Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            AtomKind::Int(val) => write!(f, "{val}"),
+            AtomKind::Unit => write!(f, "()"),
+            AtomKind::Var(x) => write!(f, "{x}"),
+            AtomKind::Builtin(op) => write!(f, "{}", builtin_print(op)),
+        }
+    }
+}
+
+pub(crate) fn binop_name(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Land => "land",
+        BinOp::Lor => "lor",
+        BinOp::Lxor => "lxor",
+        _ => binop_print(op),
+    }
+}
+
+/// Writes `t`, its blocks nested in that of `w`.
+fn write_term(w: &mut Indented, t: &Term) -> fmt::Result {
+    match t {
+        Term::Let {
+            name, val, body, ..
+        } => {
+            write!(w, "let {name} = ")?;
+            write_comp(w, val)?;
+            writeln!(w, " in")?;
+            write_term(w, body)
+        }
+        Term::LetRec { fns, body, .. } => {
+            for (i, fun) in fns.iter().enumerate() {
+                let kw = if i == 0 { "let rec" } else { "and" };
+                write!(w, "{kw} {} {} =", fun.name, fun.param)?;
+                w.block(|w| write_term(w, &fun.body))?;
+                writeln!(w)?;
+            }
+            writeln!(w, "in")?;
+            write_term(w, body)
+        }
+        Term::If { cond, tr, fl, .. } => {
+            write!(w, "if {cond} then")?;
+            w.block(|w| write_term(w, tr))?;
+            write!(w, "\nelse")?;
+            w.block(|w| write_term(w, fl))
+        }
+        Term::Join {
+            name,
+            param,
+            rest,
+            body,
+            ..
+        } => {
+            write!(w, "join {name} {param} =")?;
+            w.block(|w| write_term(w, rest))?;
+            writeln!(w, "\nin")?;
+            write_term(w, body)
+        }
+        Term::Jump { target, arg, .. } => write!(w, "jump {target} {arg}"),
+        Term::Ret(a) => write!(w, "ret {a}"),
+    }
+}
+
+fn write_comp(w: &mut Indented, c: &Comp) -> fmt::Result {
+    match &c.kind {
+        CompKind::Atom(a) => write!(w, "{a}"),
+        CompKind::Binary(op, l, r) => write!(w, "{l} {} {r}", binop_name(*op)),
+        CompKind::Unary(op, a) => write!(w, "{}{a}", unaop_print(*op)),
+        CompKind::App(fun, arg) => write!(w, "{fun} {arg}"),
+        CompKind::Tuple(subs) => {
+            let subs = subs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            write!(w, "({})", subs.join(", "))
+        }
+        CompKind::Nth(idx, a) => write!(w, "{a}.{idx}"),
+        CompKind::Abs(param, body) => {
+            write!(w, "fun {param} ->")?;
+            w.block(|w| write_term(w, body))
+        }
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_term(&mut Indented::new(f), self)
+    }
+}
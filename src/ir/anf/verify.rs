@@ -0,0 +1,153 @@
+//! Checking the invariants of ANF that later passes rely on.
+
+use std::collections::HashSet;
+
+use super::{is_temp, Atom, Comp, CompKind, Term};
+
+/// Check that
+/// * every variable is bound once, and used where it is in scope or is one of `globals`,
+/// * every temporary bound by a `Let` is used exactly once, in the same function,
+/// * every `Jump` targets an enclosing join point of the same function, from its body.
+pub fn verify(t: &Term, globals: &[String]) -> Result<(), String> {
+    let mut v = Verify {
+        bound: HashSet::new(),
+        scope: globals.to_vec(),
+        let_temps: HashSet::new(),
+        temps: Vec::new(),
+        joins: Vec::new(),
+    };
+    v.function(t)
+}
+
+struct Verify {
+    bound: HashSet<String>,
+    scope: Vec<String>,
+    /// Temporaries bound by a `Let`, rather than by a join point.
+    let_temps: HashSet<String>,
+    /// Those of the current function that are not used yet.
+    temps: Vec<String>,
+    /// Join points that can be jumped to.
+    joins: Vec<String>,
+}
+
+impl Verify {
+    fn bind(&mut self, name: &str) -> Result<(), String> {
+        if name == "_" {
+            return Ok(());
+        }
+        if !self.bound.insert(name.to_string()) {
+            return Err(format!("{name} is bound twice"));
+        }
+        self.scope.push(name.to_string());
+        Ok(())
+    }
+
+    /// The body of a function, or the program.
+    fn function(&mut self, t: &Term) -> Result<(), String> {
+        let temps = std::mem::take(&mut self.temps);
+        let joins = std::mem::take(&mut self.joins);
+        let scope_len = self.scope.len();
+        self.term(t)?;
+        if let Some(x) = self.temps.first() {
+            return Err(format!("temporary {x} is never used"));
+        }
+        self.scope.truncate(scope_len);
+        self.temps = temps;
+        self.joins = joins;
+        Ok(())
+    }
+
+    fn term(&mut self, t: &Term) -> Result<(), String> {
+        let scope_len = self.scope.len();
+        match t {
+            Term::Let {
+                name, val, body, ..
+            } => {
+                self.comp(val)?;
+                self.bind(name)?;
+                if is_temp(name) {
+                    self.let_temps.insert(name.clone());
+                    self.temps.push(name.clone());
+                }
+                self.term(body)?;
+            }
+            Term::LetRec { fns, body, .. } => {
+                for fun in fns {
+                    self.bind(&fun.name)?;
+                }
+                for fun in fns {
+                    let fn_scope = self.scope.len();
+                    self.bind(&fun.param)?;
+                    self.function(&fun.body)?;
+                    self.scope.truncate(fn_scope);
+                }
+                self.term(body)?;
+            }
+            Term::If { cond, tr, fl, .. } => {
+                self.atom(cond)?;
+                self.term(tr)?;
+                self.term(fl)?;
+            }
+            Term::Join {
+                name,
+                param,
+                rest,
+                body,
+                ..
+            } => {
+                if self.joins.contains(name) {
+                    return Err(format!("join point {name} is bound twice"));
+                }
+                self.joins.push(name.clone());
+                self.term(body)?;
+                self.joins.pop();
+                self.bind(param)?;
+                self.term(rest)?;
+            }
+            Term::Jump { target, arg, .. } => {
+                if !self.joins.contains(target) {
+                    return Err(format!("jump to {target}, which is not in scope"));
+                }
+                self.atom(arg)?;
+            }
+            Term::Ret(a) => self.atom(a)?,
+        }
+        self.scope.truncate(scope_len);
+        Ok(())
+    }
+
+    fn comp(&mut self, c: &Comp) -> Result<(), String> {
+        if let CompKind::Abs(param, body) = &c.kind {
+            let scope_len = self.scope.len();
+            self.bind(param)?;
+            self.function(body)?;
+            self.scope.truncate(scope_len);
+        }
+        for a in c.atoms() {
+            self.atom(a)?;
+        }
+        Ok(())
+    }
+
+    fn atom(&mut self, a: &Atom) -> Result<(), String> {
+        let Some(x) = a.var() else {
+            return Ok(());
+        };
+        if !self.scope.iter().any(|y| y == x) {
+            return Err(format!("{x} is not in scope"));
+        }
+        if self.let_temps.contains(x) {
+            match self.temps.iter().position(|y| y == x) {
+                Some(i) => {
+                    self.temps.remove(i);
+                }
+                None => {
+                    return Err(format!(
+                        "temporary {x} is used twice or in another function"
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+//! Converting ANF into CPS.
+//!
+//! Administrative redexes, continuations that only pass their argument on to another one,
+//! are not made: a call whose value is returned or jumped with right away is given the
+//! continuation it goes to, and such join points are replaced by their continuation.
+
+use crate::ir::anf::{self, Atom, AtomKind, Comp, CompKind};
+
+use super::{Fun, Prim, Term, Value, HALT};
+
+/// Convert `main`, the ANF of a program.
+pub fn convert(main: &anf::Term) -> Term {
+    Convert { nconts: 0 }.term(main, HALT)
+}
+
+struct Convert {
+    nconts: usize,
+}
+
+fn value(a: &Atom) -> Value {
+    a.kind.clone()
+}
+
+/// Replace throws to and passes of the continuation `from` by `to`.
+fn rename_cont(t: &mut Term, from: &str, to: &str) {
+    let rename = |k: &mut String| {
+        if k == from {
+            *k = to.to_string();
+        }
+    };
+    match t {
+        Term::LetVal { body, .. } => rename_cont(body, from, to),
+        Term::LetFun { fun, body } => {
+            rename_cont(&mut fun.body, from, to);
+            rename_cont(body, from, to);
+        }
+        Term::LetRec { fns, body } => {
+            for fun in fns {
+                rename_cont(&mut fun.body, from, to);
+            }
+            rename_cont(body, from, to);
+        }
+        Term::LetCont {
+            cont_body, body, ..
+        } => {
+            rename_cont(cont_body, from, to);
+            rename_cont(body, from, to);
+        }
+        Term::App { cont, .. } | Term::Throw { cont, .. } => rename(cont),
+        Term::If { tr, fl, .. } => {
+            rename_cont(tr, from, to);
+            rename_cont(fl, from, to);
+        }
+    }
+}
+
+impl Convert {
+    fn new_cont(&mut self) -> String {
+        self.nconts += 1;
+        format!("k{}", self.nconts - 1)
+    }
+
+    fn fun(&mut self, name: &str, param: &str, body: &anf::Term) -> Fun {
+        let cont = self.new_cont();
+        Fun {
+            name: name.to_string(),
+            param: param.to_string(),
+            body: self.term(body, &cont),
+            cont,
+        }
+    }
+
+    /// `t`, whose value goes to the continuation `k`.
+    fn term(&mut self, t: &anf::Term, k: &str) -> Term {
+        match t {
+            anf::Term::Let {
+                name, val, body, ..
+            } => self.bind(name, val, body, k),
+            anf::Term::LetRec { fns, body, .. } => Term::LetRec {
+                fns: fns
+                    .iter()
+                    .map(|x| self.fun(&x.name, &x.param, &x.body))
+                    .collect(),
+                body: Box::new(self.term(body, k)),
+            },
+            anf::Term::If { cond, tr, fl, .. } => Term::If {
+                cond: value(cond),
+                tr: Box::new(self.term(tr, k)),
+                fl: Box::new(self.term(fl, k)),
+            },
+            anf::Term::Join {
+                name,
+                param,
+                rest,
+                body,
+                ..
+            } => {
+                let cont_body = self.term(rest, k);
+                let mut body = self.term(body, k);
+                match cont_body {
+                    Term::Throw {
+                        cont,
+                        arg: AtomKind::Var(x),
+                    } if x == *param => {
+                        rename_cont(&mut body, name, &cont);
+                        body
+                    }
+                    cont_body => Term::LetCont {
+                        name: name.clone(),
+                        param: param.clone(),
+                        cont_body: Box::new(cont_body),
+                        body: Box::new(body),
+                    },
+                }
+            }
+            anf::Term::Jump { target, arg, .. } => Term::Throw {
+                cont: target.clone(),
+                arg: value(arg),
+            },
+            anf::Term::Ret(a) => Term::Throw {
+                cont: k.to_string(),
+                arg: value(a),
+            },
+        }
+    }
+
+    /// `let name = val in body`.
+    fn bind(&mut self, name: &str, val: &Comp, body: &anf::Term, k: &str) -> Term {
+        let prim = match &val.kind {
+            CompKind::Atom(a) => Prim::Value(value(a)),
+            CompKind::Binary(op, l, r) => Prim::Binary(*op, value(l), value(r)),
+            CompKind::Unary(op, a) => Prim::Unary(*op, value(a)),
+            CompKind::Tuple(subs) => Prim::Tuple(subs.iter().map(value).collect()),
+            CompKind::Nth(idx, a) => Prim::Nth(*idx, value(a)),
+            CompKind::App(fun, arg) if let AtomKind::Builtin(op) = &fun.kind => {
+                Prim::Builtin(op.clone(), value(arg))
+            }
+            CompKind::App(fun, arg) => {
+                let (fun, arg) = (value(fun), value(arg));
+                let passed = |a: &Atom| a.var() == Some(name);
+                return match body {
+                    anf::Term::Ret(a) if passed(a) => Term::App {
+                        fun,
+                        arg,
+                        cont: k.to_string(),
+                    },
+                    anf::Term::Jump { target, arg: a, .. } if passed(a) => Term::App {
+                        fun,
+                        arg,
+                        cont: target.clone(),
+                    },
+                    _ => {
+                        let cont = self.new_cont();
+                        Term::LetCont {
+                            name: cont.clone(),
+                            param: name.to_string(),
+                            cont_body: Box::new(self.term(body, k)),
+                            body: Box::new(Term::App { fun, arg, cont }),
+                        }
+                    }
+                };
+            }
+            CompKind::Abs(param, fbody) => {
+                return Term::LetFun {
+                    fun: Box::new(self.fun(name, param, fbody)),
+                    body: Box::new(self.term(body, k)),
+                }
+            }
+        };
+        Term::LetVal {
+            name: name.to_string(),
+            val: prim,
+            body: Box::new(self.term(body, k)),
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Continuation-passing style: no call returns, every function is passed where its value goes.
+//!
+//! Functions take their argument and a continuation. A continuation takes one value and is
+//! never returned from either, so that a `Term` ends with an `App` or a `Throw` to a
+//! continuation. The continuation of the program is `halt`.
+//!
+//! Like ANF, names are unique. Continuations are named `kN`, or `jN` for the join points
+//! they come from.
+
+mod convert;
+
+pub use convert::convert;
+
+use std::fmt::{self, Write};
+
+use crate::{
+    ast::{builtin_print, BinOp, BuiltinOp, UnaOp},
+    printer::unaop_print,
+};
+
+use super::{
+    anf::{binop_name, AtomKind},
+    Indented,
+};
+
+/// The continuation of the program.
+pub const HALT: &str = "halt";
+
+/// A value that takes no computation, as in ANF.
+pub type Value = AtomKind;
+
+/// A computation that returns right away.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Prim {
+    Value(Value),
+    Binary(BinOp, Value, Value),
+    Unary(UnaOp, Value),
+    Tuple(Vec<Value>),
+    Nth(i64, Value),
+    /// A builtin applied to its argument.
+    Builtin(BuiltinOp, Value),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fun {
+    pub name: String,
+    pub param: String,
+    pub cont: String,
+    pub body: Term,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    LetVal {
+        name: String,
+        val: Prim,
+        body: Box<Term>,
+    },
+    /// A lambda.
+    LetFun {
+        fun: Box<Fun>,
+        body: Box<Term>,
+    },
+    /// The functions of a `let rec`, which can call each other.
+    LetRec {
+        fns: Vec<Fun>,
+        body: Box<Term>,
+    },
+    /// `cont_body` runs when `name` is thrown to, with `param` bound, and `body` first.
+    LetCont {
+        name: String,
+        param: String,
+        cont_body: Box<Term>,
+        body: Box<Term>,
+    },
+    App {
+        fun: Value,
+        arg: Value,
+        cont: String,
+    },
+    Throw {
+        cont: String,
+        arg: Value,
+    },
+    If {
+        cond: Value,
+        tr: Box<Term>,
+        fl: Box<Term>,
+    },
+}
+
+impl Term {
+    /// Whether the continuation `k` is passed to a function, rather than only thrown to.
+    pub fn passes(&self, k: &str) -> bool {
+        match self {
+            Term::LetVal { body, .. } => body.passes(k),
+            Term::LetFun { fun, body } => fun.body.passes(k) || body.passes(k),
+            Term::LetRec { fns, body } => fns.iter().any(|x| x.body.passes(k)) || body.passes(k),
+            Term::LetCont {
+                cont_body, body, ..
+            } => cont_body.passes(k) || body.passes(k),
+            Term::App { cont, .. } => cont == k,
+            Term::Throw { .. } => false,
+            Term::If { tr, fl, .. } => tr.passes(k) || fl.passes(k),
+        }
+    }
+}
+
+fn value(v: &Value) -> String {
+    match v {
+        AtomKind::Int(val) => val.to_string(),
+        AtomKind::Unit => "()".to_string(),
+        AtomKind::Var(x) => x.clone(),
+        AtomKind::Builtin(op) => builtin_print(op).to_string(),
+    }
+}
+
+impl fmt::Display for Prim {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Prim::Value(v) => write!(f, "{}", value(v)),
+            Prim::Binary(op, l, r) => write!(f, "{} {} {}", value(l), binop_name(*op), value(r)),
+            Prim::Unary(op, v) => write!(f, "{}{}", unaop_print(*op), value(v)),
+            Prim::Tuple(subs) => {
+                let subs = subs.iter().map(value).collect::<Vec<_>>();
+                write!(f, "({})", subs.join(", "))
+            }
+            Prim::Nth(idx, v) => write!(f, "{}.{idx}", value(v)),
+            Prim::Builtin(op, v) => write!(f, "{} {}", builtin_print(op), value(v)),
+        }
+    }
+}
+
+/// Writes `t`, its blocks nested in that of `w`.
+fn write_term(w: &mut Indented, t: &Term) -> fmt::Result {
+    match t {
+        Term::LetVal { name, val, body } => {
+            writeln!(w, "let {name} = {val} in")?;
+            write_term(w, body)
+        }
+        Term::LetFun { fun, body } => {
+            write_fun(w, "let fun", fun)?;
+            writeln!(w, "in")?;
+            write_term(w, body)
+        }
+        Term::LetRec { fns, body } => {
+            for (i, fun) in fns.iter().enumerate() {
+                write_fun(w, if i == 0 { "let rec" } else { "and" }, fun)?;
+            }
+            writeln!(w, "in")?;
+            write_term(w, body)
+        }
+        Term::LetCont {
+            name,
+            param,
+            cont_body,
+            body,
+        } => {
+            write!(w, "let cont {name} {param} =")?;
+            w.block(|w| write_term(w, cont_body))?;
+            writeln!(w, "\nin")?;
+            write_term(w, body)
+        }
+        Term::App { fun, arg, cont } => write!(w, "{} {} {cont}", value(fun), value(arg)),
+        Term::Throw { cont, arg } => write!(w, "{cont} {}", value(arg)),
+        Term::If { cond, tr, fl } => {
+            write!(w, "if {} then", value(cond))?;
+            w.block(|w| write_term(w, tr))?;
+            write!(w, "\nelse")?;
+            w.block(|w| write_term(w, fl))
+        }
+    }
+}
+
+fn write_fun(w: &mut Indented, kw: &str, fun: &Fun) -> fmt::Result {
+    write!(w, "{kw} {} {} {} =", fun.name, fun.param, fun.cont)?;
+    w.block(|w| write_term(w, &fun.body))?;
+    writeln!(w)
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_term(&mut Indented::new(f), self)
+    }
+}
@@ -0,0 +1,42 @@
+//! Intermediate representations between the named AST and SECD code.
+
+pub mod anf;
+pub mod cps;
+
+use std::fmt;
+
+const INDENT: &str = "    ";
+
+/// Writes to `out` with every line after the first indented by `depth` levels,
+/// to print terms whose blocks nest.
+pub(crate) struct Indented<'a> {
+    out: &'a mut dyn fmt::Write,
+    depth: usize,
+}
+
+impl<'a> Indented<'a> {
+    pub(crate) fn new(out: &'a mut dyn fmt::Write) -> Self {
+        Indented { out, depth: 0 }
+    }
+
+    /// Start a new line one level deeper, and write what `f` writes at that level.
+    pub(crate) fn block(&mut self, f: impl FnOnce(&mut Self) -> fmt::Result) -> fmt::Result {
+        self.depth += 1;
+        let res = fmt::Write::write_char(self, '\n').and_then(|()| f(self));
+        self.depth -= 1;
+        res
+    }
+}
+
+impl fmt::Write for Indented<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, line) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.out.write_char('\n')?;
+                self.out.write_str(&INDENT.repeat(self.depth))?;
+            }
+            self.out.write_str(line)?;
+        }
+        Ok(())
+    }
+}
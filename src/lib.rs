@@ -1,13 +1,22 @@
 #![feature(box_patterns)]
 #![feature(if_let_guard)]
 #![allow(unreachable_patterns)]
+pub mod analysis;
 pub mod ast;
+pub mod desugar;
 pub mod error;
 pub mod inspector;
+pub mod ir;
+pub mod lsp;
 pub mod namer;
 pub mod node_id;
+pub mod opt;
 pub mod parser;
 pub mod pass;
+pub mod printer;
+pub mod repl;
+pub mod spans;
+pub mod typer;
 mod utils;
 
 pub mod debrujin;
@@ -0,0 +1,289 @@
+//! A language server for MiniML.
+//!
+//! `Server` handles decoded JSON-RPC messages, and `bin/miniml-lsp.rs` moves them over stdio.
+//! Documents are synced in full and reanalyzed on every change.
+
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+
+use crate::{
+    analysis::{analyze, Analysis, Occurrence, Symbol, SymbolKind},
+    parser::is_keyword,
+};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REQUEST_FAILED: i64 = -32803;
+
+type RequestResult = Result<Value, (i64, String)>;
+
+struct Document {
+    text: String,
+    analysis: Analysis,
+}
+
+#[derive(Default)]
+pub struct Server {
+    docs: HashMap<String, Document>,
+    shutdown: bool,
+    /// The exit code, once the client asked the server to exit.
+    pub exit: Option<i32>,
+}
+
+/// LSP position of a byte offset: a 0-based line and a column in UTF-16 code units.
+fn position(text: &str, offset: usize) -> Value {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |x| x + 1);
+    json!({
+        "line": before.matches('\n').count(),
+        "character": before[line_start..].encode_utf16().count(),
+    })
+}
+
+/// Byte offset of an LSP position, clamped to the line.
+fn offset(text: &str, pos: &Value) -> usize {
+    let line = pos["line"].as_u64().unwrap_or(0) as usize;
+    let mut character = pos["character"].as_u64().unwrap_or(0) as usize;
+    let line_start = match line {
+        0 => 0,
+        _ => match text.match_indices('\n').nth(line - 1) {
+            Some((at, _)) => at + 1,
+            None => return text.len(),
+        },
+    };
+    let mut at = line_start;
+    for c in text[line_start..].chars() {
+        if c == '\n' || character < c.len_utf16() {
+            break;
+        }
+        character -= c.len_utf16();
+        at += c.len_utf8();
+    }
+    at
+}
+
+fn range(text: &str, offset: usize, len: usize) -> Value {
+    json!({
+        "start": position(text, offset),
+        "end": position(text, offset + len),
+    })
+}
+
+fn is_ident(s: &str) -> bool {
+    let mut cs = s.chars();
+    cs.next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+        && cs.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !is_keyword(s)
+}
+
+fn symbol_kind(kind: SymbolKind) -> u32 {
+    match kind {
+        SymbolKind::DataType => 10,
+        SymbolKind::Ctor => 22,
+        SymbolKind::Let => 13,
+        SymbolKind::LetRec => 12,
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handle one message, returning the messages to send back.
+    pub fn handle(&mut self, msg: &Value) -> Vec<Value> {
+        let params = &msg["params"];
+        match (msg["method"].as_str(), msg.get("id")) {
+            (Some(method), Some(id)) => {
+                let mut res = json!({ "jsonrpc": "2.0", "id": id });
+                match self.request(method, params) {
+                    Ok(result) => res["result"] = result,
+                    Err((code, message)) => {
+                        res["error"] = json!({ "code": code, "message": message });
+                    }
+                }
+                vec![res]
+            }
+            (Some(method), None) => self.notification(method, params),
+            // a response, but we send no requests
+            (None, _) => Vec::new(),
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or("")
+            .to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                self.update(uri, text.to_string())
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                match changes.and_then(|x| x.last()) {
+                    Some(change) => {
+                        let text = change["text"].as_str().unwrap_or("");
+                        self.update(uri, text.to_string())
+                    }
+                    None => Vec::new(),
+                }
+            }
+            "textDocument/didClose" => {
+                self.docs.remove(&uri);
+                vec![publish_diagnostics(&uri, Vec::new())]
+            }
+            "exit" => {
+                self.exit = Some(if self.shutdown { 0 } else { 1 });
+                Vec::new()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn update(&mut self, uri: String, text: String) -> Vec<Value> {
+        let analysis = analyze(&text);
+        let errors = analysis.diags.iter().map(|x| (x, 1));
+        let warnings = analysis.warnings.iter().map(|x| (x, 2));
+        let diags = errors
+            .chain(warnings)
+            .map(|(x, severity)| {
+                json!({
+                    "range": range(&text, x.offset, x.len),
+                    "severity": severity,
+                    "source": "miniml",
+                    "message": x.msg,
+                })
+            })
+            .collect();
+        self.docs.insert(uri.clone(), Document { text, analysis });
+        vec![publish_diagnostics(&uri, diags)]
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> RequestResult {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "renameProvider": true,
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "miniml-lsp" },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/hover"
+            | "textDocument/definition"
+            | "textDocument/references"
+            | "textDocument/rename"
+            | "textDocument/documentSymbol" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                let Some(doc) = self.docs.get(uri) else {
+                    return Err((INVALID_PARAMS, format!("unknown document {uri}")));
+                };
+                let at = offset(&doc.text, &params["position"]);
+                doc.request(uri, method, at, params)
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method {method}"))),
+        }
+    }
+}
+
+fn publish_diagnostics(uri: &str, diags: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diags },
+    })
+}
+
+impl Document {
+    fn location(&self, uri: &str, x: &Occurrence) -> Value {
+        json!({ "uri": uri, "range": range(&self.text, x.offset, x.len) })
+    }
+
+    fn symbol(&self, x: &Symbol) -> Value {
+        let range = range(&self.text, x.offset, x.len);
+        let children = x
+            .children
+            .iter()
+            .map(|x| self.symbol(x))
+            .collect::<Vec<_>>();
+        json!({
+            "name": x.name,
+            "kind": symbol_kind(x.kind),
+            "range": range,
+            "selectionRange": range,
+            "children": children,
+        })
+    }
+
+    /// Requests about position `at` of this document.
+    fn request(&self, uri: &str, method: &str, at: usize, params: &Value) -> RequestResult {
+        let a = &self.analysis;
+        let res = match method {
+            "textDocument/hover" => match (a.at(at), a.hover(at)) {
+                (Some(x), Some(info)) => json!({
+                    "contents": { "kind": "plaintext", "value": info },
+                    "range": range(&self.text, x.offset, x.len),
+                }),
+                _ => Value::Null,
+            },
+            "textDocument/definition" => match a.definition(at) {
+                Some(x) => self.location(uri, x),
+                None => Value::Null,
+            },
+            "textDocument/references" => {
+                let include_def = params["context"]["includeDeclaration"]
+                    .as_bool()
+                    .unwrap_or(true);
+                let refs = a.references(at, include_def);
+                Value::Array(refs.iter().map(|x| self.location(uri, x)).collect())
+            }
+            "textDocument/rename" => {
+                let new = params["newName"].as_str().unwrap_or("");
+                return self.rename(uri, at, new);
+            }
+            "textDocument/documentSymbol" => {
+                Value::Array(a.symbols.iter().map(|x| self.symbol(x)).collect())
+            }
+            _ => unreachable!(),
+        };
+        Ok(res)
+    }
+
+    /// Renames the name at `at`, unless that would make some name refer to something else.
+    fn rename(&self, uri: &str, at: usize, new: &str) -> RequestResult {
+        if !is_ident(new) {
+            return Err((INVALID_PARAMS, format!("`{new}` is not an identifier")));
+        }
+        let refs = self.analysis.references(at, true);
+        if refs.is_empty() {
+            return Ok(Value::Null);
+        }
+        let mut text = self.text.clone();
+        for x in refs.iter().rev() {
+            text.replace_range(x.offset..x.offset + x.len, new);
+        }
+        let renamed = analyze(&text);
+        if !renamed.diags.is_empty() || renamed.bindings() != self.analysis.bindings() {
+            return Err((
+                REQUEST_FAILED,
+                format!("renaming to `{new}` would change what names refer to"),
+            ));
+        }
+        let edits = refs
+            .iter()
+            .map(|x| json!({ "range": range(&self.text, x.offset, x.len), "newText": new }))
+            .collect::<Vec<_>>();
+        Ok(json!({ "changes": { uri: edits } }))
+    }
+}
@@ -14,6 +14,10 @@ use std::vec::Vec;
 pub struct Namer {
     name_suffix: HashMap<String, usize>,
     old_new_varname: Vec<(String, String)>,
+    /// Names of host functions, referred to when no variable is in scope.
+    hosts: Vec<String>,
+    /// Variables that shadow another, and the one they shadow, by their new names.
+    shadowed: Vec<(String, String)>,
 }
 
 #[derive(Debug)]
@@ -22,6 +26,15 @@ pub enum NamerErrKind {
     DuplicateLetRecFn {},
 }
 
+impl std::fmt::Display for NamerErrKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NamerErrKind::UnknownVarRef { id } => write!(f, "unknown variable {id}"),
+            NamerErrKind::DuplicateLetRecFn {} => write!(f, "function defined twice in let rec"),
+        }
+    }
+}
+
 type NamerResult = Result<(), NamerErrKind>;
 
 impl Namer {
@@ -31,9 +44,41 @@ impl Namer {
         Namer {
             name_suffix,
             old_new_varname,
+            hosts: Vec::new(),
+            shadowed: Vec::new(),
         }
     }
 
+    /// Bring a variable defined outside the visited expression into scope,
+    /// e.g. a top-level REPL binding that was renamed by an earlier visit.
+    pub fn define_global(&mut self, old: &str, new: &str) {
+        self.old_new_varname
+            .push((old.to_string(), new.to_string()));
+    }
+
+    /// Resolve otherwise unknown references to `name` to the host function of that name.
+    pub fn define_host(&mut self, name: &str) {
+        self.hosts.push(name.to_string());
+    }
+
+    /// Variables defined while another of the same name was in scope, with that one,
+    /// both by their new names.
+    pub fn shadowed(&self) -> &[(String, String)] {
+        &self.shadowed
+    }
+
+    /// Number of variables currently in scope.
+    pub fn scope_len(&self) -> usize {
+        self.old_new_varname.len()
+    }
+
+    /// Forget variables defined after `len`.
+    /// A failed visit returns early and leaves its variables in scope, so callers
+    /// that keep using the namer afterwards need to clean up.
+    pub fn truncate_scope(&mut self, len: usize) {
+        self.old_new_varname.truncate(len);
+    }
+
     fn gen_name(&mut self, name: &str) -> String {
         let suffix = *self.name_suffix.get(name).unwrap_or(&0);
         self.name_suffix.insert(name.to_string(), suffix + 1);
@@ -42,10 +87,24 @@ impl Namer {
 
     fn define_var(&mut self, old: &str) -> String {
         let new = self.gen_name(old);
+        if let Some((_, prev)) = self.old_new_varname.iter().rev().find(|x| x.0 == old) {
+            self.shadowed.push((new.clone(), prev.clone()));
+        }
         self.old_new_varname.push((old.to_string(), new.clone()));
         return new;
     }
 
+    /// Define the binders of `ptn` from left to right.
+    fn define_ptn(&mut self, ptn: &mut MatchPattern) {
+        match ptn {
+            MatchPattern::Binder { name } => *name = self.define_var(name),
+            MatchPattern::Tuple { subs } | MatchPattern::DataType { subs, .. } => {
+                subs.iter_mut().for_each(|x| self.define_ptn(x))
+            }
+            MatchPattern::Lit { .. } => (),
+        }
+    }
+
     fn undefine_var(&mut self, new: &String) {
         let (_old, new_) = self.old_new_varname.pop().unwrap();
         if &new_ != new {
@@ -71,6 +130,11 @@ impl ExprTransformer<NamerResult> for Namer {
                     return Ok(());
                 }
             }
+            if self.hosts.contains(id) {
+                let op = BuiltinOp::Host(id.clone());
+                *e = Expr::Builtin { op };
+                return Ok(());
+            }
             Err(NamerErrKind::UnknownVarRef { id: id.clone() })
         } else {
             unreachable!()
@@ -138,4 +202,12 @@ impl ExprTransformer<NamerResult> for Namer {
             unreachable!()
         }
     }
+
+    fn visit_matcharm(&mut self, arm: &mut MatchArm) -> NamerResult {
+        let len = self.scope_len();
+        self.define_ptn(&mut arm.ptn);
+        let res = self.visit(&mut arm.res);
+        self.truncate_scope(len);
+        res
+    }
 }
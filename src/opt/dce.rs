@@ -0,0 +1,119 @@
+//! Dead code elimination.
+//!
+//! Removes `let`s of pure values that are not used, `let rec` functions that cannot be called
+//! from the body, and pure expressions of a `Seq` whose value is dropped.
+//! A body is cleaned first, so a binding only used by dead code goes away with it.
+
+use std::collections::HashMap;
+
+use crate::{ast::Expr, pass::ExprTransformer};
+
+use super::{count_uses, is_pure, take};
+
+pub struct DeadCode {
+    /// References to each variable, kept up to date as code is removed.
+    uses: HashMap<String, usize>,
+}
+
+impl DeadCode {
+    /// To remove dead code in `e`.
+    pub fn new(e: &Expr) -> Self {
+        DeadCode {
+            uses: count_uses(e),
+        }
+    }
+
+    fn uses(&self, id: &str) -> usize {
+        self.uses.get(id).copied().unwrap_or(0)
+    }
+
+    fn remove(&mut self, e: &Expr) {
+        for (id, n) in count_uses(e) {
+            *self.uses.get_mut(&id).unwrap() -= n;
+        }
+    }
+}
+
+impl ExprTransformer<()> for DeadCode {
+    fn default(&mut self) {}
+
+    fn visit_let(&mut self, e: &mut Expr) {
+        let Expr::Let {
+            name,
+            ty: _,
+            box val,
+            box body,
+        } = e
+        else {
+            unreachable!()
+        };
+        self.visit(body);
+        if self.uses(name) == 0 && is_pure(val) {
+            self.remove(val);
+            *e = take(body);
+        } else {
+            self.visit(val);
+        }
+    }
+
+    fn visit_letrec(&mut self, e: &mut Expr) {
+        let Expr::LetRec { arms, box body } = e else {
+            unreachable!()
+        };
+        self.visit(body);
+        // functions are live if used outside of the arms, or by a live one
+        let arm_uses = arms.iter().map(|x| count_uses(&x.body)).collect::<Vec<_>>();
+        let mut live = arms
+            .iter()
+            .map(|x| {
+                let inner: usize = arm_uses.iter().filter_map(|u| u.get(&x.fn_name)).sum();
+                self.uses(&x.fn_name) > inner
+            })
+            .collect::<Vec<_>>();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in 0..arms.len() {
+                let called =
+                    (0..arms.len()).any(|j| live[j] && arm_uses[j].contains_key(&arms[i].fn_name));
+                if !live[i] && called {
+                    live[i] = true;
+                    changed = true;
+                }
+            }
+        }
+        let mut i = 0;
+        arms.retain(|arm| {
+            i += 1;
+            if !live[i - 1] {
+                self.remove(&arm.body);
+            }
+            live[i - 1]
+        });
+        if arms.is_empty() {
+            *e = take(body);
+        } else {
+            arms.iter_mut().for_each(|x| self.visit_letrecarm(x));
+        }
+    }
+
+    fn visit_seq(&mut self, e: &mut Expr) {
+        self.visit_children(e);
+        let Expr::Seq { subs } = e else {
+            unreachable!()
+        };
+        let last = subs.len().saturating_sub(1);
+        let mut i = 0;
+        subs.retain(|x| {
+            i += 1;
+            let dead = i - 1 < last && is_pure(x);
+            if dead {
+                self.remove(x);
+            }
+            !dead
+        });
+        if subs.len() == 1 {
+            *e = take(&mut subs[0]);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! Constant folding and algebraic simplification.
+//!
+//! Arithmetic on integer literals is done at compile time, unless the machine would fault on it,
+//! e.g. on division by zero or overflow, which is then left for the machine to report.
+//! Integer literals bound by `let` are propagated to their uses, and an `if` on a literal
+//! becomes the branch it takes.
+//! Identities like `x + 0` to `x` are only used when `x` is known to be an integer,
+//! as the machine faults on a non-integer `x`, e.g. when the program is not typed.
+//! `x * 0` to `0` also needs `x` to be pure, as it drops `x`.
+
+use std::collections::HashMap;
+
+use crate::{
+    ast::{BinOp, BuiltinOp, Expr, UnaOp},
+    pass::ExprTransformer,
+};
+
+use super::{is_pure, take};
+
+pub struct ConstFold {
+    /// Variables bound to integer literals.
+    consts: HashMap<String, i64>,
+}
+
+impl Default for ConstFold {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConstFold {
+    pub fn new() -> Self {
+        ConstFold {
+            consts: HashMap::new(),
+        }
+    }
+}
+
+/// As the machine computes it, or `None` if it faults.
+fn fold_binop(op: BinOp, lhs: i64, rhs: i64) -> Option<i64> {
+    match op {
+        BinOp::Add => lhs.checked_add(rhs),
+        BinOp::Sub => lhs.checked_sub(rhs),
+        BinOp::Mul => lhs.checked_mul(rhs),
+        BinOp::Div => lhs.checked_div(rhs),
+        BinOp::Rem => lhs.checked_rem(rhs),
+        BinOp::Gt => Some((lhs > rhs).into()),
+        BinOp::Lt => Some((lhs < rhs).into()),
+        BinOp::Ge => Some((lhs >= rhs).into()),
+        BinOp::Le => Some((lhs <= rhs).into()),
+        BinOp::Eq => Some((lhs == rhs).into()),
+        BinOp::Ne => Some((lhs != rhs).into()),
+        BinOp::Land | BinOp::Lor | BinOp::Lxor => None,
+    }
+}
+
+fn fold_unaop(op: UnaOp, arg: i64) -> Option<i64> {
+    match op {
+        UnaOp::Neg => arg.checked_neg(),
+        UnaOp::Lnot => Some((arg == 0).into()),
+    }
+}
+
+fn int_lit(e: &Expr) -> Option<i64> {
+    match e {
+        Expr::IntLit { val } => Some(*val),
+        _ => None,
+    }
+}
+
+/// Whether `e` is an integer if it evaluates to anything, as operators fault on others.
+fn is_int(e: &Expr) -> bool {
+    matches!(
+        e,
+        Expr::IntLit { .. } | Expr::Binary { .. } | Expr::Unary { .. }
+    )
+}
+
+impl ExprTransformer<()> for ConstFold {
+    fn default(&mut self) {}
+
+    fn visit_varref(&mut self, e: &mut Expr) {
+        if let Expr::VarRef { id } = e {
+            if let Some(val) = self.consts.get(id) {
+                *e = Expr::IntLit { val: *val };
+            }
+        } else {
+            unreachable!()
+        }
+    }
+
+    fn visit_let(&mut self, e: &mut Expr) {
+        if let Expr::Let {
+            name,
+            ty: _,
+            box val,
+            box body,
+        } = e
+        {
+            self.visit(val);
+            if let Some(val) = int_lit(val) {
+                self.consts.insert(name.clone(), val);
+            }
+            self.visit(body);
+        } else {
+            unreachable!()
+        }
+    }
+
+    fn visit_unary(&mut self, e: &mut Expr) {
+        self.visit_children(e);
+        if let Expr::Unary { op, box sub } = e {
+            if let Some(val) = int_lit(sub).and_then(|x| fold_unaop(*op, x)) {
+                *e = Expr::IntLit { val };
+            }
+        } else {
+            unreachable!()
+        }
+    }
+
+    fn visit_binary(&mut self, e: &mut Expr) {
+        self.visit_children(e);
+        let Expr::Binary {
+            box lhs,
+            op,
+            box rhs,
+        } = e
+        else {
+            unreachable!()
+        };
+        let new = match (int_lit(lhs), *op, int_lit(rhs)) {
+            (Some(l), op, Some(r)) => fold_binop(op, l, r).map(|val| Expr::IntLit { val }),
+            (_, BinOp::Add | BinOp::Sub, Some(0)) | (_, BinOp::Mul | BinOp::Div, Some(1))
+                if is_int(lhs) =>
+            {
+                Some(take(lhs))
+            }
+            (Some(0), BinOp::Add, _) | (Some(1), BinOp::Mul, _) if is_int(rhs) => Some(take(rhs)),
+            (_, BinOp::Mul, Some(0)) if is_int(lhs) && is_pure(lhs) => {
+                Some(Expr::IntLit { val: 0 })
+            }
+            (Some(0), BinOp::Mul, _) if is_int(rhs) && is_pure(rhs) => {
+                Some(Expr::IntLit { val: 0 })
+            }
+            _ => None,
+        };
+        if let Some(new) = new {
+            *e = new;
+        }
+    }
+
+    fn visit_ite(&mut self, e: &mut Expr) {
+        self.visit_children(e);
+        let Expr::Ite {
+            box cond,
+            box tr,
+            box fl,
+        } = e
+        else {
+            unreachable!()
+        };
+        let taken = match cond {
+            Expr::IntLit { val } => Some(*val != 0),
+            Expr::Builtin {
+                op: BuiltinOp::True,
+            } => Some(true),
+            Expr::Builtin {
+                op: BuiltinOp::False,
+            } => Some(false),
+            _ => None,
+        };
+        if let Some(taken) = taken {
+            *e = take(if taken { tr } else { fl });
+        }
+    }
+}
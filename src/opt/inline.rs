@@ -0,0 +1,298 @@
+//! Function inlining and beta reduction.
+//!
+//! Applying an `Abs` becomes a `let` of the argument, which needs no `closure`, `apply` or
+//! `return`, or the argument itself in place of the parameter if it is a variable or a literal.
+//! A function bound by `let` is copied into its calls if it is small or called once,
+//! and the `let` goes away when no use of it is left.
+//! The namer made every name unique, so a free variable of a copy cannot be captured at the
+//! call, and the binders of the copy are renamed, within their scope, to keep them unique.
+
+use std::collections::HashMap;
+
+use crate::{
+    ast::{Expr, MatchArm, MatchPattern},
+    pass::{ExprListener, ExprTransformer},
+};
+
+use super::{count_uses, take};
+
+/// Functions of at most this many nodes are inlined into every call.
+const MAX_SIZE: usize = 16;
+
+pub struct Inliner {
+    /// References to each variable, kept up to date as code is copied and removed.
+    uses: HashMap<String, usize>,
+    /// Functions bound by `let` in scope, with their size.
+    fns: HashMap<String, (Expr, usize)>,
+    /// Copies made so far, which suffix the names in the next one.
+    copies: usize,
+}
+
+impl Inliner {
+    /// To inline in `e`.
+    pub fn new(e: &Expr) -> Self {
+        Inliner {
+            uses: count_uses(e),
+            fns: HashMap::new(),
+            copies: 0,
+        }
+    }
+
+    fn add_uses(&mut self, e: &Expr, add: bool) {
+        for (id, n) in count_uses(e) {
+            let uses = self.uses.entry(id).or_insert(0);
+            *uses = if add { *uses + n } else { *uses - n };
+        }
+    }
+
+    /// A fresh copy of the function `id`, if it is to be inlined.
+    fn copy_of(&mut self, id: &str) -> Option<Expr> {
+        let (f, size) = self.fns.get(id)?;
+        if *size > MAX_SIZE && self.uses[id] > 1 {
+            return None;
+        }
+        self.copies += 1;
+        let mut copy = f.clone();
+        Freshen {
+            suffix: format!(".{}", self.copies),
+            names: Vec::new(),
+        }
+        .visit(&mut copy);
+        *self.uses.get_mut(id).unwrap() -= 1;
+        self.add_uses(&copy, true);
+        Some(copy)
+    }
+
+    /// `(\x -> body) arg` to `let x = arg in body`, also when the function is under `let`s.
+    /// Moving `arg` under them keeps the order of evaluation, and names are unique.
+    /// A variable or literal `arg` is put in place of `x` instead, so that no alias is left.
+    fn beta(&mut self, e: Expr) -> Expr {
+        match e {
+            Expr::App {
+                fun:
+                    box Expr::Abs {
+                        arg_name,
+                        arg_ty: _,
+                        box mut body,
+                    },
+                box arg,
+            } if is_atom(&arg) => {
+                let mut subst = Subst {
+                    name: &arg_name,
+                    by: &arg,
+                    n: 0,
+                };
+                subst.visit(&mut body);
+                if let Expr::VarRef { id } = &arg {
+                    let uses = self.uses.get_mut(id).unwrap();
+                    *uses = *uses + subst.n - 1;
+                }
+                body
+            }
+            Expr::App {
+                fun:
+                    box Expr::Abs {
+                        arg_name,
+                        arg_ty,
+                        body,
+                    },
+                arg,
+            } => Expr::Let {
+                name: arg_name,
+                ty: arg_ty,
+                val: arg,
+                body,
+            },
+            Expr::App {
+                fun:
+                    box Expr::Let {
+                        name,
+                        ty,
+                        val,
+                        body: fun @ box (Expr::Abs { .. } | Expr::Let { .. }),
+                    },
+                arg,
+            } => Expr::Let {
+                name,
+                ty,
+                val,
+                body: Box::new(self.beta(Expr::App { fun, arg })),
+            },
+            e => e,
+        }
+    }
+}
+
+/// Whether `e` can be put in place of a variable bound to it: evaluating it has no effect,
+/// and copying it copies no work.
+fn is_atom(e: &Expr) -> bool {
+    matches!(
+        e,
+        Expr::VarRef { .. } | Expr::IntLit { .. } | Expr::UnitLit {}
+    )
+}
+
+struct Size(usize);
+
+impl ExprListener for Size {
+    fn walk(&mut self, e: &Expr) {
+        self.0 += 1;
+        self.default_walk(e)
+    }
+}
+
+impl ExprTransformer<()> for Inliner {
+    fn default(&mut self) {}
+
+    fn visit_let(&mut self, e: &mut Expr) {
+        let Expr::Let {
+            name,
+            ty: _,
+            box val,
+            box body,
+        } = e
+        else {
+            unreachable!()
+        };
+        self.visit(val);
+        if let Expr::Abs { .. } = val {
+            let mut size = Size(0);
+            size.walk(val);
+            self.fns.insert(name.clone(), (val.clone(), size.0));
+        }
+        self.visit(body);
+        if self.fns.remove(name).is_some() && self.uses.get(name) == Some(&0) {
+            self.add_uses(val, false);
+            *e = take(body);
+        }
+    }
+
+    fn visit_app(&mut self, e: &mut Expr) {
+        self.visit_children(e);
+        let Expr::App { box fun, arg: _ } = e else {
+            unreachable!()
+        };
+        if let Expr::VarRef { id } = fun {
+            if let Some(copy) = self.copy_of(id) {
+                *fun = copy;
+            }
+        }
+        *e = self.beta(take(e));
+    }
+}
+
+/// Renames the binders of a copy, and the references to them within their scope.
+struct Freshen {
+    suffix: String,
+    /// Old and new names of the binders in scope, innermost last.
+    names: Vec<(String, String)>,
+}
+
+impl Freshen {
+    /// Rename `name`, which stays in scope until `names` is truncated again.
+    fn rename(&mut self, name: &mut String) {
+        let new = format!("{name}{}", self.suffix);
+        self.names.push((name.clone(), new.clone()));
+        *name = new;
+    }
+
+    fn rename_ptn(&mut self, ptn: &mut MatchPattern) {
+        match ptn {
+            MatchPattern::Binder { name } => self.rename(name),
+            MatchPattern::Tuple { subs } | MatchPattern::DataType { subs, .. } => {
+                subs.iter_mut().for_each(|x| self.rename_ptn(x))
+            }
+            MatchPattern::Lit { .. } => (),
+        }
+    }
+}
+
+impl ExprTransformer<()> for Freshen {
+    fn default(&mut self) {}
+
+    fn visit_varref(&mut self, e: &mut Expr) {
+        if let Expr::VarRef { id } = e {
+            if let Some((_, new)) = self.names.iter().rev().find(|x| &x.0 == id) {
+                *id = new.clone();
+            }
+        } else {
+            unreachable!()
+        }
+    }
+
+    fn visit_abs(&mut self, e: &mut Expr) {
+        if let Expr::Abs {
+            arg_name, box body, ..
+        } = e
+        {
+            let len = self.names.len();
+            self.rename(arg_name);
+            self.visit(body);
+            self.names.truncate(len);
+        } else {
+            unreachable!()
+        }
+    }
+
+    fn visit_let(&mut self, e: &mut Expr) {
+        if let Expr::Let {
+            name,
+            box val,
+            box body,
+            ..
+        } = e
+        {
+            self.visit(val);
+            let len = self.names.len();
+            self.rename(name);
+            self.visit(body);
+            self.names.truncate(len);
+        } else {
+            unreachable!()
+        }
+    }
+
+    fn visit_letrec(&mut self, e: &mut Expr) {
+        if let Expr::LetRec { arms, box body } = e {
+            let len = self.names.len();
+            for arm in arms.iter_mut() {
+                self.rename(&mut arm.fn_name);
+            }
+            for arm in arms.iter_mut() {
+                let fns_len = self.names.len();
+                self.rename(&mut arm.arg_name);
+                self.visit(&mut arm.body);
+                self.names.truncate(fns_len);
+            }
+            self.visit(body);
+            self.names.truncate(len);
+        } else {
+            unreachable!()
+        }
+    }
+
+    fn visit_matcharm(&mut self, arm: &mut MatchArm) {
+        let len = self.names.len();
+        self.rename_ptn(&mut arm.ptn);
+        self.visit(&mut arm.res);
+        self.names.truncate(len);
+    }
+}
+
+/// Puts `by` in place of the references to `name`, counting them.
+struct Subst<'a> {
+    name: &'a str,
+    by: &'a Expr,
+    n: usize,
+}
+
+impl ExprTransformer<()> for Subst<'_> {
+    fn default(&mut self) {}
+
+    fn visit_varref(&mut self, e: &mut Expr) {
+        if matches!(e, Expr::VarRef { id } if id == self.name) {
+            *e = self.by.clone();
+            self.n += 1;
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! Optimization passes on the named AST.
+
+mod dce;
+mod fold;
+mod inline;
+
+pub use dce::DeadCode;
+pub use fold::ConstFold;
+pub use inline::Inliner;
+
+use std::collections::HashMap;
+
+use crate::{
+    ast::Expr,
+    pass::{ExprListener, ExprTransformer},
+};
+
+/// Optimize `e` at `level`: 0 leaves it alone, 1 folds constants and removes dead code,
+/// 2 also inlines functions.
+/// `e` must have been through the namer, so that every binder has its own name.
+pub fn optimize(e: &mut Expr, level: u8) {
+    if level >= 1 {
+        ConstFold::new().visit(e);
+    }
+    if level >= 2 {
+        Inliner::new(e).visit(e);
+        ConstFold::new().visit(e);
+    }
+    if level >= 1 {
+        DeadCode::new(e).visit(e);
+    }
+}
+
+struct Uses(HashMap<String, usize>);
+
+impl ExprListener for Uses {
+    fn walk_varref(&mut self, id: &String, _eself: &Expr) {
+        *self.0.entry(id.clone()).or_insert(0) += 1;
+    }
+}
+
+/// How many times each variable is referred to in `e`.
+pub(crate) fn count_uses(e: &Expr) -> HashMap<String, usize> {
+    let mut uses = Uses(HashMap::new());
+    uses.walk(e);
+    uses.0
+}
+
+/// Whether evaluating `e` cannot have an effect, fault or fail to terminate.
+pub(crate) fn is_pure(e: &Expr) -> bool {
+    match e {
+        Expr::IntLit { .. }
+        | Expr::UnitLit {}
+        | Expr::VarRef { .. }
+        | Expr::Builtin { .. }
+        | Expr::Abs { .. } => true,
+        Expr::Tuple { subs } => subs.iter().all(|x| is_pure(x)),
+        _ => false,
+    }
+}
+
+/// Move `e` out, leaving a `()` behind.
+pub(crate) fn take(e: &mut Expr) -> Expr {
+    std::mem::replace(e, Expr::UnitLit {})
+}
@@ -3,64 +3,102 @@ use crate::ast::*;
 
 use nom::{
     branch::alt,
-    bytes::complete::tag,
     combinator::{map, opt, verify},
     multi::{many0, many1, separated_list1},
     sequence::{delimited, pair, preceded, tuple},
-    IResult,
 };
 
-use super::{ops::*, types::*};
+use super::{ops::*, recover::*, types::*};
 
-pub fn builtin(i: &str) -> IResult<&str, Expr> {
-    let (i, s) = verify(identlike, |s: &str| BUILTIN_PARSE.contains_key(s))(i)?;
+pub fn builtin(i: Span) -> PResult<Expr> {
+    let (i, s) = verify(identlike, |s: &Span| {
+        BUILTIN_PARSE.contains_key(s.fragment())
+    })(i)?;
     let o = Expr::Builtin {
-        op: BUILTIN_PARSE.get(s).unwrap().clone(),
+        op: BUILTIN_PARSE.get(s.fragment()).unwrap().clone(),
     };
     Ok((i, o))
 }
 
-pub fn unitlit(i: &str) -> IResult<&str, Expr> {
+pub fn unitlit(i: Span) -> PResult<Expr> {
     let (i, _) = pair(wstag("("), wstag(")"))(i)?;
     let o = Expr::UnitLit {};
     Ok((i, o))
 }
 
-pub fn tuplee(i: &str) -> IResult<&str, Expr> {
-    let (i, o) = delimited(wstag("("), separated_list1(wstag(","), expr), wstag(")"))(i)?;
-    let subs = o.into_iter().map(Box::new).collect();
-    let o = Expr::Tuple { subs };
+pub fn boollit(i: Span) -> PResult<Expr> {
+    let (i, s) = alt((keyword("true"), keyword("false")))(i)?;
+    let o = Expr::Builtin {
+        op: BUILTIN_PARSE.get(s.fragment()).unwrap().clone(),
+    };
+    Ok((i, o))
+}
+
+/// A parenthesized expression, or a tuple if there are commas.
+pub fn paren(i: Span) -> PResult<Expr> {
+    let (i, _) = wstag("(")(i)?;
+    let (i, o) = separated_list1(wstag(","), recover("expression", expr))(i)?;
+    let (i, _) = expect("`)`", wstag(")"))(i)?;
+    let o = if o.len() == 1 {
+        o.into_iter().next().unwrap()
+    } else {
+        let subs = o.into_iter().map(Box::new).collect();
+        Expr::Tuple { subs }
+    };
     Ok((i, o))
 }
 
-pub fn nth(i: &str) -> IResult<&str, Expr> {
+/// `paren`, whose span is recorded only if it makes a tuple,
+/// so that the span of a parenthesized expression leaves out the parentheses.
+fn paren_or_tuple(i: Span) -> PResult<Expr> {
+    let (rest, o) = paren(i)?;
+    if let Expr::Tuple { .. } = o {
+        record_span(i, rest);
+    }
+    Ok((rest, o))
+}
+
+pub fn nth(i: Span) -> PResult<Expr> {
     // Making nth a builtin requires some kind of dependent unification
     // So for now it's a separate primitive
-    let (i, (idx, sub)) = preceded(wstag("nth"), tuple((integer, expr)))(i)?;
+    let (i, _) = keyword("nth")(i)?;
+    let (i, idx) = expect("tuple index", integer)(i)?;
+    let (i, sub) = recover("expression", atom)(i)?;
+    let idx = idx.unwrap_or(0);
     let sub = Box::new(sub);
     let o = Expr::Nth { idx, sub };
     Ok((i, o))
 }
 
-pub fn lit(i: &str) -> IResult<&str, Expr> {
-    alt((unitlit, intlit))(i)
+pub fn lit(i: Span) -> PResult<Expr> {
+    spanned(alt((unitlit, intlit, boollit)))(i)
 }
 
-pub fn atom(i: &str) -> IResult<&str, Expr> {
+pub fn atom(i: Span) -> PResult<Expr> {
     alt((
         lit,
-        builtin,
-        map(ident, |id| Expr::VarRef { id }),
-        paren,
-        nth,
-        tuplee,
+        spanned(nth),
+        spanned(builtin),
+        spanned(map(ident, |id| Expr::VarRef { id })),
+        paren_or_tuple,
     ))(i)
 }
 
-pub fn app(i: &str) -> IResult<&str, Expr> {
-    let (i, head) = atom(i)?;
-    let (i, tail) = many0(atom)(i)?;
-    let o = tail.into_iter().fold(head, |acc, arg| {
+/// `p`, and the input after it, where the span of what it parsed ends.
+fn with_rest<'a, O>(
+    mut p: impl FnMut(Span<'a>) -> PResult<'a, O>,
+) -> impl FnMut(Span<'a>) -> PResult<'a, (O, Span<'a>)> {
+    move |i| {
+        let (i, o) = p(i)?;
+        Ok((i, (o, i)))
+    }
+}
+
+pub fn app(start: Span) -> PResult<Expr> {
+    let (i, head) = atom(start)?;
+    let (i, tail) = many0(with_rest(atom))(i)?;
+    let o = tail.into_iter().fold(head, |acc, (arg, rest)| {
+        record_span(start, rest);
         let fun = Box::new(acc);
         let arg = Box::new(arg);
         Expr::App { fun, arg }
@@ -68,37 +106,29 @@ pub fn app(i: &str) -> IResult<&str, Expr> {
     Ok((i, o))
 }
 
-pub fn una(i: &str) -> IResult<&str, Expr> {
-    let (i, ops) = many0(una_op)(i)?;
+pub fn una(i: Span) -> PResult<Expr> {
+    // each with where it starts
+    let (i, ops) = many0(|i| ws(una_op)(i).map(|(rest, op)| (rest, (op, i))))(i)?;
     let (i, expr) = app(i)?;
-    let o = ops.into_iter().rfold(expr, |acc, op| {
+    let o = ops.into_iter().rfold(expr, |acc, (op, start)| {
+        record_span(start, i);
         let sub = Box::new(acc);
         Expr::Unary { op, sub }
     });
     Ok((i, o))
 }
 
-pub fn mul(i: &str) -> IResult<&str, Expr> {
-    let (i, head) = una(i)?;
-    let (i, tail) = many0(|i| {
-        let (i, (op, expr)) = tuple((ws(mul_op), ws(una)))(i)?;
-        Ok((i, (op, expr)))
-    })(i)?;
-    let o = tail.into_iter().fold(head, |acc, (op, expr)| {
-        let lhs = Box::new(acc);
-        let rhs = Box::new(expr);
-        Expr::Binary { lhs, op, rhs }
-    });
-    Ok((i, o))
-}
-
-pub fn add(i: &str) -> IResult<&str, Expr> {
-    let (i, head) = mul(i)?;
-    let (i, tail) = many0(|i| {
-        let (i, (op, expr)) = tuple((ws(add_op), ws(mul)))(i)?;
-        Ok((i, (op, expr)))
-    })(i)?;
-    let o = tail.into_iter().fold(head, |acc, (op, expr)| {
+/// Left associative binary operators `op` between operands `operand`.
+fn binary<'a>(
+    start: Span<'a>,
+    operand: fn(Span<'a>) -> PResult<'a, Expr>,
+    op: fn(Span<'a>) -> PResult<'a, BinOp>,
+) -> PResult<'a, Expr> {
+    let (i, head) = operand(start)?;
+    let rhs = recover("expression", ws(operand));
+    let (i, tail) = many0(with_rest(tuple((ws(op), rhs))))(i)?;
+    let o = tail.into_iter().fold(head, |acc, ((op, expr), rest)| {
+        record_span(start, rest);
         let lhs = Box::new(acc);
         let rhs = Box::new(expr);
         Expr::Binary { lhs, op, rhs }
@@ -106,49 +136,25 @@ pub fn add(i: &str) -> IResult<&str, Expr> {
     Ok((i, o))
 }
 
-pub fn rel(i: &str) -> IResult<&str, Expr> {
-    let (i, head) = add(i)?;
-    let (i, tail) = many0(|i| {
-        let (i, (op, expr)) = tuple((ws(rel_op), ws(add)))(i)?;
-        Ok((i, (op, expr)))
-    })(i)?;
-    let o = tail.into_iter().fold(head, |acc, (op, expr)| {
-        let lhs = Box::new(acc);
-        let rhs = Box::new(expr);
-        Expr::Binary { lhs, op, rhs }
-    });
-    Ok((i, o))
+pub fn mul(i: Span) -> PResult<Expr> {
+    binary(i, una, mul_op)
 }
 
-pub fn eq_op(i: &str) -> IResult<&str, BinOp> {
-    map(alt((tag("=="), tag("!="))), |o| match o {
-        "==" => BinOp::Eq,
-        "!=" => BinOp::Ne,
-        _ => unreachable!(),
-    })(i)
+pub fn add(i: Span) -> PResult<Expr> {
+    binary(i, mul, add_op)
 }
 
-pub fn eq(i: &str) -> IResult<&str, Expr> {
-    let (i, head) = rel(i)?;
-    let (i, tail) = many0(|i| {
-        let (i, (op, expr)) = tuple((ws(eq_op), ws(rel)))(i)?;
-        Ok((i, (op, expr)))
-    })(i)?;
-    let o = tail.into_iter().fold(head, |acc, (op, expr)| {
-        let lhs = Box::new(acc);
-        let rhs = Box::new(expr);
-        Expr::Binary { lhs, op, rhs }
-    });
-    Ok((i, o))
+pub fn rel(i: Span) -> PResult<Expr> {
+    binary(i, add, rel_op)
 }
 
-pub fn ite1(i: &str) -> IResult<&str, Expr> {
-    let (i, _) = wstag("if")(i)?;
-    let (i, cond) = eq(i)?;
-    let (i, _) = wstag("then")(i)?;
-    let (i, tr) = eq(i)?;
-    let (i, _) = wstag("else")(i)?;
-    let (i, fl) = ite(i)?;
+pub fn ite1(i: Span) -> PResult<Expr> {
+    let (i, _) = keyword("if")(i)?;
+    let (i, cond) = recover("expression", rel)(i)?;
+    let (i, _) = expect("`then` after condition", keyword("then"))(i)?;
+    let (i, tr) = recover("expression", rel)(i)?;
+    let (i, _) = expect("`else` after then branch", keyword("else"))(i)?;
+    let (i, fl) = recover("expression", ite)(i)?;
     let cond = Box::new(cond);
     let tr = Box::new(tr);
     let fl = Box::new(fl);
@@ -156,31 +162,45 @@ pub fn ite1(i: &str) -> IResult<&str, Expr> {
     Ok((i, o))
 }
 
-pub fn ite(i: &str) -> IResult<&str, Expr> {
-    alt((ite1, eq))(i)
+pub fn ite(i: Span) -> PResult<Expr> {
+    alt((spanned(ite1), rel))(i)
 }
 
-pub fn seq(i: &str) -> IResult<&str, Expr> {
-    let (i, o) = separated_list1(wstag(";"), ite)(i)?;
+pub fn seq(start: Span) -> PResult<Expr> {
+    let (i, head) = ite(start)?;
+    let (i, tail) = many0(preceded(wstag(";"), recover("expression", ite)))(i)?;
+    let mut o = vec![head];
+    o.extend(tail);
     if o.len() == 1 {
         // prevent redundant seq's
         let o = o.into_iter().nth(0).unwrap();
         Ok((i, o))
     } else {
+        record_span(start, i);
         let subs = o.into_iter().map(|x| Box::new(x)).collect();
         let o = Expr::Seq { subs };
         Ok((i, o))
     }
 }
 
-pub fn lam1(i: &str) -> IResult<&str, Expr> {
+/// The optional type of a lambda argument, and the `->` after it.
+fn arg_ty(i: Span) -> PResult<Ty> {
+    let (i, o) = expect(
+        "`:` or `->` after argument name",
+        alt((
+            preceded(wstag(":"), expect("type followed by `->`", ty_arg)),
+            map(wstag("->"), |_| None),
+        )),
+    )(i)?;
+    Ok((i, o.flatten().unwrap_or(Ty::UnkTy)))
+}
+
+pub fn lam1(i: Span) -> PResult<Expr> {
     let (i, _) = wstag(r"\")(i)?;
-    let (i, arg_name) = ws(ident)(i)?;
-    let (i, arg_ty) = opt(preceded(tag(":"), ty))(i)?;
-    let (i, _) = wstag("->")(i)?;
-    let (i, body) = ws(expr)(i)?;
-    let arg_name = arg_name.to_string();
-    let arg_ty = arg_ty.unwrap_or(Ty::UnkTy);
+    let (i, arg_name) = expect("argument name", ws(ident))(i)?;
+    let (i, arg_ty) = arg_ty(i)?;
+    let (i, body) = recover("expression", ws(expr))(i)?;
+    let arg_name = arg_name.unwrap_or_default();
     let body = Box::new(body);
     let o = Expr::Abs {
         arg_name,
@@ -190,19 +210,19 @@ pub fn lam1(i: &str) -> IResult<&str, Expr> {
     Ok((i, o))
 }
 
-pub fn lam(i: &str) -> IResult<&str, Expr> {
-    alt((lam1, seq))(i)
+pub fn lam(i: Span) -> PResult<Expr> {
+    alt((spanned(lam1), seq))(i)
 }
 
-pub fn let1(i: &str) -> IResult<&str, Expr> {
-    let (i, _) = wstag("let")(i)?;
+pub fn let1(i: Span) -> PResult<Expr> {
+    let (i, _) = keyword("let")(i)?;
     let (i, name) = ws(ident)(i)?;
-    let (i, ty) = opt(preceded(wstag(":"), ty))(i)?;
-    let (i, _) = tag("=")(i)?;
-    let (i, val) = ws(expr)(i)?;
-    let (i, _) = wstag("in")(i)?;
-    let (i, body) = ws(expr)(i)?;
-    let ty = ty.unwrap_or(Ty::UnkTy);
+    let (i, ty) = opt(preceded(wstag(":"), expect("type", ty)))(i)?;
+    let (i, _) = expect("`=` after let name", wstag("="))(i)?;
+    let (i, val) = recover("expression", ws(expr))(i)?;
+    let (i, _) = expect("`in` after let binding", keyword("in"))(i)?;
+    let (i, body) = recover("expression", ws(expr))(i)?;
+    let ty = ty.flatten().unwrap_or(Ty::UnkTy);
     let val = Box::new(val);
     let body = Box::new(body);
     let o = Expr::Let {
@@ -214,17 +234,17 @@ pub fn let1(i: &str) -> IResult<&str, Expr> {
     Ok((i, o))
 }
 
-pub fn let2arm(i: &str) -> IResult<&str, LetRecArm> {
-    let (i, fn_name) = ident(i)?;
-    let (i, fn_ty) = opt(preceded(wstag(":"), ty))(i)?;
-    let (i, _) = wstag("=")(i)?;
-    let (i, _) = wstag(r"\")(i)?;
-    let (i, arg_name) = ident(i)?;
-    let (i, arg_ty) = opt(preceded(wstag(":"), ty))(i)?;
-    let (i, _) = wstag("->")(i)?;
-    let (i, body) = expr(i)?;
-    let arg_ty = arg_ty.unwrap_or(Ty::UnkTy);
-    let fn_ty = fn_ty.unwrap_or(Ty::UnkTy);
+pub fn let2arm(i: Span) -> PResult<LetRecArm> {
+    let (i, fn_name) = expect("function name", ident)(i)?;
+    let (i, fn_ty) = opt(preceded(wstag(":"), expect("type", ty)))(i)?;
+    let (i, _) = expect("`=` after function name", wstag("="))(i)?;
+    let (i, _) = expect(r"`\` after `=`", wstag(r"\"))(i)?;
+    let (i, arg_name) = expect("argument name", ident)(i)?;
+    let (i, arg_ty) = arg_ty(i)?;
+    let (i, body) = recover("expression", expr)(i)?;
+    let fn_name = fn_name.unwrap_or_default();
+    let arg_name = arg_name.unwrap_or_default();
+    let fn_ty = fn_ty.flatten().unwrap_or(Ty::UnkTy);
     let o = LetRecArm {
         fn_name,
         fn_ty,
@@ -236,17 +256,22 @@ pub fn let2arm(i: &str) -> IResult<&str, LetRecArm> {
 }
 
 /// An `let-rec`.
-pub fn let2(i: &str) -> IResult<&str, Expr> {
-    let (i, _) = wstag("let rec")(i)?;
-    let (i, arms) = separated_list1(wstag("and"), let2arm)(i)?;
-    let (i, _) = wstag("in")(i)?;
-    let (i, body) = ws(expr)(i)?;
+pub fn let2(i: Span) -> PResult<Expr> {
+    let (i, _) = pair(keyword("let"), keyword("rec"))(i)?;
+    let (i, arms) = separated_list1(keyword("and"), let2arm)(i)?;
+    let (i, _) = expect("`in` after let rec bindings", keyword("in"))(i)?;
+    let (i, body) = recover("expression", ws(expr))(i)?;
     let body = Box::new(body);
     let o = Expr::LetRec { arms, body };
     Ok((i, o))
 }
 
-pub fn ptn(i: &str) -> IResult<&str, MatchPattern> {
+pub fn ptn(i: Span) -> PResult<MatchPattern> {
+    alt((ptn_data, ptn_tuple))(i)
+}
+
+/// Tuple pattern, or a single ptn1 if there is no comma.
+pub fn ptn_tuple(i: Span) -> PResult<MatchPattern> {
     let (i, subs) = separated_list1(wstag(","), ptn1)(i)?;
     let o = if subs.len() == 1 {
         subs.into_iter().nth(0).unwrap()
@@ -256,62 +281,69 @@ pub fn ptn(i: &str) -> IResult<&str, MatchPattern> {
     Ok((i, o))
 }
 
-pub fn ptn1(i: &str) -> IResult<&str, MatchPattern> {
-    alt((ptn1_paren, ptn1_lit, ptn1_data, ptn1_binder))(i)
-}
-
-/// Tuple or binder pattern
-pub fn ptn1_data(i: &str) -> IResult<&str, MatchPattern> {
+/// Constructor pattern. Arguments are ptn1's, so `Cons a l` is `Cons (a) (l)`.
+/// Nested constructor patterns need parentheses.
+pub fn ptn_data(i: Span) -> PResult<MatchPattern> {
     let (i, ctor) = ident(i)?;
     let (i, subs) = many1(ws(ptn1))(i)?;
     let o = MatchPattern::DataType { ctor, subs };
     Ok((i, o))
 }
 
-pub fn ptn1_binder(i: &str) -> IResult<&str, MatchPattern> {
+pub fn ptn1(i: Span) -> PResult<MatchPattern> {
+    alt((ptn1_paren, ptn1_lit, ptn1_binder))(i)
+}
+
+pub fn ptn1_binder(i: Span) -> PResult<MatchPattern> {
     let (i, name) = ident(i)?;
     let o = MatchPattern::Binder { name };
     Ok((i, o))
 }
 
-pub fn ptn1_lit(i: &str) -> IResult<&str, MatchPattern> {
+pub fn ptn1_lit(i: Span) -> PResult<MatchPattern> {
     let (i, val) = lit(i)?;
     let o = MatchPattern::Lit { val };
     Ok((i, o))
 }
 
-pub fn ptn1_paren(i: &str) -> IResult<&str, MatchPattern> {
+pub fn ptn1_paren(i: Span) -> PResult<MatchPattern> {
     let (i, o) = delimited(wstag("("), ptn, wstag(")"))(i)?;
     Ok((i, o))
 }
 
-pub fn mat1arm(i: &str) -> IResult<&str, MatchArm> {
+/// A match arm, or `None` if its pattern is malformed.
+pub fn mat1arm(i: Span) -> PResult<Option<MatchArm>> {
     let (i, _) = wstag("|")(i)?;
-    let (i, ptn) = ws(ptn)(i)?;
-    let (i, _) = wstag("->")(i)?;
-    let (i, res) = ws(expr)(i)?;
+    let (i, ptn) = expect("pattern", ws(ptn))(i)?;
+    let Some(ptn) = ptn else {
+        return Ok((skip(i), None));
+    };
+    let (i, _) = expect("`->` after pattern", wstag("->"))(i)?;
+    let (i, res) = recover("expression", ws(expr))(i)?;
     let o = MatchArm { ptn, res };
-    Ok((i, o))
+    Ok((i, Some(o)))
 }
 
-pub fn mat1(i: &str) -> IResult<&str, Expr> {
-    let (i, _) = wstag("match")(i)?;
-    let (i, sub) = ws(expr)(i)?;
-    let (i, arms) = many1(mat1arm)(i)?;
-    let (i, _) = wstag("end")(i)?;
+pub fn mat1(i: Span) -> PResult<Expr> {
+    let (i, _) = keyword("match")(i)?;
+    let (i, sub) = recover("expression", ws(expr))(i)?;
+    let (i, head) = expect("match arm", mat1arm)(i)?;
+    let (i, tail) = many0(mat1arm)(i)?;
+    let (i, _) = expect("`end` after match arms", keyword("end"))(i)?;
+    let arms = head.into_iter().chain(tail).flatten().collect();
     let sub = Box::new(sub);
     let o = Expr::Match { sub, arms };
     Ok((i, o))
 }
 
-pub fn mat(i: &str) -> IResult<&str, Expr> {
-    alt((mat1, lam))(i)
+pub fn mat(i: Span) -> PResult<Expr> {
+    alt((spanned(mat1), lam))(i)
 }
 
-pub fn lett(i: &str) -> IResult<&str, Expr> {
-    alt((let1, let2, mat))(i)
+pub fn lett(i: Span) -> PResult<Expr> {
+    alt((spanned(let1), spanned(let2), mat))(i)
 }
 
-pub fn expr(i: &str) -> IResult<&str, Expr> {
+pub fn expr(i: Span) -> PResult<Expr> {
     lett(i)
 }
@@ -1,16 +1,82 @@
 use crate::ast::*;
 use crate::error::*;
 use crate::parser::ops::ws;
+use crate::spans::ExprSpans;
 use nom::{combinator::eof, sequence::terminated};
-use top::top;
+use ops::{Span, State};
+use top::{repl_binding, repl_expr, top};
 
 mod expr;
 mod ops;
+mod recover;
 mod top;
 mod types;
 
+pub use ops::is_keyword;
+
+/// Parse a program, recovering from syntax errors.
+/// Where the program is malformed, the AST has `Expr::Error`s or placeholder names.
+/// This keeps no state across calls, so it is safe to call concurrently.
+pub fn parse_with_diagnostics(buf: &str) -> (Prog, Vec<Diagnostic>) {
+    let (prog, diags, _) = parse_with_spans(buf);
+    (prog, diags)
+}
+
+/// `parse_with_diagnostics`, also giving the spans of the expressions and identifiers.
+pub fn parse_with_spans(buf: &str) -> (Prog, Vec<Diagnostic>, ExprSpans) {
+    let mut state = State::default();
+    let (_, prog) = ws(top)(Span::new_extra(buf, &state)).expect("top recovers from all errors");
+    let declared = prog
+        .data_types
+        .iter()
+        .map(|x| x.name.clone())
+        .collect::<Vec<_>>();
+    let spans = ExprSpans::new(
+        buf,
+        std::mem::take(state.spans.get_mut()),
+        std::mem::take(state.names.get_mut()),
+    );
+    let diags = resolve(state, &declared);
+    (prog, diags, spans)
+}
+
+/// Parse a program, failing with all its syntax errors.
 pub fn parse(buf: &str) -> Result<Prog, MiniMLErr> {
-    let parse_res = terminated(ws(top), eof)(buf);
-    let prog = parse_res.expect("parsing failed").1;
-    Ok(prog)
+    match parse_with_diagnostics(buf) {
+        (prog, diags) if diags.is_empty() => Ok(prog),
+        (_, diags) => Err(MiniMLErr::ParseError(diags)),
+    }
+}
+
+/// The diagnostics of a finished parse, adding those of undeclared type names.
+fn resolve(state: State, declared: &[String]) -> Vec<Diagnostic> {
+    let mut diags = state.diags.into_inner();
+    let undeclared = state
+        .ty_refs
+        .into_inner()
+        .into_iter()
+        .filter(|(name, _)| !declared.contains(name));
+    diags.extend(undeclared.map(|x| x.1));
+    diags.sort_by_key(|x| x.offset);
+    diags
+}
+
+/// Parse one REPL input: either a top-level binding or an expression.
+/// `data_types` are the names of datatypes declared so far, e.g. by loaded files.
+pub fn parse_repl(buf: &str, data_types: &[String]) -> Result<ReplInput, MiniMLErr> {
+    // each attempt has its own state, so that a failed one leaves no diagnostics behind
+    let state = State::default();
+    let binding = terminated(ws(repl_binding), eof)(Span::new_extra(buf, &state));
+    if let Ok((_, o)) = binding {
+        return match resolve(state, data_types) {
+            diags if diags.is_empty() => Ok(ReplInput::Binding(o)),
+            diags => Err(MiniMLErr::ParseError(diags)),
+        };
+    }
+    let state = State::default();
+    let (_, o) = ws(repl_expr)(Span::new_extra(buf, &state)).expect("recovers from all errors");
+    match resolve(state, data_types) {
+        diags if diags.is_empty() => Ok(ReplInput::Expr(o)),
+        diags => Err(MiniMLErr::ParseError(diags)),
+    }
 }
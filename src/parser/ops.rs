@@ -1,21 +1,39 @@
 //! Operators, delimiters, keywords etc terminal parsing.
-use std::str::FromStr;
+use std::cell::RefCell;
 
 use crate::ast::*;
+use crate::error::Diagnostic;
 
 use nom::{
     branch::alt,
     bytes::complete::{is_not, tag},
     character::complete::*,
     combinator::{map, map_res, opt, recognize, value, verify},
-    error::ParseError,
+    error::{ParseError, VerboseError},
     multi::many0,
     sequence::{delimited, pair},
     IResult,
 };
+use nom_locate::LocatedSpan;
 use phf::phf_set;
 
-use super::expr::expr;
+/// What one run of the parser records besides the AST.
+#[derive(Debug, Default)]
+pub struct State {
+    pub diags: RefCell<Vec<Diagnostic>>,
+    /// Datatype names used in types, with where they are used.
+    /// They are resolved after parsing, see `parser::parse`.
+    pub ty_refs: RefCell<Vec<(String, Diagnostic)>>,
+    /// Where each expression starts, and where the input after it does, as byte offsets.
+    /// Expressions made in recovery are not recorded.
+    pub spans: RefCell<Vec<(usize, usize)>>,
+    /// Where each identifier starts, and its length in bytes.
+    pub names: RefCell<Vec<(usize, usize)>>,
+}
+
+pub type Span<'a> = LocatedSpan<&'a str, &'a State>;
+
+pub type PResult<'a, O> = IResult<Span<'a>, O, VerboseError<Span<'a>>>;
 
 static KEYWORDS: phf::Set<&'static str> = phf_set! {
     "in",
@@ -32,17 +50,22 @@ static KEYWORDS: phf::Set<&'static str> = phf_set! {
     "int",
     "bool",
     "unit",
-};
 
-pub fn paren(i: &str) -> IResult<&str, Expr> {
-    delimited(tag("("), ws(expr), tag(")"))(i)
-}
+    "nth",
+    "println",
+    "print",
+    "panic",
+    "read_int",
+    "read_line",
+    "true",
+    "false",
+};
 
-pub fn integer(i: &str) -> IResult<&str, i64> {
-    map_res(ws(digit1), FromStr::from_str)(i)
+pub fn integer(i: Span) -> PResult<i64> {
+    map_res(ws(digit1), |s: Span| s.fragment().parse())(i)
 }
 
-pub fn intlit(i: &str) -> IResult<&str, Expr> {
+pub fn intlit(i: Span) -> PResult<Expr> {
     map(integer, |val| Expr::IntLit { val })(i)
 }
 
@@ -51,7 +74,7 @@ pub fn is_keyword(i: &str) -> bool {
 }
 
 /// Simply a regex like ident, but does not filter out keywords.
-pub fn identlike(i: &str) -> IResult<&str, &str> {
+pub fn identlike(i: Span) -> PResult<Span> {
     ws(recognize(pair(
         alt((alpha1, tag("_"))),
         many0(alt((alphanumeric1, tag("_")))),
@@ -59,75 +82,112 @@ pub fn identlike(i: &str) -> IResult<&str, &str> {
 }
 
 /// Identifier i.e. satisfies the regex and is not a keyword.
-pub fn ident(i: &str) -> IResult<&str, String> {
-    let (i, s) = verify(identlike, |s: &str| !is_keyword(s))(i)?;
-    Ok((i, String::from(s)))
+pub fn ident(i: Span) -> PResult<String> {
+    let (i, s) = verify(identlike, |s: &Span| !is_keyword(s.fragment()))(i)?;
+    let name = (s.location_offset(), s.fragment().len());
+    i.extra.names.borrow_mut().push(name);
+    Ok((i, s.fragment().to_string()))
+}
+
+/// A keyword, but not the prefix of an identifier like `nth1`.
+pub fn keyword<'a>(kw: &'static str) -> impl FnMut(Span<'a>) -> PResult<'a, Span<'a>> {
+    verify(identlike, move |s: &Span| *s.fragment() == kw)
 }
 
-pub fn una_op(i: &str) -> IResult<&str, UnaOp> {
-    map(alt((tag("!"), tag("-"))), |o| match o {
+pub fn una_op(i: Span) -> PResult<UnaOp> {
+    map(alt((tag("!"), tag("-"))), |o: Span| match *o.fragment() {
         "!" => UnaOp::Lnot,
         "-" => UnaOp::Neg,
         _ => unreachable!(),
     })(i)
 }
 
-pub fn mul_op(i: &str) -> IResult<&str, BinOp> {
-    map(alt((tag("*"), tag("/"), tag("%"))), |o| match o {
-        "*" => BinOp::Mul,
-        "/" => BinOp::Div,
-        "%" => BinOp::Rem,
-        _ => unreachable!(),
+pub fn mul_op(i: Span) -> PResult<BinOp> {
+    map(alt((tag("*"), tag("/"), tag("%"))), |o: Span| {
+        match *o.fragment() {
+            "*" => BinOp::Mul,
+            "/" => BinOp::Div,
+            "%" => BinOp::Rem,
+            _ => unreachable!(),
+        }
     })(i)
 }
 
-pub fn add_op(i: &str) -> IResult<&str, BinOp> {
-    map(alt((tag("+"), tag("-"))), |o| match o {
+pub fn add_op(i: Span) -> PResult<BinOp> {
+    map(alt((tag("+"), tag("-"))), |o: Span| match *o.fragment() {
         "+" => BinOp::Add,
         "-" => BinOp::Sub,
         _ => unreachable!(),
     })(i)
 }
 
-pub fn rel_op(i: &str) -> IResult<&str, BinOp> {
+pub fn rel_op(i: Span) -> PResult<BinOp> {
     // NOTE: this alt order matters
     map(
-        alt((tag(">="), tag("<="), tag(">"), tag("<"))),
-        |o| match o {
+        alt((
+            tag(">="),
+            tag("<="),
+            tag("=="),
+            tag("!="),
+            tag(">"),
+            tag("<"),
+        )),
+        |o: Span| match *o.fragment() {
             ">" => BinOp::Gt,
             "<" => BinOp::Lt,
             ">=" => BinOp::Ge,
             "<=" => BinOp::Le,
+            "==" => BinOp::Eq,
+            "!=" => BinOp::Ne,
             _ => unreachable!(),
         },
     )(i)
 }
 
-pub fn eol_comment<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, (), E> {
+pub fn eol_comment<'a, E: ParseError<Span<'a>>>(i: Span<'a>) -> IResult<Span<'a>, (), E> {
     value(
         (), // Output is thrown away.
-        pair(tag("--"), is_not("\n\r")),
+        pair(tag("--"), opt(is_not("\n\r"))),
     )(i)
 }
 
-pub fn ignored<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, (), E> {
-    let (i, _) = opt(eol_comment)(i)?;
+/// Whitespace and any number of comments.
+pub fn ignored<'a, E: ParseError<Span<'a>>>(i: Span<'a>) -> IResult<Span<'a>, (), E> {
+    let (i, _) = many0(pair(multispace0, eol_comment))(i)?;
     let (i, _) = multispace0(i)?;
     Ok((i, ()))
 }
 
+/// Records that an expression was parsed from `start`, after whitespace, to `rest`.
+pub fn record_span(start: Span, rest: Span) {
+    let start = ignored::<VerboseError<Span>>(start).map_or(start, |x| x.0);
+    let span = (start.location_offset(), rest.location_offset());
+    rest.extra.spans.borrow_mut().push(span);
+}
+
+/// Runs the parser `p` of an expression that it makes whole, and records its span.
+pub fn spanned<'a>(
+    mut p: impl FnMut(Span<'a>) -> PResult<'a, Expr>,
+) -> impl FnMut(Span<'a>) -> PResult<'a, Expr> {
+    move |i| {
+        let (rest, o) = p(i)?;
+        record_span(i, rest);
+        Ok((rest, o))
+    }
+}
+
 // From recipe
-pub fn ws<'a, F: 'a, O, E: ParseError<&'a str>>(
+pub fn ws<'a, F: 'a, O, E: ParseError<Span<'a>>>(
     inner: F,
-) -> impl FnMut(&'a str) -> IResult<&'a str, O, E>
+) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, O, E>
 where
-    F: FnMut(&'a str) -> IResult<&'a str, O, E>,
+    F: FnMut(Span<'a>) -> IResult<Span<'a>, O, E>,
 {
     delimited(ignored, inner, ignored)
 }
 
-pub fn wstag<'a, E: ParseError<&'a str>>(
+pub fn wstag<'a, E: ParseError<Span<'a>>>(
     s: &'a str,
-) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str, E> {
+) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, Span<'a>, E> {
     delimited(ignored, tag(s), ignored)
 }
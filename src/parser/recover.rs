@@ -0,0 +1,125 @@
+//! Recovery from syntax errors.
+//!
+//! Once a keyword like `let` or `match` commits to a construct, its parts are parsed with
+//! `expect` or `recover`, which record a `Diagnostic` instead of failing.
+//! So a committed construct always succeeds, and no enclosing parser backtracks over a report.
+use crate::ast::*;
+use crate::error::Diagnostic;
+
+use nom::{
+    bytes::complete::take,
+    error::{ContextError, VerboseError, VerboseErrorKind},
+};
+
+use super::ops::*;
+
+/// Where skipping stops after an error, unless nested in `(`, `let` or `match`.
+const SYNC: &[&str] = &["in", "end", "then", "else", "and", "|", ";", ",", ")"];
+
+pub fn diagnostic(at: Span, len: usize, msg: String) -> Diagnostic {
+    Diagnostic {
+        offset: at.location_offset(),
+        line: at.location_line(),
+        col: at.get_utf8_column(),
+        len,
+        msg,
+    }
+}
+
+/// The next token and what follows it. The token is empty at the end of input.
+fn next_token(i: Span) -> (Span, Span) {
+    let i = match ignored::<VerboseError<Span>>(i) {
+        Ok((i, _)) => i,
+        Err(_) => i,
+    };
+    let s = i.fragment();
+    let len = match s.find(|c: char| !(c.is_alphanumeric() || c == '_')) {
+        Some(0)
+            if ["->", "==", "!=", ">=", "<="]
+                .iter()
+                .any(|x| s.starts_with(x)) =>
+        {
+            2
+        }
+        Some(0) => s.chars().next().unwrap().len_utf8(),
+        Some(n) => n,
+        None => s.len(),
+    };
+    take::<_, _, VerboseError<Span>>(len)(i).unwrap()
+}
+
+/// Records a diagnostic at the next token of `i`, unless there is one already.
+pub fn report(i: Span, msg: impl FnOnce(&str) -> String) {
+    let (_, tok) = next_token(i);
+    let mut diags = i.extra.diags.borrow_mut();
+    if diags.iter().any(|x| x.offset == tok.location_offset()) {
+        return;
+    }
+    let found = match *tok.fragment() {
+        "" => "end of input".to_string(),
+        s => format!("`{s}`"),
+    };
+    diags.push(diagnostic(tok, tok.fragment().len(), msg(&found)));
+}
+
+/// Reports the context labels of `err` as the expected-token set.
+fn report_expected(i: Span, err: &VerboseError<Span>) {
+    let mut labels = Vec::new();
+    for (_, kind) in err.errors.iter() {
+        if let VerboseErrorKind::Context(x) = kind {
+            if !labels.contains(x) {
+                labels.push(*x);
+            }
+        }
+    }
+    report(i, |found| {
+        format!("expected {}, found {found}", labels.join(" or "))
+    });
+}
+
+/// Skips to the next synchronization point.
+pub fn skip(mut i: Span) -> Span {
+    let mut depth = 0;
+    loop {
+        let (rest, tok) = next_token(i);
+        match *tok.fragment() {
+            "" => return rest,
+            "(" | "let" | "match" => depth += 1,
+            s if depth == 0 && SYNC.contains(&s) => return i,
+            ")" | "in" | "end" => depth -= 1,
+            _ => (),
+        }
+        i = rest;
+    }
+}
+
+/// Parses `p`, or reports that `label` was expected and consumes nothing.
+pub fn expect<'a, O>(
+    label: &'static str,
+    mut p: impl FnMut(Span<'a>) -> PResult<'a, O>,
+) -> impl FnMut(Span<'a>) -> PResult<'a, Option<O>> {
+    move |i| match p(i) {
+        Ok((i, o)) => Ok((i, Some(o))),
+        Err(nom::Err::Error(err) | nom::Err::Failure(err)) => {
+            report_expected(i, &VerboseError::add_context(i, label, err));
+            Ok((i, None))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Parses the expression `p`, or reports that `label` was expected,
+/// skips to the next synchronization point and yields an `Expr::Error`.
+pub fn recover<'a>(
+    label: &'static str,
+    mut p: impl FnMut(Span<'a>) -> PResult<'a, Expr>,
+) -> impl FnMut(Span<'a>) -> PResult<'a, Expr> {
+    move |i| match p(i) {
+        Ok(x) => Ok(x),
+        Err(nom::Err::Error(err) | nom::Err::Failure(err)) => {
+            report_expected(i, &VerboseError::add_context(i, label, err));
+            Ok((skip(i), Expr::Error {}))
+        }
+        Err(err) => Err(err),
+    }
+}
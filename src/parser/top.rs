@@ -1,24 +1,20 @@
-use std::{collections::HashSet, sync::Mutex};
-
 /// Top-level parsing
 use crate::ast::*;
 
 use nom::{
-    multi::{many0, many1},
-    IResult,
+    branch::alt,
+    bytes::complete::tag,
+    combinator::{eof, opt},
+    multi::{many0, many1, separated_list1},
+    sequence::{pair, preceded},
 };
 
-use super::{expr::*, ops::*, types::*};
-
-use lazy_static::lazy_static;
+use super::{expr::*, ops::*, recover::*, types::*};
 
-lazy_static! {
-    pub static ref DATA_TYPE_NAMES: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
-}
-
-pub fn top(i: &str) -> IResult<&str, Prog> {
+pub fn top(i: Span) -> PResult<Prog> {
     let (i, data_types) = many0(data_type)(i)?;
-    let (i, main_expr) = expr(i)?;
+    let (i, main_expr) = recover("expression", expr)(i)?;
+    let (i, _) = expect("end of input", eof)(i)?;
     let o = Prog {
         data_types,
         main_expr,
@@ -26,29 +22,65 @@ pub fn top(i: &str) -> IResult<&str, Prog> {
     Ok((i, o))
 }
 
-pub fn data_type_arm(i: &str) -> IResult<&str, DataTypeArm> {
-    let (i, _) = wstag("|")(i)?;
-    let (i, ctor) = ident(i)?;
-    let (i, arg_tys) = many1(ty)(i)?;
-    let o = DataTypeArm { ctor, arg_tys };
+/// A `let` without `in`, only valid at the top level of the REPL.
+/// The body of the returned `Let` refers back to the bound name.
+pub fn repl_let1(i: Span) -> PResult<Expr> {
+    let (i, _) = keyword("let")(i)?;
+    let (i, name) = ws(ident)(i)?;
+    let (i, ty) = opt(preceded(wstag(":"), ty))(i)?;
+    let (i, _) = tag("=")(i)?;
+    let (i, val) = ws(expr)(i)?;
+    let ty = ty.unwrap_or(Ty::UnkTy);
+    let val = Box::new(val);
+    let body = Box::new(Expr::VarRef { id: name.clone() });
+    let o = Expr::Let {
+        name,
+        ty,
+        val,
+        body,
+    };
     Ok((i, o))
 }
 
-pub fn data_type(i: &str) -> IResult<&str, DataType> {
-    let (i, _) = wstag("datatype")(i)?;
-    let (i, name) = ident(i)?;
-    create_data_type(name.clone());
-    let (i, _) = wstag("=")(i)?;
-    let (i, arms) = many0(data_type_arm)(i)?;
-    let (i, _) = wstag("end")(i)?;
-    let o = DataType { name, arms };
+/// A `let rec` without `in`, only valid at the top level of the REPL.
+/// The body of the returned `LetRec` refers back to the first function.
+pub fn repl_let2(i: Span) -> PResult<Expr> {
+    let (i, _) = pair(keyword("let"), keyword("rec"))(i)?;
+    let (i, arms) = separated_list1(keyword("and"), let2arm)(i)?;
+    let id = arms[0].fn_name.clone();
+    let body = Box::new(Expr::VarRef { id });
+    let o = Expr::LetRec { arms, body };
+    Ok((i, o))
+}
+
+/// An expression, recovering from syntax errors like `top`.
+pub fn repl_expr(i: Span) -> PResult<Expr> {
+    let (i, o) = recover("expression", expr)(i)?;
+    let (i, _) = expect("end of input", eof)(i)?;
     Ok((i, o))
 }
 
-pub fn create_data_type(name: String) {
-    DATA_TYPE_NAMES.lock().unwrap().insert(name);
+pub fn repl_binding(i: Span) -> PResult<Expr> {
+    alt((repl_let1, repl_let2))(i)
+}
+
+pub fn data_type_arm(i: Span) -> PResult<DataTypeArm> {
+    let (i, _) = wstag("|")(i)?;
+    let (i, ctor) = expect("constructor name", ident)(i)?;
+    let (i, arg_tys) = expect("constructor argument type", many1(ty))(i)?;
+    let ctor = ctor.unwrap_or_default();
+    let arg_tys = arg_tys.unwrap_or_default();
+    let o = DataTypeArm { ctor, arg_tys };
+    Ok((i, o))
 }
 
-pub fn is_data_type_name(name: &str) -> bool {
-    DATA_TYPE_NAMES.lock().unwrap().contains(name)
+pub fn data_type(i: Span) -> PResult<DataType> {
+    let (i, _) = keyword("datatype")(i)?;
+    let (i, name) = expect("datatype name", ident)(i)?;
+    let (i, _) = expect("`=` after datatype name", wstag("="))(i)?;
+    let (i, arms) = many0(data_type_arm)(i)?;
+    let (i, _) = expect("`end` after datatype arms", keyword("end"))(i)?;
+    let name = name.unwrap_or_default();
+    let o = DataType { name, arms };
+    Ok((i, o))
 }
@@ -1,17 +1,17 @@
 //! Parsing of types
-use super::{ops::*, top::is_data_type_name};
+use super::{ops::*, recover::diagnostic};
 use crate::ast::*;
 
 use nom::{
     branch::alt,
     combinator::{fail, map_opt},
+    error::VerboseError,
     multi::separated_list1,
-    sequence::delimited,
-    IResult,
+    sequence::{delimited, preceded},
 };
 
-pub fn ty_base(i: &str) -> IResult<&str, Ty> {
-    map_opt(identlike, |s: &str| match s {
+pub fn ty_base(i: Span) -> PResult<Ty> {
+    map_opt(identlike, |s: Span| match *s.fragment() {
         "bool" => Some(Ty::BoolTy),
         "int" => Some(Ty::IntTy),
         "unit" => Some(Ty::UnitTy),
@@ -19,40 +19,68 @@ pub fn ty_base(i: &str) -> IResult<&str, Ty> {
     })(i)
 }
 
-pub fn ty_paren(i: &str) -> IResult<&str, Ty> {
+pub fn ty_paren(i: Span) -> PResult<Ty> {
     delimited(wstag("("), ty, wstag(")"))(i)
 }
 
-pub fn ty_data_type(i: &str) -> IResult<&str, Ty> {
-    let (i, name) = ident(i)?;
-    if !is_data_type_name(&name) {
-        fail::<&str, Ty, nom::error::Error<&str>>(i)?;
-    }
+/// Any identifier. Whether it names a datatype is checked after parsing, see `State::ty_refs`.
+pub fn ty_data_type(i: Span) -> PResult<Ty> {
+    let (i, _) = ignored(i)?;
+    let (rest, name) = ident(i)?;
+    let msg = format!("undeclared type {name}");
+    let at = diagnostic(i, name.len(), msg);
+    i.extra.ty_refs.borrow_mut().push((name.clone(), at));
+    let i = rest;
     let o = Ty::DataTy(name);
     Ok((i, o))
 }
 
-pub fn ty_atom(i: &str) -> IResult<&str, Ty> {
+pub fn ty_atom(i: Span) -> PResult<Ty> {
     alt((ty_paren, ty_base, ty_data_type))(i)
 }
 
-pub fn ty_lam(i: &str) -> IResult<&str, Ty> {
-    let (i, o) = separated_list1(wstag("->"), ty_atom)(i)?;
-    if o.len() == 1 {
+/// `a -> b -> c` from `[a, b, c]`.
+fn arrows(tys: Vec<Ty>) -> Ty {
+    if tys.len() == 1 {
         // prevent redundant lam's
-        let o = o.into_iter().nth(0).unwrap();
-        Ok((i, o))
+        tys.into_iter().nth(0).unwrap()
     } else {
         // rev because -> is r-assoc
-        let o = o
-            .into_iter()
+        tys.into_iter()
             .rev()
             .reduce(|rhs, lhs| Ty::AbsTy(Box::new(lhs), Box::new(rhs)))
-            .unwrap();
-        Ok((i, o))
+            .unwrap()
+    }
+}
+
+pub fn ty_lam(i: Span) -> PResult<Ty> {
+    let (i, o) = separated_list1(wstag("->"), ty_atom)(i)?;
+    Ok((i, arrows(o)))
+}
+
+/// The type of a lambda argument, consuming the `->` of the lambda too.
+/// In `\x : int -> x - 5` the type is `int` even if `x` is a datatype,
+/// so this backtracks to the longest arrow type that is followed by another `->`.
+pub fn ty_arg(i: Span) -> PResult<Ty> {
+    let (mut rest, head) = ty_atom(i)?;
+    let mut tys = vec![(head, rest)];
+    while let Ok((r, ty)) = preceded(wstag("->"), ty_atom)(rest) {
+        tys.push((ty, r));
+        rest = r;
+    }
+    while let Some((_, after)) = tys.last() {
+        if let Ok((r, _)) = wstag::<VerboseError<Span>>("->")(*after) {
+            // forget the datatypes of the atoms backtracked over
+            let end = r.location_offset();
+            i.extra.ty_refs.borrow_mut().retain(|x| x.1.offset < end);
+            let tys = tys.into_iter().map(|x| x.0).collect();
+            return Ok((r, arrows(tys)));
+        }
+        tys.pop();
     }
+    fail(i)
 }
 
-pub fn ty(i: &str) -> IResult<&str, Ty> {
+pub fn ty(i: Span) -> PResult<Ty> {
     ty_lam(i)
 }
@@ -45,6 +45,8 @@ pub trait ExprListener {
     fn enter_match(&mut self, sub: &Expr, arms: &Vec<MatchArm>, eself: &Expr) {}
     fn exit_match(&mut self, sub: &Expr, arms: &Vec<MatchArm>, eself: &Expr) {}
 
+    fn walk_error(&mut self, eself: &Expr) {}
+
     fn enter_letrecarm(&mut self, arm: &LetRecArm) {}
     fn exit_letrecarm(&mut self, arm: &LetRecArm) {}
 
@@ -166,6 +168,7 @@ pub trait ExprListener {
             Ite { cond, tr, fl } => self.walk_ite(cond, tr, fl, e),
             LetRec { arms, body } => self.walk_letrec(arms, body, e),
             Match { sub, arms } => self.walk_match(sub, arms, e),
+            Error {} => self.walk_error(e),
         }
     }
 
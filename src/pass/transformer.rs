@@ -54,6 +54,10 @@ pub trait ExprTransformer<R> {
         self.visit_children(e)
     }
 
+    fn visit_match(&mut self, e: &mut Expr) -> R {
+        self.visit_children(e)
+    }
+
     fn visit_intlit(&mut self, e: &mut Expr) -> R {
         self.visit_children(e)
     }
@@ -166,6 +170,7 @@ pub trait ExprTransformer<R> {
             Nth { .. } => self.visit_nth(e),
             Ite { .. } => self.visit_ite(e),
             LetRec { .. } => self.visit_letrec(e),
+            Match { .. } => self.visit_match(e),
             _ => todo!(),
         }
     }
@@ -0,0 +1,437 @@
+//! Printing the AST back into MiniML source.
+//!
+//! Parentheses are only inserted where the grammar needs them,
+//! so that `parse(print_prog(prog)) == prog`.
+//! The exceptions are things the parser never produces:
+//! negative `IntLit`s and single-element `Tuple`s.
+
+use crate::ast::*;
+use crate::error::MiniMLErr;
+use crate::inspector::annot;
+use crate::parser::parse;
+
+/// Preferred maximum line width.
+const WIDTH: usize = 80;
+
+const INDENT: &str = "    ";
+
+/// Binding strength, from the loosest to the tightest.
+/// Mirrors the layering of the expression parsers (`lett`, `seq`, `ite`, ..., `atom`).
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Prec {
+    /// `let`, `let rec`, `match` and lambdas, which extend as far as possible.
+    Top,
+    Seq,
+    Ite,
+    Rel,
+    Add,
+    Mul,
+    Una,
+    App,
+    Atom,
+}
+
+fn binop_prec(op: BinOp) -> Prec {
+    use BinOp::*;
+    match op {
+        Gt | Lt | Ge | Le | Eq | Ne => Prec::Rel,
+        Add | Sub => Prec::Add,
+        Mul | Div | Rem => Prec::Mul,
+        // no syntax, so parenthesized like the loosest operators
+        Land | Lor | Lxor => Prec::Rel,
+    }
+}
+
+pub fn binop_print(op: BinOp) -> &'static str {
+    use BinOp::*;
+    match op {
+        Add => "+",
+        Sub => "-",
+        Mul => "*",
+        Div => "/",
+        Rem => "%",
+        Gt => ">",
+        Lt => "<",
+        Ge => ">=",
+        Le => "<=",
+        Eq => "==",
+        Ne => "!=",
+        // no syntax, and like `<error>` not valid MiniML
+        Land => "#land",
+        Lor => "#lor",
+        Lxor => "#lxor",
+    }
+}
+
+pub fn unaop_print(op: UnaOp) -> &'static str {
+    match op {
+        UnaOp::Neg => "-",
+        UnaOp::Lnot => "!",
+    }
+}
+
+fn prec(e: &Expr) -> Prec {
+    use Expr::*;
+    match e {
+        Let { .. } | LetRec { .. } | Match { .. } | Abs { .. } => Prec::Top,
+        Seq { .. } => Prec::Seq,
+        Ite { .. } => Prec::Ite,
+        Binary { op, .. } => binop_prec(*op),
+        Unary { .. } => Prec::Una,
+        App { .. } => Prec::App,
+        IntLit { val } if *val < 0 => Prec::Una,
+        IntLit { .. }
+        | UnitLit {}
+        | VarRef { .. }
+        | Builtin { .. }
+        | Tuple { .. }
+        | Nth { .. }
+        | Error {} => Prec::Atom,
+    }
+}
+
+fn is_flat(s: &str, ind: usize) -> bool {
+    !s.contains('\n') && ind * INDENT.len() + s.len() <= WIDTH
+}
+
+fn indent(ind: usize) -> String {
+    INDENT.repeat(ind)
+}
+
+/// Like `annot`, but for arguments of lambdas where the annotation is followed by `->`.
+fn arg_annot(ty: &Ty) -> String {
+    match ty {
+        Ty::AbsTy(..) => format!(": ({ty})"),
+        _ => annot(ty),
+    }
+}
+
+/// A type as an argument of a datatype constructor.
+fn ty_atom(ty: &Ty) -> String {
+    match ty {
+        Ty::AbsTy(..) => format!("({ty})"),
+        _ => format!("{ty}"),
+    }
+}
+
+/// Print `e` so that it parses back at the position of a `level` expression.
+/// The first line is not indented, following lines are indented for nesting level `ind`.
+fn expr_at(e: &Expr, level: Prec, ind: usize) -> String {
+    if prec(e) < level {
+        format!("({})", expr(e, ind))
+    } else {
+        expr(e, ind)
+    }
+}
+
+fn expr(e: &Expr, ind: usize) -> String {
+    use Expr::*;
+    let nl = format!("\n{}", indent(ind));
+    let nl1 = format!("\n{}", indent(ind + 1));
+    match e {
+        IntLit { val } => format!("{val}"),
+        UnitLit {} => "()".to_string(),
+        // not valid MiniML, so that it is never mistaken for a program
+        Error {} => "<error>".to_string(),
+        VarRef { id } => id.clone(),
+        Builtin { op } => builtin_print(op).to_string(),
+        Binary { lhs, op, rhs } => {
+            let p = binop_prec(*op);
+            let lhs = expr_at(lhs, p, ind);
+            // Left associative, so a right operand of the same level needs parentheses.
+            let rhs = match prec(rhs) {
+                q if q <= p => format!("({})", expr(rhs, ind)),
+                _ => expr(rhs, ind),
+            };
+            format!("{lhs} {} {rhs}", binop_print(*op))
+        }
+        Unary { op, sub } => {
+            let sub = expr_at(sub, Prec::Una, ind);
+            // Avoid `--`, which starts a comment.
+            if sub.starts_with('-') {
+                format!("{}({sub})", unaop_print(*op))
+            } else {
+                format!("{}{sub}", unaop_print(*op))
+            }
+        }
+        App { fun, arg } => {
+            let fun = expr_at(fun, Prec::App, ind);
+            let arg = expr_at(arg, Prec::Atom, ind);
+            format!("{fun} {arg}")
+        }
+        Nth { idx, sub } => format!("nth {idx} {}", expr_at(sub, Prec::Atom, ind)),
+        Tuple { subs } => {
+            let subs = subs
+                .iter()
+                .map(|x| expr_at(x, Prec::Top, ind + 1))
+                .collect::<Vec<_>>();
+            format!("({})", subs.join(", "))
+        }
+        Seq { subs } => {
+            let subs = subs
+                .iter()
+                .map(|x| expr_at(x, Prec::Ite, ind))
+                .collect::<Vec<_>>();
+            let flat = subs.join("; ");
+            if is_flat(&flat, ind) {
+                flat
+            } else {
+                subs.join(&format!(";{nl}"))
+            }
+        }
+        Ite { cond, tr, fl } => {
+            let cond = expr_at(cond, Prec::Rel, ind);
+            let tr = expr_at(tr, Prec::Rel, ind + 1);
+            let fl_flat = expr_at(fl, Prec::Ite, ind + 1);
+            let flat = format!("if {cond} then {tr} else {fl_flat}");
+            if is_flat(&flat, ind) {
+                return flat;
+            }
+            let fl = match **fl {
+                // else-if chains stay at the same level
+                Ite { .. } => format!(" {}", expr(fl, ind)),
+                _ => format!("{nl1}{fl_flat}"),
+            };
+            format!("if {cond} then{nl1}{tr}{nl}else{fl}")
+        }
+        Abs {
+            arg_name,
+            arg_ty,
+            body,
+        } => {
+            let head = format!("\\{arg_name}{} ->", arg_annot(arg_ty));
+            let flat = format!("{head} {}", expr(body, ind + 1));
+            if is_flat(&flat, ind) {
+                flat
+            } else if let Abs { .. } = **body {
+                // curried lambdas stay on one line
+                format!("{head} {}", expr(body, ind))
+            } else {
+                format!("{head}{nl1}{}", expr(body, ind + 1))
+            }
+        }
+        Let {
+            name,
+            ty,
+            val,
+            body,
+        } => {
+            let head = format!("let {name}{} =", annot(ty));
+            let body = expr(body, ind);
+            let flat = format!("{head} {} in", expr(val, ind));
+            if is_flat(&flat, ind) {
+                format!("{flat}{nl}{body}")
+            } else if let Abs { .. } = **val {
+                // the lambda head stays on the let line, its body is indented
+                format!("{head} {}{nl}in{nl}{body}", expr(val, ind))
+            } else {
+                format!("{head}{nl1}{}{nl}in{nl}{body}", expr(val, ind + 1))
+            }
+        }
+        LetRec { arms, body } => {
+            let arms = arms
+                .iter()
+                .enumerate()
+                .map(|(i, arm)| {
+                    let kw = if i == 0 { "let rec" } else { "and" };
+                    let head = format!(
+                        "{kw} {}{} = \\{}{} ->",
+                        arm.fn_name,
+                        annot(&arm.fn_ty),
+                        arm.arg_name,
+                        arg_annot(&arm.arg_ty)
+                    );
+                    let flat = format!("{head} {}", expr(&arm.body, ind + 1));
+                    if is_flat(&flat, ind) {
+                        flat
+                    } else if let Abs { .. } = *arm.body {
+                        format!("{head} {}", expr(&arm.body, ind))
+                    } else {
+                        format!("{head}{nl1}{}", expr(&arm.body, ind + 1))
+                    }
+                })
+                .collect::<Vec<_>>();
+            let body = expr(body, ind);
+            format!("{}{nl}in{nl}{body}", arms.join(&nl))
+        }
+        Match { sub, arms } => {
+            let sub = expr(sub, ind + 1);
+            let arms = arms.iter().map(|arm| {
+                let head = format!("| {} ->", ptn(&arm.ptn));
+                let res = expr(&arm.res, ind + 1);
+                let flat = format!("{head} {res}");
+                if is_flat(&flat, ind) {
+                    format!("{nl}{flat}")
+                } else {
+                    format!("{nl}{head}{nl1}{res}")
+                }
+            });
+            format!("match {sub}{}{nl}end", arms.collect::<String>())
+        }
+    }
+}
+
+/// A pattern in the position of a match arm.
+fn ptn(p: &MatchPattern) -> String {
+    match p {
+        MatchPattern::Tuple { subs } => {
+            let subs = subs.iter().map(ptn1).collect::<Vec<_>>();
+            subs.join(", ")
+        }
+        MatchPattern::DataType { ctor, subs } => {
+            let subs = subs.iter().map(|x| format!(" {}", ptn1(x)));
+            format!("{ctor}{}", subs.collect::<String>())
+        }
+        _ => ptn1(p),
+    }
+}
+
+/// A pattern in the position of a tuple element or a constructor argument.
+fn ptn1(p: &MatchPattern) -> String {
+    match p {
+        MatchPattern::Binder { name } => name.clone(),
+        MatchPattern::Lit { val } => expr(val, 0),
+        MatchPattern::Tuple { .. } | MatchPattern::DataType { .. } => format!("({})", ptn(p)),
+    }
+}
+
+pub fn print_data_type(dt: &DataType) -> String {
+    let mut s = format!("datatype {} =\n", dt.name);
+    for arm in dt.arms.iter() {
+        let tys = arm.arg_tys.iter().map(|x| format!(" {}", ty_atom(x)));
+        s.push_str(&format!("| {}{}\n", arm.ctor, tys.collect::<String>()));
+    }
+    s.push_str("end\n");
+    s
+}
+
+pub fn print_expr(e: &Expr) -> String {
+    expr(e, 0)
+}
+
+pub fn print_prog(prog: &Prog) -> String {
+    let mut s = String::new();
+    for dt in prog.data_types.iter() {
+        s.push_str(&print_data_type(dt));
+        s.push('\n');
+    }
+    s.push_str(&print_expr(&prog.main_expr));
+    s.push('\n');
+    s
+}
+
+/// A token that survives printing, i.e. anything but parentheses, whitespace and comments.
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+}
+
+/// A comment and whether it has code before it on its line.
+struct Comment<'a> {
+    text: &'a str,
+    trailing: bool,
+    /// Number of tokens before the comment.
+    ntokens: usize,
+}
+
+fn tokenize(src: &str) -> (Vec<Token<'_>>, Vec<Comment<'_>>) {
+    let mut tokens = Vec::new();
+    let mut comments = Vec::new();
+    for (line, text) in src.lines().enumerate() {
+        let mut rest = text;
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                break;
+            }
+            if rest.starts_with("--") {
+                comments.push(Comment {
+                    text: rest.trim_end(),
+                    trailing: rest.len() != text.trim_start().len(),
+                    ntokens: tokens.len(),
+                });
+                break;
+            }
+            let c = rest.chars().next().unwrap();
+            let len = if c.is_ascii_alphanumeric() || c == '_' {
+                rest.find(|x: char| !(x.is_ascii_alphanumeric() || x == '_'))
+                    .unwrap_or(rest.len())
+            } else if ["->", "==", "!=", ">=", "<="]
+                .iter()
+                .any(|x| rest.starts_with(x))
+            {
+                2
+            } else {
+                c.len_utf8()
+            };
+            if c != '(' && c != ')' {
+                tokens.push(Token {
+                    text: &rest[..len],
+                    line,
+                });
+            }
+            rest = &rest[len..];
+        }
+    }
+    (tokens, comments)
+}
+
+/// Put the comments of `src` back into `printed`, the printed AST of `src`.
+/// A comment on its own line goes before the line of the token that followed it,
+/// a trailing comment to the end of the line of the token that preceded it.
+fn restore_comments(src: &str, printed: &str) -> Result<String, MiniMLErr> {
+    let (src_tokens, comments) = tokenize(src);
+    let (tokens, _) = tokenize(printed);
+    let same = src_tokens.len() == tokens.len()
+        && src_tokens.iter().zip(tokens.iter()).all(|(x, y)| {
+            let is_int = |t: &str| t.chars().all(|c| c.is_ascii_digit());
+            x.text == y.text || (is_int(x.text) && is_int(y.text))
+        });
+    if !same {
+        return Err(MiniMLErr::FormatError(
+            "cannot place comments: tokens changed while printing".to_string(),
+        ));
+    }
+
+    let lines = printed.lines().collect::<Vec<_>>();
+    let mut before = vec![Vec::new(); lines.len() + 1];
+    let mut after = vec![Vec::new(); lines.len()];
+    for c in comments.iter() {
+        if c.trailing && c.ntokens > 0 {
+            after[tokens[c.ntokens - 1].line].push(c.text);
+        } else if let Some(t) = tokens.get(c.ntokens) {
+            before[t.line].push(c.text);
+        } else {
+            before[lines.len()].push(c.text);
+        }
+    }
+
+    let mut out = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        let ind = &line[..line.len() - line.trim_start().len()];
+        for c in before[i].iter() {
+            out.push_str(&format!("{ind}{c}\n"));
+        }
+        out.push_str(line);
+        for c in after[i].iter() {
+            out.push_str(&format!(" {c}"));
+        }
+        out.push('\n');
+    }
+    for c in before[lines.len()].iter() {
+        out.push_str(&format!("{c}\n"));
+    }
+    Ok(out)
+}
+
+/// Reformat MiniML source, keeping its comments.
+pub fn format_source(src: &str) -> Result<String, MiniMLErr> {
+    let prog = parse(src)?;
+    let printed = print_prog(&prog);
+    if parse(&printed)? != prog {
+        return Err(MiniMLErr::FormatError(
+            "printed program parses differently".to_string(),
+        ));
+    }
+    restore_comments(src, &printed)
+}
@@ -0,0 +1,272 @@
+//! Interactive read-eval-print loop.
+//!
+//! Every input is compiled on its own and loaded into one persistent `SECDMachine`.
+//! Top-level bindings (`let` or `let rec` without `in`) are left on the machine env,
+//! and the namer and code generator scopes of later inputs are seeded with them.
+
+use std::fs;
+
+use crate::{
+    ast::{Expr, ReplInput},
+    namer::Namer,
+    parser::{parse, parse_repl},
+    pass::ExprTransformer,
+    secd::{
+        langdef::{SECDInstr, SECDVal},
+        machine::{SECDMachine, SECDState},
+        secdgen::SECDGen,
+    },
+};
+
+/// Renamed names of a top-level binding, mirroring the env entry it occupies.
+enum Global {
+    Var(String),
+    Rec(Vec<String>),
+}
+
+pub struct Repl {
+    namer: Namer,
+    /// From the oldest to the latest binding, i.e. from bottom to top of the env.
+    globals: Vec<Global>,
+    /// Names of the datatypes declared by loaded files.
+    data_types: Vec<String>,
+    machine: SECDMachine,
+    ninputs: usize,
+}
+
+pub const HELP: &str = "\
+<expr>                 evaluate an expression
+let x = <expr>         bind x for later inputs (also `let rec f = ... and g = ...`)
+:type <expr>           show the type of an expression
+:secd <expr>           show the SECD code of an expression
+:ast <expr>            show the parsed AST of an input
+:load <file.ml>        evaluate a file, keeping its top-level lets
+:help                  show this message
+:quit                  exit";
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self {
+            namer: Namer::new(),
+            globals: Vec::new(),
+            data_types: Vec::new(),
+            machine: SECDMachine::new(),
+            ninputs: 0,
+        }
+    }
+
+    /// Handle one line of input, returning what should be printed.
+    /// `None` means the user asked to quit.
+    pub fn handle(&mut self, line: &str) -> Option<Result<String, String>> {
+        let line = line.trim();
+        let (cmd, arg) = match line.split_once(char::is_whitespace) {
+            Some((cmd, arg)) => (cmd, arg.trim()),
+            None => (line, ""),
+        };
+        let res = match cmd {
+            "" => Ok(String::new()),
+            ":q" | ":quit" => return None,
+            ":h" | ":help" => Ok(HELP.to_string()),
+            ":t" | ":type" => {
+                Err("types are not available: MiniML-rs has no type checker yet".to_string())
+            }
+            ":secd" => self.show_secd(arg),
+            ":ast" => parse_repl(arg, &self.data_types)
+                .map(|x| format!("{x:#?}"))
+                .map_err(|x| x.to_string()),
+            ":load" => self.load(arg),
+            _ if cmd.starts_with(':') => Err(format!("unknown command {cmd}, try :help")),
+            _ => parse_repl(line, &self.data_types)
+                .map_err(|x| x.to_string())
+                .and_then(|x| self.eval(x)),
+        };
+        Some(res)
+    }
+
+    fn eval(&mut self, input: ReplInput) -> Result<String, String> {
+        match input {
+            ReplInput::Binding(e) => self.bind(e),
+            ReplInput::Expr(mut e) => {
+                let (instrs, entry) = self.compile(&mut e)?;
+                let (effects, val) = self.run(instrs, &entry, false)?;
+                Ok(format!("{effects}- = {}", show_val(&val)))
+            }
+        }
+    }
+
+    /// Evaluate a `Let` or `LetRec` whose body refers back to its bound name.
+    fn bind(&mut self, mut e: Expr) -> Result<String, String> {
+        let olds = bound_names(&e);
+        let (instrs, entry) = self.compile(&mut e)?;
+        let (effects, val) = self.run(instrs, &entry, true)?;
+        let news = bound_names(&e);
+        for (old, new) in olds.iter().zip(news.iter()) {
+            self.namer.define_global(old, new);
+        }
+        let res = match e {
+            Expr::Let { .. } => {
+                self.globals.push(Global::Var(news[0].clone()));
+                format!("{effects}val {} = {}", olds[0], show_val(&val))
+            }
+            Expr::LetRec { .. } => {
+                self.globals.push(Global::Rec(news));
+                let fns = olds
+                    .iter()
+                    .map(|x| format!("val {x} = <fun>"))
+                    .collect::<Vec<_>>();
+                format!("{effects}{}", fns.join("\n"))
+            }
+            _ => unreachable!(),
+        };
+        Ok(res)
+    }
+
+    /// Returns the code and its entry label.
+    fn compile(&mut self, e: &mut Expr) -> Result<(Vec<SECDInstr>, String), String> {
+        let scope_len = self.namer.scope_len();
+        if let Err(err) = self.namer.visit(e) {
+            self.namer.truncate_scope(scope_len);
+            return Err(err.to_string());
+        }
+
+        let prefix = format!("in{}_", self.ninputs);
+        self.ninputs += 1;
+        let mut secdgen = SECDGen::new().with_label_prefix(&prefix);
+        for g in self.globals.iter() {
+            match g {
+                Global::Var(id) => secdgen.define_var(id),
+                Global::Rec(ids) => secdgen.define_rec(ids),
+            }
+        }
+        secdgen.visit_main_expr(e);
+        Ok((secdgen.program(), secdgen.main_label()))
+    }
+
+    /// Run until halt. Returns the printed effects and the value left on the stack.
+    /// With `keep_binding`, the top of the env (the newly bound value) is kept.
+    fn run(
+        &mut self,
+        instrs: Vec<SECDInstr>,
+        entry: &str,
+        keep_binding: bool,
+    ) -> Result<(String, SECDVal), String> {
+        let env_len = self.machine.state.2.len();
+        self.machine
+            .load(instrs, entry)
+            .map_err(|err| format!("runtime error: {err}"))?;
+        let res = loop {
+            if self.machine.halted() {
+                break Ok(());
+            }
+            if let Err(err) = self.machine.step() {
+                break Err(err);
+            }
+        };
+
+        let mut effects = String::new();
+        for eff in self.machine.sink.effects.drain(..) {
+            effects.push_str(&eff.text());
+        }
+
+        let SECDState(_pc, stk, env) = &mut self.machine.state;
+        let val = stk.pop();
+        stk.clear();
+        // Lets never pop the env, so whatever they pushed is dropped here.
+        let binding = if env.len() > env_len { env.pop() } else { None };
+        env.truncate(env_len);
+        match res {
+            Ok(()) => {
+                if keep_binding {
+                    env.push(binding.unwrap());
+                }
+                Ok((effects, val.unwrap()))
+            }
+            Err(err) => Err(format!("{effects}runtime error: {err}")),
+        }
+    }
+
+    fn show_secd(&mut self, arg: &str) -> Result<String, String> {
+        let mut e = match parse_repl(arg, &self.data_types).map_err(|x| x.to_string())? {
+            ReplInput::Binding(e) => e,
+            ReplInput::Expr(e) => e,
+        };
+        let (instrs, _) = self.compile(&mut e)?;
+        let lines = instrs
+            .iter()
+            .map(|x| match x {
+                SECDInstr::Label(_) => format!("{x}"),
+                _ => format!("    {x}"),
+            })
+            .collect::<Vec<_>>();
+        Ok(lines.join("\n"))
+    }
+
+    /// Evaluate a file, binding its leading `let`s and `let rec`s as if entered one by one.
+    fn load(&mut self, path: &str) -> Result<String, String> {
+        let buf = fs::read_to_string(path).map_err(|x| format!("cannot read {path}: {x}"))?;
+        let prog = parse(&buf).map_err(|x| x.to_string())?;
+        self.data_types
+            .extend(prog.data_types.iter().map(|x| x.name.clone()));
+        let mut outs = Vec::new();
+        let mut e = prog.main_expr;
+        loop {
+            e = match e {
+                Expr::Let {
+                    name,
+                    ty,
+                    val,
+                    body,
+                } => {
+                    let id = name.clone();
+                    let binding = Expr::Let {
+                        name,
+                        ty,
+                        val,
+                        body: Box::new(Expr::VarRef { id }),
+                    };
+                    outs.push(self.bind(binding)?);
+                    *body
+                }
+                Expr::LetRec { arms, body } => {
+                    let id = arms[0].fn_name.clone();
+                    let binding = Expr::LetRec {
+                        arms,
+                        body: Box::new(Expr::VarRef { id }),
+                    };
+                    outs.push(self.bind(binding)?);
+                    *body
+                }
+                e => {
+                    outs.push(self.eval(ReplInput::Expr(e))?);
+                    break;
+                }
+            }
+        }
+        Ok(outs.join("\n"))
+    }
+}
+
+fn bound_names(e: &Expr) -> Vec<String> {
+    match e {
+        Expr::Let { name, .. } => vec![name.clone()],
+        Expr::LetRec { arms, .. } => arms.iter().map(|x| x.fn_name.clone()).collect(),
+        _ => unreachable!(),
+    }
+}
+
+fn show_val(v: &SECDVal) -> String {
+    match v {
+        SECDVal::ClosureVal { .. } => "<fun>".to_string(),
+        SECDVal::TupleVal(vs) => {
+            let vs = vs.iter().map(show_val).collect::<Vec<_>>();
+            format!("({})", vs.join(", "))
+        }
+        _ => format!("{v}"),
+    }
+}
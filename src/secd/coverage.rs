@@ -0,0 +1,156 @@
+//! Source coverage, from the instructions a run executed and the source map.
+//!
+//! A line is covered if an instruction located on it ran.
+//! A branch, i.e. an arm of `if` or `match`, is taken if an instruction located in it ran.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::{
+    ast::{Expr, MatchArm, Prog},
+    pass::ExprListener,
+    spans::{ExprSpans, SpanInfo, SrcSpan},
+};
+
+use super::srcmap::SourceMap;
+
+/// The arms of one `if` or `match`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BranchCoverage {
+    /// Where the `if` or `match` starts.
+    pub line: u32,
+    /// For each arm, in source order.
+    pub taken: Vec<bool>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct SrcCoverage {
+    /// Lines that have code, and whether any of it ran.
+    pub lines: BTreeMap<u32, bool>,
+    pub branches: Vec<BranchCoverage>,
+}
+
+/// Collects the spans of the arms of every `if` and `match`.
+struct Arms<'a> {
+    spans: &'a SpanInfo,
+    arms: Vec<(u32, Vec<SrcSpan>)>,
+}
+
+impl Arms<'_> {
+    fn add<'e>(&mut self, eself: &Expr, arms: impl Iterator<Item = &'e Expr>) {
+        if let Some(span) = self.spans.get(eself) {
+            let arms = arms.filter_map(|x| self.spans.get(x).copied()).collect();
+            self.arms.push((span.line, arms));
+        }
+    }
+}
+
+impl ExprListener for Arms<'_> {
+    fn enter_ite(&mut self, _cond: &Expr, tr: &Expr, fl: &Expr, eself: &Expr) {
+        self.add(eself, [tr, fl].into_iter());
+    }
+
+    fn enter_match(&mut self, _sub: &Expr, arms: &Vec<MatchArm>, eself: &Expr) {
+        self.add(eself, arms.iter().map(|x| &x.res));
+    }
+}
+
+impl SrcCoverage {
+    /// Coverage of `prog`, parsed with `spans`, given which instructions ran by pc.
+    /// `None` if the spans do not line up with `prog`.
+    pub fn new(prog: &Prog, spans: &ExprSpans, map: &SourceMap, hits: &[bool]) -> Option<Self> {
+        let spans = spans.info(prog)?;
+        let mut res = SrcCoverage::default();
+        let mut ran = Vec::new();
+        for pc in 0..map.instr_locs.len() {
+            if let Some(loc) = map.loc(pc) {
+                let hit = hits.get(pc).copied().unwrap_or(false);
+                *res.lines.entry(loc.span.line).or_insert(false) |= hit;
+                if hit {
+                    ran.push(loc.span);
+                }
+            }
+        }
+        let mut arms = Arms {
+            spans: &spans,
+            arms: Vec::new(),
+        };
+        arms.walk(&prog.main_expr);
+        res.branches = arms
+            .arms
+            .into_iter()
+            .map(|(line, arms)| BranchCoverage {
+                line,
+                taken: arms
+                    .iter()
+                    .map(|arm| ran.iter().any(|x| arm.contains(x)))
+                    .collect(),
+            })
+            .collect();
+        Some(res)
+    }
+
+    /// As an lcov tracefile for the source at `path`.
+    pub fn lcov(&self, path: &str) -> String {
+        let mut s = String::new();
+        writeln!(s, "TN:").unwrap();
+        writeln!(s, "SF:{path}").unwrap();
+        let mut nbranches = 0;
+        let mut nbranches_hit = 0;
+        for (block, branch) in self.branches.iter().enumerate() {
+            let reached = branch.taken.iter().any(|x| *x);
+            for (i, taken) in branch.taken.iter().enumerate() {
+                let taken = match (reached, taken) {
+                    (false, _) => "-",
+                    (true, false) => "0",
+                    (true, true) => "1",
+                };
+                writeln!(s, "BRDA:{},{block},{i},{taken}", branch.line).unwrap();
+            }
+            nbranches += branch.taken.len();
+            nbranches_hit += branch.taken.iter().filter(|x| **x).count();
+        }
+        writeln!(s, "BRF:{nbranches}").unwrap();
+        writeln!(s, "BRH:{nbranches_hit}").unwrap();
+        for (line, hit) in self.lines.iter() {
+            writeln!(s, "DA:{line},{}", *hit as u8).unwrap();
+        }
+        writeln!(s, "LF:{}", self.lines.len()).unwrap();
+        writeln!(s, "LH:{}", self.lines.values().filter(|x| **x).count()).unwrap();
+        writeln!(s, "end_of_record").unwrap();
+        s
+    }
+
+    /// `src` with every line marked `-` if it has no code, `#####` if none of it ran,
+    /// and `1` otherwise, followed by the arms that were not taken.
+    pub fn annotate(&self, src: &str) -> String {
+        let mut s = String::new();
+        for (i, text) in src.lines().enumerate() {
+            let line = i as u32 + 1;
+            let mark = match self.lines.get(&line) {
+                None => "-",
+                Some(false) => "#####",
+                Some(true) => "1",
+            };
+            writeln!(s, "{mark:>9}: {line:>4}: {text}").unwrap();
+            for branch in self.branches.iter().filter(|x| x.line == line) {
+                for (arm, taken) in branch.taken.iter().enumerate() {
+                    if !taken {
+                        writeln!(s, "{:>17}arm {arm} never taken", "").unwrap();
+                    }
+                }
+            }
+        }
+        let lh = self.lines.values().filter(|x| **x).count();
+        let bh = self
+            .branches
+            .iter()
+            .flat_map(|x| x.taken.iter())
+            .filter(|x| **x);
+        let nbranches: usize = self.branches.iter().map(|x| x.taken.len()).sum();
+        writeln!(s).unwrap();
+        writeln!(s, "lines:    {lh}/{}", self.lines.len()).unwrap();
+        writeln!(s, "branches: {}/{nbranches}", bh.count()).unwrap();
+        s
+    }
+}
@@ -0,0 +1,265 @@
+//! Generating SECD code from CPS, so that it runs without a dump.
+//!
+//! Every call is a `tailapply`, which jumps into the callee and is never returned to,
+//! and the stack is empty between terms. A function is entered with its argument on the env
+//! and its continuation on the stack, which it pushes to the env as well. A continuation is a
+//! closure of one argument, unless it is only thrown to: then it is a join point, reached by
+//! a branch after dropping the env entries pushed since it was bound.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::{
+    ast::Expr,
+    ir::{
+        anf,
+        cps::{self, Fun, Prim, Term, Value, HALT},
+    },
+};
+
+use super::{
+    langdef::{BrOp, SECDInstr, SECDVal},
+    repr::{translate_binop, translate_builtinop, translate_unaop},
+    secdgen::access,
+};
+
+/// * `label_instrs`: maps function name to its instructions, ordered so that code is reproducible.
+/// * `scope`: names of the env entries at the generated instruction, from the bottom.
+/// * `recs`: names bound by a `let rec`, whose env entry holds all closures of the bundle.
+/// * `joins`: labels of the continuations compiled as join points, with the length of
+///   the scope at each.
+#[derive(Default)]
+pub struct CPSGen {
+    label_instrs: BTreeMap<String, Vec<SECDInstr>>,
+    label_suffix: HashMap<String, usize>,
+    scope: Vec<String>,
+    recs: HashSet<String>,
+    joins: HashMap<String, (String, usize)>,
+}
+
+impl CPSGen {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn new_label(&mut self, prefix: &str) -> String {
+        let suffix = self.label_suffix.get(prefix).unwrap_or(&0);
+        let res = format!("{prefix}{suffix}");
+        self.label_suffix.insert(prefix.to_string(), suffix + 1);
+        res
+    }
+
+    pub fn gen_main(&mut self, main: &Term) {
+        let instrs = self.term(main);
+        self.label_instrs.insert("main".to_string(), instrs);
+    }
+
+    /// The generated functions laid out one after another, each headed by its label.
+    pub fn program(&self) -> Vec<SECDInstr> {
+        let mut instrs = Vec::new();
+        for (fnlabel, fninstrs) in self.label_instrs.iter() {
+            instrs.push(SECDInstr::Label(fnlabel.clone()));
+            instrs.extend(fninstrs.iter().cloned());
+        }
+        instrs
+    }
+
+    pub fn assemble(&self) -> String {
+        let mut lines = Vec::new();
+        for (fnlabel, fninstrs) in self.label_instrs.iter() {
+            lines.push(format!("{fnlabel}:"));
+            lines.extend(fninstrs.iter().map(|x| x.to_string()));
+            lines.push("\n".to_string());
+        }
+        lines.join("\n")
+    }
+
+    fn value(&self, v: &Value) -> Vec<SECDInstr> {
+        match v {
+            // todo: isize vs i64
+            Value::Int(val) => vec![SECDInstr::Const(SECDVal::IntVal(*val as isize))],
+            Value::Unit => vec![SECDInstr::Const(SECDVal::UnitVal)],
+            Value::Builtin(op) => vec![SECDInstr::Builtin(translate_builtinop(op))],
+            Value::Var(x) => access(&self.scope, &self.recs, x),
+        }
+    }
+
+    /// The closure of the continuation `k`.
+    fn cont(&mut self, k: &str) -> Vec<SECDInstr> {
+        if k != HALT {
+            return access(&self.scope, &self.recs, k);
+        }
+        self.label_instrs
+            .entry(HALT.to_string())
+            .or_insert_with(|| vec![SECDInstr::Access(1), SECDInstr::Halt]);
+        vec![SECDInstr::Closure(HALT.to_string())]
+    }
+
+    fn prim(&self, p: &Prim) -> Vec<SECDInstr> {
+        match p {
+            Prim::Value(v) => self.value(v),
+            Prim::Binary(op, lhs, rhs) => [
+                self.value(lhs),
+                self.value(rhs),
+                vec![SECDInstr::Binary(translate_binop(*op))],
+            ]
+            .concat(),
+            Prim::Unary(op, sub) => [
+                self.value(sub),
+                vec![SECDInstr::Unary(translate_unaop(*op))],
+            ]
+            .concat(),
+            Prim::Tuple(subs) => {
+                let mut code = subs.iter().flat_map(|x| self.value(x)).collect::<Vec<_>>();
+                code.push(SECDInstr::Tuple(subs.len()));
+                code
+            }
+            Prim::Nth(idx, sub) => [self.value(sub), vec![SECDInstr::Nth(*idx as usize)]].concat(),
+            // builtins return right away, without touching the dump
+            Prim::Builtin(op, arg) => [
+                vec![SECDInstr::Builtin(translate_builtinop(op))],
+                self.value(arg),
+                vec![SECDInstr::Apply],
+            ]
+            .concat(),
+        }
+    }
+
+    /// Generate a function at `label`, whose closure captures the current scope.
+    fn function(&mut self, label: &str, fun: &Fun) {
+        let scope_len = self.scope.len();
+        self.scope.push(fun.param.clone());
+        self.scope.push(fun.cont.clone());
+        let joins = std::mem::take(&mut self.joins);
+        let mut instrs = vec![SECDInstr::PushEnv];
+        instrs.extend(self.term(&fun.body));
+        self.joins = joins;
+        self.scope.truncate(scope_len);
+        self.label_instrs.insert(label.to_string(), instrs);
+    }
+
+    fn term(&mut self, t: &Term) -> Vec<SECDInstr> {
+        match t {
+            Term::LetVal { name, val, body } => {
+                let mut code = self.prim(val);
+                if name == "_" {
+                    code.push(SECDInstr::Pop(1));
+                } else {
+                    code.push(SECDInstr::PushEnv);
+                    self.scope.push(name.clone());
+                }
+                code.extend(self.term(body));
+                code
+            }
+            Term::LetFun { fun, body } => {
+                let label = self.new_label("lam");
+                self.function(&label, fun);
+                let mut code = vec![SECDInstr::Closure(label), SECDInstr::PushEnv];
+                self.scope.push(fun.name.clone());
+                code.extend(self.term(body));
+                code
+            }
+            Term::LetRec { fns, body } => {
+                let names = fns.iter().map(|x| x.name.clone()).collect::<Vec<_>>();
+                self.scope.push(names.join(","));
+                self.recs.extend(names);
+                let labels = fns
+                    .iter()
+                    .map(|x| {
+                        let label = self.new_label("clos");
+                        self.function(&label, x);
+                        label
+                    })
+                    .collect();
+                let mut code = vec![SECDInstr::Closures(labels)];
+                code.extend(self.term(body));
+                code
+            }
+            Term::LetCont {
+                name,
+                param,
+                cont_body,
+                body,
+            } if body.passes(name) => {
+                let label = self.new_label("cont");
+                let scope_len = self.scope.len();
+                self.scope.push(param.clone());
+                let instrs = self.term(cont_body);
+                self.scope.truncate(scope_len);
+                self.label_instrs.insert(label.clone(), instrs);
+                let mut code = vec![SECDInstr::Closure(label), SECDInstr::PushEnv];
+                self.scope.push(name.clone());
+                code.extend(self.term(body));
+                code
+            }
+            Term::LetCont {
+                name,
+                param,
+                cont_body,
+                body,
+            } => {
+                let label = self.new_label("join");
+                let scope_len = self.scope.len();
+                self.joins
+                    .insert(name.clone(), (label.clone(), scope_len));
+                let mut code = self.term(body);
+                self.joins.remove(name);
+                self.scope.truncate(scope_len);
+                if matches!(code.last(), Some(SECDInstr::Branch(BrOp::Br, l)) if *l == label) {
+                    code.pop();
+                }
+                code.extend([SECDInstr::Label(label), SECDInstr::PushEnv]);
+                self.scope.push(param.clone());
+                code.extend(self.term(cont_body));
+                code
+            }
+            Term::App { fun, arg, cont } => [
+                self.cont(cont),
+                self.value(fun),
+                self.value(arg),
+                // a builtin leaves its result, which the second one throws to `cont`
+                vec![SECDInstr::TailApply, SECDInstr::TailApply],
+            ]
+            .concat(),
+            Term::Throw { cont, arg } if cont == HALT => {
+                [self.value(arg), vec![SECDInstr::Halt]].concat()
+            }
+            Term::Throw { cont, arg } if let Some((label, scope_len)) = self.joins.get(cont) => {
+                let mut code = self.value(arg);
+                let n = self.scope.len() - scope_len;
+                if n > 0 {
+                    code.push(SECDInstr::PopEnv(n));
+                }
+                code.push(SECDInstr::Branch(BrOp::Br, label.clone()));
+                code
+            }
+            Term::Throw { cont, arg } => [
+                self.cont(cont),
+                self.value(arg),
+                vec![SECDInstr::TailApply],
+            ]
+            .concat(),
+            Term::If { cond, tr, fl } => {
+                let (l1, l2) = (self.new_label("tr"), self.new_label("fl"));
+                let mut code = self.value(cond);
+                code.extend([
+                    SECDInstr::Branch(BrOp::BrFalse, l2.clone()),
+                    SECDInstr::Label(l1),
+                ]);
+                let scope_len = self.scope.len();
+                code.extend(self.term(tr));
+                self.scope.truncate(scope_len);
+                code.push(SECDInstr::Label(l2));
+                code.extend(self.term(fl));
+                code
+            }
+        }
+    }
+}
+
+/// Like `secdgen_program`, but through CPS.
+pub fn cpsgen_program(main_expr: &Expr) -> Vec<SECDInstr> {
+    let main = cps::convert(&anf::lower(main_expr, None));
+    let mut cpsgen = CPSGen::new();
+    cpsgen.gen_main(&main);
+    cpsgen.program()
+}
@@ -0,0 +1,79 @@
+//! Where the machine sends output and gets input from.
+
+use std::io::{stdin, stdout, BufRead, Write};
+
+use super::machine::SECDEffect;
+
+pub trait EffectSink {
+    fn emit(&mut self, effect: SECDEffect);
+
+    /// A line of input without its newline, or `None` at the end of input.
+    fn read_line(&mut self) -> Option<String>;
+}
+
+/// Prints right away and reads stdin.
+pub struct Stdout;
+
+impl EffectSink for Stdout {
+    fn emit(&mut self, effect: SECDEffect) {
+        match effect {
+            SECDEffect::Println(s) => println!("{s}"),
+            SECDEffect::Print(s) => {
+                print!("{s}");
+                stdout().flush().unwrap();
+            }
+        }
+    }
+
+    fn read_line(&mut self) -> Option<String> {
+        stdin().lock().lines().next()?.ok()
+    }
+}
+
+/// Keeps the output, and reads canned input.
+#[derive(Debug, Default)]
+pub struct Capture {
+    pub effects: Vec<SECDEffect>,
+    pub input: Vec<String>,
+    /// Lines of `input` read so far.
+    pub read: usize,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Input is read line by line from `input`.
+    pub fn with_input(input: &str) -> Self {
+        Self {
+            input: input.lines().map(|x| x.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl EffectSink for Capture {
+    fn emit(&mut self, effect: SECDEffect) {
+        self.effects.push(effect);
+    }
+
+    fn read_line(&mut self) -> Option<String> {
+        let line = self.input.get(self.read)?.clone();
+        self.read += 1;
+        Some(line)
+    }
+}
+
+/// Hands the output to a function. There is no input.
+pub struct Callback<F: FnMut(SECDEffect)>(pub F);
+
+impl<F: FnMut(SECDEffect)> EffectSink for Callback<F> {
+    fn emit(&mut self, effect: SECDEffect) {
+        (self.0)(effect)
+    }
+
+    fn read_line(&mut self) -> Option<String> {
+        None
+    }
+}
@@ -0,0 +1,40 @@
+//! Builtins provided by the application embedding the machine.
+//!
+//! The compiler resolves a free variable to a host function if one of that name is registered,
+//! see `Namer::define_host`, and the machine calls it when the resulting `BuiltinVal` is applied.
+
+use std::collections::HashMap;
+
+use super::langdef::SECDVal;
+
+/// Takes the elements of the argument if it is a tuple, otherwise the argument alone.
+/// An `Err` stops the machine with a fault.
+pub type HostFn = Box<dyn Fn(&[SECDVal]) -> Result<SECDVal, String>>;
+
+#[derive(Default)]
+pub struct HostFunctions {
+    fns: HashMap<String, HostFn>,
+}
+
+impl HostFunctions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces any function of the same name.
+    pub fn register(
+        &mut self,
+        name: &str,
+        f: impl Fn(&[SECDVal]) -> Result<SECDVal, String> + 'static,
+    ) {
+        self.fns.insert(name.to_string(), Box::new(f));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&HostFn> {
+        self.fns.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.fns.keys().map(|x| x.as_str())
+    }
+}
@@ -1,15 +1,20 @@
 //! SECD language syntax definition.
 
+use std::rc::Rc;
+
 #[derive(Debug, Clone)]
 pub enum SECDVal {
     IntVal(isize),
     UnitVal,
+    /// Only made by `read_line`, as there are no string literals.
+    StrVal(String),
     TupleVal(Vec<SECDVal>),
     ClosureVal {
         // Functions are represented with pc.
         focused_fn: Option<usize>,
         mutrec_fns: Vec<usize>,
-        env: Vec<SECDVal>, // TODO: optimize this
+        /// Shared, as closures capture each other's envs, e.g. continuations in CPS code.
+        env: Rc<Vec<SECDVal>>,
     },
     BuiltinVal(BuiltinOp),
     EnvVal(Vec<SECDVal>),
@@ -21,18 +26,34 @@ pub enum SECDInstr {
     Halt,
     Pop(usize),
     Apply,
+    /// Like `Apply`, but does not return: nothing is pushed for `Return`.
+    /// Builtins still leave their result on the stack and fall through.
+    TailApply,
     Const(SECDVal),
     Access(usize),
     Focus(usize),
     Return,
     Closure(String),
     Closures(Vec<String>),
+    /// Like `Closure`, but the env is the given number of values popped from the stack,
+    /// the first pushed at the bottom, rather than a copy of the current one.
+    FlatClosure(String, usize),
+    /// Like `Closures`, with the env of `FlatClosure`.
+    FlatClosures(Vec<String>, usize),
     Builtin(BuiltinOp),
+    /// Pop the given number of values into a tuple, the first pushed first.
+    Tuple(usize),
+    /// Replace a tuple by its element at the given index, from 0.
+    Nth(usize),
     Binary(BinOp),
+    /// `Const` of an int followed by `Binary`, as fused by the peephole optimizer.
+    BinaryImm(BinOp, isize),
     Unary(UnaOp),
     Branch(BrOp, String),
     Label(String),
     PushEnv,
+    /// Drop env entries, pushed by an expression whose value is not returned right away.
+    PopEnv(usize),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Hash)]
@@ -65,7 +86,14 @@ pub enum BrOp {
     BrFalse,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Hash)]
+#[derive(Debug, Clone, PartialEq, Hash)]
 pub enum BuiltinOp {
     Println,
+    Print,
+    /// Stops the machine with `SECDError::Panic`.
+    Panic,
+    ReadInt,
+    ReadLine,
+    /// Looked up by name in `SECDMachine::hosts` when applied.
+    Host(String),
 }
@@ -1,71 +1,387 @@
 //! SECD language semantics definition: interpreter.
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
 
+use super::effects::{Capture, EffectSink};
+use super::host::HostFunctions;
 use super::langdef::{BinOp, BrOp, SECDInstr, SECDVal, UnaOp};
+use super::profile::Profiler;
 
+#[derive(Clone)]
 pub struct SECDState(pub usize, pub Vec<SECDVal>, pub Vec<SECDVal>);
 
 #[derive(Debug)]
 pub enum SECDEffect {
     Println(String),
+    /// Without a newline.
+    Print(String),
 }
 
-pub struct SECDMachine {
+impl SECDEffect {
+    /// What it writes to stdout.
+    pub fn text(&self) -> String {
+        match self {
+            SECDEffect::Println(s) => format!("{s}\n"),
+            SECDEffect::Print(s) => s.clone(),
+        }
+    }
+}
+
+/// Bounds on a run. `None` means unbounded.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// Steps that may be taken.
+    pub fuel: Option<usize>,
+    pub max_stack: Option<usize>,
+    pub max_env: Option<usize>,
+    /// Bytes of the values on the stack and in the env, closures' envs included.
+    pub max_heap: Option<usize>,
+    /// Bytes printed, newlines included.
+    pub max_output: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Fuel,
+    Stack,
+    Env,
+    Heap,
+    Output,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SECDError {
+    /// At `halt`, so no further step can be taken.
+    Halted,
+    LimitExceeded(Limit),
+    /// The code is wrong, e.g. it pops an empty stack.
+    Fault(String),
+    /// The program called `panic`, with this message.
+    Panic(String),
+}
+
+impl fmt::Display for SECDError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SECDError::Halted => write!(f, "execution halted"),
+            SECDError::LimitExceeded(limit) => {
+                let what = match limit {
+                    Limit::Fuel => "step",
+                    Limit::Stack => "stack",
+                    Limit::Env => "env",
+                    Limit::Heap => "heap",
+                    Limit::Output => "output",
+                };
+                write!(f, "{what} limit exceeded")
+            }
+            SECDError::Fault(msg) => write!(f, "{msg}"),
+            SECDError::Panic(msg) => write!(f, "panic: {msg}"),
+        }
+    }
+}
+
+fn fault<T>(msg: impl Into<String>) -> Result<T, SECDError> {
+    Err(SECDError::Fault(msg.into()))
+}
+
+fn over(max: Option<usize>, n: usize) -> bool {
+    matches!(max, Some(max) if n > max)
+}
+
+fn pop(stk: &mut Vec<SECDVal>) -> Result<SECDVal, SECDError> {
+    stk.pop()
+        .ok_or_else(|| SECDError::Fault("stack underflow".to_string()))
+}
+
+/// Bytes taken by `v`, counting what it owns.
+/// Closure envs are shared, so only those not in `seen` are counted.
+fn val_bytes(v: &SECDVal, seen: &mut HashSet<*const Vec<SECDVal>>) -> usize {
+    let owned = match v {
+        SECDVal::TupleVal(vs) | SECDVal::EnvVal(vs) => vs.iter().map(|x| val_bytes(x, seen)).sum(),
+        SECDVal::ClosureVal { env, .. } if seen.insert(Rc::as_ptr(env)) => {
+            env.iter().map(|x| val_bytes(x, seen)).sum()
+        }
+        SECDVal::StrVal(s) => s.len(),
+        _ => 0,
+    };
+    std::mem::size_of::<SECDVal>() + owned
+}
+
+/// Bytes owned by a copy of `v`, not counting `v` itself. Copies share closure envs.
+fn copied_bytes(v: &SECDVal) -> usize {
+    match v {
+        SECDVal::TupleVal(vs) | SECDVal::EnvVal(vs) => vs_bytes(vs),
+        SECDVal::StrVal(s) => s.len(),
+        _ => 0,
+    }
+}
+
+/// Bytes owned by copies of `vs`, counting the values themselves.
+fn vs_bytes(vs: &[SECDVal]) -> usize {
+    vs.iter()
+        .map(|x| std::mem::size_of::<SECDVal>() + copied_bytes(x))
+        .sum()
+}
+
+/// What an instruction copies or makes, besides stack and env entries, and where it leaves
+/// it, to count it against `Limits::max_heap`.
+enum Made {
+    /// A copy on top of the stack.
+    Copy,
+    /// A new tuple or closure on top of the stack.
+    New,
+    /// A new closure on top of the env.
+    NewInEnv,
+    /// A call to a closure, which copies its env, and unless `tail` saves a copy of the
+    /// caller's below the return pc.
+    Call { tail: bool },
+}
+
+/// Bytes owned by `v`, newly made, not counting `v` itself.
+fn made_bytes(v: &SECDVal) -> usize {
+    match v {
+        SECDVal::ClosureVal { env, .. } => vs_bytes(env),
+        _ => copied_bytes(v),
+    }
+}
+
+pub struct SECDMachine<E: EffectSink = Capture> {
     pub instrs: Vec<SECDInstr>,
     pub state: SECDState,
-    pub effects: Vec<SECDEffect>,
+    /// Receives output and provides input.
+    pub sink: E,
+    pub limits: Limits,
+    /// Called by `builtin` instructions that name them.
+    pub hosts: HostFunctions,
+    /// Steps taken, counting the one that failed if any.
+    pub steps: usize,
+    /// Bytes printed so far, as counted against `Limits::max_output`.
+    pub output_bytes: usize,
+    /// If set, sees every step before it is taken.
+    pub profiler: Option<Profiler>,
+    /// If set, which instructions were executed, by pc.
+    pub coverage: Option<Vec<bool>>,
     // TODO: make labels integer. remove this
     pc_from_label: HashMap<String, usize>,
+    /// At least the bytes of the stack and the env, if measured since the state was last set.
+    /// Steps add what they copy or make, and the heap is walked again only when this exceeds
+    /// `Limits::max_heap`.
+    heap_bytes: Option<usize>,
 }
 
-pub type SECDStepResult = Result<(), String>;
+pub type SECDStepResult = Result<(), SECDError>;
+
+impl Default for SECDMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl SECDMachine {
-    pub fn init(instrs: Vec<SECDInstr>) -> Self {
-        let mut pc_from_label = HashMap::new();
-        for (i, instr) in instrs.iter().enumerate() {
-            if let SECDInstr::Label(label) = instr {
-                pc_from_label.insert(label.clone(), i);
-            }
-        }
+    /// Output is kept in `sink.effects`. Fails if there is no `main` label.
+    pub fn init(instrs: Vec<SECDInstr>) -> Result<Self, SECDError> {
+        let mut machine = Self::new();
+        machine.load(instrs, "main")?;
+        Ok(machine)
+    }
+
+    /// A machine without any code. Use `load` to give it some.
+    pub fn new() -> Self {
+        Self::with_sink(Capture::new())
+    }
+}
+
+impl<E: EffectSink> SECDMachine<E> {
+    /// A machine without any code, doing its effects through `sink`.
+    pub fn with_sink(sink: E) -> Self {
         Self {
-            instrs,
-            state: SECDState(pc_from_label["main"], Vec::new(), Vec::new()),
-            pc_from_label,
-            effects: Vec::new(),
+            instrs: Vec::new(),
+            state: SECDState(0, Vec::new(), Vec::new()),
+            pc_from_label: HashMap::new(),
+            sink,
+            limits: Limits::default(),
+            hosts: HostFunctions::new(),
+            steps: 0,
+            output_bytes: 0,
+            profiler: None,
+            coverage: None,
+            heap_bytes: None,
         }
     }
 
+    /// Append `instrs` to the code and jump to label `entry`.
+    /// The stack and env are kept, and so are closures into previously loaded code.
+    /// Nothing is loaded if there is no label `entry`.
+    pub fn load(&mut self, instrs: Vec<SECDInstr>, entry: &str) -> SECDStepResult {
+        let base = self.instrs.len();
+        let labels = instrs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, instr)| match instr {
+                SECDInstr::Label(label) => Some((label.clone(), base + i)),
+                _ => None,
+            })
+            .collect::<HashMap<_, _>>();
+        let Some(&pc) = labels.get(entry).or_else(|| self.pc_from_label.get(entry)) else {
+            return fault(format!("no label {entry}"));
+        };
+        self.pc_from_label.extend(labels);
+        self.instrs.extend(instrs);
+        self.state.0 = pc;
+        Ok(())
+    }
+
+    /// Replace the state, e.g. to go back to an earlier one.
+    pub fn set_state(&mut self, state: SECDState) {
+        self.state = state;
+        self.heap_bytes = None;
+    }
+
+    /// Step under `limits` until halted, and take the value left on the stack.
+    pub fn run(&mut self, limits: Limits) -> Result<SECDVal, SECDError> {
+        self.limits = limits;
+        self.heap_bytes = None;
+        loop {
+            match self.step() {
+                Ok(()) => (),
+                Err(SECDError::Halted) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        pop(&mut self.state.1)
+    }
+
+    pub fn halted(&self) -> bool {
+        matches!(self.instrs.get(self.state.0), Some(SECDInstr::Halt))
+    }
+
     pub fn step(&mut self) -> SECDStepResult {
+        if matches!(self.limits.fuel, Some(x) if self.steps >= x) {
+            return Err(SECDError::LimitExceeded(Limit::Fuel));
+        }
+        self.steps += 1;
+        if let Some(profiler) = &mut self.profiler {
+            profiler.before_step(&self.instrs, &self.state);
+        }
+        if let Some(hits) = &mut self.coverage {
+            hits.resize(self.instrs.len(), false);
+            if let Some(hit) = hits.get_mut(self.state.0) {
+                *hit = true;
+            }
+        }
+        let made = self.made();
+        let slots = self.state.1.len() + self.state.2.len();
+        self.exec()?;
+        let SECDState(_pc, stk, env) = &self.state;
+        let limits = &self.limits;
+        if over(limits.max_stack, stk.len()) {
+            return Err(SECDError::LimitExceeded(Limit::Stack));
+        }
+        if over(limits.max_env, env.len()) {
+            return Err(SECDError::LimitExceeded(Limit::Env));
+        }
+        if limits.max_heap.is_some() {
+            let size = std::mem::size_of::<SECDVal>();
+            let bound = self.heap_bytes.map(|x| {
+                let made = made.map_or(0, |made| self.made_bytes(made));
+                (x + (stk.len() + env.len()) * size + made).saturating_sub(slots * size)
+            });
+            let heap = match bound {
+                Some(bound) if !over(limits.max_heap, bound) => bound,
+                _ => self.heap(),
+            };
+            self.heap_bytes = Some(heap);
+            if over(limits.max_heap, heap) {
+                return Err(SECDError::LimitExceeded(Limit::Heap));
+            }
+        }
+        Ok(())
+    }
+
+    /// What the instruction at pc copies or makes, if anything.
+    fn made(&self) -> Option<Made> {
+        let SECDState(pc, stk, _env) = &self.state;
+        match self.instrs.get(*pc)? {
+            SECDInstr::Apply | SECDInstr::TailApply
+                if matches!(stk.iter().rev().nth(1), Some(SECDVal::BuiltinVal(_))) =>
+            {
+                Some(Made::Copy)
+            }
+            SECDInstr::Apply => Some(Made::Call { tail: false }),
+            SECDInstr::TailApply => Some(Made::Call { tail: true }),
+            SECDInstr::Access(_) | SECDInstr::Const(_) => Some(Made::Copy),
+            SECDInstr::Closure(_) | SECDInstr::FlatClosure(..) | SECDInstr::Tuple(_) => {
+                Some(Made::New)
+            }
+            SECDInstr::Closures(_) | SECDInstr::FlatClosures(..) => Some(Made::NewInEnv),
+            _ => None,
+        }
+    }
+
+    /// At least the bytes the last step copied or made, given where it left them.
+    fn made_bytes(&self, made: Made) -> usize {
+        let SECDState(_pc, stk, env) = &self.state;
+        match made {
+            Made::Copy => stk.last().map_or(0, copied_bytes),
+            Made::New => stk.last().map_or(0, made_bytes),
+            Made::NewInEnv => env.last().map_or(0, made_bytes),
+            Made::Call { tail: true } => vs_bytes(env),
+            // the saved env is below the return pc
+            Made::Call { tail: false } => {
+                vs_bytes(env) + stk.iter().rev().nth(1).map_or(0, copied_bytes)
+            }
+        }
+    }
+
+    /// The bytes of the stack and the env.
+    fn heap(&self) -> usize {
+        let SECDState(_pc, stk, env) = &self.state;
+        let mut seen = HashSet::new();
+        stk.iter()
+            .chain(env.iter())
+            .map(|x| val_bytes(x, &mut seen))
+            .sum()
+    }
+
+    fn exec(&mut self) -> SECDStepResult {
         let SECDState(pc, stk, env) = &mut self.state;
-        let instr = self
-            .instrs
-            .get(*pc)
-            .expect(format!("pc {pc} out of range").as_str());
+        let Some(instr) = self.instrs.get(*pc) else {
+            return fault(format!("pc {pc} out of range"));
+        };
+        let label = |label: &String| match self.pc_from_label.get(label) {
+            Some(pc) => Ok(*pc),
+            None => fault(format!("no label {label}")),
+        };
         match instr {
-            SECDInstr::Halt => Err("execution halted".to_string()),
+            SECDInstr::Halt => Err(SECDError::Halted),
             SECDInstr::Pop(n) => {
                 *pc += 1;
-                *stk = stk[..stk.len() - n].to_vec();
+                let Some(len) = stk.len().checked_sub(*n) else {
+                    return fault("stack underflow");
+                };
+                stk.truncate(len);
                 Ok(())
             }
-            SECDInstr::Apply => {
-                let arg = stk.pop().expect("cannot pop arg");
-                let cl = stk.pop().expect("cannot pop closure");
+            SECDInstr::Apply | SECDInstr::TailApply => {
+                let arg = pop(stk)?;
+                let cl = pop(stk)?;
                 match cl {
                     SECDVal::ClosureVal {
                         focused_fn: Some(focused_fn),
                         mutrec_fns,
                         env: env1,
                     } => {
-                        stk.push(SECDVal::EnvVal(env.clone()));
-                        stk.push(SECDVal::PCVal(*pc + 1));
+                        if let SECDInstr::Apply = instr {
+                            stk.push(SECDVal::EnvVal(env.clone()));
+                            stk.push(SECDVal::PCVal(*pc + 1));
+                        }
                         *pc = focused_fn;
-                        *env = env1.clone();
-                        if mutrec_fns.len() > 0 {
+                        *env = env1.to_vec();
+                        if !mutrec_fns.is_empty() {
                             env.push(SECDVal::ClosureVal {
                                 focused_fn: None,
-                                mutrec_fns: mutrec_fns,
+                                mutrec_fns,
                                 env: env1.clone(),
                             });
                         }
@@ -76,13 +392,61 @@ impl SECDMachine {
                         *pc += 1;
                         match op {
                             super::langdef::BuiltinOp::Println => {
-                                self.effects.push(SECDEffect::Println(format!("{arg}")));
+                                let s = format!("{arg}");
+                                let bytes = self.output_bytes + s.len() + 1;
+                                if over(self.limits.max_output, bytes) {
+                                    return Err(SECDError::LimitExceeded(Limit::Output));
+                                }
+                                self.output_bytes = bytes;
+                                self.sink.emit(SECDEffect::Println(s));
                                 stk.push(SECDVal::UnitVal);
                                 Ok(())
                             }
+                            super::langdef::BuiltinOp::Print => {
+                                let s = format!("{arg}");
+                                let bytes = self.output_bytes + s.len();
+                                if over(self.limits.max_output, bytes) {
+                                    return Err(SECDError::LimitExceeded(Limit::Output));
+                                }
+                                self.output_bytes = bytes;
+                                self.sink.emit(SECDEffect::Print(s));
+                                stk.push(SECDVal::UnitVal);
+                                Ok(())
+                            }
+                            super::langdef::BuiltinOp::Panic => {
+                                Err(SECDError::Panic(format!("{arg}")))
+                            }
+                            super::langdef::BuiltinOp::ReadInt => {
+                                let Some(line) = self.sink.read_line() else {
+                                    return fault("read_int: end of input");
+                                };
+                                let Ok(v) = line.trim().parse() else {
+                                    return fault(format!("read_int: not an int: {line}"));
+                                };
+                                stk.push(SECDVal::IntVal(v));
+                                Ok(())
+                            }
+                            super::langdef::BuiltinOp::ReadLine => {
+                                let Some(line) = self.sink.read_line() else {
+                                    return fault("read_line: end of input");
+                                };
+                                stk.push(SECDVal::StrVal(line));
+                                Ok(())
+                            }
+                            super::langdef::BuiltinOp::Host(name) => {
+                                let Some(f) = self.hosts.get(&name) else {
+                                    return fault(format!("no host function {name}"));
+                                };
+                                let res = match &arg {
+                                    SECDVal::TupleVal(args) => f(args),
+                                    _ => f(std::slice::from_ref(&arg)),
+                                };
+                                stk.push(res.or_else(|err| fault(format!("{name}: {err}")))?);
+                                Ok(())
+                            }
                         }
                     }
-                    _ => Err(format!("apply to non-ready-closure {cl:?}!")),
+                    _ => fault(format!("apply to non-ready-closure {cl:?}!")),
                 }
             }
             SECDInstr::Const(val) => {
@@ -92,15 +456,15 @@ impl SECDMachine {
             }
             SECDInstr::Access(n) => {
                 *pc += 1;
-                let val = env
-                    .get(env.len() - *n)
-                    .expect(format!("access {n} oob").as_str());
+                let Some(val) = env.len().checked_sub(*n).and_then(|x| env.get(x)) else {
+                    return fault(format!("access {n} oob"));
+                };
                 stk.push(val.clone());
                 Ok(())
             }
             SECDInstr::Focus(n) => {
                 *pc += 1;
-                let cl = stk.pop().unwrap();
+                let cl = pop(stk)?;
                 if let SECDVal::ClosureVal {
                     focused_fn,
                     mutrec_fns,
@@ -108,10 +472,10 @@ impl SECDMachine {
                 } = cl
                 {
                     if let Some { .. } = focused_fn {
-                        return Err("re-focusing closure".to_string());
+                        return fault("re-focusing closure");
                     }
-                    if *n > mutrec_fns.len() {
-                        return Err("focus oob".to_string());
+                    if *n == 0 || *n > mutrec_fns.len() {
+                        return fault("focus oob");
                     }
                     stk.push(SECDVal::ClosureVal {
                         focused_fn: Some(mutrec_fns[*n - 1]),
@@ -120,82 +484,137 @@ impl SECDMachine {
                     });
                     Ok(())
                 } else {
-                    Err("focus to non-closure".to_string())
+                    fault("focus to non-closure")
                 }
             }
             SECDInstr::Return => {
-                let retval = stk.pop().unwrap();
-                let retpc = if let SECDVal::PCVal(retpc) = stk.pop().unwrap() {
+                let retval = pop(stk)?;
+                let retpc = if let SECDVal::PCVal(retpc) = pop(stk)? {
                     retpc
                 } else {
-                    return Err("return without valid ret pc".to_string());
+                    return fault("return without valid ret pc");
                 };
-                let retenv = if let SECDVal::EnvVal(retenv) = stk.pop().unwrap() {
+                let retenv = if let SECDVal::EnvVal(retenv) = pop(stk)? {
                     retenv
                 } else {
-                    return Err("return without valid ret env".to_string());
+                    return fault("return without valid ret env");
                 };
                 *pc = retpc;
                 stk.push(retval);
                 *env = retenv;
                 Ok(())
             }
-            SECDInstr::Closure(label) => {
+            SECDInstr::Closure(fnlabel) => {
                 *pc += 1;
-                let focused_fn = Some(self.pc_from_label[label]);
+                let focused_fn = Some(label(fnlabel)?);
                 let cl = SECDVal::ClosureVal {
                     focused_fn,
                     mutrec_fns: Vec::new(),
-                    env: env.clone(),
+                    env: Rc::new(env.clone()),
                 };
                 stk.push(cl);
                 Ok(())
             }
             SECDInstr::Closures(labels) => {
                 *pc += 1;
-                let mutrec_fns = labels.iter().map(|l| self.pc_from_label[l]).collect();
+                let mutrec_fns = labels.iter().map(label).collect::<Result<_, _>>()?;
+                let cl = SECDVal::ClosureVal {
+                    focused_fn: None,
+                    mutrec_fns,
+                    env: Rc::new(env.clone()),
+                };
+                env.push(cl);
+                Ok(())
+            }
+            SECDInstr::FlatClosure(fnlabel, n) => {
+                *pc += 1;
+                let focused_fn = Some(label(fnlabel)?);
+                let Some(len) = stk.len().checked_sub(*n) else {
+                    return fault("stack underflow");
+                };
+                let cl = SECDVal::ClosureVal {
+                    focused_fn,
+                    mutrec_fns: Vec::new(),
+                    env: Rc::new(stk.split_off(len)),
+                };
+                stk.push(cl);
+                Ok(())
+            }
+            SECDInstr::FlatClosures(labels, n) => {
+                *pc += 1;
+                let mutrec_fns = labels.iter().map(label).collect::<Result<_, _>>()?;
+                let Some(len) = stk.len().checked_sub(*n) else {
+                    return fault("stack underflow");
+                };
                 let cl = SECDVal::ClosureVal {
                     focused_fn: None,
                     mutrec_fns,
-                    env: env.clone(),
+                    env: Rc::new(stk.split_off(len)),
                 };
                 env.push(cl);
                 Ok(())
             }
             SECDInstr::Builtin(op) => {
                 *pc += 1;
-                let op = SECDVal::BuiltinVal(*op);
+                let op = SECDVal::BuiltinVal(op.clone());
                 stk.push(op);
                 Ok(())
             }
+            SECDInstr::Tuple(n) => {
+                *pc += 1;
+                let Some(len) = stk.len().checked_sub(*n) else {
+                    return fault("stack underflow");
+                };
+                let vs = stk.split_off(len);
+                stk.push(SECDVal::TupleVal(vs));
+                Ok(())
+            }
+            SECDInstr::Nth(idx) => {
+                *pc += 1;
+                let SECDVal::TupleVal(mut vs) = pop(stk)? else {
+                    return fault("nth of non-tuple");
+                };
+                if *idx >= vs.len() {
+                    return fault(format!("nth {idx} of a {}-tuple", vs.len()));
+                }
+                stk.push(vs.swap_remove(*idx));
+                Ok(())
+            }
             SECDInstr::Binary(op) => {
                 *pc += 1;
-                let rhs = stk.pop().unwrap();
-                let lhs = stk.pop().unwrap();
-                let res = Self::eval_binop(*op, lhs, rhs).unwrap();
+                let rhs = pop(stk)?;
+                let lhs = pop(stk)?;
+                let res = Self::eval_binop(*op, lhs, rhs)?;
+                stk.push(res);
+                Ok(())
+            }
+            SECDInstr::BinaryImm(op, rhs) => {
+                *pc += 1;
+                let lhs = pop(stk)?;
+                let res = Self::eval_binop(*op, lhs, SECDVal::IntVal(*rhs))?;
                 stk.push(res);
                 Ok(())
             }
             SECDInstr::Unary(op) => {
                 *pc += 1;
-                let arg = stk.pop().unwrap();
-                let res = Self::eval_unaop(*op, arg).unwrap();
+                let arg = pop(stk)?;
+                let res = Self::eval_unaop(*op, arg)?;
                 stk.push(res);
                 Ok(())
             }
-            SECDInstr::Branch(op, label) => {
-                let br_dst = self.pc_from_label[label];
+            SECDInstr::Branch(op, dst) => {
+                let br_dst = label(dst)?;
                 match op {
                     BrOp::Br => {
                         *pc = br_dst;
                         Ok(())
                     }
                     BrOp::BrFalse => {
-                        let arg = stk.pop().unwrap();
+                        let arg = pop(stk)?;
                         let arg = if let SECDVal::IntVal(v) = arg {
                             v
                         } else {
-                            return Err("bad arg for BrFalse".to_string());
+                            return fault("bad arg for BrFalse");
                         };
                         if arg == 0 {
                             *pc = br_dst;
@@ -213,36 +632,56 @@ impl SECDMachine {
             }
             SECDInstr::PushEnv => {
                 *pc += 1;
-                let v = stk.pop().unwrap();
+                let v = pop(stk)?;
                 env.push(v);
                 Ok(())
             }
+            SECDInstr::PopEnv(n) => {
+                *pc += 1;
+                let Some(len) = env.len().checked_sub(*n) else {
+                    return fault("env underflow");
+                };
+                env.truncate(len);
+                Ok(())
+            }
         }
     }
 
-    fn eval_binop(op: BinOp, lhs: SECDVal, rhs: SECDVal) -> Option<SECDVal> {
-        match (lhs, rhs) {
-            (SECDVal::IntVal(lhs), SECDVal::IntVal(rhs)) => match op {
-                BinOp::Add => Some(SECDVal::IntVal(lhs + rhs)),
-                BinOp::Sub => Some(SECDVal::IntVal(lhs - rhs)),
-                BinOp::Mul => Some(SECDVal::IntVal(lhs * rhs)),
-                BinOp::Div => Some(SECDVal::IntVal(lhs / rhs)),
-                BinOp::Rem => Some(SECDVal::IntVal(lhs % rhs)),
-                BinOp::Gt => Some(SECDVal::IntVal((lhs > rhs).into())),
-                BinOp::Lt => Some(SECDVal::IntVal((lhs < rhs).into())),
-                BinOp::Ge => Some(SECDVal::IntVal((lhs >= rhs).into())),
-                BinOp::Le => Some(SECDVal::IntVal((lhs <= rhs).into())),
-                BinOp::Eq => Some(SECDVal::IntVal((lhs == rhs).into())),
-                BinOp::Ne => Some(SECDVal::IntVal((lhs != rhs).into())),
-                BinOp::Land => todo!(),
-                BinOp::Lor => todo!(),
-                BinOp::Lxor => todo!(),
-            },
-            _ => todo!(),
+    fn eval_binop(op: BinOp, lhs: SECDVal, rhs: SECDVal) -> Result<SECDVal, SECDError> {
+        let (SECDVal::IntVal(lhs), SECDVal::IntVal(rhs)) = (lhs, rhs) else {
+            return fault(format!("{op:?} of non-integers"));
+        };
+        let res = match op {
+            BinOp::Add => lhs.checked_add(rhs),
+            BinOp::Sub => lhs.checked_sub(rhs),
+            BinOp::Mul => lhs.checked_mul(rhs),
+            BinOp::Div | BinOp::Rem if rhs == 0 => return fault("division by zero"),
+            BinOp::Div => lhs.checked_div(rhs),
+            BinOp::Rem => lhs.checked_rem(rhs),
+            BinOp::Gt => Some((lhs > rhs).into()),
+            BinOp::Lt => Some((lhs < rhs).into()),
+            BinOp::Ge => Some((lhs >= rhs).into()),
+            BinOp::Le => Some((lhs <= rhs).into()),
+            BinOp::Eq => Some((lhs == rhs).into()),
+            BinOp::Ne => Some((lhs != rhs).into()),
+            BinOp::Land | BinOp::Lor | BinOp::Lxor => return fault(format!("{op:?} unsupported")),
+        };
+        match res {
+            Some(v) => Ok(SECDVal::IntVal(v)),
+            None => fault("integer overflow"),
         }
     }
 
-    fn eval_unaop(_op: UnaOp, _arg: SECDVal) -> Option<SECDVal> {
-        todo!()
+    fn eval_unaop(op: UnaOp, arg: SECDVal) -> Result<SECDVal, SECDError> {
+        let SECDVal::IntVal(arg) = arg else {
+            return fault(format!("{op:?} of non-integer"));
+        };
+        match op {
+            UnaOp::Neg => arg
+                .checked_neg()
+                .map(SECDVal::IntVal)
+                .ok_or_else(|| SECDError::Fault("integer overflow".to_string())),
+            UnaOp::Lnot => Ok(SECDVal::IntVal((arg == 0).into())),
+        }
     }
 }
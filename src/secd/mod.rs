@@ -1,4 +1,12 @@
+pub mod coverage;
+pub mod cpsgen;
+pub mod effects;
+pub mod host;
 pub mod langdef;
 pub mod machine;
+pub mod peephole;
+pub mod profile;
 pub mod repr;
 pub mod secdgen;
+pub mod snapshot;
+pub mod srcmap;
@@ -0,0 +1,159 @@
+//! Peephole optimization of SECD code, after it is laid out.
+//!
+//! Short sequences of instructions are rewritten until none is left to rewrite:
+//! * a branch to a `br` goes to its target, and a `br` to a `return` or `halt` becomes one,
+//! * a `br` to the next instruction is removed, as are labels nothing refers to and
+//!   the instructions after a `br`, `return` or `halt` that no label leads to,
+//! * `const n` followed by a binary operator becomes its immediate form, e.g. `sub 1`,
+//! * a value put on the env and taken back right away, or never, stays on the stack.
+//!
+//! Instructions are removed, so the result has no source map.
+
+use std::collections::{HashMap, HashSet};
+
+use super::langdef::{BrOp, SECDInstr, SECDVal};
+
+/// Optimize `instrs`, a program entered at the label `entry`.
+pub fn peephole(mut instrs: Vec<SECDInstr>, entry: &str) -> Vec<SECDInstr> {
+    loop {
+        let mut changed = thread_jumps(&mut instrs);
+        changed |= fuse(&mut instrs);
+        changed |= clean(&mut instrs, entry);
+        if !changed {
+            return instrs;
+        }
+    }
+}
+
+/// For each label, the index of the first instruction after it that is not a label.
+fn targets(instrs: &[SECDInstr]) -> HashMap<&str, usize> {
+    let mut res = HashMap::new();
+    let mut labels = Vec::new();
+    for (i, instr) in instrs.iter().enumerate() {
+        match instr {
+            SECDInstr::Label(label) => labels.push(label.as_str()),
+            _ => res.extend(labels.drain(..).map(|x| (x, i))),
+        }
+    }
+    res
+}
+
+fn thread_jumps(instrs: &mut [SECDInstr]) -> bool {
+    let targets = targets(instrs);
+    let mut rewrites = Vec::new();
+    for (i, instr) in instrs.iter().enumerate() {
+        let SECDInstr::Branch(op, label) = instr else {
+            continue;
+        };
+        let mut dst = label;
+        // a loop of branches is left as it is
+        let mut seen = HashSet::from([label]);
+        while let Some(SECDInstr::Branch(BrOp::Br, next)) =
+            targets.get(dst.as_str()).map(|x| &instrs[*x])
+        {
+            if !seen.insert(next) {
+                break;
+            }
+            dst = next;
+        }
+        match targets.get(dst.as_str()).map(|x| &instrs[*x]) {
+            Some(end @ (SECDInstr::Return | SECDInstr::Halt)) if *op == BrOp::Br => {
+                rewrites.push((i, end.clone()))
+            }
+            _ if dst != label => rewrites.push((i, SECDInstr::Branch(*op, dst.clone()))),
+            _ => {}
+        }
+    }
+    let changed = !rewrites.is_empty();
+    for (i, instr) in rewrites {
+        instrs[i] = instr;
+    }
+    changed
+}
+
+fn fuse(instrs: &mut Vec<SECDInstr>) -> bool {
+    use SECDInstr::*;
+    let mut res = Vec::with_capacity(instrs.len());
+    let mut i = 0;
+    while i < instrs.len() {
+        match (&instrs[i], instrs.get(i + 1), instrs.get(i + 2)) {
+            (Const(SECDVal::IntVal(n)), Some(Binary(op)), _) => {
+                res.push(BinaryImm(*op, *n));
+                i += 2;
+            }
+            // the env is dropped right after
+            (PushEnv, Some(Access(1)), Some(Return)) => {
+                res.push(Return);
+                i += 3;
+            }
+            (PushEnv, Some(Access(1)), Some(PopEnv(n))) => {
+                if *n > 1 {
+                    res.push(PopEnv(n - 1));
+                }
+                i += 3;
+            }
+            (PushEnv, Some(PopEnv(1)), _) => {
+                res.push(Pop(1));
+                i += 2;
+            }
+            (instr, _, _) => {
+                res.push(instr.clone());
+                i += 1;
+            }
+        }
+    }
+    let changed = res.len() != instrs.len();
+    *instrs = res;
+    changed
+}
+
+/// Remove branches to the next instruction, unreachable instructions and unused labels.
+fn clean(instrs: &mut Vec<SECDInstr>, entry: &str) -> bool {
+    let mut used = HashSet::from([entry.to_string()]);
+    for instr in instrs.iter() {
+        match instr {
+            SECDInstr::Branch(_, label)
+            | SECDInstr::Closure(label)
+            | SECDInstr::FlatClosure(label, _) => {
+                used.insert(label.clone());
+            }
+            SECDInstr::Closures(labels) | SECDInstr::FlatClosures(labels, _) => {
+                used.extend(labels.iter().cloned())
+            }
+            _ => {}
+        }
+    }
+    let mut res = Vec::with_capacity(instrs.len());
+    let mut reachable = true;
+    for (i, instr) in instrs.iter().enumerate() {
+        match instr {
+            SECDInstr::Label(label) => {
+                // others are only fallen through to
+                if used.contains(label) {
+                    reachable = true;
+                    res.push(instr.clone());
+                }
+                continue;
+            }
+            _ if !reachable => continue,
+            SECDInstr::Branch(BrOp::Br, label) => {
+                let next = instrs[i + 1..].iter();
+                let mut labels = next.map_while(|x| match x {
+                    SECDInstr::Label(l) => Some(l),
+                    _ => None,
+                });
+                if !labels.any(|x| x == label) {
+                    res.push(instr.clone());
+                }
+            }
+            _ => res.push(instr.clone()),
+        }
+        reachable = !matches!(
+            instr,
+            SECDInstr::Branch(BrOp::Br, _) | SECDInstr::Return | SECDInstr::Halt
+        );
+    }
+    let changed = res.len() != instrs.len();
+    *instrs = res;
+    changed
+}
@@ -0,0 +1,196 @@
+//! Profiling of SECD execution, per function.
+//!
+//! A function is the code from a label that is the target of `closure` or `closures`, or `main`,
+//! up to the next such label.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use super::langdef::{SECDInstr, SECDVal};
+use super::machine::SECDState;
+
+#[derive(Debug, Default, Clone)]
+pub struct FnProfile {
+    pub name: String,
+    /// Instructions executed in this function, not counting its callees.
+    pub instrs: usize,
+    /// Times it was applied.
+    pub calls: usize,
+}
+
+/// Set as `SECDMachine::profiler` to have every step counted.
+#[derive(Debug, Default)]
+pub struct Profiler {
+    pub fns: Vec<FnProfile>,
+    /// Index into `fns` for every pc.
+    fn_of: Vec<usize>,
+    /// Functions being applied, outermost first.
+    calls: Vec<usize>,
+    /// Instructions executed under each stack of `calls`.
+    folded: HashMap<Vec<usize>, usize>,
+    pub steps: usize,
+    pub applies: usize,
+    pub returns: usize,
+    pub max_stk: usize,
+    pub max_env: usize,
+    /// Most calls in progress at once.
+    pub max_dump: usize,
+    /// Env entries copied when closures are made and applied.
+    pub env_cloned: usize,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Find the functions of `instrs`, keeping the counts of the ones already known.
+    fn index(&mut self, instrs: &[SECDInstr]) {
+        let mut targets = vec!["main"];
+        for instr in instrs.iter() {
+            match instr {
+                SECDInstr::Closure(label) | SECDInstr::FlatClosure(label, _) => targets.push(label),
+                SECDInstr::Closures(labels) | SECDInstr::FlatClosures(labels, _) => {
+                    targets.extend(labels.iter().map(|x| x.as_str()))
+                }
+                _ => {}
+            }
+        }
+        let mut cur = None;
+        let mut fn_of = Vec::with_capacity(instrs.len());
+        for instr in instrs.iter() {
+            if let SECDInstr::Label(label) = instr {
+                if targets.contains(&label.as_str()) {
+                    cur = Some(self.fn_index(label));
+                }
+            }
+            fn_of.push(match cur {
+                Some(i) => i,
+                None => self.fn_index("?"),
+            });
+        }
+        self.fn_of = fn_of;
+        if self.calls.is_empty() {
+            let main = self.fn_index("main");
+            self.calls.push(main);
+        }
+    }
+
+    fn fn_index(&mut self, name: &str) -> usize {
+        match self.fns.iter().position(|x| x.name == name) {
+            Some(i) => i,
+            None => {
+                self.fns.push(FnProfile {
+                    name: name.to_string(),
+                    ..Default::default()
+                });
+                self.fns.len() - 1
+            }
+        }
+    }
+
+    /// Count the instruction about to be executed in `state`.
+    pub fn before_step(&mut self, instrs: &[SECDInstr], state: &SECDState) {
+        if self.fn_of.len() != instrs.len() {
+            self.index(instrs);
+        }
+        let SECDState(pc, stk, env) = state;
+        let Some(instr) = instrs.get(*pc) else {
+            return;
+        };
+        self.steps += 1;
+        self.max_stk = self.max_stk.max(stk.len());
+        self.max_env = self.max_env.max(env.len());
+        self.fns[self.fn_of[*pc]].instrs += 1;
+        match self.folded.get_mut(&self.calls[..]) {
+            Some(n) => *n += 1,
+            None => {
+                self.folded.insert(self.calls.clone(), 1);
+            }
+        }
+        match instr {
+            SECDInstr::Apply | SECDInstr::TailApply => {
+                if let Some(SECDVal::ClosureVal {
+                    focused_fn: Some(f),
+                    mutrec_fns,
+                    env: env1,
+                }) = stk.len().checked_sub(2).map(|x| &stk[x])
+                {
+                    self.applies += 1;
+                    self.env_cloned += env.len() + env1.len();
+                    if !mutrec_fns.is_empty() {
+                        self.env_cloned += env1.len();
+                    }
+                    let callee = self.fn_of[*f];
+                    self.fns[callee].calls += 1;
+                    // the caller is not returned to
+                    if matches!(instr, SECDInstr::TailApply) {
+                        self.calls.pop();
+                    }
+                    self.calls.push(callee);
+                    self.max_dump = self.max_dump.max(self.calls.len() - 1);
+                }
+            }
+            SECDInstr::Return => {
+                self.returns += 1;
+                if self.calls.len() > 1 {
+                    self.calls.pop();
+                }
+            }
+            SECDInstr::Closure(_) | SECDInstr::Closures(_) => self.env_cloned += env.len(),
+            SECDInstr::FlatClosure(_, n) | SECDInstr::FlatClosures(_, n) => self.env_cloned += n,
+            _ => {}
+        }
+    }
+
+    /// Functions by instructions executed, most first, then the totals.
+    pub fn report(&self) -> String {
+        let mut fns = self.fns.iter().filter(|x| x.instrs > 0).collect::<Vec<_>>();
+        fns.sort_by(|a, b| b.instrs.cmp(&a.instrs).then(a.name.cmp(&b.name)));
+        let mut s = String::new();
+        writeln!(
+            s,
+            "{:<20} {:>10} {:>7} {:>8}",
+            "function", "instrs", "%", "calls"
+        )
+        .unwrap();
+        for f in fns {
+            let percent = f.instrs as f64 * 100.0 / self.steps.max(1) as f64;
+            writeln!(
+                s,
+                "{:<20} {:>10} {:>6.2}% {:>8}",
+                f.name, f.instrs, percent, f.calls
+            )
+            .unwrap();
+        }
+        writeln!(s).unwrap();
+        writeln!(s, "steps:      {}", self.steps).unwrap();
+        writeln!(s, "applies:    {}", self.applies).unwrap();
+        writeln!(s, "returns:    {}", self.returns).unwrap();
+        writeln!(s, "max stack:  {}", self.max_stk).unwrap();
+        writeln!(s, "max env:    {}", self.max_env).unwrap();
+        writeln!(s, "max dump:   {}", self.max_dump).unwrap();
+        writeln!(
+            s,
+            "env cloned: {} values, {} bytes",
+            self.env_cloned,
+            self.env_cloned * std::mem::size_of::<SECDVal>()
+        )
+        .unwrap();
+        s
+    }
+
+    /// One line per call stack, `main;f;g <instrs>`, as taken by `flamegraph.pl` and the like.
+    pub fn folded_stacks(&self) -> String {
+        let mut lines = self
+            .folded
+            .iter()
+            .map(|(calls, n)| {
+                let names = calls.iter().map(|x| self.fns[*x].name.as_str());
+                format!("{} {n}", names.collect::<Vec<_>>().join(";"))
+            })
+            .collect::<Vec<_>>();
+        lines.sort();
+        lines.iter().map(|x| format!("{x}\n")).collect()
+    }
+}
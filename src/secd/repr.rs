@@ -1,8 +1,12 @@
 //! Parsing and printing of SECD values.
 
+use std::{collections::HashMap, marker::PhantomData, rc::Rc};
+
 use phf::phf_map;
+use serde_json::{json, Value};
 
 use super::langdef::{BinOp, BrOp, BuiltinOp, SECDInstr, SECDVal, UnaOp};
+use super::machine::SECDEffect;
 
 static BINOPS_PARSE: phf::Map<&'static str, BinOp> = {
     use BinOp::*;
@@ -40,6 +44,10 @@ static BUILTINOPS_PARSE: phf::Map<&'static str, BuiltinOp> = {
     use BuiltinOp::*;
     phf_map! {
         "println" => Println,
+        "print" => Print,
+        "panic" => Panic,
+        "read_int" => ReadInt,
+        "read_line" => ReadLine,
     }
 };
 
@@ -78,10 +86,15 @@ pub fn brops_print(op: BrOp) -> &'static str {
     }
 }
 
-pub fn builtinops_print(op: BuiltinOp) -> &'static str {
+pub fn builtinops_print(op: &BuiltinOp) -> &str {
     use BuiltinOp::*;
     match op {
         Println => "println",
+        Print => "print",
+        Panic => "panic",
+        ReadInt => "read_int",
+        ReadLine => "read_line",
+        Host(name) => name,
     }
 }
 
@@ -111,12 +124,16 @@ pub fn translate_unaop(op: crate::ast::UnaOp) -> crate::secd::langdef::UnaOp {
     }
 }
 
-pub fn translate_builtinop(op: crate::ast::BuiltinOp) -> crate::secd::langdef::BuiltinOp {
+pub fn translate_builtinop(op: &crate::ast::BuiltinOp) -> crate::secd::langdef::BuiltinOp {
     match op {
         crate::ast::BuiltinOp::Println => crate::secd::langdef::BuiltinOp::Println,
-        crate::ast::BuiltinOp::Nth => todo!(),
+        crate::ast::BuiltinOp::ReadInt => crate::secd::langdef::BuiltinOp::ReadInt,
+        crate::ast::BuiltinOp::ReadLine => crate::secd::langdef::BuiltinOp::ReadLine,
+        crate::ast::BuiltinOp::Print => crate::secd::langdef::BuiltinOp::Print,
+        crate::ast::BuiltinOp::Panic => crate::secd::langdef::BuiltinOp::Panic,
         crate::ast::BuiltinOp::True => unreachable!(),
         crate::ast::BuiltinOp::False => unreachable!(),
+        crate::ast::BuiltinOp::Host(name) => crate::secd::langdef::BuiltinOp::Host(name.clone()),
     }
 }
 
@@ -133,7 +150,11 @@ pub fn secd_parse(lines: &Vec<String>) -> Vec<SECDInstr> {
                 return Label(line[..line.len() - 1].to_string());
             }
             if BINOPS_PARSE.contains_key(op) {
-                return Binary(BINOPS_PARSE[op]);
+                assert!(args.len() <= 1);
+                return match args.first() {
+                    None => Binary(BINOPS_PARSE[op]),
+                    Some(imm) => BinaryImm(BINOPS_PARSE[op], imm.parse().unwrap()),
+                };
             }
             if UNAOPS_PARSE.contains_key(op) {
                 return Unary(UNAOPS_PARSE[op]);
@@ -158,6 +179,25 @@ pub fn secd_parse(lines: &Vec<String>) -> Vec<SECDInstr> {
                     let mutrec_fns = args.iter().map(|x| x.to_string()).collect();
                     Closures(mutrec_fns)
                 }
+                "flatclosure" => {
+                    assert_eq!(args.len(), 2);
+                    let n: usize = args[0].parse().unwrap();
+                    FlatClosure(args[1].to_string(), n)
+                }
+                "flatclosures" => {
+                    assert!(args.len() >= 2);
+                    let n: usize = args[0].parse().unwrap();
+                    let mutrec_fns = args[1..].iter().map(|x| x.to_string()).collect();
+                    FlatClosures(mutrec_fns, n)
+                }
+                "tuple" => {
+                    assert_eq!(args.len(), 1);
+                    Tuple(args[0].parse().unwrap())
+                }
+                "nth" => {
+                    assert_eq!(args.len(), 1);
+                    Nth(args[0].parse().unwrap())
+                }
                 "return" => Return,
                 "halt" => Halt,
                 "focus" => {
@@ -166,16 +206,28 @@ pub fn secd_parse(lines: &Vec<String>) -> Vec<SECDInstr> {
                     Focus(n)
                 }
                 "apply" => Apply,
+                "tailapply" => TailApply,
                 "builtin" => {
                     assert_eq!(args.len(), 1);
-                    Builtin(BUILTINOPS_PARSE[args[0]])
+                    match BUILTINOPS_PARSE.get(args[0]) {
+                        Some(op) => Builtin(op.clone()),
+                        None => Builtin(BuiltinOp::Host(args[0].to_string())),
+                    }
                 }
                 "pushenv" => PushEnv,
+                "popenv" => {
+                    assert_eq!(args.len(), 1);
+                    let n: usize = args[0].parse().unwrap();
+                    PopEnv(n)
+                }
                 "const" => {
                     assert_eq!(args.len(), 1);
                     if let Ok(v) = args[0].parse::<isize>() {
                         return Const(SECDVal::IntVal(v));
                     }
+                    if args[0] == "()" {
+                        return Const(SECDVal::UnitVal);
+                    }
                     eprintln!("bad const line: {}", line);
                     std::process::exit(1)
                 }
@@ -193,14 +245,22 @@ pub fn secd_parse(lines: &Vec<String>) -> Vec<SECDInstr> {
         .collect()
 }
 
+/// `instrs` one per line, as read by `secd_parse`.
+pub fn secd_print(instrs: &[SECDInstr]) -> String {
+    let lines = instrs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+    lines.join("\n")
+}
+
 impl std::fmt::Display for SECDInstr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             SECDInstr::Halt => write!(f, "halt"),
             SECDInstr::Pop(n) => write!(f, "pop {n}"),
             SECDInstr::Apply => write!(f, "apply"),
+            SECDInstr::TailApply => write!(f, "tailapply"),
             SECDInstr::Const(v) => match v {
                 SECDVal::IntVal(v) => write!(f, "const {v}"),
+                SECDVal::UnitVal => write!(f, "const ()"),
                 _ => todo!(),
             },
             SECDInstr::Access(n) => write!(f, "access {n}"),
@@ -208,12 +268,18 @@ impl std::fmt::Display for SECDInstr {
             SECDInstr::Return => write!(f, "return"),
             SECDInstr::Closure(fnn) => write!(f, "closure {}", fnn),
             SECDInstr::Closures(fns) => write!(f, "closures {}", fns.join(" ")),
-            SECDInstr::Builtin(op) => write!(f, "builtin {}", builtinops_print(*op)),
+            SECDInstr::FlatClosure(fnn, n) => write!(f, "flatclosure {n} {fnn}"),
+            SECDInstr::FlatClosures(fns, n) => write!(f, "flatclosures {n} {}", fns.join(" ")),
+            SECDInstr::Builtin(op) => write!(f, "builtin {}", builtinops_print(op)),
+            SECDInstr::Tuple(n) => write!(f, "tuple {n}"),
+            SECDInstr::Nth(idx) => write!(f, "nth {idx}"),
             SECDInstr::Binary(op) => write!(f, "{}", binops_print(*op)),
+            SECDInstr::BinaryImm(op, imm) => write!(f, "{} {imm}", binops_print(*op)),
             SECDInstr::Unary(op) => write!(f, "{}", unaops_print(*op)),
             SECDInstr::Branch(op, label) => write!(f, "{} {label}", brops_print(*op)),
             SECDInstr::Label(label) => write!(f, "{label}:"),
             SECDInstr::PushEnv => write!(f, "pushenv"),
+            SECDInstr::PopEnv(n) => write!(f, "popenv {n}"),
         }
     }
 }
@@ -223,15 +289,136 @@ impl std::fmt::Display for SECDVal {
         match self {
             SECDVal::IntVal(v) => write!(f, "{v}"),
             SECDVal::UnitVal => write!(f, "()"),
+            SECDVal::StrVal(s) => write!(f, "{s}"),
             SECDVal::TupleVal(vs) => {
-                write!(f, "(")?;
-                for x in vs {
-                    x.fmt(f)?;
-                }
-                write!(f, ")")
+                let vs = vs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+                write!(f, "({})", vs.join(", "))
             }
-            SECDVal::BuiltinVal(op) => write!(f, "{}", builtinops_print(*op)),
+            SECDVal::BuiltinVal(op) => write!(f, "{}", builtinops_print(op)),
             _ => write!(f, "{:?}", self),
         }
     }
 }
+
+/// Values as JSON, tagged by their `kind`. Functions are given by their pc.
+/// A closure gives the index of its env in a table of envs rather than the env itself,
+/// as closures share envs, and writing each in full can take space exponential in the
+/// size of the values. An env only refers to envs before it in the table.
+#[derive(Default)]
+pub struct EnvTable<'a> {
+    ids: HashMap<*const Vec<SECDVal>, usize>,
+    envs: Vec<Value>,
+    /// Keeps the envs alive, so that their addresses are not reused.
+    vals: PhantomData<&'a SECDVal>,
+}
+
+impl<'a> EnvTable<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `v` as JSON, adding the envs of its closures to the table.
+    pub fn val_to_json(&mut self, v: &'a SECDVal) -> Value {
+        let mut vals =
+            |vs: &'a [SECDVal]| vs.iter().map(|x| self.val_to_json(x)).collect::<Vec<_>>();
+        match v {
+            SECDVal::IntVal(v) => json!({"kind": "int", "value": v}),
+            SECDVal::UnitVal => json!({"kind": "unit"}),
+            SECDVal::StrVal(s) => json!({"kind": "str", "value": s}),
+            SECDVal::TupleVal(vs) => json!({"kind": "tuple", "elems": vals(vs)}),
+            SECDVal::ClosureVal {
+                focused_fn,
+                mutrec_fns,
+                env,
+            } => json!({
+                "kind": "closure",
+                "focused_fn": focused_fn,
+                "mutrec_fns": mutrec_fns,
+                "env": self.env_id(env),
+            }),
+            SECDVal::BuiltinVal(op) => json!({"kind": "builtin", "op": builtinops_print(op)}),
+            SECDVal::EnvVal(vs) => json!({"kind": "env", "elems": vals(vs)}),
+            SECDVal::PCVal(pc) => json!({"kind": "pc", "value": pc}),
+        }
+    }
+
+    fn env_id(&mut self, env: &'a Rc<Vec<SECDVal>>) -> usize {
+        if let Some(id) = self.ids.get(&Rc::as_ptr(env)) {
+            return *id;
+        }
+        let vs = env.iter().map(|x| self.val_to_json(x)).collect::<Vec<_>>();
+        self.ids.insert(Rc::as_ptr(env), self.envs.len());
+        self.envs.push(json!(vs));
+        self.envs.len() - 1
+    }
+
+    /// The envs met so far, by their index.
+    pub fn into_json(self) -> Value {
+        json!(self.envs)
+    }
+}
+
+/// The envs of a table written by `EnvTable::into_json`, sharing them as they were.
+pub fn envs_from_json(v: &Value) -> Option<Vec<Rc<Vec<SECDVal>>>> {
+    let mut envs = Vec::new();
+    for env in v.as_array()? {
+        let env = env.as_array()?.iter().map(|x| val_from_json(x, &envs));
+        envs.push(Rc::new(env.collect::<Option<_>>()?));
+    }
+    Some(envs)
+}
+
+pub fn effect_to_json(e: &SECDEffect) -> Value {
+    match e {
+        SECDEffect::Println(s) => json!({"kind": "println", "text": s}),
+        SECDEffect::Print(s) => json!({"kind": "print", "text": s}),
+    }
+}
+
+/// The inverse of `EnvTable::val_to_json`, with the envs read by `envs_from_json`.
+pub fn val_from_json(v: &Value, envs: &[Rc<Vec<SECDVal>>]) -> Option<SECDVal> {
+    let vals = |x: &Value| -> Option<Vec<SECDVal>> {
+        x.as_array()?
+            .iter()
+            .map(|x| val_from_json(x, envs))
+            .collect()
+    };
+    let pc = |x: &Value| -> Option<usize> { x.as_u64()?.try_into().ok() };
+    Some(match v["kind"].as_str()? {
+        "int" => SECDVal::IntVal(v["value"].as_i64()?.try_into().ok()?),
+        "unit" => SECDVal::UnitVal,
+        "str" => SECDVal::StrVal(v["value"].as_str()?.to_string()),
+        "tuple" => SECDVal::TupleVal(vals(&v["elems"])?),
+        "closure" => SECDVal::ClosureVal {
+            focused_fn: match &v["focused_fn"] {
+                Value::Null => None,
+                x => Some(pc(x)?),
+            },
+            mutrec_fns: v["mutrec_fns"]
+                .as_array()?
+                .iter()
+                .map(pc)
+                .collect::<Option<_>>()?,
+            env: envs.get(pc(&v["env"])?)?.clone(),
+        },
+        "builtin" => {
+            let op = v["op"].as_str()?;
+            SECDVal::BuiltinVal(match BUILTINOPS_PARSE.get(op) {
+                Some(op) => op.clone(),
+                None => BuiltinOp::Host(op.to_string()),
+            })
+        }
+        "env" => SECDVal::EnvVal(vals(&v["elems"])?),
+        "pc" => SECDVal::PCVal(pc(&v["value"])?),
+        _ => return None,
+    })
+}
+
+/// The inverse of `effect_to_json`.
+pub fn effect_from_json(e: &Value) -> Option<SECDEffect> {
+    match e["kind"].as_str()? {
+        "println" => Some(SECDEffect::Println(e["text"].as_str()?.to_string())),
+        "print" => Some(SECDEffect::Print(e["text"].as_str()?.to_string())),
+        _ => None,
+    }
+}
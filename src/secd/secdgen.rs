@@ -1,199 +1,482 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use crate::{
-    ast::{Expr, Ty},
-    debrujin::{DeBrujinIdx, DeBrujinInfo},
-    pass::ExprVisitor,
+    ast::Expr,
+    ir::anf::{
+        self, abs_free_vars, is_temp, rec_free_vars, Atom, AtomKind, Comp, CompKind, RecFn, Span,
+        Term,
+    },
+    spans::SpanInfo,
 };
 
 use super::{
     langdef::{BrOp, SECDInstr, SECDVal},
     repr::{translate_binop, translate_builtinop, translate_unaop},
+    srcmap::{Loc, SourceMap},
 };
 
-/// * `label_instrs`: maps function name to its instructions.
+/// Instructions, each with the index of its location in `SECDGen::locs`.
+type Code = Vec<(SECDInstr, Option<usize>)>;
+
+/// Generates SECD code from ANF.
+///
+/// * `label_instrs`: maps function name to its instructions, ordered so that code is reproducible.
+/// * `label_prefix`: prepended to every generated label, including `main`.
+/// * `spans`: if given, instructions are mapped back to the expressions they came from.
+/// * `flat`: whether closures capture only their free variables, rather than the whole env.
+/// * `scope`: names of the env entries at the generated instruction, from the bottom.
+/// * `recs`: names bound by a `let rec`, whose env entry holds all closures of the bundle.
+/// * `pending`: temporaries whose code is held back until they are used, in binding order.
+///   They are put on the env if something else has to run first.
+/// * `ready`: code of the temporaries read by the computation being generated.
+/// * `joins`: labels of the join points in scope, with the length of the scope at each.
+/// * `fn_span`: of the function being generated, `None` for `main`.
+#[derive(Default)]
 pub struct SECDGen {
-    label_instrs: HashMap<String, Vec<SECDInstr>>,
+    label_instrs: BTreeMap<String, Code>,
     label_suffix: HashMap<String, usize>,
-    debrujin_info: DeBrujinInfo,
+    label_prefix: String,
+    spans: Option<SpanInfo>,
+    flat: bool,
+    scope: Vec<String>,
+    recs: HashSet<String>,
+    pending: Vec<(String, Code, Span)>,
+    ready: HashMap<String, Code>,
+    joins: HashMap<String, (String, usize)>,
+    fn_span: Option<Span>,
+    locs: Vec<Loc>,
+}
+
+/// Instructions that push the value of `x`, given the names of the env entries
+/// and of those bound by a `let rec`.
+pub(super) fn access(scope: &[String], recs: &HashSet<String>, x: &str) -> Vec<SECDInstr> {
+    let rec = recs.contains(x);
+    scope
+        .iter()
+        .rev()
+        .enumerate()
+        .find_map(|(i, entry)| match rec {
+            true => entry
+                .split(',')
+                .position(|y| y == x)
+                .map(|j| vec![SECDInstr::Access(1 + i), SECDInstr::Focus(1 + j)]),
+            false => (entry == x).then(|| vec![SECDInstr::Access(1 + i)]),
+        })
+        .unwrap_or_else(|| panic!("{x} is not in scope"))
 }
 
 // todo: str than String
 impl SECDGen {
-    pub fn new(debrujin_info: DeBrujinInfo) -> Self {
-        Self {
-            label_instrs: HashMap::new(),
-            label_suffix: HashMap::new(),
-            debrujin_info,
-        }
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Code generated with different prefixes can be loaded into the same machine.
+    pub fn with_label_prefix(mut self, label_prefix: &str) -> Self {
+        self.label_prefix = label_prefix.to_string();
+        self
+    }
+
+    /// Keep a source map, see `source_map` and `assemble`.
+    /// The spans are used by `visit_main_expr`, `gen_main` takes them from the ANF.
+    pub fn with_spans(mut self, spans: SpanInfo) -> Self {
+        self.spans = Some(spans);
+        self
+    }
+
+    /// Make closures with only the variables they use, see `SECDInstr::FlatClosure`.
+    pub fn with_flat_closures(mut self) -> Self {
+        self.flat = true;
+        self
+    }
+
+    /// A variable already on the env, pushed after the previous ones.
+    pub fn define_var(&mut self, name: &str) {
+        self.scope.push(name.to_string());
+    }
+
+    /// Like `define_var`, for the closures of a `let rec`.
+    pub fn define_rec(&mut self, names: &[String]) {
+        self.scope.push(names.join(","));
+        self.recs.extend(names.iter().cloned());
     }
 
     fn new_label(&mut self, prefix: &str) -> String {
         let suffix = self.label_suffix.get(prefix).unwrap_or(&0);
-        let res = format!("{}{}", prefix, suffix);
+        let res = format!("{}{}{}", self.label_prefix, prefix, suffix);
         self.label_suffix.insert(prefix.to_string(), suffix + 1);
         res
     }
 
+    /// The location of `span` at the current scope.
+    fn loc(&mut self, span: Span) -> Option<usize> {
+        self.spans.as_ref()?;
+        let loc = Loc {
+            span: span?,
+            env: self.scope.clone(),
+        };
+        // subexpressions come in between, so this only catches the common case
+        if self.locs.last() != Some(&loc) {
+            self.locs.push(loc);
+        }
+        Some(self.locs.len() - 1)
+    }
+
+    fn emit(&mut self, span: Span, instrs: Vec<SECDInstr>) -> Code {
+        let loc = self.loc(span);
+        instrs.into_iter().map(|x| (x, loc)).collect()
+    }
+
+    pub fn main_label(&self) -> String {
+        format!("{}main", self.label_prefix)
+    }
+
+    /// Lower `main_expr` to ANF and generate it, see `gen_main`.
     pub fn visit_main_expr(&mut self, main_expr: &Expr) {
-        let mut main_instrs = self.visit(main_expr);
-        main_instrs.push(SECDInstr::Halt);
-        self.label_instrs.insert("main".to_string(), main_instrs);
+        let main = anf::lower(main_expr, self.spans.as_ref());
+        if let Err(err) = anf::verify(&main, &self.globals()) {
+            panic!("malformed ANF: {err}\n{main}");
+        }
+        self.gen_main(&main);
+    }
+
+    /// Generate `main`, which should pass `anf::verify` with `globals`.
+    pub fn gen_main(&mut self, main: &Term) {
+        self.fn_span = None;
+        let instrs = self.term(main);
+        self.label_instrs.insert(self.main_label(), instrs);
+    }
+
+    /// The variables defined by `define_var` and `define_rec`.
+    pub fn globals(&self) -> Vec<String> {
+        self.scope
+            .iter()
+            .flat_map(|x| x.split(','))
+            .map(|x| x.to_string())
+            .collect()
+    }
+
+    /// The generated functions laid out one after another, each headed by its label.
+    pub fn program(&self) -> Vec<SECDInstr> {
+        let mut instrs = Vec::new();
+        for (fnlabel, fninstrs) in self.label_instrs.iter() {
+            instrs.push(SECDInstr::Label(fnlabel.clone()));
+            instrs.extend(fninstrs.iter().map(|x| x.0.clone()));
+        }
+        instrs
+    }
+
+    /// The source map of `program`. Empty without `with_spans`.
+    pub fn source_map(&self) -> SourceMap {
+        let mut instr_locs = Vec::new();
+        if self.spans.is_some() {
+            for fninstrs in self.label_instrs.values() {
+                instr_locs.push(None);
+                instr_locs.extend(fninstrs.iter().map(|x| x.1));
+            }
+        }
+        SourceMap {
+            source: None,
+            locs: self.locs.clone(),
+            instr_locs,
+        }
     }
 
+    /// With `with_spans`, the source map is kept in comments, see `srcmap`.
     pub fn assemble(&self) -> String {
         let mut lines = Vec::<String>::new();
+        let mut cur = None;
         for (fnlabel, fninstrs) in self.label_instrs.iter() {
-            lines.push(format!("{fnlabel}:"));
-            lines.extend(fninstrs.iter().map(|x| format!("{x}")));
+            let head = (SECDInstr::Label(fnlabel.clone()), None);
+            for (instr, loc) in std::iter::once(&head).chain(fninstrs.iter()) {
+                if self.spans.is_some() && *loc != cur {
+                    match loc {
+                        Some(loc) => lines.push(format!("#@ {}", self.locs[*loc])),
+                        None => lines.push("#@ -".to_string()),
+                    }
+                    cur = *loc;
+                }
+                lines.push(format!("{instr}"));
+            }
             lines.push("\n".to_string());
         }
         lines.join("\n")
     }
 }
 
-impl ExprVisitor<Vec<SECDInstr>> for SECDGen {
-    fn default(&mut self) -> Vec<SECDInstr> {
-        todo!()
-    }
-
-    fn join_results(&mut self, res: Vec<Vec<SECDInstr>>) -> Vec<SECDInstr> {
-        res.concat()
+impl SECDGen {
+    /// Code that puts every pending temporary on the env.
+    fn flush(&mut self) -> Code {
+        let pending = std::mem::take(&mut self.pending);
+        let mut code = Vec::new();
+        // each was generated at the current scope, so none may be pushed before the others run
+        for (_, instrs, _) in pending.iter() {
+            code.extend(instrs.iter().cloned());
+        }
+        for (name, _, span) in pending.into_iter().rev() {
+            code.extend(self.emit(span, vec![SECDInstr::PushEnv]));
+            self.scope.push(name);
+        }
+        code
     }
 
-    fn visit_seq(&mut self, subs: &Vec<Box<Expr>>, _eself: &Expr) -> Vec<SECDInstr> {
-        subs.iter()
-            .map(|x| self.visit(x))
-            .collect::<Vec<_>>()
-            .join(&SECDInstr::Pop(1))
+    /// Make ready the temporaries among `atoms`, returning the code that has to run first.
+    /// They stay in place if they are the last pending ones, in order, and either nothing
+    /// else is pending or the computation is `deferred` too. Otherwise all are flushed.
+    fn prepare(&mut self, atoms: &[&Atom], deferred: bool) -> Code {
+        let temps = atoms
+            .iter()
+            .filter_map(|x| x.var())
+            .filter(|x| self.pending.iter().any(|y| y.0 == *x))
+            .collect::<Vec<_>>();
+        let (n, k) = (self.pending.len(), temps.len());
+        let in_order = k <= n && self.pending[n - k..].iter().map(|x| x.0.as_str()).eq(temps);
+        if !in_order || !(deferred || k == n) {
+            return self.flush();
+        }
+        for (name, instrs, _) in self.pending.drain(n - k..) {
+            self.ready.insert(name, instrs);
+        }
+        Vec::new()
     }
 
-    fn visit_app(&mut self, fun: &Expr, arg: &Expr, _eself: &Expr) -> Vec<SECDInstr> {
-        [self.visit(fun), self.visit(arg), vec![SECDInstr::Apply]].concat()
+    fn lookup(&mut self, x: &str, span: Span) -> Code {
+        let instrs = access(&self.scope, &self.recs, x);
+        self.emit(span, instrs)
     }
 
-    // todo: isize vs i64
-    fn visit_intlit(&mut self, val: &i64, _eself: &Expr) -> Vec<SECDInstr> {
-        vec![SECDInstr::Const(SECDVal::IntVal(*val as isize))]
+    fn atom(&mut self, a: &Atom) -> Code {
+        // todo: isize vs i64
+        let instr = match &a.kind {
+            AtomKind::Int(val) => SECDInstr::Const(SECDVal::IntVal(*val as isize)),
+            AtomKind::Unit => SECDInstr::Const(SECDVal::UnitVal),
+            AtomKind::Builtin(op) => SECDInstr::Builtin(translate_builtinop(op)),
+            AtomKind::Var(x) => match self.ready.remove(x) {
+                Some(instrs) => return instrs,
+                None => return self.lookup(x, a.span),
+            },
+        };
+        self.emit(a.span, vec![instr])
     }
 
-    fn visit_varref(&mut self, _id: &String, eself: &Expr) -> Vec<SECDInstr> {
-        match self.debrujin_info.get(eself).unwrap() {
-            DeBrujinIdx::Var(idx) => vec![SECDInstr::Access(1 + idx)],
-            DeBrujinIdx::Rec(fnidx, subidx) => {
-                vec![SECDInstr::Access(1 + fnidx), SECDInstr::Focus(1 + subidx)]
+    fn comp(&mut self, c: &Comp) -> Code {
+        match &c.kind {
+            CompKind::Atom(a) => self.atom(a),
+            CompKind::Binary(op, lhs, rhs) => [
+                self.atom(lhs),
+                self.atom(rhs),
+                self.emit(c.span, vec![SECDInstr::Binary(translate_binop(*op))]),
+            ]
+            .concat(),
+            CompKind::Unary(op, sub) => [
+                self.atom(sub),
+                self.emit(c.span, vec![SECDInstr::Unary(translate_unaop(*op))]),
+            ]
+            .concat(),
+            CompKind::App(fun, arg) => [
+                self.atom(fun),
+                self.atom(arg),
+                self.emit(c.span, vec![SECDInstr::Apply]),
+            ]
+            .concat(),
+            CompKind::Tuple(subs) => {
+                let mut code = subs.iter().flat_map(|x| self.atom(x)).collect::<Code>();
+                code.extend(self.emit(c.span, vec![SECDInstr::Tuple(subs.len())]));
+                code
+            }
+            CompKind::Nth(idx, sub) => [
+                self.atom(sub),
+                self.emit(c.span, vec![SECDInstr::Nth(*idx as usize)]),
+            ]
+            .concat(),
+            CompKind::Abs(param, body) if self.flat => {
+                let label = self.new_label("lam");
+                let (env, mut code) = self.capture(&abs_free_vars(param, body), c.span);
+                self.function(&label, env, param, body, c.span);
+                let n = code.len();
+                code.extend(self.emit(c.span, vec![SECDInstr::FlatClosure(label, n)]));
+                code
+            }
+            CompKind::Abs(param, body) => {
+                let label = self.new_label("lam");
+                self.function(&label, self.scope.clone(), param, body, c.span);
+                self.emit(c.span, vec![SECDInstr::Closure(label)])
             }
         }
     }
 
-    fn visit_abs(
-        &mut self,
-        _arg_name: &String,
-        _arg_ty: &Ty,
-        body: &Expr,
-        _eself: &Expr,
-    ) -> Vec<SECDInstr> {
-        let label = self.new_label("lam");
-        let mut instrs = self.visit(body);
-        instrs.push(SECDInstr::Return);
-        self.label_instrs.insert(label.clone(), instrs);
-        vec![SECDInstr::Closure(label)]
-    }
-
-    fn visit_builtin(&mut self, op: &crate::ast::BuiltinOp, _eself: &Expr) -> Vec<SECDInstr> {
-        vec![SECDInstr::Builtin(translate_builtinop(*op))]
-    }
-
-    fn visit_ite(&mut self, cond: &Expr, tr: &Expr, fl: &Expr, _eself: &Expr) -> Vec<SECDInstr> {
-        let (l1, l2, l3) = (
-            self.new_label("tr"),
-            self.new_label("fl"),
-            self.new_label("endif"),
-        );
-        vec![
-            self.visit(cond),
-            vec![
-                SECDInstr::Branch(BrOp::BrFalse, l2.clone()),
-                SECDInstr::Label(l1),
-            ],
-            self.visit(tr),
-            vec![
-                SECDInstr::Branch(BrOp::Br, l3.clone()),
-                SECDInstr::Label(l2),
-            ],
-            self.visit(fl),
-            vec![SECDInstr::Label(l3)],
-        ]
-        .concat()
-    }
-
-    fn visit_binary(
-        &mut self,
-        lhs: &Expr,
-        op: &crate::ast::BinOp,
-        rhs: &Expr,
-        _eself: &Expr,
-    ) -> Vec<SECDInstr> {
-        vec![
-            self.visit(lhs),
-            self.visit(rhs),
-            vec![SECDInstr::Binary(translate_binop(*op))],
-        ]
-        .concat()
-    }
-
-    fn visit_unary(&mut self, op: &crate::ast::UnaOp, sub: &Expr, _eself: &Expr) -> Vec<SECDInstr> {
-        vec![
-            self.visit(sub),
-            vec![SECDInstr::Unary(translate_unaop(*op))],
-        ]
-        .concat()
-    }
-
-    fn visit_let(
-        &mut self,
-        _name: &String,
-        _ty: &Ty,
-        val: &Expr,
-        body: &Expr,
-        _eself: &Expr,
-    ) -> Vec<SECDInstr> {
-        vec![self.visit(val), vec![SECDInstr::PushEnv], self.visit(body)].concat()
-    }
-
-    fn visit_letrecarm(&mut self, e: &crate::ast::LetRecArm) -> Vec<SECDInstr> {
-        let label = self.new_label("clos");
-        let instrs = vec![self.visit(&e.body), vec![SECDInstr::Return]].concat();
-        self.label_instrs.insert(label.clone(), instrs);
-        // This is actually misuse since we need a label rather than an instruction.
-        vec![SECDInstr::Label(label)]
-    }
-
-    fn visit_letrec(
-        &mut self,
-        arms: &Vec<crate::ast::LetRecArm>,
-        body: &Expr,
-        _eself: &Expr,
-    ) -> Vec<SECDInstr> {
-        let arms_labels = arms
+    /// The env entries holding `fvs`, bottom first, and the code that pushes them.
+    fn capture(&mut self, fvs: &[String], span: Span) -> (Vec<String>, Code) {
+        let mut entries = fvs
             .iter()
             .map(|x| {
-                let res = self.visit_letrecarm(x);
-                assert!(res.len() == 1);
-                match res.into_iter().nth(0) {
-                    Some(SECDInstr::Label(l)) => l,
-                    _ => unreachable!(),
-                }
+                let entry = self
+                    .scope
+                    .iter()
+                    .rposition(|y| y.split(',').any(|y| y == x));
+                entry.unwrap_or_else(|| panic!("{x} is not in scope"))
             })
             .collect::<Vec<_>>();
-        vec![vec![SECDInstr::Closures(arms_labels)], self.visit(body)].concat()
+        entries.sort();
+        entries.dedup();
+        let n = self.scope.len();
+        let instrs = entries.iter().map(|i| SECDInstr::Access(n - i)).collect();
+        let env = entries.iter().map(|i| self.scope[*i].clone()).collect();
+        (env, self.emit(span, instrs))
+    }
+
+    /// Generate a function at `label`, whose closure holds the env entries `env`.
+    fn function(&mut self, label: &str, env: Vec<String>, param: &str, body: &Term, span: Span) {
+        let scope = std::mem::replace(&mut self.scope, env);
+        self.scope.push(param.to_string());
+        let pending = std::mem::take(&mut self.pending);
+        let joins = std::mem::take(&mut self.joins);
+        let fn_span = self.fn_span.replace(span);
+        let instrs = self.term(body);
+        self.pending = pending;
+        self.joins = joins;
+        self.fn_span = fn_span;
+        self.scope = scope;
+        self.label_instrs.insert(label.to_string(), instrs);
     }
+
+    fn rec_fn(&mut self, env: &[String], fun: &RecFn) -> String {
+        let label = self.new_label("clos");
+        self.function(&label, env.to_vec(), &fun.param, &fun.body, fun.span);
+        label
+    }
+
+    fn term(&mut self, t: &Term) -> Code {
+        match t {
+            Term::Let {
+                name,
+                val,
+                body,
+                span,
+            } => {
+                let deferred = is_temp(name);
+                let mut code = self.prepare(&val.atoms(), deferred);
+                let val = self.comp(val);
+                if deferred {
+                    self.pending.push((name.clone(), val, *span));
+                } else if name == "_" {
+                    code.extend(val);
+                    code.extend(self.emit(*span, vec![SECDInstr::Pop(1)]));
+                } else {
+                    code.extend(val);
+                    code.extend(self.emit(*span, vec![SECDInstr::PushEnv]));
+                    self.scope.push(name.clone());
+                }
+                code.extend(self.term(body));
+                code
+            }
+            Term::LetRec { fns, body, span } => {
+                let mut code = self.flush();
+                let (mut env, captured) = match self.flat {
+                    true => self.capture(&rec_free_vars(fns), *span),
+                    false => (self.scope.clone(), Vec::new()),
+                };
+                let n = captured.len();
+                code.extend(captured);
+                let loc = self.loc(*span);
+                let names = fns.iter().map(|x| x.name.clone()).collect::<Vec<_>>();
+                self.define_rec(&names);
+                // applying one of them puts the bundle on the env of the closure
+                env.push(names.join(","));
+                let labels = fns.iter().map(|x| self.rec_fn(&env, x)).collect();
+                let closures = match self.flat {
+                    true => SECDInstr::FlatClosures(labels, n),
+                    false => SECDInstr::Closures(labels),
+                };
+                code.push((closures, loc));
+                code.extend(self.term(body));
+                code
+            }
+            Term::If { cond, tr, fl, span } => {
+                let mut code = self.prepare(&[cond], false);
+                code.extend(self.atom(cond));
+                let (l1, l2) = (self.new_label("tr"), self.new_label("fl"));
+                code.extend(self.emit(
+                    *span,
+                    vec![
+                        SECDInstr::Branch(BrOp::BrFalse, l2.clone()),
+                        SECDInstr::Label(l1),
+                    ],
+                ));
+                // each branch starts from the env of the condition,
+                // even if the other jumped out of some of it
+                let scope = self.scope.clone();
+                code.extend(self.term(tr));
+                self.scope = scope;
+                code.extend(self.emit(*span, vec![SECDInstr::Label(l2)]));
+                code.extend(self.term(fl));
+                code
+            }
+            Term::Join {
+                name,
+                param,
+                rest,
+                body,
+                span,
+            } => {
+                // the condition of an `if` can still be generated in place
+                let mut code = match &**body {
+                    Term::If { cond, .. } => self.prepare(&[cond], false),
+                    _ => self.flush(),
+                };
+                let label = self.new_label("join");
+                let scope = self.scope.clone();
+                self.joins
+                    .insert(name.clone(), (label.clone(), scope.len()));
+                code.extend(self.term(body));
+                self.joins.remove(name);
+                // the last branch falls through
+                if matches!(code.last(), Some((SECDInstr::Branch(BrOp::Br, l), _)) if *l == label) {
+                    code.pop();
+                }
+                self.scope = scope;
+                code.extend(self.emit(*span, vec![SECDInstr::Label(label)]));
+                code.extend(self.emit(*span, vec![SECDInstr::PushEnv]));
+                self.scope.push(param.clone());
+                code.extend(self.term(rest));
+                code
+            }
+            Term::Jump { target, arg, span } => {
+                let mut code = self.prepare(&[arg], false);
+                code.extend(self.atom(arg));
+                let (label, scope_len) = self.joins[target].clone();
+                // drop what the branch put on the env, so that all branches join with the same
+                let n = self.scope.len() - scope_len;
+                if n > 0 {
+                    code.extend(self.emit(*span, vec![SECDInstr::PopEnv(n)]));
+                    self.scope.truncate(scope_len);
+                }
+                code.extend(self.emit(*span, vec![SECDInstr::Branch(BrOp::Br, label)]));
+                code
+            }
+            Term::Ret(a) => {
+                let mut code = self.prepare(&[a], false);
+                code.extend(self.atom(a));
+                let ret = match self.fn_span {
+                    Some(span) => self.emit(span, vec![SECDInstr::Return]),
+                    None => vec![(SECDInstr::Halt, None)],
+                };
+                code.extend(ret);
+                code
+            }
+        }
+    }
+}
+
+pub fn secdgen(main_expr: &Expr) -> String {
+    let mut secdgen = SECDGen::new();
+    secdgen.visit_main_expr(main_expr);
+    secdgen.assemble()
 }
 
-// todo: borrow debrujin info
-pub fn secdgen(debrujin_info: DeBrujinInfo, main_expr: &Expr) -> String {
-    let mut secdgen = SECDGen::new(debrujin_info);
+/// Like `secdgen`, but without the round trip through text.
+pub fn secdgen_program(main_expr: &Expr) -> Vec<SECDInstr> {
+    let mut secdgen = SECDGen::new();
     secdgen.visit_main_expr(main_expr);
-    let secd_prog = secdgen.assemble();
-    secd_prog
+    secdgen.program()
 }
@@ -0,0 +1,99 @@
+//! Saving the state of a machine, to resume it later or elsewhere.
+//!
+//! The stack, which also holds the dump, the env, the effects and the input read so far are
+//! saved as JSON. Closure envs are saved once each, in a table, and shared again on restore.
+//! The code is not, only a hash of it, so it must be loaded separately.
+//! Pcs, e.g. of closures, are only meaningful for the same code.
+
+use serde_json::{json, Value};
+
+use super::{
+    langdef::SECDInstr,
+    machine::{SECDMachine, SECDState},
+    repr::{effect_from_json, effect_to_json, envs_from_json, val_from_json, EnvTable},
+};
+
+const VERSION: u64 = 2;
+
+/// FNV-1a of the instructions as printed, which is stable across builds.
+fn code_hash(instrs: &[SECDInstr]) -> String {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for instr in instrs.iter() {
+        for b in format!("{instr}\n").bytes() {
+            h ^= b as u64;
+            h = h.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{h:016x}")
+}
+
+impl SECDMachine {
+    /// The state, to be `restore`d into a machine with the same code.
+    pub fn save(&self) -> Value {
+        let SECDState(pc, stk, env) = &self.state;
+        let mut envs = EnvTable::new();
+        let stk = stk.iter().map(|x| envs.val_to_json(x)).collect::<Vec<_>>();
+        let env = env.iter().map(|x| envs.val_to_json(x)).collect::<Vec<_>>();
+        json!({
+            "version": VERSION,
+            "code": code_hash(&self.instrs),
+            "pc": pc,
+            "envs": envs.into_json(),
+            "stk": stk,
+            "env": env,
+            "steps": self.steps,
+            "output_bytes": self.output_bytes,
+            "effects": self.sink.effects.iter().map(effect_to_json).collect::<Vec<_>>(),
+            "input": self.sink.input,
+            "read": self.sink.read,
+        })
+    }
+
+    /// Continue from a state `save`d from a machine with the same code.
+    pub fn restore(&mut self, snap: &Value) -> Result<(), String> {
+        if snap["version"] != VERSION {
+            return Err(format!("unknown snapshot version {}", snap["version"]));
+        }
+        if snap["code"] != code_hash(&self.instrs) {
+            return Err("snapshot was taken with different code".to_string());
+        }
+        let bad = |what: &str| format!("bad {what} in snapshot");
+        let usize_of = |what: &str| -> Result<usize, String> {
+            let n = snap[what].as_u64().ok_or_else(|| bad(what))?;
+            n.try_into().map_err(|_| bad(what))
+        };
+        let envs = envs_from_json(&snap["envs"]).ok_or_else(|| bad("envs"))?;
+        let vals = |what: &str| -> Result<Vec<_>, String> {
+            let vs = snap[what].as_array().ok_or_else(|| bad(what))?;
+            vs.iter()
+                .map(|x| val_from_json(x, &envs).ok_or_else(|| bad(what)))
+                .collect()
+        };
+        let pc = usize_of("pc")?;
+        if pc >= self.instrs.len() {
+            return Err(bad("pc"));
+        }
+        let state = SECDState(pc, vals("stk")?, vals("env")?);
+        let effects = snap["effects"].as_array().ok_or_else(|| bad("effects"))?;
+        let effects = effects
+            .iter()
+            .map(|x| effect_from_json(x).ok_or_else(|| bad("effects")))
+            .collect::<Result<_, _>>()?;
+        let input = snap["input"].as_array().ok_or_else(|| bad("input"))?;
+        let input = input
+            .iter()
+            .map(|x| {
+                x.as_str()
+                    .map(|x| x.to_string())
+                    .ok_or_else(|| bad("input"))
+            })
+            .collect::<Result<_, _>>()?;
+        self.steps = usize_of("steps")?;
+        self.output_bytes = usize_of("output_bytes")?;
+        self.sink.read = usize_of("read")?;
+        self.sink.effects = effects;
+        self.sink.input = input;
+        self.set_state(state);
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+//! Mapping SECD instructions back to the MiniML expressions they came from.
+//!
+//! In assembled code the map is kept in comments, so that it is ignored by `secd_parse`.
+//! `# source: <path>` names the MiniML file, and `#@ <span> <names>...` gives the location
+//! of the instructions that follow, up to the next `#@`. `#@ -` means no location.
+
+use std::fmt;
+
+use crate::spans::SrcSpan;
+
+/// Where an instruction came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loc {
+    /// Of the innermost expression that generated the instruction.
+    pub span: SrcSpan,
+    /// Names of the env entries, from the bottom to the top.
+    /// The closures of a `let rec` are named by their functions joined with `,`.
+    pub env: Vec<String>,
+}
+
+impl fmt::Display for Loc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.span)?;
+        for name in self.env.iter() {
+            write!(f, " {name}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Loc {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let mut t = s.split_whitespace();
+        let span = t.next().ok_or(())?.parse()?;
+        let env = t.map(|x| x.to_string()).collect();
+        Ok(Loc { span, env })
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct SourceMap {
+    /// Path of the MiniML file, if known.
+    pub source: Option<String>,
+    pub locs: Vec<Loc>,
+    /// For each instruction, the index of its location in `locs`.
+    pub instr_locs: Vec<Option<usize>>,
+}
+
+impl SourceMap {
+    pub fn loc(&self, pc: usize) -> Option<&Loc> {
+        Some(&self.locs[(*self.instr_locs.get(pc)?)?])
+    }
+
+    /// Read the map from the comments of assembled code.
+    /// Instructions are counted like `secdi` does: non-blank lines that are not comments.
+    pub fn parse(code: &str) -> Self {
+        let mut map = SourceMap::default();
+        let mut cur = None;
+        for line in code.lines().map(|x| x.trim()) {
+            if let Some(path) = line.strip_prefix("# source:") {
+                map.source = Some(path.trim().to_string());
+            } else if let Some(loc) = line.strip_prefix("#@") {
+                cur = loc.parse().ok().map(|loc| {
+                    map.locs.push(loc);
+                    map.locs.len() - 1
+                });
+            } else if !line.is_empty() && !line.starts_with('#') {
+                map.instr_locs.push(cur);
+            }
+        }
+        map
+    }
+}
@@ -0,0 +1,187 @@
+//! Source spans of expressions.
+//!
+//! The AST has no positions, so the parser records the spans of the expressions it makes.
+//! They are in the order of a pre-order walk of the AST, which matches them to its nodes.
+//! It also records where the identifiers are, which are in the order the AST keeps its names.
+
+use std::fmt;
+
+use crate::{ast::*, node_id::NodeInfo};
+
+/// Where an expression is in the source. Lines and columns are 1-based, columns in chars.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SrcSpan {
+    pub line: u32,
+    pub col: usize,
+    pub end_line: u32,
+    /// Exclusive.
+    pub end_col: usize,
+}
+
+pub type SpanInfo = NodeInfo<SrcSpan>;
+
+impl SrcSpan {
+    pub fn contains(&self, other: &SrcSpan) -> bool {
+        (self.line, self.col) <= (other.line, other.col)
+            && (other.end_line, other.end_col) <= (self.end_line, self.end_col)
+    }
+}
+
+impl fmt::Display for SrcSpan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}-{}:{}",
+            self.line, self.col, self.end_line, self.end_col
+        )
+    }
+}
+
+impl std::str::FromStr for SrcSpan {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let pos = |s: &str| -> Option<(u32, usize)> {
+            let (line, col) = s.split_once(':')?;
+            Some((line.parse().ok()?, col.parse().ok()?))
+        };
+        let (start, end) = s.split_once('-').ok_or(())?;
+        let ((line, col), (end_line, end_col)) = pos(start).zip(pos(end)).ok_or(())?;
+        Ok(SrcSpan {
+            line,
+            col,
+            end_line,
+            end_col,
+        })
+    }
+}
+
+/// The spans of the expressions of a parse, in pre-order, as recorded by the parser.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExprSpans {
+    pub exprs: Vec<SrcSpan>,
+    /// The identifiers, as byte offsets and lengths, in source order.
+    pub names: Vec<(usize, usize)>,
+}
+
+impl ExprSpans {
+    /// Spans from byte ranges of `src`, and identifiers, sorted and deduplicated,
+    /// as the parser may go over the same input more than once.
+    /// A range may end after whitespace and comments, which are left out.
+    pub(crate) fn new(
+        src: &str,
+        mut ranges: Vec<(usize, usize)>,
+        mut names: Vec<(usize, usize)>,
+    ) -> Self {
+        for (start, end) in ranges.iter_mut() {
+            *end = trim_end(&src[..*end]).len().max(*start);
+        }
+        // an expression comes before those it contains
+        ranges.sort_by_key(|&(start, end)| (start, std::cmp::Reverse(end)));
+        ranges.dedup();
+        let lines = std::iter::once(0)
+            .chain(src.match_indices('\n').map(|x| x.0 + 1))
+            .collect::<Vec<_>>();
+        let position = |offset: usize| {
+            let line = lines.partition_point(|x| *x <= offset) - 1;
+            let col = src[lines[line]..offset].chars().count() + 1;
+            (line as u32 + 1, col)
+        };
+        let spans = ranges.into_iter().map(|(start, end)| {
+            let ((line, col), (end_line, end_col)) = (position(start), position(end));
+            SrcSpan {
+                line,
+                col,
+                end_line,
+                end_col,
+            }
+        });
+        names.sort();
+        names.dedup();
+        ExprSpans {
+            exprs: spans.collect(),
+            names,
+        }
+    }
+
+    /// The span of every expression of `prog`, which was parsed with these spans.
+    /// `None` if they do not line up, e.g. if `prog` was transformed after parsing.
+    pub fn info(&self, prog: &Prog) -> Option<SpanInfo> {
+        let mut exprs = Vec::new();
+        pre_order(&prog.main_expr, &mut exprs);
+        if exprs.len() != self.exprs.len() {
+            return None;
+        }
+        let mut info = NodeInfo::new();
+        for (e, span) in exprs.into_iter().zip(self.exprs.iter()) {
+            info.insert(e, *span);
+        }
+        Some(info)
+    }
+}
+
+/// `src` without the whitespace and comments at its end.
+fn trim_end(mut src: &str) -> &str {
+    loop {
+        src = src.trim_end();
+        let line = &src[src.rfind('\n').map_or(0, |x| x + 1)..];
+        match line.find("--") {
+            Some(at) => src = &src[..src.len() - line.len() + at],
+            None => return src,
+        }
+    }
+}
+
+/// The expressions of `e` in source order, each before those it contains.
+fn pre_order<'a>(e: &'a Expr, out: &mut Vec<&'a Expr>) {
+    use Expr::*;
+    if let Error {} = e {
+        return;
+    }
+    out.push(e);
+    match e {
+        IntLit { .. } | UnitLit {} | VarRef { .. } | Builtin { .. } | Error {} => (),
+        Binary { lhs, rhs, .. } => {
+            pre_order(lhs, out);
+            pre_order(rhs, out);
+        }
+        Unary { sub, .. } | Nth { sub, .. } => pre_order(sub, out),
+        App { fun, arg } => {
+            pre_order(fun, out);
+            pre_order(arg, out);
+        }
+        Seq { subs } | Tuple { subs } => subs.iter().for_each(|x| pre_order(x, out)),
+        Ite { cond, tr, fl } => {
+            pre_order(cond, out);
+            pre_order(tr, out);
+            pre_order(fl, out);
+        }
+        Abs { body, .. } => pre_order(body, out),
+        Let { val, body, .. } => {
+            pre_order(val, out);
+            pre_order(body, out);
+        }
+        LetRec { arms, body } => {
+            arms.iter().for_each(|x| pre_order(&x.body, out));
+            pre_order(body, out);
+        }
+        Match { sub, arms } => {
+            pre_order(sub, out);
+            for arm in arms.iter() {
+                ptn_pre_order(&arm.ptn, out);
+                pre_order(&arm.res, out);
+            }
+        }
+    }
+}
+
+/// The literals of a pattern, which are expressions too.
+fn ptn_pre_order<'a>(ptn: &'a MatchPattern, out: &mut Vec<&'a Expr>) {
+    match ptn {
+        MatchPattern::Binder { .. } => (),
+        MatchPattern::Lit { val } => pre_order(val, out),
+        MatchPattern::Tuple { subs } | MatchPattern::DataType { subs, .. } => {
+            subs.iter().for_each(|x| ptn_pre_order(x, out))
+        }
+    }
+}
@@ -0,0 +1,544 @@
+//! Type inference, in the style of Hindley-Milner: variables bound by `let` and `let rec`
+//! are polymorphic, those bound by functions and patterns are not.
+//!
+//! The rest of the pipeline does not need types, and runs programs that are not well typed,
+//! e.g. tuples used as lists. The types are only found for the `typed` stage of `miniml`.
+//!
+//! The type of a tuple must be known where `nth` is applied to it,
+//! as there is no type for "a tuple with at least that many parts".
+
+use std::fmt;
+
+use crate::{
+    ast::{BinOp, BuiltinOp, DataType, Expr, LetRecArm, MatchPattern, Prog, Ty, UnaOp},
+    node_id::NodeInfo,
+};
+
+#[derive(Clone, PartialEq)]
+pub enum Type {
+    Unit,
+    Int,
+    Bool,
+    /// Of what `read_line` returns, which has no syntax.
+    Str,
+    Var(usize),
+    Fun(Box<Type>, Box<Type>),
+    Tuple(Vec<Type>),
+    Data(String),
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Unit => write!(f, "unit"),
+            Type::Int => write!(f, "int"),
+            Type::Bool => write!(f, "bool"),
+            Type::Str => write!(f, "string"),
+            // 'a to 'z, then 'a1 and so on
+            Type::Var(n) => {
+                let letter = (b'a' + (n % 26) as u8) as char;
+                match n / 26 {
+                    0 => write!(f, "'{letter}"),
+                    k => write!(f, "'{letter}{k}"),
+                }
+            }
+            // -> is r-assoc
+            Type::Fun(box lhs @ Type::Fun(..), rhs) => write!(f, "({lhs}) -> {rhs}"),
+            Type::Fun(lhs, rhs) => write!(f, "{lhs} -> {rhs}"),
+            Type::Tuple(subs) => {
+                let subs = subs.iter().map(|x| match x {
+                    Type::Fun(..) | Type::Tuple(..) => format!("({x})"),
+                    _ => x.to_string(),
+                });
+                write!(f, "{}", subs.collect::<Vec<_>>().join(" * "))
+            }
+            Type::Data(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+// as the `Inspector` shows the info of a node with `{:?}`
+impl fmt::Debug for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+/// The type of each expression, with its type variables named from `'a` on.
+pub type TypeInfo = NodeInfo<Type>;
+
+#[derive(Debug)]
+pub enum TypeErrKind {
+    Mismatch {
+        expected: Type,
+        found: Type,
+    },
+    /// A type that would have to contain itself, like that of `\x -> x x`.
+    Infinite {
+        var: Type,
+        ty: Type,
+    },
+    UnknownVarRef {
+        id: String,
+    },
+    UnknownCtor {
+        ctor: String,
+    },
+    /// `nth` of something not known to be a tuple, or of one too short.
+    Nth {
+        idx: i64,
+        ty: Type,
+    },
+}
+
+impl fmt::Display for TypeErrKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeErrKind::Mismatch { expected, found } => {
+                write!(f, "expected {expected}, found {found}")
+            }
+            TypeErrKind::Infinite { var, ty } => {
+                write!(f, "{var} would be {ty}, which contains it")
+            }
+            TypeErrKind::UnknownVarRef { id } => write!(f, "unknown variable {id}"),
+            TypeErrKind::UnknownCtor { ctor } => write!(f, "unknown constructor {ctor}"),
+            TypeErrKind::Nth { idx, ty } => {
+                write!(f, "nth {idx} of {ty}, which is not a tuple that long")
+            }
+        }
+    }
+}
+
+/// A type error, at the expression whose type could not be found.
+#[derive(Debug)]
+pub struct TypeErr<'a> {
+    pub expr: &'a Expr,
+    pub kind: TypeErrKind,
+}
+
+/// A type whose variables in `vars` stand for any type.
+#[derive(Clone)]
+struct Scheme {
+    vars: Vec<usize>,
+    ty: Type,
+}
+
+pub struct Typer<'a> {
+    data_types: &'a [DataType],
+    /// What each type variable was found to be.
+    subst: Vec<Option<Type>>,
+    /// The variables in scope, the innermost last.
+    env: Vec<(String, Scheme)>,
+    types: Vec<(&'a Expr, Type)>,
+}
+
+type TypeResult<T> = Result<T, TypeErrKind>;
+
+/// The types of the expressions of `prog`, or the first type error.
+pub fn infer(prog: &Prog) -> Result<TypeInfo, TypeErr<'_>> {
+    let mut typer = Typer::new(&prog.data_types);
+    typer.expr(&prog.main_expr)?;
+    Ok(typer.get_info())
+}
+
+impl<'a> Typer<'a> {
+    pub fn new(data_types: &'a [DataType]) -> Self {
+        Typer {
+            data_types,
+            subst: Vec::new(),
+            env: Vec::new(),
+            types: Vec::new(),
+        }
+    }
+
+    pub fn get_info(self) -> TypeInfo {
+        let mut info = NodeInfo::new();
+        for (e, ty) in self.types.iter() {
+            info.insert(e, self.shown(ty, &mut Vec::new()));
+        }
+        info
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.subst.push(None);
+        Type::Var(self.subst.len() - 1)
+    }
+
+    /// `ty` with the variables found so far replaced, at its root.
+    fn resolve(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Var(n) if let Some(x) = &self.subst[*n] => self.resolve(x),
+            _ => ty.clone(),
+        }
+    }
+
+    /// `ty` with all the variables found so far replaced.
+    fn zonk(&self, ty: &Type) -> Type {
+        match self.resolve(ty) {
+            Type::Fun(lhs, rhs) => Type::Fun(Box::new(self.zonk(&lhs)), Box::new(self.zonk(&rhs))),
+            Type::Tuple(subs) => Type::Tuple(subs.iter().map(|x| self.zonk(x)).collect()),
+            ty => ty,
+        }
+    }
+
+    /// `ty` to show, with the variables not found yet numbered in the order they appear
+    /// after those in `vars`, which they are added to.
+    fn shown(&self, ty: &Type, vars: &mut Vec<usize>) -> Type {
+        match self.resolve(ty) {
+            Type::Var(n) => match vars.iter().position(|&x| x == n) {
+                Some(i) => Type::Var(i),
+                None => {
+                    vars.push(n);
+                    Type::Var(vars.len() - 1)
+                }
+            },
+            Type::Fun(lhs, rhs) => Type::Fun(
+                Box::new(self.shown(&lhs, vars)),
+                Box::new(self.shown(&rhs, vars)),
+            ),
+            Type::Tuple(subs) => Type::Tuple(subs.iter().map(|x| self.shown(x, vars)).collect()),
+            ty => ty,
+        }
+    }
+
+    /// The variables of `ty` that are not found yet.
+    fn free_vars(&self, ty: &Type, out: &mut Vec<usize>) {
+        match self.resolve(ty) {
+            Type::Var(n) if !out.contains(&n) => out.push(n),
+            Type::Fun(lhs, rhs) => {
+                self.free_vars(&lhs, out);
+                self.free_vars(&rhs, out);
+            }
+            Type::Tuple(subs) => subs.iter().for_each(|x| self.free_vars(x, out)),
+            _ => (),
+        }
+    }
+
+    fn unify(&mut self, expected: &Type, found: &Type) -> TypeResult<()> {
+        let mismatch = |this: &Self| {
+            let mut vars = Vec::new();
+            TypeErrKind::Mismatch {
+                expected: this.shown(expected, &mut vars),
+                found: this.shown(found, &mut vars),
+            }
+        };
+        match (self.resolve(expected), self.resolve(found)) {
+            (Type::Var(a), Type::Var(b)) if a == b => Ok(()),
+            (Type::Var(n), ty) | (ty, Type::Var(n)) => {
+                let mut vars = Vec::new();
+                self.free_vars(&ty, &mut vars);
+                if vars.contains(&n) {
+                    let mut vars = Vec::new();
+                    return Err(TypeErrKind::Infinite {
+                        var: self.shown(&Type::Var(n), &mut vars),
+                        ty: self.shown(&ty, &mut vars),
+                    });
+                }
+                self.subst[n] = Some(ty);
+                Ok(())
+            }
+            // a mismatch of the parts is reported as one of the whole
+            (Type::Fun(a, b), Type::Fun(c, d)) => self
+                .unify(&a, &c)
+                .and_then(|()| self.unify(&b, &d))
+                .map_err(|err| match err {
+                    TypeErrKind::Mismatch { .. } => mismatch(self),
+                    err => err,
+                }),
+            (Type::Tuple(xs), Type::Tuple(ys)) if xs.len() == ys.len() => xs
+                .iter()
+                .zip(ys.iter())
+                .try_for_each(|(x, y)| self.unify(x, y))
+                .map_err(|err| match err {
+                    TypeErrKind::Mismatch { .. } => mismatch(self),
+                    err => err,
+                }),
+            (a, b) if a == b => Ok(()),
+            _ => Err(mismatch(self)),
+        }
+    }
+
+    /// `ty` as generic as it can be where `env_len` variables are in scope.
+    fn generalize(&self, ty: &Type, env_len: usize) -> Scheme {
+        let mut in_env = Vec::new();
+        for (_, scheme) in self.env[..env_len].iter() {
+            let mut vars = Vec::new();
+            self.free_vars(&scheme.ty, &mut vars);
+            in_env.extend(vars.into_iter().filter(|x| !scheme.vars.contains(x)));
+        }
+        let mut vars = Vec::new();
+        self.free_vars(ty, &mut vars);
+        vars.retain(|x| !in_env.contains(x));
+        Scheme {
+            vars,
+            ty: self.zonk(ty),
+        }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        fn go(ty: &Type, map: &[(usize, Type)]) -> Type {
+            match ty {
+                Type::Var(n) => map
+                    .iter()
+                    .find(|(x, _)| x == n)
+                    .map_or(ty.clone(), |(_, x)| x.clone()),
+                Type::Fun(lhs, rhs) => Type::Fun(Box::new(go(lhs, map)), Box::new(go(rhs, map))),
+                Type::Tuple(subs) => Type::Tuple(subs.iter().map(|x| go(x, map)).collect()),
+                _ => ty.clone(),
+            }
+        }
+        let map = scheme
+            .vars
+            .iter()
+            .map(|&x| (x, self.fresh()))
+            .collect::<Vec<_>>();
+        go(&scheme.ty, &map)
+    }
+
+    /// The type written as `ty`, with a new variable for each unknown part.
+    fn annotated(&mut self, ty: &Ty) -> Type {
+        match ty {
+            Ty::UnitTy => Type::Unit,
+            Ty::IntTy => Type::Int,
+            Ty::BoolTy => Type::Bool,
+            Ty::UnkTy => self.fresh(),
+            Ty::AbsTy(lhs, rhs) => {
+                Type::Fun(Box::new(self.annotated(lhs)), Box::new(self.annotated(rhs)))
+            }
+            Ty::DataTy(name) => Type::Data(name.clone()),
+        }
+    }
+
+    fn bind(&mut self, name: &str, ty: Type) {
+        let scheme = Scheme { vars: vec![], ty };
+        self.env.push((name.to_string(), scheme));
+    }
+
+    /// The type of the constructor `ctor`, which takes its arguments one at a time.
+    fn ctor(&mut self, ctor: &str) -> Option<Type> {
+        let data_types = self.data_types;
+        let (dt, arm) = data_types
+            .iter()
+            .find_map(|dt| Some((dt, dt.arms.iter().find(|x| x.ctor == ctor)?)))?;
+        let data = Type::Data(dt.name.clone());
+        Some(arm.arg_tys.iter().rev().fold(data, |res, ty| {
+            Type::Fun(Box::new(self.annotated(ty)), Box::new(res))
+        }))
+    }
+
+    fn builtin(&mut self, op: &BuiltinOp) -> Type {
+        let fun = |a, b| Type::Fun(Box::new(a), Box::new(b));
+        match op {
+            BuiltinOp::Println | BuiltinOp::Print => fun(self.fresh(), Type::Unit),
+            BuiltinOp::Panic => fun(self.fresh(), self.fresh()),
+            BuiltinOp::True | BuiltinOp::False => Type::Bool,
+            BuiltinOp::ReadInt => fun(Type::Unit, Type::Int),
+            BuiltinOp::ReadLine => fun(Type::Unit, Type::Str),
+            // whatever the embedding application made it
+            BuiltinOp::Host(_) => self.fresh(),
+        }
+    }
+
+    /// Fails at `at` unless its type `found` can be `expected`.
+    fn expect(&mut self, expected: &Type, found: &Type, at: &'a Expr) -> Result<(), TypeErr<'a>> {
+        self.unify(expected, found)
+            .map_err(|kind| TypeErr { expr: at, kind })
+    }
+
+    /// Binds the binders of `ptn`, which matches a value of type `ty` in the match `at`.
+    fn ptn(&mut self, ptn: &'a MatchPattern, ty: &Type, at: &'a Expr) -> Result<(), TypeErr<'a>> {
+        match ptn {
+            MatchPattern::Binder { name } => self.bind(name, ty.clone()),
+            MatchPattern::Lit { val } => {
+                let lit = self.expr(val)?;
+                self.expect(&lit, ty, at)?;
+            }
+            MatchPattern::Tuple { subs } => {
+                let tys = subs.iter().map(|_| self.fresh()).collect::<Vec<_>>();
+                self.expect(&Type::Tuple(tys.clone()), ty, at)?;
+                for (sub, ty) in subs.iter().zip(tys.iter()) {
+                    self.ptn(sub, ty, at)?;
+                }
+            }
+            MatchPattern::DataType { ctor, subs } => {
+                let Some(cty) = self.ctor(ctor) else {
+                    let ctor = ctor.clone();
+                    return Err(TypeErr {
+                        expr: at,
+                        kind: TypeErrKind::UnknownCtor { ctor },
+                    });
+                };
+                let tys = subs.iter().map(|_| self.fresh()).collect::<Vec<_>>();
+                let fun = tys.iter().rev().fold(ty.clone(), |res, x| {
+                    Type::Fun(Box::new(x.clone()), Box::new(res))
+                });
+                self.expect(&cty, &fun, at)?;
+                for (sub, ty) in subs.iter().zip(tys.iter()) {
+                    self.ptn(sub, ty, at)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn letrec(&mut self, arms: &'a [LetRecArm]) -> Result<(), TypeErr<'a>> {
+        let n = self.env.len();
+        for arm in arms.iter() {
+            let ty = self.annotated(&arm.fn_ty);
+            self.bind(&arm.fn_name, ty);
+        }
+        for (i, arm) in arms.iter().enumerate() {
+            let arg = self.annotated(&arm.arg_ty);
+            self.bind(&arm.arg_name, arg.clone());
+            let body = self.expr(&arm.body)?;
+            self.env.truncate(n + arms.len());
+            let ty = self.env[n + i].1.ty.clone();
+            self.expect(&ty, &Type::Fun(Box::new(arg), Box::new(body)), &arm.body)?;
+        }
+        // generic only once all of them are known
+        for i in n..self.env.len() {
+            self.env[i].1 = self.generalize(&self.env[i].1.ty, n);
+        }
+        Ok(())
+    }
+
+    /// Infers the type of `e`, and records it.
+    pub fn expr(&mut self, e: &'a Expr) -> Result<Type, TypeErr<'a>> {
+        let ty = match e {
+            Expr::IntLit { .. } => Type::Int,
+            Expr::UnitLit {} => Type::Unit,
+            Expr::Binary { lhs, op, rhs } => {
+                use BinOp::*;
+                let (arg, res) = match op {
+                    Add | Sub | Mul | Div | Rem => (Type::Int, Type::Int),
+                    Gt | Lt | Ge | Le => (Type::Int, Type::Bool),
+                    Eq | Ne => (self.fresh(), Type::Bool),
+                    Land | Lor | Lxor => (Type::Bool, Type::Bool),
+                };
+                let ty = self.expr(lhs)?;
+                self.expect(&arg, &ty, lhs)?;
+                let ty = self.expr(rhs)?;
+                self.expect(&arg, &ty, rhs)?;
+                res
+            }
+            Expr::Unary { op, sub } => {
+                let arg = match op {
+                    UnaOp::Neg => Type::Int,
+                    UnaOp::Lnot => Type::Bool,
+                };
+                let ty = self.expr(sub)?;
+                self.expect(&arg, &ty, sub)?;
+                arg
+            }
+            Expr::VarRef { id } => {
+                let scheme = self.env.iter().rev().find(|(x, _)| x == id);
+                match scheme.map(|(_, x)| x.clone()) {
+                    Some(scheme) => self.instantiate(&scheme),
+                    None => self.ctor(id).ok_or_else(|| TypeErr {
+                        expr: e,
+                        kind: TypeErrKind::UnknownVarRef { id: id.clone() },
+                    })?,
+                }
+            }
+            Expr::Builtin { op } => self.builtin(op),
+            Expr::App { fun, arg } => {
+                let fty = self.expr(fun)?;
+                let (param, res) = match self.resolve(&fty) {
+                    Type::Fun(param, res) => (*param, *res),
+                    _ => {
+                        let (param, res) = (self.fresh(), self.fresh());
+                        let ty = Type::Fun(Box::new(param.clone()), Box::new(res.clone()));
+                        self.expect(&ty, &fty, fun)?;
+                        (param, res)
+                    }
+                };
+                let ty = self.expr(arg)?;
+                self.expect(&param, &ty, arg)?;
+                res
+            }
+            Expr::Seq { subs } => {
+                let mut ty = Type::Unit;
+                for sub in subs.iter() {
+                    ty = self.expr(sub)?;
+                }
+                ty
+            }
+            Expr::Abs {
+                arg_name,
+                arg_ty,
+                body,
+            } => {
+                let arg = self.annotated(arg_ty);
+                self.bind(arg_name, arg.clone());
+                let body = self.expr(body)?;
+                self.env.pop();
+                Type::Fun(Box::new(arg), Box::new(body))
+            }
+            Expr::Let {
+                name,
+                ty,
+                val,
+                body,
+            } => {
+                let n = self.env.len();
+                let vty = self.expr(val)?;
+                let ty = self.annotated(ty);
+                self.expect(&ty, &vty, val)?;
+                let scheme = self.generalize(&vty, n);
+                self.env.push((name.clone(), scheme));
+                let ty = self.expr(body)?;
+                self.env.truncate(n);
+                ty
+            }
+            Expr::Tuple { subs } => {
+                let subs = subs.iter().map(|x| self.expr(x));
+                Type::Tuple(subs.collect::<Result<_, _>>()?)
+            }
+            Expr::Nth { idx, sub } => {
+                let ty = self.expr(sub)?;
+                match self.resolve(&ty) {
+                    Type::Tuple(subs) if (0..subs.len() as i64).contains(idx) => {
+                        subs[*idx as usize].clone()
+                    }
+                    _ => {
+                        let kind = TypeErrKind::Nth {
+                            idx: *idx,
+                            ty: self.shown(&ty, &mut Vec::new()),
+                        };
+                        return Err(TypeErr { expr: e, kind });
+                    }
+                }
+            }
+            Expr::Ite { cond, tr, fl } => {
+                let ty = self.expr(cond)?;
+                self.expect(&Type::Bool, &ty, cond)?;
+                let ty = self.expr(tr)?;
+                let fty = self.expr(fl)?;
+                self.expect(&ty, &fty, fl)?;
+                ty
+            }
+            Expr::LetRec { arms, body } => {
+                let n = self.env.len();
+                self.letrec(arms)?;
+                let ty = self.expr(body)?;
+                self.env.truncate(n);
+                ty
+            }
+            Expr::Match { sub, arms } => {
+                let ty = self.expr(sub)?;
+                let res = self.fresh();
+                for arm in arms.iter() {
+                    let n = self.env.len();
+                    self.ptn(&arm.ptn, &ty, e)?;
+                    let rty = self.expr(&arm.res)?;
+                    self.expect(&res, &rty, &arm.res)?;
+                    self.env.truncate(n);
+                }
+                res
+            }
+            Expr::Error {} => self.fresh(),
+        };
+        self.types.push((e, ty.clone()));
+        Ok(ty)
+    }
+}
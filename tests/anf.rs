@@ -0,0 +1,242 @@
+//! Lowering to ANF, its printer and verifier, and the SECD code generated from it.
+
+mod common;
+
+use std::fs;
+
+use common::{named, parsed};
+use tut::{
+    ir::anf::{lower, verify, Atom, AtomKind, Comp, CompKind, Term},
+    namer::Namer,
+    parser::parse,
+    pass::ExprTransformer,
+    secd::{
+        machine::{Limits, SECDEffect, SECDMachine},
+        secdgen::secdgen_program,
+    },
+};
+
+/// `src` lowered and printed.
+fn lowered(src: &str) -> String {
+    let main = lower(&parsed(src), None);
+    verify(&main, &[]).unwrap();
+    main.to_string()
+}
+
+#[test]
+fn temporaries() {
+    assert_eq!(
+        lowered("let x = 1 + 2 * 3 in println (x - 1)"),
+        "let %0 = 2 * 3 in
+let x = 1 + %0 in
+let %1 = x - 1 in
+let %2 = println %1 in
+ret %2"
+    );
+    assert_eq!(lowered("println 1; 2"), "let _ = println 1 in\nret 2");
+}
+
+#[test]
+fn join_points() {
+    assert_eq!(
+        lowered("let c = 1 in (if c > 0 then 1 else 2) + 3"),
+        "let c = 1 in
+let %0 = c > 0 in
+join j0 %1 =
+    let %2 = %1 + 3 in
+    ret %2
+in
+if %0 then
+    jump j0 1
+else
+    jump j0 2"
+    );
+    // no join point in tail position, and a let binds the value of the join point
+    assert_eq!(
+        lowered("\\a -> let b = (if a then 1 else 2) in if b then 3 else 4"),
+        "let %1 = fun a ->
+    join j0 b =
+        if b then
+            ret 3
+        else
+            ret 4
+    in
+    if a then
+        jump j0 1
+    else
+        jump j0 2 in
+ret %1"
+    );
+    // the join point keeps binding `v`, which is still used after `w`
+    let src = "let v = (if 8 < 8 then 1 else 2) in let w = v in println v";
+    assert_eq!(
+        lowered(src),
+        "let %0 = 8 < 8 in
+join j0 v =
+    let w = v in
+    let %2 = println v in
+    ret %2
+in
+if %0 then
+    jump j0 1
+else
+    jump j0 2"
+    );
+    assert_eq!(output(src), ["2"]);
+}
+
+fn var(x: &str) -> Atom {
+    Atom {
+        kind: AtomKind::Var(x.to_string()),
+        span: None,
+    }
+}
+
+fn let_(name: &str, val: CompKind, body: Term) -> Term {
+    Term::Let {
+        name: name.to_string(),
+        val: Comp {
+            kind: val,
+            span: None,
+        },
+        body: Box::new(body),
+        span: None,
+    }
+}
+
+#[test]
+fn verifier() {
+    let twice = let_(
+        "%0",
+        CompKind::App(var("f"), var("f")),
+        let_(
+            "%1",
+            CompKind::App(var("%0"), var("%0")),
+            Term::Ret(var("%1")),
+        ),
+    );
+    let globals = ["f".to_string()];
+    assert_eq!(
+        verify(&twice, &globals),
+        Err("temporary %0 is used twice or in another function".to_string())
+    );
+    assert_eq!(verify(&twice, &[]), Err("f is not in scope".to_string()));
+    let unused = let_("%0", CompKind::Atom(var("f")), Term::Ret(var("f")));
+    assert_eq!(
+        verify(&unused, &globals),
+        Err("temporary %0 is never used".to_string())
+    );
+    let jump = let_(
+        "g",
+        CompKind::Abs(
+            "x".to_string(),
+            Box::new(Term::Jump {
+                target: "j0".to_string(),
+                arg: var("x"),
+                span: None,
+            }),
+        ),
+        Term::Ret(var("g")),
+    );
+    let join = Term::Join {
+        name: "j0".to_string(),
+        param: "y".to_string(),
+        rest: Box::new(Term::Ret(var("y"))),
+        body: Box::new(jump),
+        span: None,
+    };
+    assert_eq!(
+        verify(&join, &[]),
+        Err("jump to j0, which is not in scope".to_string())
+    );
+}
+
+#[test]
+fn testcases() {
+    for entry in fs::read_dir("testcases").unwrap() {
+        let path = entry.unwrap().path();
+        let src = fs::read_to_string(&path).unwrap();
+        let Ok(mut prog) = parse(&src) else {
+            continue;
+        };
+        if Namer::new().visit(&mut prog.main_expr).is_err() {
+            continue;
+        }
+        let main = lower(&prog.main_expr, None);
+        assert_eq!(verify(&main, &[]), Ok(()), "{}", path.display());
+    }
+}
+
+/// What `src` prints when run.
+fn output(src: &str) -> Vec<String> {
+    let mut machine = SECDMachine::init(secdgen_program(&named(src))).unwrap();
+    machine.run(Limits::default()).unwrap();
+    let out = machine.sink.effects.iter().map(SECDEffect::text);
+    out.collect::<String>()
+        .lines()
+        .map(|x| x.to_string())
+        .collect()
+}
+
+#[test]
+fn evaluation_order() {
+    let f = "let f = \\x -> println x; x in";
+    assert_eq!(
+        output(&format!("{f} println (f 1 + (println 2; 3))")),
+        ["1", "2", "4"]
+    );
+    assert_eq!(
+        output(&format!("{f} println (f 1 + f 2 * (println 3; 4))")),
+        ["1", "2", "3", "9"]
+    );
+    assert_eq!(
+        output(&format!(
+            "{f} println (f 1 + (if f 2 == 2 then (let y = f 3 in y) else 0))"
+        )),
+        ["1", "2", "3", "4"]
+    );
+}
+
+#[test]
+fn tuples() {
+    assert_eq!(output("println (nth 0 (1, 2, (3, 4)))"), ["1"]);
+    assert_eq!(output("println (nth 2 (1, 2, (3, 4)))"), ["(3, 4)"]);
+    assert_eq!(
+        output("println ((\\p -> nth 0 p + nth 1 p) (5, 6))"),
+        ["11"]
+    );
+}
+
+#[test]
+fn matches() {
+    // an arm that can fail continues with the next one
+    assert_eq!(
+        lowered("match (1, 2) | (0, y) -> y | x -> nth 0 x end"),
+        "let match%0 = (1, 2) in
+join j0 _ =
+    let x = match%0 in
+    let %3 = x.0 in
+    ret %3
+in
+let match%1 = match%0.0 in
+let %2 = match%1 == 0 in
+if %2 then
+    let y = match%0.1 in
+    ret y
+else
+    jump j0 ()"
+    );
+    let f = "let f = \\p -> 1 + (match p | (0, _) -> 10 | (n, 0) -> n | _ -> 20 end) in";
+    assert_eq!(
+        output(&format!(
+            "{f} println (f (0, 1)); println (f (5, 0)); println (f (6, 7))"
+        )),
+        ["11", "6", "21"]
+    );
+    assert_eq!(
+        output("match (1, (2, 3)) | (x, (y, z)) -> println (x + y + z) end"),
+        ["6"]
+    );
+    let mut machine = SECDMachine::init(secdgen_program(&named("match 1 | 0 -> 0 end"))).unwrap();
+    assert!(machine.run(Limits::default()).is_err());
+}
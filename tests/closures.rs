@@ -0,0 +1,98 @@
+//! Free variables, lambda lifting, and flat closures that capture only what they use.
+
+mod common;
+
+use common::{named, parsed, run, testcases};
+use tut::{
+    ast::Expr,
+    ir::anf::{free_vars, lift, lower, verify, Term},
+    secd::{profile::Profiler, secdgen::SECDGen},
+};
+
+/// `src` lowered to ANF.
+fn lowered(src: &str) -> Term {
+    lower(&parsed(src), None)
+}
+
+#[test]
+fn free_variables() {
+    let fvs = |src| free_vars(&lowered(src));
+    assert_eq!(fvs("\\x -> x + y * z + y"), ["y", "z"]);
+    assert_eq!(fvs("let rec f = \\n -> g (f n) in f a"), ["g", "a"]);
+    assert_eq!(
+        fvs("(if a then (let b = 1 in b) else c) + b"),
+        ["a", "c", "b"]
+    );
+}
+
+#[test]
+fn lifting() {
+    let lifted = |src| {
+        let main = lift(lowered(src));
+        verify(&main, &[]).unwrap();
+        main.to_string()
+    };
+    // closed functions are lifted, even if they use others that are lifted
+    assert_eq!(
+        lifted("\\x -> let f = \\y -> y + 1 in (\\z -> f z) x"),
+        "let f = fun y ->
+    let %0 = y + 1 in
+    ret %0 in
+let lam%2 = fun z ->
+    let %1 = f z in
+    ret %1 in
+let %4 = fun x ->
+    let %3 = lam%2 x in
+    ret %3 in
+ret %4"
+    );
+    // a function that captures a parameter stays
+    assert_eq!(
+        lifted("\\x -> let rec f = \\n -> f (n + x) in f"),
+        "let %2 = fun x ->
+    let rec f n =
+        let %0 = n + x in
+        let %1 = f %0 in
+        ret %1
+    in
+    ret f in
+ret %2"
+    );
+}
+
+/// What running `e` prints and returns, and the profile of the run.
+fn run_closures(e: &Expr, flat: bool, lifted: bool) -> (String, Profiler) {
+    let mut main = lower(e, None);
+    if lifted {
+        main = lift(main);
+    }
+    let mut secdgen = SECDGen::new();
+    if flat {
+        secdgen = secdgen.with_flat_closures();
+    }
+    secdgen.gen_main(&main);
+    let (out, machine) = run(secdgen.program());
+    (out, machine.profiler.unwrap())
+}
+
+#[test]
+fn behavior_kept() {
+    for (name, e) in testcases() {
+        let (shared, _) = run_closures(&e, false, false);
+        assert_eq!(run_closures(&e, true, false).0, shared, "{name}");
+        assert_eq!(run_closures(&e, true, true).0, shared, "{name}");
+    }
+}
+
+#[test]
+fn less_env_cloned() {
+    let src = "let a = 1 in let b = 2 in let c = 3 in let d = 4 in
+        let add = \\x -> x + a in
+        let rec sum = \\n -> if n == 0 then b else add n + sum (n - c) in
+        println (sum (30 * d))";
+    let e = named(src);
+    let cloned = |flat, lifted| run_closures(&e, flat, lifted).1.env_cloned;
+    let (shared, flat) = (cloned(false, false), cloned(true, false));
+    assert!(flat * 3 < shared * 2, "{flat} vs {shared}");
+    assert_eq!(cloned(true, true), flat);
+}
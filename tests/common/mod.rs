@@ -0,0 +1,90 @@
+//! Shared by the integration tests.
+
+// Each test uses only some of it.
+#![allow(dead_code)]
+
+use std::fs;
+
+use tut::{
+    ast::Expr,
+    namer::Namer,
+    parser::parse,
+    pass::ExprTransformer,
+    secd::{
+        langdef::SECDInstr,
+        machine::{Limits, SECDMachine},
+        profile::Profiler,
+    },
+};
+
+/// xorshift64, so that failures are reproducible from the seed.
+pub struct Rng(u64);
+
+impl Rng {
+    /// Any seed works, including 0.
+    pub fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9E3779B97F4A7C15) | 1)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    pub fn pick<'a, T>(&mut self, xs: &'a [T]) -> &'a T {
+        &xs[self.below(xs.len())]
+    }
+}
+
+/// The testcases that run to the end without input, to check that a pass keeps their behavior.
+pub const TESTCASES: [&str; 9] = [
+    "fact",
+    "gcd",
+    "evenodd",
+    "summod",
+    "relu",
+    "curry",
+    "namer",
+    "higherorder",
+    "helloworld",
+];
+
+/// The main expressions of `TESTCASES`, named, with their names.
+pub fn testcases() -> impl Iterator<Item = (&'static str, Expr)> {
+    TESTCASES.into_iter().map(|name| {
+        let src = fs::read_to_string(format!("testcases/{name}.ml")).unwrap();
+        (name, named(&src))
+    })
+}
+
+/// The main expression of `src`. Binders in `src` must have distinct names,
+/// as the namer is not run.
+pub fn parsed(src: &str) -> Expr {
+    parse(src).unwrap().main_expr
+}
+
+/// The main expression of `src`, after the namer.
+pub fn named(src: &str) -> Expr {
+    let mut e = parsed(src);
+    Namer::new().visit(&mut e).unwrap();
+    e
+}
+
+/// What running `instrs` prints and returns, and the machine it ran on, with its profile.
+pub fn run(instrs: Vec<SECDInstr>) -> (String, SECDMachine) {
+    let mut machine = SECDMachine::init(instrs).unwrap();
+    machine.profiler = Some(Profiler::new());
+    let res = machine.run(Limits::default());
+    let out = format!(
+        "{:?} {:?}",
+        machine.sink.effects,
+        res.map(|x| x.to_string())
+    );
+    (out, machine)
+}
@@ -0,0 +1,437 @@
+//! Just enough of ANTLR's grammar syntax to read `docs/ref.g4`,
+//! a lexer following ANTLR's rules and an Earley recognizer for the result.
+
+use std::collections::{BTreeMap, HashSet};
+
+#[derive(Debug, Clone)]
+pub enum Atom {
+    Lit(String),
+    Rule(String),
+    /// A lexer rule, i.e. `Integer` or `Ident`.
+    Token(String),
+    Group(Vec<Alt>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rep {
+    One,
+    Opt,
+    Star,
+    Plus,
+}
+
+#[derive(Debug, Clone)]
+pub struct Elem {
+    /// `t1` in `t1=ty`.
+    pub field: Option<String>,
+    pub atom: Atom,
+    pub rep: Rep,
+}
+
+#[derive(Debug, Clone)]
+pub struct Alt {
+    /// `let1` in `# let1`.
+    pub label: Option<String>,
+    pub elems: Vec<Elem>,
+}
+
+impl Alt {
+    /// `rel_ : add` and the like, which only forward to another rule.
+    pub fn passthrough(&self) -> Option<&str> {
+        match &self.elems[..] {
+            [Elem {
+                atom: Atom::Rule(r),
+                rep: Rep::One,
+                ..
+            }] => Some(r),
+            _ => None,
+        }
+    }
+}
+
+/// Parser rules only, lexer rules are hard coded in `lex`.
+pub struct Grammar {
+    pub rules: BTreeMap<String, Vec<Alt>>,
+}
+
+fn g4_tokens(src: &str) -> Vec<String> {
+    let cs = src.chars().collect::<Vec<_>>();
+    let mut toks = Vec::new();
+    let mut i = 0;
+    while i < cs.len() {
+        let c = cs[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if c == '/' && cs.get(i + 1) == Some(&'/') {
+            while i < cs.len() && cs[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        } else if c == '\'' || c == '[' {
+            let close = if c == '\'' { '\'' } else { ']' };
+            i += 1;
+            while cs[i] != close {
+                i += if cs[i] == '\\' { 2 } else { 1 };
+            }
+            i += 1;
+        } else if c.is_alphanumeric() || c == '_' {
+            while i < cs.len() && (cs[i].is_alphanumeric() || cs[i] == '_') {
+                i += 1;
+            }
+        } else if c == '-' && cs.get(i + 1) == Some(&'>') {
+            i += 2;
+        } else {
+            i += 1;
+        }
+        toks.push(cs[start..i].iter().collect());
+    }
+    toks
+}
+
+/// `'\\'` to `\`.
+fn unquote(s: &str) -> String {
+    let mut out = String::new();
+    let mut cs = s[1..s.len() - 1].chars();
+    while let Some(c) = cs.next() {
+        out.push(if c == '\\' { cs.next().unwrap() } else { c });
+    }
+    out
+}
+
+struct G4Parser {
+    toks: Vec<String>,
+    pos: usize,
+}
+
+impl G4Parser {
+    fn peek(&self) -> &str {
+        self.toks.get(self.pos).map_or("", |x| x)
+    }
+
+    fn next(&mut self) -> String {
+        self.pos += 1;
+        self.toks[self.pos - 1].clone()
+    }
+
+    fn expect(&mut self, s: &str) {
+        let t = self.next();
+        assert_eq!(t, s, "at g4 token {}", self.pos);
+    }
+
+    fn alts(&mut self) -> Vec<Alt> {
+        let mut alts = vec![self.alt()];
+        while self.peek() == "|" {
+            self.next();
+            alts.push(self.alt());
+        }
+        alts
+    }
+
+    fn alt(&mut self) -> Alt {
+        let mut elems = Vec::new();
+        let mut label = None;
+        loop {
+            match self.peek() {
+                "|" | ")" | ";" => break,
+                "#" => {
+                    self.next();
+                    label = Some(self.next());
+                }
+                "EOF" => {
+                    // sentences are always checked as a whole
+                    self.next();
+                }
+                "<" => {
+                    // <assoc=right>, only matters for left recursive rules
+                    while self.next() != ">" {}
+                }
+                _ => elems.push(self.elem()),
+            }
+        }
+        Alt { label, elems }
+    }
+
+    fn elem(&mut self) -> Elem {
+        let mut field = None;
+        if self.toks.get(self.pos + 1).map(|x| &x[..]) == Some("=") {
+            field = Some(self.next());
+            self.next();
+        }
+        let t = self.next();
+        let atom = if t == "(" {
+            let alts = self.alts();
+            self.expect(")");
+            Atom::Group(alts)
+        } else if t.starts_with('\'') {
+            Atom::Lit(unquote(&t))
+        } else if t.starts_with(char::is_uppercase) {
+            Atom::Token(t)
+        } else {
+            Atom::Rule(t)
+        };
+        let rep = match self.peek() {
+            "?" => Rep::Opt,
+            "*" => Rep::Star,
+            "+" => Rep::Plus,
+            _ => Rep::One,
+        };
+        if rep != Rep::One {
+            self.next();
+        }
+        Elem { field, atom, rep }
+    }
+}
+
+impl Grammar {
+    pub fn read(src: &str) -> Self {
+        let mut p = G4Parser {
+            toks: g4_tokens(src),
+            pos: 0,
+        };
+        let mut rules = BTreeMap::new();
+        while p.pos < p.toks.len() {
+            let name = p.next();
+            if name == "grammar" || name == "fragment" || name.starts_with(char::is_uppercase) {
+                while p.next() != ";" {}
+                continue;
+            }
+            p.expect(":");
+            let alts = p.alts();
+            p.expect(";");
+            rules.insert(name, alts);
+        }
+        Grammar { rules }
+    }
+
+    /// Every literal, which the lexer treats as a keyword or punctuation.
+    pub fn literals(&self) -> Vec<String> {
+        fn walk(alts: &[Alt], out: &mut Vec<String>) {
+            for e in alts.iter().flat_map(|x| x.elems.iter()) {
+                match &e.atom {
+                    Atom::Lit(s) if !out.contains(s) => out.push(s.clone()),
+                    Atom::Group(alts) => walk(alts, out),
+                    _ => (),
+                }
+            }
+        }
+        let mut out = Vec::new();
+        for alts in self.rules.values() {
+            walk(alts, &mut out);
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Tok {
+    Lit(String),
+    Integer,
+    Ident,
+    /// An `Ident` that names a datatype.
+    TypeName,
+}
+
+fn is_word(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Longest match, ties go to literals as they come first in an ANTLR lexer.
+pub fn lex(literals: &[String], type_names: &[&str], src: &str) -> Option<Vec<Tok>> {
+    let mut toks = Vec::new();
+    let mut i = src;
+    loop {
+        i = i.trim_start();
+        if i.starts_with("--") {
+            i = i.find('\n').map_or("", |n| &i[n..]);
+            continue;
+        }
+        if i.is_empty() {
+            return Some(toks);
+        }
+        let word = i.find(|c| !is_word(c)).unwrap_or(i.len());
+        let mut best = match i.chars().next().unwrap() {
+            c if c.is_ascii_digit() => {
+                let n = i.find(|c: char| !c.is_ascii_digit()).unwrap_or(i.len());
+                Some((n, Tok::Integer))
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => match type_names.contains(&&i[..word]) {
+                true => Some((word, Tok::TypeName)),
+                false => Some((word, Tok::Ident)),
+            },
+            _ => None,
+        };
+        for lit in literals {
+            if i.starts_with(&lit[..]) && best.as_ref().map_or(true, |(n, _)| lit.len() >= *n) {
+                best = Some((lit.len(), Tok::Lit(lit.clone())));
+            }
+        }
+        let (n, tok) = best?;
+        toks.push(tok);
+        i = &i[n..];
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Sym {
+    T(Tok),
+    N(usize),
+}
+
+/// The grammar in BNF, for the recognizer.
+///
+/// Unlike in `ref.g4`, an `Ident` in a type must name a datatype,
+/// as the parser only accepts declared datatype names there.
+pub struct Bnf {
+    prods: Vec<(usize, Vec<Sym>)>,
+    nullable: Vec<bool>,
+    nnonterms: usize,
+    start: usize,
+}
+
+impl Bnf {
+    pub fn new(g: &Grammar, start: &str) -> Self {
+        let names = g.rules.keys().cloned().collect::<Vec<_>>();
+        let mut bnf = Bnf {
+            prods: Vec::new(),
+            nullable: Vec::new(),
+            nnonterms: names.len(),
+            start: 0,
+        };
+        for (n, (r, alts)) in g.rules.iter().enumerate() {
+            bnf.add_alts(&names, r == "ty", n, alts);
+        }
+        // S' -> start, so that acceptance is a single completed item
+        bnf.start = bnf.fresh();
+        let s = names.iter().position(|x| x == start).unwrap();
+        bnf.prods.push((bnf.start, vec![Sym::N(s)]));
+
+        bnf.nullable = vec![false; bnf.nnonterms];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (lhs, rhs) in bnf.prods.iter() {
+                if !bnf.nullable[*lhs]
+                    && rhs
+                        .iter()
+                        .all(|x| matches!(x, Sym::N(n) if bnf.nullable[*n]))
+                {
+                    bnf.nullable[*lhs] = true;
+                    changed = true;
+                }
+            }
+        }
+        bnf
+    }
+
+    fn fresh(&mut self) -> usize {
+        self.nnonterms += 1;
+        self.nnonterms - 1
+    }
+
+    fn add_alts(&mut self, names: &[String], in_ty: bool, lhs: usize, alts: &[Alt]) {
+        for alt in alts {
+            let rhs = alt
+                .elems
+                .iter()
+                .map(|e| self.elem(names, in_ty, e))
+                .collect();
+            self.prods.push((lhs, rhs));
+        }
+    }
+
+    fn elem(&mut self, names: &[String], in_ty: bool, e: &Elem) -> Sym {
+        let one = match &e.atom {
+            Atom::Lit(s) => Sym::T(Tok::Lit(s.clone())),
+            Atom::Token(t) if t == "Integer" => Sym::T(Tok::Integer),
+            Atom::Token(t) if t == "Ident" && in_ty => Sym::T(Tok::TypeName),
+            Atom::Token(t) if t == "Ident" => Sym::T(Tok::Ident),
+            Atom::Token(t) => panic!("unknown token {t}"),
+            Atom::Rule(r) => Sym::N(names.iter().position(|x| x == r).unwrap()),
+            Atom::Group(alts) => {
+                let n = self.fresh();
+                self.add_alts(names, in_ty, n, alts);
+                Sym::N(n)
+            }
+        };
+        if e.rep == Rep::One {
+            return one;
+        }
+        let n = self.fresh();
+        match e.rep {
+            Rep::Opt => {
+                self.prods.push((n, vec![]));
+                self.prods.push((n, vec![one]));
+            }
+            Rep::Star => {
+                self.prods.push((n, vec![]));
+                self.prods.push((n, vec![Sym::N(n), one]));
+            }
+            Rep::Plus => {
+                self.prods.push((n, vec![one.clone()]));
+                self.prods.push((n, vec![Sym::N(n), one]));
+            }
+            Rep::One => unreachable!(),
+        }
+        Sym::N(n)
+    }
+
+    /// Earley recognizer, with the Aycock-Horspool fix for nullable nonterminals.
+    pub fn accepts(&self, toks: &[Tok]) -> bool {
+        // (production, dot, origin)
+        type Item = (usize, usize, usize);
+        let mut sets: Vec<Vec<Item>> = vec![Vec::new(); toks.len() + 1];
+        let mut seen: Vec<HashSet<Item>> = vec![HashSet::new(); toks.len() + 1];
+        let add = |sets: &mut Vec<Vec<Item>>, seen: &mut Vec<HashSet<Item>>, k: usize, it| {
+            if seen[k].insert(it) {
+                sets[k].push(it);
+            }
+        };
+        for (p, (lhs, _)) in self.prods.iter().enumerate() {
+            if *lhs == self.start {
+                add(&mut sets, &mut seen, 0, (p, 0, 0));
+            }
+        }
+        for k in 0..=toks.len() {
+            let mut j = 0;
+            while j < sets[k].len() {
+                let (p, dot, origin) = sets[k][j];
+                j += 1;
+                let (lhs, rhs) = &self.prods[p];
+                match rhs.get(dot) {
+                    None => {
+                        for m in 0..sets[origin].len() {
+                            let (q, qdot, qorigin) = sets[origin][m];
+                            if self.prods[q].1.get(qdot) == Some(&Sym::N(*lhs)) {
+                                add(&mut sets, &mut seen, k, (q, qdot + 1, qorigin));
+                            }
+                        }
+                    }
+                    Some(Sym::N(n)) => {
+                        for (q, (qlhs, _)) in self.prods.iter().enumerate() {
+                            if qlhs == n {
+                                add(&mut sets, &mut seen, k, (q, 0, k));
+                            }
+                        }
+                        if self.nullable[*n] {
+                            add(&mut sets, &mut seen, k, (p, dot + 1, origin));
+                        }
+                    }
+                    Some(Sym::T(t)) => {
+                        let matches = match (t, toks.get(k)) {
+                            (Tok::Ident, Some(Tok::TypeName)) => true,
+                            (t, tok) => tok == Some(t),
+                        };
+                        if matches {
+                            add(&mut sets, &mut seen, k + 1, (p, dot + 1, origin));
+                        }
+                    }
+                }
+            }
+        }
+        sets[toks.len()]
+            .iter()
+            .any(|&(p, dot, origin)| self.prods[p].0 == self.start && dot == 1 && origin == 0)
+    }
+}
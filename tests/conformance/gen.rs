@@ -0,0 +1,261 @@
+//! Random derivations of the grammar.
+
+use std::collections::BTreeMap;
+
+use super::g4::{Alt, Atom, Elem, Grammar, Rep, Tok};
+use crate::common::Rng;
+
+/// A derivation. Groups are flattened into the node of the rule they appear in.
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub rule: String,
+    pub label: Option<String>,
+    /// The field of the parent this node was derived for, e.g. `t1` in `t1=ty`.
+    pub field: Option<String>,
+    pub kids: Vec<Kid>,
+    /// Ranges of `kids` from `?`, `*` and `+` that can be dropped.
+    pub opt: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Kid {
+    Tok(Tok, String),
+    Node(Node),
+}
+
+impl Node {
+    pub fn text(&self) -> Vec<String> {
+        let mut out = Vec::new();
+        self.text_into(&mut out);
+        out
+    }
+
+    fn text_into(&self, out: &mut Vec<String>) {
+        for kid in self.kids.iter() {
+            match kid {
+                Kid::Tok(_, s) => out.push(s.clone()),
+                Kid::Node(n) => n.text_into(out),
+            }
+        }
+    }
+
+    /// Nodes in preorder.
+    pub fn nodes(&self) -> Vec<&Node> {
+        let mut out = vec![self];
+        for kid in self.kids.iter() {
+            if let Kid::Node(n) = kid {
+                out.extend(n.nodes());
+            }
+        }
+        out
+    }
+
+    /// A copy with `f` applied to the `idx`-th node in preorder.
+    pub fn map_nth(&self, idx: usize, f: &dyn Fn(&Node) -> Node) -> Node {
+        fn go(n: &Node, idx: &mut usize, f: &dyn Fn(&Node) -> Node) -> Node {
+            if *idx == 0 {
+                *idx = usize::MAX;
+                return f(n);
+            }
+            *idx -= 1;
+            let kids = n
+                .kids
+                .iter()
+                .map(|kid| match kid {
+                    Kid::Node(m) if *idx != usize::MAX => Kid::Node(go(m, idx, f)),
+                    _ => kid.clone(),
+                })
+                .collect();
+            Node { kids, ..n.clone() }
+        }
+        go(self, &mut { idx }, f)
+    }
+
+    /// A copy with the `idx`-th node in preorder replaced.
+    pub fn replace(&self, idx: usize, new: &Node) -> Node {
+        self.map_nth(idx, &|n| Node {
+            field: n.field.clone(),
+            ..new.clone()
+        })
+    }
+
+    /// A copy without the kids in the `j`-th optional range.
+    pub fn drop_opt(&self, j: usize) -> Node {
+        let (s, e) = self.opt[j];
+        let mut kids = self.kids.clone();
+        kids.drain(s..e);
+        let opt = self
+            .opt
+            .iter()
+            .filter(|&&(s1, e1)| !(s <= s1 && e1 <= e))
+            .map(|&(s1, e1)| match (s1 >= e, e1 >= e) {
+                (true, _) => (s1 - (e - s), e1 - (e - s)),
+                // an enclosing range
+                (false, true) => (s1, e1 - (e - s)),
+                (false, false) => (s1, e1),
+            })
+            .collect();
+        Node {
+            kids,
+            opt,
+            ..self.clone()
+        }
+    }
+}
+
+/// `T` is also a type name, as in `testcases/ambig.ml`.
+pub const VARS: &[&str] = &["a", "b", "f", "x", "xs", "T"];
+pub const TYPE_NAMES: &[&str] = &["T", "List"];
+
+/// Derives sentences bounded by a budget,
+/// which limits the number of non-trivial rules on any path of the tree.
+pub struct Gen<'g> {
+    g: &'g Grammar,
+    pub rng: Rng,
+    /// The least budget each rule needs.
+    cost: BTreeMap<String, usize>,
+    /// Names of datatypes declared so far.
+    declared: Vec<String>,
+}
+
+const INF: usize = usize::MAX / 2;
+
+impl<'g> Gen<'g> {
+    pub fn new(g: &'g Grammar, seed: u64) -> Self {
+        let mut gen = Gen {
+            g,
+            rng: Rng::new(seed),
+            cost: g.rules.keys().map(|r| (r.clone(), INF)).collect(),
+            declared: Vec::new(),
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (r, alts) in g.rules.iter() {
+                let c = alts.iter().map(|x| gen.alt_cost(x)).min().unwrap();
+                if c < gen.cost[r] {
+                    gen.cost.insert(r.clone(), c);
+                    changed = true;
+                }
+            }
+        }
+        gen
+    }
+
+    fn alt_cost(&self, alt: &Alt) -> usize {
+        match alt.passthrough() {
+            Some(r) => self.cost[r],
+            None => self.seq_cost(&alt.elems).saturating_add(1),
+        }
+    }
+
+    fn seq_cost(&self, elems: &[Elem]) -> usize {
+        elems.iter().map(|x| self.elem_cost(x)).max().unwrap_or(0)
+    }
+
+    fn elem_cost(&self, e: &Elem) -> usize {
+        match e.rep {
+            Rep::Opt | Rep::Star => 0,
+            Rep::One | Rep::Plus => self.atom_cost(&e.atom),
+        }
+    }
+
+    fn atom_cost(&self, a: &Atom) -> usize {
+        match a {
+            Atom::Lit(_) | Atom::Token(_) => 0,
+            Atom::Rule(r) => self.cost[r],
+            Atom::Group(alts) => alts.iter().map(|x| self.seq_cost(&x.elems)).min().unwrap(),
+        }
+    }
+
+    pub fn cost(&self, r: &str) -> usize {
+        self.cost[r]
+    }
+
+    /// A derivation of `r`, unless the budget is too small.
+    pub fn try_rule(&mut self, r: &str, budget: usize) -> Option<Node> {
+        (self.cost[r] <= budget).then(|| self.rule(r, budget))
+    }
+
+    pub fn rule(&mut self, r: &str, budget: usize) -> Node {
+        let alts = self.g.rules[r]
+            .iter()
+            .filter(|x| self.alt_cost(x) <= budget)
+            .collect::<Vec<_>>();
+        let alt = *self.rng.pick(&alts);
+        let mut n = Node {
+            rule: r.to_string(),
+            label: alt.label.clone(),
+            field: None,
+            kids: Vec::new(),
+            opt: Vec::new(),
+        };
+        match alt.passthrough() {
+            Some(sub) => n.kids.push(Kid::Node(self.rule(sub, budget))),
+            None => self.elems(&alt.elems, budget - 1, &mut n),
+        }
+        n
+    }
+
+    fn elems(&mut self, elems: &[Elem], budget: usize, n: &mut Node) {
+        for e in elems {
+            let affordable = self.atom_cost(&e.atom) <= budget;
+            let reps = match e.rep {
+                Rep::One => 1,
+                Rep::Plus => 1 + self.rng.below(2),
+                Rep::Opt if affordable => self.rng.below(2),
+                Rep::Star if affordable => self.rng.below(3),
+                Rep::Opt | Rep::Star => 0,
+            };
+            for j in 0..reps {
+                let start = n.kids.len();
+                self.atom(&e.field, &e.atom, budget, n);
+                let droppable = e.rep != Rep::One && !(e.rep == Rep::Plus && j == 0);
+                if droppable && n.kids.len() > start {
+                    n.opt.push((start, n.kids.len()));
+                }
+            }
+        }
+    }
+
+    fn atom(&mut self, field: &Option<String>, a: &Atom, budget: usize, n: &mut Node) {
+        match a {
+            Atom::Lit(s) => n.kids.push(Kid::Tok(Tok::Lit(s.clone()), s.clone())),
+            Atom::Token(t) if t == "Integer" => {
+                let s = format!("{}", self.rng.below(100));
+                n.kids.push(Kid::Tok(Tok::Integer, s));
+            }
+            Atom::Token(_) => {
+                let s = self.ident(&n.rule);
+                n.kids.push(Kid::Tok(Tok::Ident, s));
+            }
+            Atom::Rule(sub) => {
+                let mut m = self.rule(sub, budget);
+                m.field = field.clone();
+                n.kids.push(Kid::Node(m));
+            }
+            Atom::Group(alts) => {
+                let alts = alts
+                    .iter()
+                    .filter(|x| self.seq_cost(&x.elems) <= budget)
+                    .collect::<Vec<_>>();
+                let alt = *self.rng.pick(&alts);
+                self.elems(&alt.elems, budget, n);
+            }
+        }
+    }
+
+    /// Type names are only valid once declared, which a CFG cannot say.
+    fn ident(&mut self, r: &str) -> String {
+        match r {
+            "dataType" => {
+                let s = self.rng.pick(TYPE_NAMES).to_string();
+                self.declared.push(s.clone());
+                s
+            }
+            "ty" if !self.declared.is_empty() => self.rng.pick(&self.declared).clone(),
+            "ty" => self.rng.pick(TYPE_NAMES).to_string(),
+            _ => self.rng.pick(VARS).to_string(),
+        }
+    }
+}
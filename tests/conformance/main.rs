@@ -0,0 +1,244 @@
+//! Checks the nom parser against the reference grammar `docs/ref.g4`.
+//!
+//! Sentences derived from the grammar must parse to the tree of their derivation.
+//! Mutations of them must be accepted by the parser exactly when the grammar accepts them,
+//! which is decided by an Earley recognizer.
+//! Mismatches are minimized before they are reported.
+
+#[path = "../common/mod.rs"]
+mod common;
+mod g4;
+mod gen;
+mod shape;
+
+use std::fs;
+
+use g4::{lex, Bnf, Grammar};
+use gen::{Gen, Kid, Node, TYPE_NAMES};
+use tut::{parser::parse, printer::print_prog};
+
+const SENTENCES: u64 = 600;
+const MUTANTS: usize = 4;
+const MAX_TOKENS: usize = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mismatch {
+    /// Derived from the grammar, but rejected by the parser.
+    Rejected,
+    /// Derived from the grammar, but parsed to another tree.
+    WrongTree,
+    /// Accepted by the parser, but not by the grammar.
+    Accepted,
+}
+
+struct Checker {
+    literals: Vec<String>,
+    bnf: Bnf,
+}
+
+/// Type names must be declared, which the grammar cannot say.
+fn undeclared_types(tree: &Node) -> bool {
+    let mut declared = Vec::new();
+    tree.nodes().into_iter().any(|n| match n.label.as_deref() {
+        _ if n.rule == "dataType" => {
+            declared.push(n.idents()[0].clone());
+            false
+        }
+        Some("tyIdent") => !declared.contains(&n.idents()[0]),
+        _ => false,
+    })
+}
+
+/// Like `undeclared_types`, but for any sentence, so more conservative.
+/// Also rejects datatype names that `lex` would not take as type names.
+fn undeclared_types_toks(toks: &[String]) -> bool {
+    let mut declared = Vec::new();
+    for (i, t) in toks.iter().enumerate() {
+        if i > 0 && toks[i - 1] == "datatype" {
+            if !TYPE_NAMES.contains(&&t[..]) {
+                return true;
+            }
+            declared.push(t);
+        } else if TYPE_NAMES.contains(&&t[..]) && !declared.contains(&t) {
+            return true;
+        }
+    }
+    false
+}
+
+/// `<assoc=right>` makes `a -> b -> c` unambiguous, so derivations of the other tree are skipped.
+fn ambiguous(tree: &Node) -> bool {
+    tree.nodes().iter().any(|n| {
+        n.label.as_deref() == Some("tyArrow")
+            && matches!(&n.kids[0], Kid::Node(m) if m.label.as_deref() == Some("tyArrow"))
+    })
+}
+
+impl Checker {
+    fn g4_accepts(&self, text: &str) -> bool {
+        lex(&self.literals, TYPE_NAMES, text).map_or(false, |toks| self.bnf.accepts(&toks))
+    }
+
+    fn check_tree(&self, tree: &Node) -> Option<(Mismatch, String)> {
+        let text = tree.text().join(" ");
+        let expected = shape::prog(tree);
+        match parse(&text) {
+            Err(err) => Some((Mismatch::Rejected, format!("{err:?}"))),
+            Ok(prog) if prog != expected => {
+                let detail = format!(
+                    "parsed as\n{}\nbut the grammar derives\n{}",
+                    print_prog(&prog),
+                    print_prog(&expected)
+                );
+                Some((Mismatch::WrongTree, detail))
+            }
+            Ok(_) => None,
+        }
+    }
+
+    fn check_toks(&self, toks: &[String]) -> Option<Mismatch> {
+        if undeclared_types_toks(toks) {
+            return None;
+        }
+        let text = toks.join(" ");
+        match (self.g4_accepts(&text), parse(&text).is_ok()) {
+            (true, false) => Some(Mismatch::Rejected),
+            (false, true) => Some(Mismatch::Accepted),
+            _ => None,
+        }
+    }
+
+    /// Replace subtrees by smaller derivations of the same rule while the mismatch persists.
+    fn minimize_tree(&self, gen: &mut Gen, mut tree: Node, kind: Mismatch) -> Node {
+        let still = |t: &Node| {
+            !ambiguous(t) && !undeclared_types(t) && self.check_tree(t).map(|x| x.0) == Some(kind)
+        };
+        let mut progress = true;
+        while progress {
+            progress = false;
+            let size = tree.text().len();
+            let nodes = tree.nodes();
+            let mut candidates = Vec::new();
+            for (i, n) in nodes.iter().enumerate() {
+                for j in 0..n.opt.len() {
+                    candidates.push(tree.map_nth(i, &|n| n.drop_opt(j)));
+                }
+                // hoist a descendant of the same rule
+                for m in n.nodes().into_iter().skip(1) {
+                    if m.rule == n.rule {
+                        candidates.push(tree.replace(i, m));
+                    }
+                }
+                for budget in 0..3 {
+                    for _ in 0..3 {
+                        if let Some(m) = gen.try_rule(&n.rule, budget) {
+                            candidates.push(tree.replace(i, &m));
+                        }
+                    }
+                }
+            }
+            if let Some(t) = candidates
+                .into_iter()
+                .filter(|t| t.text().len() < size && still(t))
+                .min_by_key(|t| t.text().len())
+            {
+                tree = t;
+                progress = true;
+            }
+        }
+        tree
+    }
+
+    /// Drop tokens while the mismatch persists.
+    fn minimize_toks(&self, mut toks: Vec<String>, kind: Mismatch) -> Vec<String> {
+        let mut len = toks.len() / 2;
+        while len > 0 {
+            let mut i = 0;
+            while i + len <= toks.len() {
+                let mut shorter = toks.clone();
+                shorter.drain(i..i + len);
+                if self.check_toks(&shorter) == Some(kind) {
+                    toks = shorter;
+                } else {
+                    i += 1;
+                }
+            }
+            len /= 2;
+        }
+        toks
+    }
+}
+
+fn mutate(gen: &mut Gen, literals: &[String], toks: &[String]) -> Vec<String> {
+    let mut toks = toks.to_vec();
+    let i = gen.rng.below(toks.len());
+    match gen.rng.below(5) {
+        0 => {
+            toks.remove(i);
+        }
+        1 => toks.insert(i, toks[i].clone()),
+        2 if i + 1 < toks.len() => toks.swap(i, i + 1),
+        3 => toks.insert(i, gen.rng.pick(literals).clone()),
+        _ => toks[i] = gen.rng.pick(literals).clone(),
+    }
+    toks
+}
+
+#[test]
+fn conformance() {
+    let g = Grammar::read(&fs::read_to_string("docs/ref.g4").unwrap());
+    let checker = Checker {
+        literals: g.literals(),
+        bnf: Bnf::new(&g, "top"),
+    };
+
+    let mut reports: Vec<(Mismatch, String, String)> = Vec::new();
+    let mut report = |kind, text: String, detail: String| {
+        if !reports.iter().any(|x| x.0 == kind && x.1 == text) {
+            reports.push((kind, text, detail));
+        }
+    };
+    for seed in 0..SENTENCES {
+        let mut gen = Gen::new(&g, seed);
+        let budget = gen.cost("top") + seed as usize % 5;
+        let tree = gen.rule("top", budget);
+        let toks = tree.text();
+        if toks.len() > MAX_TOKENS || ambiguous(&tree) || undeclared_types(&tree) {
+            continue;
+        }
+        let text = toks.join(" ");
+        assert!(
+            checker.g4_accepts(&text),
+            "the recognizer rejects a derivation: {text}"
+        );
+
+        if let Some((kind, _)) = checker.check_tree(&tree) {
+            let tree = checker.minimize_tree(&mut gen, tree, kind);
+            let (_, detail) = checker.check_tree(&tree).unwrap();
+            report(kind, tree.text().join(" "), detail);
+        }
+
+        for _ in 0..MUTANTS {
+            let mutant = mutate(&mut gen, &checker.literals, &toks);
+            if let Some(kind) = checker.check_toks(&mutant) {
+                let mutant = checker.minimize_toks(mutant, kind);
+                let detail = match parse(&mutant.join(" ")) {
+                    Ok(prog) => format!("parsed as\n{}", print_prog(&prog)),
+                    Err(err) => format!("{err:?}"),
+                };
+                report(kind, mutant.join(" "), detail);
+            }
+        }
+    }
+
+    let msgs = reports
+        .iter()
+        .map(|(kind, text, detail)| format!("{kind:?}: {text}\n{detail}\n"))
+        .collect::<Vec<_>>();
+    assert!(
+        reports.is_empty(),
+        "{} mismatches against docs/ref.g4:\n\n{}",
+        reports.len(),
+        msgs.join("\n")
+    );
+}
@@ -0,0 +1,252 @@
+//! The AST a derivation should parse to.
+
+use tut::ast::*;
+
+use super::gen::{Kid, Node};
+
+impl Node {
+    fn subs(&self, rule: &str) -> Vec<&Node> {
+        self.kids
+            .iter()
+            .filter_map(|x| match x {
+                Kid::Node(n) if n.rule == rule => Some(n),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn sub(&self, rule: &str) -> &Node {
+        self.subs(rule)[0]
+    }
+
+    fn field(&self, field: &str) -> Option<&Node> {
+        self.kids.iter().find_map(|x| match x {
+            Kid::Node(n) if n.field.as_deref() == Some(field) => Some(n),
+            _ => None,
+        })
+    }
+
+    /// Texts of the direct terminals.
+    fn toks(&self) -> Vec<&str> {
+        self.kids
+            .iter()
+            .filter_map(|x| match x {
+                Kid::Tok(_, s) => Some(&s[..]),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// `Ident`s, for rules where they are the only non-literal terminals.
+    pub fn idents(&self) -> Vec<String> {
+        use super::g4::Tok;
+        self.kids
+            .iter()
+            .filter_map(|x| match x {
+                Kid::Tok(Tok::Ident, s) => Some(s.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The only node among the kids, e.g. the `expr` in `'(' expr ')'`.
+    fn inner(&self) -> &Node {
+        let ns = self
+            .kids
+            .iter()
+            .filter_map(|x| match x {
+                Kid::Node(n) => Some(n),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        match &ns[..] {
+            [n] => n,
+            _ => panic!("unexpected {} alternative {:?}", self.rule, self.label),
+        }
+    }
+
+    fn label(&self) -> &str {
+        self.label.as_deref().unwrap_or("")
+    }
+}
+
+pub fn prog(n: &Node) -> Prog {
+    Prog {
+        data_types: n.subs("dataType").into_iter().map(data_type).collect(),
+        main_expr: expr(n.sub("expr")),
+    }
+}
+
+fn data_type(n: &Node) -> DataType {
+    let arms = n
+        .subs("dataTypeArm")
+        .into_iter()
+        .map(|arm| DataTypeArm {
+            ctor: arm.idents()[0].clone(),
+            arg_tys: arm.subs("ty").into_iter().map(ty).collect(),
+        })
+        .collect();
+    DataType {
+        name: n.idents()[0].clone(),
+        arms,
+    }
+}
+
+fn ty(n: &Node) -> Ty {
+    match n.label() {
+        "tyUnit" => Ty::UnitTy,
+        "tyInt" => Ty::IntTy,
+        "tyParen" => ty(n.sub("ty")),
+        "tyIdent" => Ty::DataTy(n.idents()[0].clone()),
+        "tyArrow" => {
+            let tys = n.subs("ty");
+            Ty::AbsTy(Box::new(ty(tys[0])), Box::new(ty(tys[1])))
+        }
+        l => panic!("unknown ty alternative {l}"),
+    }
+}
+
+fn opt_ty(n: Option<&Node>) -> Ty {
+    n.map_or(Ty::UnkTy, ty)
+}
+
+fn boxed(n: &Node) -> Box<Expr> {
+    Box::new(expr(n))
+}
+
+fn binop(n: &Node) -> BinOp {
+    match n.toks()[0] {
+        "*" => BinOp::Mul,
+        "/" => BinOp::Div,
+        "%" => BinOp::Rem,
+        "+" => BinOp::Add,
+        "-" => BinOp::Sub,
+        ">" => BinOp::Gt,
+        "<" => BinOp::Lt,
+        ">=" => BinOp::Ge,
+        "<=" => BinOp::Le,
+        "==" => BinOp::Eq,
+        "!=" => BinOp::Ne,
+        op => panic!("unknown operator {op}"),
+    }
+}
+
+pub fn expr(n: &Node) -> Expr {
+    use Expr::*;
+    match (&n.rule[..], n.label()) {
+        ("let", "let1") => LetRec {
+            arms: n
+                .subs("letRecArm")
+                .into_iter()
+                .map(|arm| {
+                    let ids = arm.idents();
+                    LetRecArm {
+                        fn_name: ids[0].clone(),
+                        fn_ty: opt_ty(arm.field("t1")),
+                        arg_name: ids[1].clone(),
+                        arg_ty: opt_ty(arm.field("t2")),
+                        body: boxed(arm.sub("expr")),
+                    }
+                })
+                .collect(),
+            body: boxed(n.sub("expr")),
+        },
+        ("let", "let2") => {
+            let es = n.subs("expr");
+            Let {
+                name: n.idents()[0].clone(),
+                ty: opt_ty(n.subs("ty").first().copied()),
+                val: boxed(es[0]),
+                body: boxed(es[1]),
+            }
+        }
+        ("mat", "mat1") => Match {
+            sub: boxed(n.sub("expr")),
+            arms: n
+                .subs("matchArm")
+                .into_iter()
+                .map(|arm| MatchArm {
+                    ptn: ptn(arm.sub("ptn")),
+                    res: expr(arm.field("body").unwrap()),
+                })
+                .collect(),
+        },
+        ("lam", "lam1") => Abs {
+            arg_name: n.idents()[0].clone(),
+            arg_ty: opt_ty(n.subs("ty").first().copied()),
+            body: boxed(n.sub("expr")),
+        },
+        ("seq", _) if n.kids.len() > 1 => Seq {
+            subs: n.subs("ite").into_iter().map(boxed).collect(),
+        },
+        ("ite", "ite1") => {
+            let rels = n.subs("rel");
+            Ite {
+                cond: boxed(rels[0]),
+                tr: boxed(rels[1]),
+                fl: boxed(n.sub("ite")),
+            }
+        }
+        ("rel", "rel1") | ("add", "add1") | ("mul", "mul1") => {
+            let operand = match &n.rule[..] {
+                "rel" => "add",
+                "add" => "mul",
+                _ => "una",
+            };
+            Binary {
+                lhs: boxed(n.sub(&n.rule)),
+                op: binop(n.sub(&format!("{}Op", n.rule))),
+                rhs: boxed(n.sub(operand)),
+            }
+        }
+        ("una", "una1") => Unary {
+            op: UnaOp::Neg,
+            sub: boxed(n.sub("una")),
+        },
+        ("app", "app1") => App {
+            fun: boxed(n.sub("app")),
+            arg: boxed(n.sub("atom")),
+        },
+        ("atom", "atomTuple") => Tuple {
+            subs: n.subs("expr").into_iter().map(boxed).collect(),
+        },
+        ("atom", "atomIdent") => VarRef {
+            id: n.idents()[0].clone(),
+        },
+        ("atom", "atomNth") => Nth {
+            idx: n.toks()[1].parse().unwrap(),
+            sub: boxed(n.sub("atom")),
+        },
+        ("builtin", _) => Builtin {
+            op: BUILTIN_PARSE.get(n.toks()[0]).unwrap().clone(),
+        },
+        ("lit", "litInt") => IntLit {
+            val: n.toks()[0].parse().unwrap(),
+        },
+        ("lit", "litUnit") => UnitLit {},
+        ("lit", "litBool") => Builtin {
+            op: BUILTIN_PARSE.get(n.toks()[0]).unwrap().clone(),
+        },
+        // passthroughs, e.g. `rel_ : add`, and parentheses
+        _ => expr(n.inner()),
+    }
+}
+
+fn ptn(n: &Node) -> MatchPattern {
+    match n.label() {
+        "ptnTuple" => MatchPattern::Tuple {
+            subs: n.subs("ptn1").into_iter().map(ptn).collect(),
+        },
+        "ptnData" => MatchPattern::DataType {
+            ctor: n.idents()[0].clone(),
+            subs: n.subs("ptn1").into_iter().map(ptn).collect(),
+        },
+        "ptnBinder" => MatchPattern::Binder {
+            name: n.idents()[0].clone(),
+        },
+        "ptnLit" => MatchPattern::Lit {
+            val: expr(n.sub("lit")),
+        },
+        _ => ptn(n.inner()),
+    }
+}
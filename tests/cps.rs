@@ -0,0 +1,126 @@
+//! CPS conversion, and the SECD code generated from it, which runs without a dump.
+
+mod common;
+
+use common::{named, parsed, run, testcases};
+use tut::{
+    ir::{anf, cps},
+    secd::{
+        cpsgen::cpsgen_program,
+        machine::{Limits, SECDMachine},
+        profile::Profiler,
+        secdgen::secdgen_program,
+    },
+};
+
+/// `src` converted and printed.
+fn converted(src: &str) -> String {
+    cps::convert(&anf::lower(&parsed(src), None)).to_string()
+}
+
+#[test]
+fn conversion() {
+    assert_eq!(
+        converted("let f = \\x -> x + 1 in println (f 2)"),
+        "let fun f x k0 =
+    let %0 = x + 1 in
+    k0 %0
+in
+let cont k1 %1 =
+    let %2 = println %1 in
+    halt %2
+in
+f 2 k1"
+    );
+}
+
+#[test]
+fn no_administrative_redexes() {
+    // a call in tail position is given the continuation of its function
+    assert_eq!(
+        converted("\\f -> \\x -> f x"),
+        "let fun %2 f k0 =
+    let fun %1 x k1 =
+        f x k1
+    in
+    k0 %1
+in
+halt %2"
+    );
+    // a join point that returns its value is the continuation itself
+    assert_eq!(
+        converted("\\f -> \\x -> let y = (if x then f 1 else 2) in y"),
+        "let fun %3 f k0 =
+    let fun %2 x k1 =
+        if x then
+            f 1 k1
+        else
+            k1 2
+    in
+    k0 %2
+in
+halt %3"
+    );
+}
+
+/// The output and result of running `src`, compiled with or without CPS.
+fn run_src(src: &str, cps: bool) -> String {
+    let e = named(src);
+    let instrs = match cps {
+        true => cpsgen_program(&e),
+        false => secdgen_program(&e),
+    };
+    run(instrs).0
+}
+
+#[test]
+fn behavior_kept() {
+    for (name, e) in testcases() {
+        assert_eq!(
+            run(cpsgen_program(&e)).0,
+            run(secdgen_program(&e)).0,
+            "{name}"
+        );
+    }
+}
+
+#[test]
+fn no_dump() {
+    let src = "let rec count = \\n -> if n == 0 then 0 else 1 + count (n - 1) in
+        println (count 1000)";
+    let mut machine = SECDMachine::init(cpsgen_program(&named(src))).unwrap();
+    machine.profiler = Some(Profiler::new());
+    let limits = Limits {
+        max_stack: Some(4),
+        ..Default::default()
+    };
+    machine.run(limits).unwrap();
+    assert_eq!(machine.sink.effects.len(), 1);
+    let profiler = machine.profiler.unwrap();
+    assert_eq!(profiler.returns, 0);
+    assert_eq!(profiler.max_dump, 0);
+}
+
+#[test]
+fn tuples() {
+    for src in [
+        "println (nth 0 (1, 2, (3, 4))); println (nth 2 (1, 2, (3, 4)))",
+        "println ((\\p -> nth 0 p + nth 1 p) (5, 6))",
+    ] {
+        assert_eq!(run_src(src, true), run_src(src, false), "{src}");
+    }
+    assert_eq!(
+        run_src("println (nth 1 (5, 6))", true),
+        r#"[Println("6")] Ok("()")"#
+    );
+}
+
+#[test]
+fn matches() {
+    for src in [
+        "match (1, (2, 3)) | (x, (2, z)) -> println (x + z) | (x, (y, z)) -> println y end",
+        "let f = \\p -> 1 + (match p | 0 -> 10 | 1 -> 20 end) in println (f 0); println (f 1); f 2",
+    ] {
+        assert_eq!(run_src(src, true), run_src(src, false), "{src}");
+    }
+}
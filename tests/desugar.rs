@@ -0,0 +1,132 @@
+//! Desugaring, on its output and on the behavior of the programs it desugars.
+
+mod common;
+
+use std::fs;
+
+use common::{named, parsed};
+use tut::{
+    ast::{Expr, MatchArm},
+    desugar::Desugar,
+    namer::Namer,
+    parser::parse,
+    pass::{ExprListener, ExprTransformer},
+    printer::print_expr,
+    secd::{
+        machine::{Limit, Limits, SECDError, SECDMachine},
+        secdgen::secdgen_program,
+    },
+};
+
+fn desugared(src: &str) -> String {
+    let mut e = parsed(src);
+    Desugar::new().visit(&mut e);
+    print_expr(&e)
+}
+
+#[test]
+fn seq() {
+    assert_eq!(
+        desugared("println 1; println 2; 3"),
+        "let _ = println 1 in\nlet _ = println 2 in\n3"
+    );
+}
+
+#[test]
+fn match_arms() {
+    let src = "match (1, (2, 3)) | x, (2, z) -> x + z | x, (y, 3) -> y | w -> 0 end";
+    let expected = "\
+let sub%0 = (1, (2, 3)) in
+if nth 0 nth 1 sub%0 == 2 then
+    (let x = nth 0 sub%0 in
+    let z = nth 1 nth 1 sub%0 in
+    x + z)
+else if nth 1 nth 1 sub%0 == 3 then
+    (let x = nth 0 sub%0 in
+    let y = nth 0 nth 1 sub%0 in
+    y)
+else
+    (let w = sub%0 in
+    0)";
+    assert_eq!(desugared(src), expected);
+    assert_eq!(
+        desugared("match (1, 2) | 1, 2 -> 3 | x -> 4 end"),
+        "let sub%0 = (1, 2) in\n\
+         if (if nth 0 sub%0 == 1 then nth 1 sub%0 == 2 else 0) then\n    3\n\
+         else\n    (let x = sub%0 in\n    4)"
+    );
+    // no arm after one that always matches, and a panic if none does
+    assert_eq!(
+        desugared("match () | () -> 1 | x -> 2 end"),
+        "let sub%0 = () in\n1"
+    );
+    assert_eq!(
+        desugared("match 1 | 0 -> 1 end"),
+        "let sub%0 = 1 in\nif sub%0 == 0 then 1 else panic ()"
+    );
+}
+
+struct Sugar(usize);
+
+impl ExprListener for Sugar {
+    fn enter_seq(&mut self, _subs: &Vec<Box<Expr>>, _eself: &Expr) {
+        self.0 += 1;
+    }
+
+    fn enter_match(&mut self, _sub: &Expr, _arms: &Vec<MatchArm>, _eself: &Expr) {
+        self.0 += 1;
+    }
+}
+
+/// What `e` prints and returns, desugared first if `desugar`.
+fn run(e: &Expr, desugar: bool) -> (String, Result<String, SECDError>) {
+    let mut e = e.clone();
+    if desugar {
+        Desugar::new().visit(&mut e);
+        let mut sugar = Sugar(0);
+        sugar.walk(&e);
+        assert_eq!(sugar.0, 0, "{}", print_expr(&e));
+    }
+    let mut machine = SECDMachine::init(secdgen_program(&e)).unwrap();
+    let limits = Limits {
+        fuel: Some(1_000_000),
+        ..Limits::default()
+    };
+    let res = machine.run(limits).map(|x| x.to_string());
+    (format!("{:?}", machine.sink.effects), res)
+}
+
+#[test]
+fn behavior_kept() {
+    for src in [
+        "let a = (1, (2, 3)) in match a | x, (2, z) -> x + z | x, (y, z) -> y end",
+        "match (1, (5, 3)) | x, (2, z) -> x + z | x, (y, 3) -> y end",
+        "match 3 | 0 -> 1 | 1 -> 2 end",
+        "(match println 1 | () -> 2 end); println 3",
+        "match (1, 0) | 0, x -> x | 1, 0 -> 7 | y -> 8 end",
+        "match (1, (2, 3)) | 1, (2, 4) -> 0 | 1, (2, 3) -> 5 end",
+    ] {
+        let e = named(src);
+        assert_eq!(run(&e, true), run(&e, false), "{src}");
+    }
+}
+
+/// All of them, as long as they get through the namer and halt.
+#[test]
+fn behavior_kept_testcases() {
+    for entry in fs::read_dir("testcases").unwrap() {
+        let path = entry.unwrap().path();
+        let src = fs::read_to_string(&path).unwrap();
+        let Ok(mut prog) = parse(&src) else {
+            continue;
+        };
+        if Namer::new().visit(&mut prog.main_expr).is_err() {
+            continue;
+        }
+        let out = run(&prog.main_expr, false);
+        if out.1 == Err(SECDError::LimitExceeded(Limit::Fuel)) {
+            continue;
+        }
+        assert_eq!(run(&prog.main_expr, true), out, "{}", path.display());
+    }
+}
@@ -0,0 +1,271 @@
+//! The language server, driven with decoded messages.
+
+use serde_json::{json, Value};
+use tut::lsp::Server;
+
+const URI: &str = "file:///len.ml";
+const SRC: &str = "\
+datatype List =
+    | Cons int List
+    | Nil unit
+end
+let rec len = \\l ->
+    match l
+    | Cons _x xs -> 1 + len xs
+    | Nil _u -> 0
+    end
+in
+let n : int = len (Cons 1 (Nil ())) in
+println n
+";
+
+/// Position of the `nth` occurrence of `s` in `SRC`.
+fn pos(s: &str, nth: usize) -> Value {
+    let (at, _) = SRC.match_indices(s).nth(nth).unwrap();
+    pos_at(at)
+}
+
+fn pos_at(at: usize) -> Value {
+    let line = SRC[..at].matches('\n').count();
+    let line_start = SRC[..at].rfind('\n').map_or(0, |x| x + 1);
+    json!({ "line": line, "character": at - line_start })
+}
+
+/// Range of the first `len` bytes of the `nth` occurrence of `s`.
+fn range(s: &str, nth: usize, len: u64) -> Value {
+    let start = pos(s, nth);
+    let character = start["character"].as_u64().unwrap() + len;
+    let end = json!({ "line": start["line"], "character": character });
+    json!({ "start": start, "end": end })
+}
+
+fn open() -> Server {
+    let mut server = Server::new();
+    server.handle(&json!({ "jsonrpc": "2.0", "id": 0, "method": "initialize", "params": {} }));
+    let out = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": { "textDocument": { "uri": URI, "languageId": "miniml", "version": 0, "text": SRC } },
+    }));
+    assert_eq!(out[0]["params"]["diagnostics"], json!([]));
+    server
+}
+
+fn request(server: &mut Server, method: &str, params: Value) -> Value {
+    let mut params = params;
+    params["textDocument"] = json!({ "uri": URI });
+    let out =
+        server.handle(&json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }));
+    assert_eq!(out.len(), 1);
+    out[0].clone()
+}
+
+#[test]
+fn definition_and_references() {
+    let mut server = open();
+    let res = request(
+        &mut server,
+        "textDocument/definition",
+        json!({ "position": pos("xs", 1) }),
+    );
+    assert_eq!(
+        res["result"],
+        json!({ "uri": URI, "range": range("xs", 0, 2) })
+    );
+
+    let params = json!({ "position": pos("len", 2), "context": { "includeDeclaration": true } });
+    let res = request(&mut server, "textDocument/references", params);
+    let ranges = res["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| x["range"].clone());
+    assert_eq!(
+        ranges.collect::<Vec<_>>(),
+        (0..3).map(|i| range("len", i, 3)).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn hover() {
+    let mut server = open();
+    let res = request(
+        &mut server,
+        "textDocument/hover",
+        json!({ "position": pos_at(SRC.len() - 2) }),
+    );
+    assert_eq!(res["result"]["contents"]["value"], "n : int");
+    let res = request(
+        &mut server,
+        "textDocument/hover",
+        json!({ "position": pos("Cons", 2) }),
+    );
+    assert_eq!(
+        res["result"]["contents"]["value"],
+        "Cons : int -> List -> List"
+    );
+    let res = request(
+        &mut server,
+        "textDocument/hover",
+        json!({ "position": pos("1 +", 0) }),
+    );
+    assert_eq!(res["result"], Value::Null);
+}
+
+#[test]
+fn rename() {
+    let mut server = open();
+    let params = json!({ "position": pos("n :", 0), "newName": "m" });
+    let res = request(&mut server, "textDocument/rename", params);
+    let edits = res["result"]["changes"][URI].as_array().unwrap();
+    assert_eq!(edits.len(), 2);
+    assert_eq!(edits[1]["range"], range("n\n", 2, 1));
+
+    // the argument would capture the recursive call
+    let params = json!({ "position": pos("l ->", 0), "newName": "len" });
+    let res = request(&mut server, "textDocument/rename", params);
+    assert!(res["error"].is_object(), "{res}");
+
+    let params = json!({ "position": pos("xs", 0), "newName": "in" });
+    let res = request(&mut server, "textDocument/rename", params);
+    assert!(res["error"].is_object(), "{res}");
+}
+
+#[test]
+fn document_symbols() {
+    let mut server = open();
+    let res = request(&mut server, "textDocument/documentSymbol", json!({}));
+    let names = |x: &Value| {
+        x.as_array()
+            .unwrap()
+            .iter()
+            .map(|x| {
+                (
+                    x["name"].as_str().unwrap().to_string(),
+                    x["kind"].as_u64().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    let syms = &res["result"];
+    assert_eq!(
+        names(syms),
+        [("List".into(), 10), ("len".into(), 12), ("n".into(), 13)]
+    );
+    assert_eq!(
+        names(&syms[0]["children"]),
+        [("Cons".into(), 22), ("Nil".into(), 22)]
+    );
+}
+
+#[test]
+fn diagnostics() {
+    let mut server = open();
+    let out = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": {
+            "textDocument": { "uri": URI, "version": 1 },
+            "contentChanges": [{ "text": "let x = 1 +\nin y" }],
+        },
+    }));
+    let diags = out[0]["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diags.len(), 1);
+    assert_eq!(diags[0]["message"], "expected expression, found `in`");
+    assert_eq!(
+        diags[0]["range"]["start"],
+        json!({ "line": 1, "character": 0 })
+    );
+
+    let out = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": {
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "text": "let x = 1 in y" }],
+        },
+    }));
+    let diags = out[0]["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diags[0]["message"], "unknown variable y");
+    assert_eq!(
+        diags[0]["range"]["start"],
+        json!({ "line": 0, "character": 13 })
+    );
+}
+
+#[test]
+fn warnings() {
+    let mut server = open();
+    let out = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": {
+            "textDocument": { "uri": URI, "version": 1 },
+            "contentChanges": [{ "text": "let x = 1 in\nlet y = 2 in\nlet x = y in\n\\_z -> x" }],
+        },
+    }));
+    let diags = out[0]["params"]["diagnostics"].as_array().unwrap();
+    let diags = diags.iter().map(|x| {
+        (
+            x["severity"].as_u64().unwrap(),
+            x["message"].as_str().unwrap(),
+            x["range"]["start"]["line"].as_u64().unwrap(),
+            x["range"]["start"]["character"].as_u64().unwrap(),
+        )
+    });
+    assert_eq!(
+        diags.collect::<Vec<_>>(),
+        [
+            (2, "unused variable `x`", 0, 4),
+            (2, "`x` shadows the one at 1:5", 2, 4),
+        ]
+    );
+}
+
+/// The warnings of a parsed program, which is left as it was.
+#[test]
+fn analyze_parsed() {
+    let src = "let x = 1 in\nlet y = 2 in\nlet x = y in\n\\_z -> x";
+    let (prog, _, spans) = tut::parser::parse_with_spans(src);
+    let a = tut::analysis::analyze_prog(src, &prog, &spans);
+    assert_eq!(a.warnings, tut::analysis::analyze(src).warnings);
+    assert_eq!(a.warnings.len(), 2);
+    assert_eq!(prog, tut::parser::parse(src).unwrap());
+}
+
+/// Spans of another source are an error, not an empty analysis.
+#[test]
+fn analyze_mismatch() {
+    let src = "let x = 1 in x";
+    let (prog, _, _) = tut::parser::parse_with_spans(src);
+    let (_, _, spans) = tut::parser::parse_with_spans("let x = 1 in x + y");
+    let a = tut::analysis::analyze_prog(src, &prog, &spans);
+    assert_eq!(a.diags.len(), 1);
+    assert!(a.diags[0].msg.starts_with("internal error"));
+}
+
+#[test]
+fn testcases_line_up() {
+    for entry in std::fs::read_dir("testcases").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().map_or(true, |x| x != "ml") {
+            continue;
+        }
+        let src = std::fs::read_to_string(&path).unwrap();
+        // without bindings there is nothing to line up
+        if tut::parser::parse(&src).is_err() || !src.contains("let") {
+            continue;
+        }
+        let a = tut::analysis::analyze(&src);
+        assert!(
+            !a.occurrences.is_empty() || !a.diags.is_empty(),
+            "{}",
+            path.display()
+        );
+        assert!(
+            a.diags.iter().all(|x| !x.msg.starts_with("internal error")),
+            "{}",
+            path.display()
+        );
+    }
+}
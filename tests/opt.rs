@@ -0,0 +1,202 @@
+//! Optimization passes, on their output and on the behavior of the programs they optimize.
+
+mod common;
+
+use std::fs;
+
+use common::{named, parsed, run, testcases};
+use tut::{
+    ast::Expr,
+    namer::Namer,
+    opt::optimize,
+    parser::parse,
+    pass::ExprTransformer,
+    printer::print_expr,
+    secd::{
+        machine::{Limit, Limits, SECDError, SECDMachine},
+        secdgen::secdgen_program,
+    },
+};
+
+/// `src` optimized at `level` and printed.
+fn optimized(src: &str, level: u8) -> String {
+    let mut e = parsed(src);
+    optimize(&mut e, level);
+    print_expr(&e)
+}
+
+fn same_at(level: u8, src: &str, expected: &str) {
+    let expected = print_expr(&parsed(expected));
+    assert_eq!(optimized(src, level), expected);
+}
+
+fn same(src: &str, expected: &str) {
+    same_at(1, src, expected)
+}
+
+#[test]
+fn fold() {
+    same("1 + 2 * 3", "7");
+    same("-(4 - 6) < 3", "1");
+    same("let x = 3 in x * 4 + x", "15");
+    same("!(0 == 0)", "0");
+    same("if 2 > 1 then println 1 else println 2", "println 1");
+    same(
+        "let x = 0 in if x then println 1 else println 2",
+        "println 2",
+    );
+    same("if true then 1 else 2", "1");
+}
+
+#[test]
+fn identities() {
+    same("\\x -> (x - 1) + 0", "\\x -> x - 1");
+    same("\\x -> 0 + -x - 0", "\\x -> -x");
+    same("\\x -> 1 * (x * 2) / 1", "\\x -> x * 2");
+    // `x` may not be an integer, on which the machine faults
+    same("\\x -> x + 0", "\\x -> x + 0");
+    same("\\x -> 0 + x - 0", "\\x -> 0 + x");
+    same("\\x -> 0 * (x, \\y -> y)", "\\x -> 0 * (x, \\y -> y)");
+    same("\\x -> (x * 2) * 0", "\\x -> x * 2 * 0");
+    same("\\x -> 0 * -(x / 0)", "\\x -> 0 * -(x / 0)");
+}
+
+#[test]
+fn faults_kept() {
+    same("1 / 0", "1 / 0");
+    same("\\x -> x % (1 - 1)", "\\x -> x % 0");
+    same("9223372036854775807 + 1", "9223372036854775807 + 1");
+    same("0 - 9223372036854775807 - 2", "-9223372036854775807 - 2");
+}
+
+#[test]
+fn dead_code() {
+    same("let x = 1 in let y = (x, 2) in 3", "3");
+    // only dead once the use in `y` is gone
+    same("let x = 1 in let y = \\z -> x in 3", "3");
+    same("let x = println 1 in 2", "let x = println 1 in 2");
+    same("1; (\\x -> x); println 2; 3", "println 2; 3");
+    same(
+        "let rec f = \\n -> g n and g = \\n -> f n and h = \\n -> h n in f",
+        "let rec f = \\n -> g n and g = \\n -> f n in f",
+    );
+    same("let rec h = \\n -> h n in 1", "1");
+    same("\\f -> (f 1; 2)", "\\f -> (f 1; 2)");
+}
+
+#[test]
+fn level_zero() {
+    assert_eq!(
+        optimized("let x = 1 in x + 2", 0),
+        print_expr(&parsed("let x = 1 in x + 2"))
+    );
+}
+
+#[test]
+fn inline() {
+    let inlined = |src| optimized(src, 2);
+    assert_eq!(inlined("(\\x -> x + 1) 2"), "3");
+    assert_eq!(inlined("let plus = \\x -> \\y -> x + y in plus 1 2"), "3");
+    // each copy gets its own names
+    assert_eq!(
+        inlined("let inc = \\x -> x + 1 in \\z -> inc (inc z)"),
+        "\\z ->\n    let x.2 = z + 1 in\n    x.2 + 1"
+    );
+    // kept for the use that is not a call
+    assert_eq!(
+        inlined("let inc = \\x -> x + 1 in \\g -> g inc (inc 1)"),
+        "let inc = \\x -> x + 1 in\n\\g -> g inc 2"
+    );
+}
+
+#[test]
+fn inline_large() {
+    let big = "\\x -> (x, x, x, x, x, x, x, x, x, x, x, x, x, x, x, x)";
+    // called once, so moved into the call
+    assert_eq!(
+        optimized(&format!("let f = {big} in f 1"), 2),
+        "(1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1)"
+    );
+    assert_eq!(
+        optimized(&format!("let f = {big} in (f 1, f 2)"), 2),
+        format!("let f = {big} in\n(f 1, f 2)")
+    );
+}
+
+/// The output and result of running `e` optimized at `level`, and the steps it took.
+fn run_at(e: &Expr, level: u8) -> (String, usize) {
+    let mut e = e.clone();
+    optimize(&mut e, level);
+    let (out, machine) = run(secdgen_program(&e));
+    (out, machine.steps)
+}
+
+#[test]
+fn behavior_kept() {
+    for (name, e) in testcases() {
+        let out = run_at(&e, 0).0;
+        assert_eq!(run_at(&e, 1).0, out, "{name}");
+        assert_eq!(run_at(&e, 2).0, out, "{name}");
+    }
+    for src in [
+        "println (1 / 0)",
+        "let x = 2 in println (x * 3); x % (x - 2)",
+        "let f = \\x -> println x; x in f 1 + f (f 2)",
+        "println ((\\p -> nth 0 p + nth 1 p) (5, 6))",
+        "let v = \\x -> x + 0 in v ()",
+        "let id = \\x -> x in let v = (if 8 < 8 then 1 else 2) in let w = id v in println (id w)",
+        "let k = \\a -> \\b -> a in let f = \\c -> k c (println c) in println (f 1 + f 2)",
+    ] {
+        let e = named(src);
+        let out = run_at(&e, 0).0;
+        assert_eq!(run_at(&e, 1).0, out, "{src}");
+        assert_eq!(run_at(&e, 2).0, out, "{src}");
+    }
+}
+
+/// All of them, not only `TESTCASES`, as long as they get through the namer and halt.
+#[test]
+fn behavior_kept_testcases() {
+    let run_at = |e: &Expr, level| {
+        let mut e = e.clone();
+        optimize(&mut e, level);
+        let mut machine = SECDMachine::init(secdgen_program(&e)).unwrap();
+        let limits = Limits {
+            fuel: Some(1_000_000),
+            ..Limits::default()
+        };
+        let res = machine.run(limits).map(|x| x.to_string());
+        (format!("{:?}", machine.sink.effects), res)
+    };
+    for entry in fs::read_dir("testcases").unwrap() {
+        let path = entry.unwrap().path();
+        let src = fs::read_to_string(&path).unwrap();
+        let Ok(mut prog) = parse(&src) else {
+            continue;
+        };
+        if Namer::new().visit(&mut prog.main_expr).is_err() {
+            continue;
+        }
+        let out = run_at(&prog.main_expr, 0);
+        if out.1 == Err(SECDError::LimitExceeded(Limit::Fuel)) {
+            continue;
+        }
+        assert_eq!(run_at(&prog.main_expr, 2), out, "{}", path.display());
+    }
+}
+
+#[test]
+fn fewer_steps() {
+    let mut total = [0; 3];
+    for (name, e) in testcases() {
+        let steps = [0, 1, 2].map(|level| run_at(&e, level).1);
+        println!("{name:<12} {:>8} {:>8} {:>8}", steps[0], steps[1], steps[2]);
+        assert!(steps[0] >= steps[1] && steps[1] >= steps[2], "{name}");
+        (0..3).for_each(|i| total[i] += steps[i]);
+    }
+    println!(
+        "{:<12} {:>8} {:>8} {:>8}",
+        "total", total[0], total[1], total[2]
+    );
+    assert!(total[2] < total[0]);
+}
@@ -0,0 +1,77 @@
+//! Parsing keeps no state across calls, and reports all syntax errors.
+#![feature(box_patterns)]
+
+use std::thread;
+
+use tut::{
+    ast::Expr,
+    parser::{parse, parse_with_diagnostics},
+};
+
+const DECL: &str = "datatype T = | C int end (\\x : T -> x) (C 1)";
+const USE: &str = "(\\x : T -> x) 1";
+
+#[test]
+fn type_names_do_not_leak() {
+    parse(DECL).unwrap();
+    assert!(parse(USE).is_err());
+}
+
+#[test]
+fn forward_type_references() {
+    parse("datatype A = | A1 B end datatype B = | B1 A end 0").unwrap();
+}
+
+#[test]
+fn concurrent() {
+    let threads = (0..8)
+        .map(|i| {
+            thread::spawn(move || {
+                for _ in 0..200 {
+                    let src = if i % 2 == 0 { DECL } else { USE };
+                    assert_eq!(parse(src).is_ok(), i % 2 == 0);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    threads.into_iter().for_each(|x| x.join().unwrap());
+}
+
+#[test]
+fn reports_all_errors() {
+    let src = "let x = 1 + in\nlet y = (x, 2 in\nif y then 3 4 else -> 5";
+    let (prog, diags) = parse_with_diagnostics(src);
+    let diags = diags.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        diags,
+        [
+            "1:13: expected expression, found `in`",
+            "2:15: expected `)`, found `in`",
+            "3:20: expected expression, found `->`",
+        ]
+    );
+    let Expr::Let { val, .. } = prog.main_expr else {
+        panic!("{:?}", prog.main_expr)
+    };
+    assert!(matches!(
+        *val,
+        Expr::Binary {
+            rhs: box Expr::Error {},
+            ..
+        }
+    ));
+}
+
+#[test]
+fn expected_keywords() {
+    let err = parse("let x = 1 x").unwrap_err().to_string();
+    assert_eq!(
+        err,
+        "1:12: expected `in` after let binding, found end of input"
+    );
+    let err = parse("match x | 1 -> 2").unwrap_err().to_string();
+    assert_eq!(
+        err,
+        "1:17: expected `end` after match arms, found end of input"
+    );
+}
@@ -0,0 +1,72 @@
+//! The peephole optimizer, checked by running the testcases before and after it.
+
+mod common;
+
+use common::{run, testcases};
+use tut::secd::{
+    cpsgen::cpsgen_program,
+    peephole::peephole,
+    repr::{secd_parse, secd_print},
+    secdgen::secdgen_program,
+};
+
+/// `code` optimized, parsed from and printed to one instruction per line.
+fn optimized(code: &str) -> String {
+    let lines = code.lines().map(|x| x.trim().to_string()).collect();
+    secd_print(&peephole(secd_parse(&lines), "main"))
+}
+
+#[test]
+fn branches() {
+    // to the next instruction, and to a branch
+    assert_eq!(
+        optimized(
+            "main:
+            const 1
+            brfl l1
+            br l2
+            l1:
+            br l3
+            l2:
+            halt
+            l3:
+            const 2
+            br l2"
+        ),
+        "main:\nconst 1\nbrfl l3\nhalt\nl3:\nconst 2\nhalt"
+    );
+    // a loop of branches stays
+    assert_eq!(optimized("main:\nl1:\nbr l1"), "main:\nl1:\nbr l1");
+}
+
+#[test]
+fn fused() {
+    assert_eq!(
+        optimized("f:\naccess 1\nconst 1\nsub\npushenv\naccess 1\nreturn\nmain:\nclosure f\nhalt"),
+        "f:\naccess 1\nsub 1\nreturn\nmain:\nclosure f\nhalt"
+    );
+    assert_eq!(
+        optimized("main:\nconst 1\npushenv\nconst 2\npushenv\naccess 1\npopenv 2\nhalt"),
+        "main:\nconst 1\npushenv\nconst 2\npopenv 1\nhalt"
+    );
+    assert_eq!(
+        optimized("main:\nconst ()\npushenv\npopenv 1\nconst 0\nhalt"),
+        "main:\nconst ()\npop 1\nconst 0\nhalt"
+    );
+}
+
+#[test]
+fn behavior_kept() {
+    let (mut before, mut after) = (0, 0);
+    for (name, e) in testcases() {
+        for instrs in [secdgen_program(&e), cpsgen_program(&e)] {
+            let (out, machine) = run(instrs.clone());
+            let (out1, machine1) = run(peephole(instrs, "main"));
+            assert_eq!(out1, out, "{name}");
+            assert!(machine1.steps <= machine.steps, "{name}");
+            (before, after) = (before + machine.steps, after + machine1.steps);
+        }
+    }
+    println!("steps: {before} before, {after} after");
+    assert!(after < before);
+}
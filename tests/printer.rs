@@ -0,0 +1,278 @@
+//! The printer must satisfy parse(print(ast)) == ast.
+
+mod common;
+
+use std::fs;
+
+use common::Rng;
+use tut::{
+    ast::*,
+    parser::parse,
+    printer::{format_source, print_expr, print_prog},
+};
+
+const VARS: &[&str] = &["a", "b", "x", "y", "f", "g", "foo", "bar_1"];
+const BINOPS: &[BinOp] = &[
+    BinOp::Add,
+    BinOp::Sub,
+    BinOp::Mul,
+    BinOp::Div,
+    BinOp::Rem,
+    BinOp::Gt,
+    BinOp::Lt,
+    BinOp::Ge,
+    BinOp::Le,
+    BinOp::Eq,
+    BinOp::Ne,
+];
+const UNAOPS: &[UnaOp] = &[UnaOp::Neg, UnaOp::Lnot];
+const BUILTINS: &[BuiltinOp] = &[
+    BuiltinOp::Println,
+    BuiltinOp::Print,
+    BuiltinOp::Panic,
+    BuiltinOp::True,
+    BuiltinOp::False,
+    BuiltinOp::ReadInt,
+    BuiltinOp::ReadLine,
+];
+
+/// Generates programs in the subset the parser can produce,
+/// e.g. no negative literals and no single-element tuples.
+struct Gen {
+    rng: Rng,
+    data_types: Vec<String>,
+    ctors: Vec<String>,
+}
+
+impl Gen {
+    fn new(seed: u64) -> Self {
+        Gen {
+            rng: Rng::new(seed),
+            data_types: Vec::new(),
+            ctors: Vec::new(),
+        }
+    }
+
+    fn var(&mut self) -> String {
+        self.rng.pick(VARS).to_string()
+    }
+
+    fn ty(&mut self, depth: usize) -> Ty {
+        let n = if depth == 0 { 4 } else { 6 };
+        match self.rng.below(n) {
+            0 => Ty::IntTy,
+            1 => Ty::BoolTy,
+            2 => Ty::UnitTy,
+            3 if !self.data_types.is_empty() => Ty::DataTy(self.rng.pick(&self.data_types).clone()),
+            3 => Ty::IntTy,
+            _ => Ty::AbsTy(Box::new(self.ty(depth - 1)), Box::new(self.ty(depth - 1))),
+        }
+    }
+
+    fn opt_ty(&mut self) -> Ty {
+        if self.rng.below(4) == 0 {
+            self.ty(2)
+        } else {
+            Ty::UnkTy
+        }
+    }
+
+    fn lit(&mut self) -> Expr {
+        match self.rng.below(6) {
+            0 => Expr::UnitLit {},
+            1 => Expr::Builtin {
+                op: BuiltinOp::True,
+            },
+            2 => Expr::Builtin {
+                op: BuiltinOp::False,
+            },
+            _ => Expr::IntLit {
+                val: self.rng.below(1000) as i64,
+            },
+        }
+    }
+
+    fn ptn1(&mut self, depth: usize) -> MatchPattern {
+        let n = if depth == 0 { 2 } else { 4 };
+        match self.rng.below(n) {
+            0 => MatchPattern::Binder { name: self.var() },
+            1 => MatchPattern::Lit { val: self.lit() },
+            _ => self.ptn(depth - 1),
+        }
+    }
+
+    fn ptn(&mut self, depth: usize) -> MatchPattern {
+        match self.rng.below(3) {
+            0 if !self.ctors.is_empty() => {
+                let ctor = self.rng.pick(&self.ctors).clone();
+                let n = 1 + self.rng.below(2);
+                let subs = (0..n).map(|_| self.ptn1(depth)).collect();
+                MatchPattern::DataType { ctor, subs }
+            }
+            1 => {
+                let n = 2 + self.rng.below(2);
+                let subs = (0..n).map(|_| self.ptn1(depth)).collect();
+                MatchPattern::Tuple { subs }
+            }
+            _ => self.ptn1(0),
+        }
+    }
+
+    fn boxed(&mut self, depth: usize) -> Box<Expr> {
+        Box::new(self.expr(depth))
+    }
+
+    fn expr(&mut self, depth: usize) -> Expr {
+        use Expr::*;
+        if depth == 0 {
+            return match self.rng.below(3) {
+                0 => self.lit(),
+                1 => Builtin {
+                    op: self.rng.pick(BUILTINS).clone(),
+                },
+                _ => VarRef { id: self.var() },
+            };
+        }
+        let d = depth - 1;
+        match self.rng.below(13) {
+            0 => Binary {
+                lhs: self.boxed(d),
+                op: *self.rng.pick(BINOPS),
+                rhs: self.boxed(d),
+            },
+            1 => Unary {
+                op: *self.rng.pick(UNAOPS),
+                sub: self.boxed(d),
+            },
+            2 => App {
+                fun: self.boxed(d),
+                arg: self.boxed(d),
+            },
+            3 => Seq {
+                subs: (0..2 + self.rng.below(2)).map(|_| self.boxed(d)).collect(),
+            },
+            4 => Abs {
+                arg_name: self.var(),
+                arg_ty: self.opt_ty(),
+                body: self.boxed(d),
+            },
+            5 => Let {
+                name: self.var(),
+                ty: self.opt_ty(),
+                val: self.boxed(d),
+                body: self.boxed(d),
+            },
+            6 => Tuple {
+                subs: (0..2 + self.rng.below(2)).map(|_| self.boxed(d)).collect(),
+            },
+            7 => Ite {
+                cond: self.boxed(d),
+                tr: self.boxed(d),
+                fl: self.boxed(d),
+            },
+            8 => LetRec {
+                arms: (0..1 + self.rng.below(2))
+                    .map(|_| LetRecArm {
+                        fn_name: self.var(),
+                        fn_ty: self.opt_ty(),
+                        arg_name: self.var(),
+                        arg_ty: self.opt_ty(),
+                        body: self.boxed(d),
+                    })
+                    .collect(),
+                body: self.boxed(d),
+            },
+            9 => Match {
+                sub: self.boxed(d),
+                arms: (0..1 + self.rng.below(3))
+                    .map(|_| MatchArm {
+                        ptn: self.ptn(2),
+                        res: self.expr(d),
+                    })
+                    .collect(),
+            },
+            10 => Nth {
+                idx: self.rng.below(4) as i64,
+                sub: self.boxed(d),
+            },
+            _ => self.expr(0),
+        }
+    }
+
+    fn prog(&mut self) -> Prog {
+        self.data_types = (0..self.rng.below(3)).map(|i| format!("T{i}")).collect();
+        self.ctors = Vec::new();
+        let mut data_types = Vec::new();
+        for name in self.data_types.clone() {
+            let arms = (0..self.rng.below(3))
+                .map(|_| {
+                    let ctor = format!("C{}", self.ctors.len());
+                    self.ctors.push(ctor.clone());
+                    let arg_tys = (0..1 + self.rng.below(2)).map(|_| self.ty(2)).collect();
+                    DataTypeArm { ctor, arg_tys }
+                })
+                .collect();
+            data_types.push(DataType { name, arms });
+        }
+        let depth = 1 + self.rng.below(5);
+        Prog {
+            data_types,
+            main_expr: self.expr(depth),
+        }
+    }
+}
+
+#[test]
+fn roundtrip_random() {
+    for seed in 1..3000u64 {
+        let prog = Gen::new(seed).prog();
+        let printed = print_prog(&prog);
+        match parse(&printed) {
+            Ok(reparsed) if reparsed == prog => (),
+            res => panic!("seed {seed}: printed\n{printed}\nparsed back as\n{res:#?}"),
+        }
+    }
+}
+
+#[test]
+fn roundtrip_testcases() {
+    for entry in fs::read_dir("testcases").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().map_or(true, |x| x != "ml") {
+            continue;
+        }
+        let src = fs::read_to_string(&path).unwrap();
+        // Some testcases use syntax that is not supported (yet).
+        let Ok(prog) = parse(&src) else { continue };
+        let printed = print_prog(&prog);
+        assert_eq!(parse(&printed).unwrap(), prog, "{}", path.display());
+
+        let formatted = format_source(&src).unwrap();
+        assert_eq!(
+            format_source(&formatted).unwrap(),
+            formatted,
+            "{}",
+            path.display()
+        );
+        let ncomments = |s: &str| s.matches("--").count();
+        assert_eq!(ncomments(&formatted), ncomments(&src), "{}", path.display());
+    }
+}
+
+#[test]
+fn no_syntax() {
+    let var = |x: &str| Box::new(Expr::VarRef { id: x.to_string() });
+    let land = Expr::Binary {
+        lhs: var("a"),
+        op: BinOp::Land,
+        rhs: var("b"),
+    };
+    let e = Expr::Binary {
+        lhs: Box::new(land),
+        op: BinOp::Add,
+        rhs: var("c"),
+    };
+    let printed = print_expr(&e);
+    assert_eq!(printed, "(a #land b) + c");
+    assert!(parse(&printed).is_err());
+}
@@ -0,0 +1,81 @@
+//! The REPL, fed one line at a time like `miniml repl` does.
+
+use tut::repl::Repl;
+
+/// Feeds `lines` to a new REPL, returning what each one printed.
+fn session(lines: &[&str]) -> Vec<Result<String, String>> {
+    let mut repl = Repl::new();
+    lines
+        .iter()
+        .map(|x| repl.handle(x).expect("no line quits"))
+        .collect()
+}
+
+fn ok(s: &str) -> Result<String, String> {
+    Ok(s.to_string())
+}
+
+#[test]
+fn bindings() {
+    let out = session(&[
+        "let x = 2",
+        "let rec even = \\n -> if n == 0 then 1 else odd (n - 1) \
+         and odd = \\n -> if n == 0 then 0 else even (n - 1)",
+        // sees the earlier x, and shadows it
+        "let x = x * 10",
+        "println x; (x, odd 3)",
+        "let id = \\z -> z",
+        "id x",
+    ]);
+    assert_eq!(
+        out,
+        [
+            ok("val x = 2"),
+            ok("val even = <fun>\nval odd = <fun>"),
+            ok("val x = 20"),
+            ok("20\n- = (20, 1)"),
+            ok("val id = <fun>"),
+            ok("- = 20"),
+        ]
+    );
+}
+
+#[test]
+fn load() {
+    let out = session(&[":load testcases/fact.ml", "fact 4", ":load nosuch.ml"]);
+    assert_eq!(out[0], ok("val fact = <fun>\n1\n6\n720\n- = ()"));
+    assert_eq!(out[1], ok("- = 24"));
+    assert!(matches!(&out[2], Err(x) if x.starts_with("cannot read nosuch.ml")));
+}
+
+/// An input that fails leaves the bindings as they were.
+#[test]
+fn recovery() {
+    let out = session(&[
+        "let x = 1",
+        "let y = (",
+        "x + 1",
+        "let w = 1 / 0",
+        "w",
+        "println 5; panic ()",
+        "x",
+    ]);
+    assert_eq!(out[0], ok("val x = 1"));
+    assert_eq!(
+        out[1],
+        Err("1:10: expected expression, found end of input".to_string())
+    );
+    assert_eq!(out[2], ok("- = 2"));
+    assert_eq!(out[3], Err("runtime error: division by zero".to_string()));
+    assert_eq!(out[4], Err("unknown variable w".to_string()));
+    // effects before the error are still printed
+    assert_eq!(out[5], Err("5\nruntime error: panic: ()".to_string()));
+    assert_eq!(out[6], ok("- = 1"));
+}
+
+#[test]
+fn quit() {
+    let mut repl = Repl::new();
+    assert_eq!(repl.handle(":help").unwrap(), ok(tut::repl::HELP));
+    assert_eq!(repl.handle(":quit"), None);
+}
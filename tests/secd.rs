@@ -0,0 +1,350 @@
+//! JSON output of SECD values, profiling, limits, host functions, effects, and snapshots.
+
+use std::{fs, rc::Rc};
+
+use serde_json::json;
+use tut::{
+    namer::Namer,
+    parser::parse,
+    pass::ExprTransformer,
+    secd::{
+        effects::{Callback, Capture},
+        host::HostFunctions,
+        langdef::SECDVal,
+        machine::{Limit, Limits, SECDEffect, SECDError, SECDMachine},
+        profile::Profiler,
+        repr::{effect_to_json, envs_from_json, val_from_json, EnvTable},
+        secdgen::SECDGen,
+    },
+};
+
+#[test]
+fn values() {
+    let closure = SECDVal::ClosureVal {
+        focused_fn: Some(3),
+        mutrec_fns: vec![3, 7],
+        env: Rc::new(vec![SECDVal::IntVal(1)]),
+    };
+    let v = SECDVal::TupleVal(vec![SECDVal::UnitVal, closure]);
+    let mut envs = EnvTable::new();
+    let json = envs.val_to_json(&v);
+    assert_eq!(
+        json,
+        json!({"kind": "tuple", "elems": [
+            {"kind": "unit"},
+            {
+                "kind": "closure",
+                "focused_fn": 3,
+                "mutrec_fns": [3, 7],
+                "env": 0,
+            },
+        ]})
+    );
+    let envs = envs.into_json();
+    assert_eq!(envs, json!([[{"kind": "int", "value": 1}]]));
+    let back = val_from_json(&json, &envs_from_json(&envs).unwrap()).unwrap();
+    assert_eq!(EnvTable::new().val_to_json(&back), json);
+}
+
+#[test]
+fn env_table() {
+    let env = Rc::new(vec![SECDVal::IntVal(1)]);
+    let closure = |pc| SECDVal::ClosureVal {
+        focused_fn: Some(pc),
+        mutrec_fns: vec![],
+        env: env.clone(),
+    };
+    let v = SECDVal::TupleVal(vec![closure(3), closure(5)]);
+    let mut envs = EnvTable::new();
+    let json = envs.val_to_json(&v);
+    let closure = |pc| json!({"kind": "closure", "focused_fn": pc, "mutrec_fns": [], "env": 0});
+    assert_eq!(
+        json,
+        json!({"kind": "tuple", "elems": [closure(3), closure(5)]})
+    );
+    let envs = envs.into_json();
+    assert_eq!(envs, json!([[{"kind": "int", "value": 1}]]));
+    // and shared again when read back
+    let envs = envs_from_json(&envs).unwrap();
+    let Some(SECDVal::TupleVal(back)) = val_from_json(&json, &envs) else {
+        panic!()
+    };
+    let [SECDVal::ClosureVal { env: a, .. }, SECDVal::ClosureVal { env: b, .. }] = &back[..] else {
+        panic!()
+    };
+    assert!(Rc::ptr_eq(a, b));
+}
+
+/// Functions that each capture the env of the one before, the last of them being the value.
+fn let_lambdas(n: usize) -> String {
+    let lets = (1..n)
+        .map(|i| format!("let f{i} = \\x -> f{} x in ", i - 1))
+        .collect::<String>();
+    format!("let f0 = \\x -> x in {lets}f{}", n - 1)
+}
+
+#[test]
+fn env_table_size() {
+    let mut m = machine(&let_lambdas(22));
+    let v = m.run(Limits::default()).unwrap();
+    let mut envs = EnvTable::new();
+    let json = json!([envs.val_to_json(&v), envs.into_json()]);
+    assert!(json.to_string().len() < 20_000);
+}
+
+#[test]
+fn effects() {
+    let e = SECDEffect::Println("hi".to_string());
+    assert_eq!(effect_to_json(&e), json!({"kind": "println", "text": "hi"}));
+}
+
+fn machine(src: &str) -> SECDMachine {
+    machine_with_hosts(src, HostFunctions::new())
+}
+
+fn machine_with_hosts(src: &str, hosts: HostFunctions) -> SECDMachine {
+    let mut prog = parse(src).unwrap();
+    let mut namer = Namer::new();
+    for name in hosts.names() {
+        namer.define_host(name);
+    }
+    namer.visit(&mut prog.main_expr).unwrap();
+    let mut secdgen = SECDGen::new();
+    secdgen.visit_main_expr(&prog.main_expr);
+    let mut machine = SECDMachine::init(secdgen.program()).unwrap();
+    machine.hosts = hosts;
+    machine
+}
+
+fn run(machine: &mut SECDMachine) -> SECDError {
+    loop {
+        if let Err(err) = machine.step() {
+            return err;
+        }
+    }
+}
+
+#[test]
+fn profile() {
+    let mut machine = machine(&fs::read_to_string("testcases/fact.ml").unwrap());
+    machine.profiler = Some(Profiler::new());
+    let mut steps = 0;
+    while machine.step().is_ok() {
+        steps += 1;
+    }
+    let profiler = machine.profiler.unwrap();
+    assert_eq!(profiler.steps, steps + 1);
+    assert_eq!(profiler.applies, profiler.returns);
+    let instrs: usize = profiler.fns.iter().map(|x| x.instrs).sum();
+    assert_eq!(instrs, profiler.steps);
+    let folded: usize = profiler
+        .folded_stacks()
+        .lines()
+        .map(|x| x.rsplit_once(' ').unwrap().1.parse::<usize>().unwrap())
+        .sum();
+    assert_eq!(folded, profiler.steps);
+    assert!(profiler.max_dump > 1);
+}
+
+#[test]
+fn limits() {
+    let fact = fs::read_to_string("testcases/fact.ml").unwrap();
+    let mut m = machine(&fact);
+    assert_eq!(run(&mut m), SECDError::Halted);
+    let steps = m.steps;
+
+    let exceeded = |limits: Limits| {
+        let mut m = machine(&fact);
+        m.limits = limits;
+        let err = run(&mut m);
+        (err, m.steps)
+    };
+    let fuel = Some(steps - 1);
+    let err = exceeded(Limits {
+        fuel,
+        ..Default::default()
+    });
+    assert_eq!(err, (SECDError::LimitExceeded(Limit::Fuel), steps - 1));
+    let err = exceeded(Limits {
+        fuel: Some(steps),
+        ..Default::default()
+    });
+    assert_eq!(err, (SECDError::Halted, steps));
+
+    let err = exceeded(Limits {
+        max_stack: Some(5),
+        ..Default::default()
+    });
+    assert_eq!(err.0, SECDError::LimitExceeded(Limit::Stack));
+    let err = exceeded(Limits {
+        max_env: Some(1),
+        ..Default::default()
+    });
+    assert_eq!(err.0, SECDError::LimitExceeded(Limit::Env));
+    let err = exceeded(Limits {
+        max_heap: Some(256),
+        ..Default::default()
+    });
+    assert_eq!(err.0, SECDError::LimitExceeded(Limit::Heap));
+    let err = exceeded(Limits {
+        max_heap: Some(1 << 20),
+        ..Default::default()
+    });
+    assert_eq!(err, (SECDError::Halted, steps));
+
+    // "1\n6\n" fits, "720\n" does not, and is not printed
+    let mut m = machine(&fact);
+    m.limits = Limits {
+        max_output: Some(4),
+        ..Default::default()
+    };
+    assert_eq!(run(&mut m), SECDError::LimitExceeded(Limit::Output));
+    assert_eq!(m.output_bytes, 4);
+    let out = m.sink.effects.iter().map(|x| x.text()).collect::<String>();
+    assert_eq!(out, "1\n6\n");
+}
+
+#[test]
+fn missing_entry() {
+    let mut m = machine("1");
+    let instrs = std::mem::take(&mut m.instrs);
+    let err = SECDMachine::new()
+        .load(instrs.clone(), "start")
+        .unwrap_err();
+    assert_eq!(err, SECDError::Fault("no label start".to_string()));
+    assert!(SECDMachine::init(instrs).is_ok());
+}
+
+#[test]
+fn faults() {
+    for src in ["1 / 0", "1 % (2 - 2)", "(\\x -> x) 1 2"] {
+        assert!(
+            matches!(run(&mut machine(src)), SECDError::Fault(_)),
+            "{src}"
+        );
+    }
+}
+
+fn hosts() -> HostFunctions {
+    let mut hosts = HostFunctions::new();
+    hosts.register("square", |args| match args {
+        [SECDVal::IntVal(x)] => Ok(SECDVal::IntVal(x * x)),
+        _ => Err("expected an int".to_string()),
+    });
+    hosts
+}
+
+#[test]
+fn host_functions() {
+    let mut m = machine_with_hosts("println (square 7); square (square 3)", hosts());
+    let res = m.run(Limits::default()).unwrap();
+    assert_eq!(res.to_string(), "81");
+    assert_eq!(m.sink.effects.len(), 1);
+    assert_eq!(effect_to_json(&m.sink.effects[0])["text"], "49");
+
+    // variables shadow host functions
+    let mut m = machine_with_hosts("let square = \\x -> x + 1 in square 3", hosts());
+    assert_eq!(m.run(Limits::default()).unwrap().to_string(), "4");
+
+    let mut m = machine_with_hosts("square println", hosts());
+    let err = m.run(Limits::default()).unwrap_err();
+    assert_eq!(err, SECDError::Fault("square: expected an int".to_string()));
+}
+
+#[test]
+fn input() {
+    let src = "let n = read_int () in println (n * 2); println (read_line ()); read_int ()";
+    let mut m = machine(src);
+    m.sink = Capture::with_input(" 21\nhello world\n");
+    let err = m.run(Limits::default()).unwrap_err();
+    assert_eq!(err, SECDError::Fault("read_int: end of input".to_string()));
+    let out = m
+        .sink
+        .effects
+        .iter()
+        .map(|x| effect_to_json(x)["text"].clone());
+    assert_eq!(out.collect::<Vec<_>>(), ["42", "hello world"]);
+}
+
+#[test]
+fn callback() {
+    let mut m = machine(&fs::read_to_string("testcases/fact.ml").unwrap());
+    let mut out = Vec::new();
+    let mut cb = SECDMachine::with_sink(Callback(|e: SECDEffect| out.push(e.text())));
+    cb.load(std::mem::take(&mut m.instrs), "main").unwrap();
+    cb.run(Limits::default()).unwrap();
+    drop(cb);
+    assert_eq!(out, ["1\n", "6\n", "720\n"]);
+}
+
+#[test]
+fn print_and_panic() {
+    let mut m = machine("print 1; print (2, 3); println 4; panic 5; println 6");
+    let err = m.run(Limits::default()).unwrap_err();
+    assert_eq!(err, SECDError::Panic("5".to_string()));
+    assert_eq!(err.to_string(), "panic: 5");
+    let out = m.sink.effects.iter().map(|x| x.text()).collect::<String>();
+    assert_eq!(out, "1(2, 3)4\n");
+}
+
+#[test]
+fn snapshots() {
+    let fact = fs::read_to_string("testcases/fact.ml").unwrap();
+    let mut whole = machine(&fact);
+    whole.run(Limits::default()).unwrap();
+
+    let mut first = machine(&fact);
+    let fuel = Limits {
+        fuel: Some(50),
+        ..Default::default()
+    };
+    assert_eq!(
+        first.run(fuel).unwrap_err(),
+        SECDError::LimitExceeded(Limit::Fuel)
+    );
+    let snap = first.save().to_string();
+
+    let mut second = machine(&fact);
+    second
+        .restore(&serde_json::from_str(&snap).unwrap())
+        .unwrap();
+    assert_eq!(second.steps, 50);
+    second.run(Limits::default()).unwrap();
+    assert_eq!(second.steps, whole.steps);
+    assert_eq!(second.sink.effects.len(), whole.sink.effects.len());
+    assert_eq!(second.save(), whole.save());
+
+    let mut other = machine("println 1");
+    assert!(other
+        .restore(&serde_json::from_str(&snap).unwrap())
+        .is_err());
+}
+
+#[test]
+fn snapshot_size() {
+    let src = let_lambdas(22);
+    let mut first = machine(&src);
+    first.run(Limits::default()).unwrap();
+    let snap = first.save();
+    assert!(snap.to_string().len() < 20_000);
+    let mut second = machine(&src);
+    second.restore(&snap).unwrap();
+    assert_eq!(second.save(), snap);
+}
+
+#[test]
+fn inner_lets() {
+    let mut m = machine(
+        "let z = 5 in
+        let x = (let y = 1 in y + 1) in
+        let f = (let rec g = \\n -> n in g) in
+        println x; println (f 3); println ((if z > 0 then (let w = 7 in w) else 0) + z)",
+    );
+    m.run(Limits::default()).unwrap();
+    let out = m
+        .sink
+        .effects
+        .iter()
+        .map(|x| effect_to_json(x)["text"].clone());
+    assert_eq!(out.collect::<Vec<_>>(), ["2", "3", "12"]);
+}
@@ -0,0 +1,124 @@
+//! Expression spans, and the source maps of generated SECD code.
+
+use std::fs;
+
+use tut::{
+    ast::{Expr, Prog},
+    namer::Namer,
+    parser::parse_with_spans,
+    pass::ExprTransformer,
+    secd::{coverage::SrcCoverage, machine::SECDMachine, secdgen::SECDGen, srcmap::SourceMap},
+    spans::ExprSpans,
+};
+
+/// Parses `src`, which must be valid.
+fn parse_spans(src: &str) -> (Prog, ExprSpans) {
+    let (prog, diags, spans) = parse_with_spans(src);
+    assert!(diags.is_empty(), "{diags:?}");
+    (prog, spans)
+}
+
+#[test]
+fn spans() {
+    let src = "let x = 1 + 2 in\n-- the body\nx * (x - 1) -- done\n";
+    let (prog, spans) = parse_spans(src);
+    let info = spans.info(&prog).unwrap();
+    let span_of = |e| info.get(e).unwrap().to_string();
+    let main = &prog.main_expr;
+    assert_eq!(span_of(main), "1:1-3:12");
+    let Expr::Let { val, body, .. } = main else {
+        panic!("{main:?}")
+    };
+    assert_eq!(span_of(val), "1:9-1:14");
+    let Expr::Binary { rhs, .. } = &**body else {
+        panic!("{body:?}")
+    };
+    // the parentheses around it are not part of the span
+    assert_eq!(span_of(rhs), "3:6-3:11");
+}
+
+#[test]
+fn unit_spans() {
+    let (prog, spans) = parse_spans("println ( )");
+    let info = spans.info(&prog).unwrap();
+    let Expr::App { arg, .. } = &prog.main_expr else {
+        panic!("{:?}", prog.main_expr)
+    };
+    assert_eq!(info.get(arg).unwrap().to_string(), "1:9-1:12");
+}
+
+#[test]
+fn testcases_line_up() {
+    for entry in fs::read_dir("testcases").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().map_or(true, |x| x != "ml") {
+            continue;
+        }
+        let src = fs::read_to_string(&path).unwrap();
+        let (prog, diags, spans) = parse_with_spans(&src);
+        if diags.is_empty() {
+            assert!(spans.info(&prog).is_some(), "{}", path.display());
+        }
+    }
+}
+
+fn compile(src: &str) -> SECDGen {
+    let (mut prog, spans) = parse_spans(src);
+    Namer::new().visit(&mut prog.main_expr).unwrap();
+    let spans = spans.info(&prog).unwrap();
+    let mut secdgen = SECDGen::new().with_spans(spans);
+    secdgen.visit_main_expr(&prog.main_expr);
+    secdgen
+}
+
+#[test]
+fn assembled_map() {
+    let secdgen = compile(&fs::read_to_string("testcases/fact.ml").unwrap());
+    let map = secdgen.source_map();
+    let parsed = SourceMap::parse(&secdgen.assemble());
+    assert_eq!(parsed.instr_locs.len(), secdgen.program().len());
+    for pc in 0..secdgen.program().len() {
+        assert_eq!(parsed.loc(pc), map.loc(pc), "pc {pc}");
+    }
+}
+
+/// Every located instruction names all of the env.
+#[test]
+fn locals_line_up() {
+    for name in ["fact", "closure", "evenodd", "higherorder"] {
+        let secdgen = compile(&fs::read_to_string(format!("testcases/{name}.ml")).unwrap());
+        let map = secdgen.source_map();
+        let mut machine = SECDMachine::init(secdgen.program()).unwrap();
+        let mut located = 0;
+        while !machine.halted() {
+            let pc = machine.state.0;
+            if let Some(loc) = map.loc(pc) {
+                assert_eq!(loc.env.len(), machine.state.2.len(), "{name}: pc {pc}");
+                located += 1;
+            }
+            machine.step().unwrap();
+        }
+        assert!(located > 0, "{name}");
+    }
+}
+
+#[test]
+fn coverage() {
+    let src = "let f = \\x : int ->\n  if x > 0 then\n    x\n  else\n    0 - x\nin\nf 3";
+    let secdgen = compile(src);
+    let mut machine = SECDMachine::init(secdgen.program()).unwrap();
+    machine.coverage = Some(Vec::new());
+    while machine.step().is_ok() {}
+    let hits = machine.coverage.unwrap();
+    let (prog, spans) = parse_spans(src);
+    let cov = SrcCoverage::new(&prog, &spans, &secdgen.source_map(), &hits).unwrap();
+    assert_eq!(cov.lines.get(&3), Some(&true));
+    assert_eq!(cov.lines.get(&4), None);
+    assert_eq!(cov.lines.get(&5), Some(&false));
+    assert_eq!(cov.branches.len(), 1);
+    assert_eq!(cov.branches[0].line, 2);
+    assert_eq!(cov.branches[0].taken, [true, false]);
+    let lcov = cov.lcov("t.ml");
+    assert!(lcov.contains("BRDA:2,0,1,0\n"), "{lcov}");
+    assert!(lcov.contains("DA:5,0\n"), "{lcov}");
+}
@@ -0,0 +1,128 @@
+//! Type inference.
+
+use std::fs;
+
+use tut::{namer::Namer, parser::parse, pass::ExprTransformer, typer::infer};
+
+/// The type of the program `src`, or its type error.
+fn type_of(src: &str) -> Result<String, String> {
+    let prog = parse(src).unwrap();
+    match infer(&prog) {
+        Ok(info) => Ok(info.get(&prog.main_expr).unwrap().to_string()),
+        Err(err) => Err(err.kind.to_string()),
+    }
+}
+
+fn ok(s: &str) -> Result<String, String> {
+    Ok(s.to_string())
+}
+
+fn err(s: &str) -> Result<String, String> {
+    Err(s.to_string())
+}
+
+#[test]
+fn exprs() {
+    assert_eq!(type_of("1 + 2"), ok("int"));
+    assert_eq!(type_of("1 < 2"), ok("bool"));
+    assert_eq!(type_of("\\x -> x"), ok("'a -> 'a"));
+    assert_eq!(
+        type_of("\\f -> \\x -> f (f x)"),
+        ok("('a -> 'a) -> 'a -> 'a")
+    );
+    assert_eq!(
+        type_of("(1, (), \\x: int -> x)"),
+        ok("int * unit * (int -> int)")
+    );
+    assert_eq!(type_of("nth 1 (1, true)"), ok("bool"));
+    assert_eq!(type_of("println 1; read_int ()"), ok("int"));
+    assert_eq!(
+        type_of("match (1, 2) | x, 0 -> x | _, y -> y end"),
+        ok("int")
+    );
+}
+
+#[test]
+fn polymorphism() {
+    assert_eq!(
+        type_of("let id = \\x -> x in (id 1, id ())"),
+        ok("int * unit")
+    );
+    assert_eq!(
+        type_of("let rec f = \\x -> x in (f 1, f ())"),
+        ok("int * unit")
+    );
+    // not the argument of a function
+    assert_eq!(
+        type_of("(\\id -> (id 1, id ())) (\\x -> x)"),
+        err("expected int, found unit")
+    );
+}
+
+#[test]
+fn errors() {
+    assert_eq!(
+        type_of("if 1 then 2 else 3"),
+        err("expected bool, found int")
+    );
+    assert_eq!(
+        type_of("if true then 2 else ()"),
+        err("expected int, found unit")
+    );
+    assert_eq!(type_of("1 + (1 < 2)"), err("expected int, found bool"));
+    assert_eq!(type_of("y"), err("unknown variable y"));
+    assert_eq!(
+        type_of("\\x -> x x"),
+        err("'a would be 'a -> 'b, which contains it")
+    );
+    assert_eq!(
+        type_of("nth 2 (1, 2)"),
+        err("nth 2 of int * int, which is not a tuple that long")
+    );
+    assert_eq!(
+        type_of("\\p -> nth 0 p"),
+        err("nth 0 of 'a, which is not a tuple that long")
+    );
+    assert_eq!(
+        type_of("let f: int -> int = \\x -> () in f"),
+        err("expected int -> int, found int -> unit")
+    );
+}
+
+/// The testcases that are well typed, and the first error of those that are not.
+#[test]
+fn testcases() {
+    let typed = [
+        "fact",
+        "typer",
+        "let_poly",
+        "let_rec_poly",
+        "let_rec_poly_constrfv",
+        "let_rec_variad_poly",
+        "tuple",
+        "patmat_tup",
+        "evenodd",
+        "curry",
+    ];
+    let bad = [
+        ("typerbad", "expected bool, found int"),
+        ("let_poly_constr", "expected int, found unit"),
+        ("let_rec_variad_poly_badtype", "expected int, found unit"),
+        (
+            "higherorder_badtype",
+            "expected int -> int, found (int -> int) -> int -> int",
+        ),
+    ];
+    let infer_file = |name| {
+        let src = fs::read_to_string(format!("testcases/{name}.ml")).unwrap();
+        let mut prog = parse(&src).unwrap();
+        Namer::new().visit(&mut prog.main_expr).unwrap();
+        infer(&prog).map(|_| ()).map_err(|x| x.kind.to_string())
+    };
+    for name in typed {
+        assert_eq!(infer_file(name), Ok(()), "{name}");
+    }
+    for (name, msg) in bad {
+        assert_eq!(infer_file(name), Err(msg.to_string()), "{name}");
+    }
+}